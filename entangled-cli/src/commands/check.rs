@@ -0,0 +1,93 @@
+//! Check command implementation.
+//!
+//! Reports undefined references, reference cycles, unused blocks and blocks
+//! of unknown languages without tangling anything.
+
+use entangled::errors::{EntangledError, Result};
+use entangled::interface::{Context, Document};
+use entangled::model::{MergePolicy, ValidationIssue};
+
+/// Options for the check command.
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    /// Suppress normal output.
+    pub quiet: bool,
+}
+
+/// Executes the check command.
+///
+/// Prints every issue found in the source documents, and fails if any of
+/// them is an error rather than a warning.
+pub fn check(ctx: &Context, options: CheckOptions) -> Result<()> {
+    let issues = collect_issues(ctx)?;
+
+    let mut errors = 0;
+    for issue in &issues {
+        let level = if issue.is_error() {
+            errors += 1;
+            "error"
+        } else {
+            "warning"
+        };
+        println!("{}: {}: {}", issue.location(), level, issue);
+    }
+
+    if errors > 0 {
+        return Err(EntangledError::Other(format!(
+            "check found {} error{}",
+            errors,
+            if errors == 1 { "" } else { "s" }
+        )));
+    }
+    if issues.is_empty() && !options.quiet {
+        println!("No problems found.");
+    }
+    Ok(())
+}
+
+/// Validates the references and languages of all source documents.
+fn collect_issues(ctx: &Context) -> Result<Vec<ValidationIssue>> {
+    let mut refs = ctx.new_reference_map();
+    for path in ctx.source_files()? {
+        refs.merge(Document::load(&path, ctx)?.refs(), MergePolicy::Append)?;
+    }
+    refs.sort_by_location();
+
+    Ok(refs
+        .validate()
+        .into_iter()
+        .chain(refs.validate_languages(&ctx.config))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_fails_on_errors_only() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let md = dir.path().join("test.md");
+
+        // An unused block is only a warning
+        fs::write(
+            &md,
+            "```python #main file=out.py\nx = 1\n```\n\n```python #unused\ny = 2\n```\n",
+        )
+        .unwrap();
+        let issues = collect_issues(&ctx).unwrap();
+        assert_eq!(issues.len(), 1);
+        check(&ctx, CheckOptions::default()).unwrap();
+
+        // An annotated target in an unknown language fails the check
+        fs::write(&md, "```snobol #main file=out.sno\nOUTPUT = 3\n```\n").unwrap();
+        assert!(matches!(
+            collect_issues(&ctx).unwrap().as_slice(),
+            [ValidationIssue::UnknownLanguage { .. }]
+        ));
+        assert!(check(&ctx, CheckOptions::default()).is_err());
+    }
+}
@@ -1,26 +1,127 @@
 //! Config command implementation.
 
-use entangled::errors::Result;
+use std::path::{Path, PathBuf};
+
+use entangled::config::find_config_file;
+use entangled::errors::{EntangledError, Result};
 use entangled::interface::Context;
+use entangled::{Config, Style};
+
+/// Options for the config command.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOptions {
+    /// Annotate each setting with where its value came from.
+    pub explain: bool,
+    /// Config file that was loaded; discovered from the base directory when
+    /// not set.
+    pub config_path: Option<PathBuf>,
+    /// Style override from the command line.
+    pub style: Option<Style>,
+    /// Whether `--skip-invalid` was given on the command line.
+    pub skip_invalid: bool,
+    /// Whether `--keep-going` was given on the command line.
+    pub keep_going: bool,
+}
 
 /// Executes the config command -- prints the effective resolved configuration.
-pub fn config(ctx: &Context) -> Result<()> {
-    let toml_str = toml::to_string_pretty(&ctx.config).map_err(|e| {
-        entangled::EntangledError::Other(format!("Failed to serialize config: {}", e))
-    })?;
+pub fn config(ctx: &Context, options: ConfigOptions) -> Result<()> {
+    if options.explain {
+        let config_path = options
+            .config_path
+            .or_else(|| find_config_file(&ctx.base_dir));
+        let mut overrides = Vec::new();
+        if options.style.is_some() {
+            overrides.push(("style", "--style"));
+        }
+        if options.skip_invalid {
+            overrides.push(("skip_invalid", "--skip-invalid"));
+        }
+        if options.keep_going {
+            overrides.push(("keep_going", "--keep-going"));
+        }
+        print!(
+            "{}",
+            explain(&ctx.config, config_path.as_deref(), &overrides)?
+        );
+        return Ok(());
+    }
+
+    let toml_str = toml::to_string_pretty(&ctx.config)
+        .map_err(|e| EntangledError::Other(format!("Failed to serialize config: {}", e)))?;
     print!("{}", toml_str);
     Ok(())
 }
 
+/// Lists every top-level setting with the source of its value.
+///
+/// Settings named in `overrides` (field, flag) came from the command line.
+/// Otherwise a setting that differs from the default is attributed to
+/// `config_path`; anything else is the default. A value set in the file
+/// that equals the default is therefore reported as the default.
+fn explain(
+    config: &Config,
+    config_path: Option<&Path>,
+    overrides: &[(&str, &str)],
+) -> Result<String> {
+    let to_table = |config: &Config| match toml::Value::try_from(config) {
+        Ok(toml::Value::Table(table)) => Ok(table),
+        Ok(_) => Err(EntangledError::Other(
+            "Config did not serialize to a table".to_string(),
+        )),
+        Err(e) => Err(EntangledError::Other(format!(
+            "Failed to serialize config: {}",
+            e
+        ))),
+    };
+    let effective = to_table(config)?;
+    let defaults = to_table(&Config::default())?;
+    let file_name = config_path.and_then(Path::file_name).map_or_else(
+        || "config file".to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    );
+
+    let mut out = String::new();
+    for (key, value) in &effective {
+        let source = if let Some((_, flag)) = overrides.iter().find(|(field, _)| field == key) {
+            format!("from {}", flag)
+        } else if defaults.get(key) != Some(value) {
+            format!("from {}", file_name)
+        } else {
+            "default".to_string()
+        };
+        out.push_str(&format!("{} = {}  ({})\n", key, value, source));
+    }
+    Ok(out)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use entangled::config::AnnotationMethod;
     use tempfile::tempdir;
 
     #[test]
     fn test_config_prints() {
         let dir = tempdir().unwrap();
         let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
-        config(&ctx).unwrap();
+        config(&ctx, ConfigOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn test_explain_sources() {
+        let config = Config::builder()
+            .annotation(AnnotationMethod::Naked)
+            .style(Style::Pandoc)
+            .build();
+        let out = explain(
+            &config,
+            Some(Path::new("/project/entangled.toml")),
+            &[("style", "--style")],
+        )
+        .unwrap();
+
+        assert!(out.contains("annotation = \"naked\"  (from entangled.toml)\n"));
+        assert!(out.contains("style = \"pandoc\"  (from --style)\n"));
+        assert!(out.contains("namespace_default = \"file\"  (default)\n"));
     }
 }
@@ -0,0 +1,168 @@
+//! Diff command implementation.
+//!
+//! Compares tangled files on disk with what a fresh tangle would produce.
+
+use std::path::{Path, PathBuf};
+
+use entangled::errors::Result;
+use entangled::interface::{load_document, Context};
+use entangled::io::{unified_diff, DEFAULT_DIFF_CONTEXT};
+
+use super::helpers::colorize_diff;
+
+/// Options for the diff command.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Target files to compare (empty means every target).
+    pub files: Vec<PathBuf>,
+    /// Number of context lines in diffs (default: 3).
+    pub context: Option<usize>,
+    /// Colorize diff output.
+    pub color: bool,
+    /// Suppress normal output.
+    pub quiet: bool,
+}
+
+/// Executes the diff command.
+///
+/// Prints a unified diff from each target's content on disk to its fresh
+/// tangle. Nothing is written and the file database is not consulted.
+pub fn diff(ctx: &Context, options: DiffOptions) -> Result<()> {
+    let targets = if options.files.is_empty() {
+        all_targets(ctx)?
+    } else {
+        options
+            .files
+            .iter()
+            .map(|f| f.strip_prefix(&ctx.base_dir).unwrap_or(f).to_path_buf())
+            .collect()
+    };
+
+    let context = options.context.unwrap_or(DEFAULT_DIFF_CONTEXT);
+    let mut differs = 0;
+    for target in &targets {
+        let diff = target_diff(ctx, target, context)?;
+        if diff.is_empty() {
+            continue;
+        }
+        differs += 1;
+        if options.color {
+            println!("{}", colorize_diff(&diff));
+        } else {
+            println!("{}", diff);
+        }
+    }
+
+    if differs == 0 && !options.quiet {
+        println!("No differences.");
+    }
+    Ok(())
+}
+
+/// Returns every target of the source files, sorted.
+///
+/// Sources are loaded as for a tangle, so `skip_invalid` and `keep_going`
+/// skip the documents they would skip there.
+fn all_targets(ctx: &Context) -> Result<Vec<PathBuf>> {
+    let mut targets = Vec::new();
+    for path in ctx.source_files()? {
+        if let Some(doc) = load_document(ctx, &path)? {
+            targets.extend(doc.targets());
+        }
+    }
+    targets.sort();
+    targets.dedup();
+    Ok(targets)
+}
+
+/// Diffs the on-disk content of `target` against its fresh tangle. A missing
+/// file is shown as created.
+fn target_diff(ctx: &Context, target: &Path, context: usize) -> Result<String> {
+    let tangled = ctx.tangle_target(target)?;
+    let full_path = ctx.resolve_path(target);
+    let label = target.display().to_string();
+    if ctx.file_cache.exists(&full_path) {
+        let on_disk = ctx.file_cache.read(&full_path)?;
+        Ok(unified_diff(
+            &on_disk,
+            &tangled,
+            &format!("a/{}", label),
+            &format!("b/{}", label),
+            context,
+        ))
+    } else {
+        Ok(unified_diff(
+            "",
+            &tangled,
+            "/dev/null",
+            &format!("b/{}", label),
+            context,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_target_diff_shows_drift() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("test.md"),
+            "```python file=output.py\nprint('hello')\n```\n",
+        )
+        .unwrap();
+
+        // Not yet tangled: the whole file is new
+        let target = PathBuf::from("output.py");
+        let changes = target_diff(&ctx, &target, 3).unwrap();
+        assert!(changes.starts_with("--- /dev/null\n+++ b/output.py"));
+
+        // Matching content has no diff
+        fs::write(
+            dir.path().join("output.py"),
+            ctx.tangle_target(&target).unwrap(),
+        )
+        .unwrap();
+        assert!(target_diff(&ctx, &target, 3).unwrap().is_empty());
+
+        // A manual edit shows up as a change back to the tangled content
+        let edited = fs::read_to_string(dir.path().join("output.py"))
+            .unwrap()
+            .replace("hello", "bye");
+        fs::write(dir.path().join("output.py"), edited).unwrap();
+        let changes = target_diff(&ctx, &target, 3).unwrap();
+        assert!(changes.contains("-print('bye')"));
+        assert!(changes.contains("+print('hello')"));
+
+        assert_eq!(all_targets(&ctx).unwrap(), vec![target]);
+        diff(&ctx, DiffOptions::default()).unwrap();
+        // Nothing is written
+        assert!(fs::read_to_string(dir.path().join("output.py"))
+            .unwrap()
+            .contains("bye"));
+    }
+
+    #[test]
+    fn test_all_targets_skips_broken_sources() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("a.md"),
+            "```python file=a.py\nprint('a')\n```\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("b.md"), b"```python file=b.py\n\xff\n```\n").unwrap();
+
+        assert!(all_targets(&ctx).is_err());
+        ctx.config.skip_invalid = true;
+        assert_eq!(all_targets(&ctx).unwrap(), vec![PathBuf::from("a.py")]);
+        ctx.config.skip_invalid = false;
+        ctx.config.keep_going = true;
+        assert_eq!(all_targets(&ctx).unwrap(), vec![PathBuf::from("a.py")]);
+    }
+}
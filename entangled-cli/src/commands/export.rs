@@ -0,0 +1,116 @@
+//! Export command implementation.
+//!
+//! Renders a markdown document with its code blocks expanded, as Markdown
+//! or HTML.
+
+use std::fs;
+use std::path::PathBuf;
+
+use entangled::errors::Result;
+use entangled::interface::{export_document, Context, ExportFormat};
+
+/// Options for the export command.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// Markdown source file path.
+    pub file: PathBuf,
+    /// Output format.
+    pub format: ExportFormat,
+    /// Output file; stdout when not set.
+    pub output: Option<PathBuf>,
+}
+
+/// Executes the export command.
+pub fn export(ctx: &Context, options: ExportOptions) -> Result<()> {
+    let full_path = ctx.resolve_path(&options.file);
+
+    if !full_path.exists() {
+        return Err(entangled::EntangledError::Other(format!(
+            "File not found: {}",
+            full_path.display()
+        )));
+    }
+
+    let rendered = export_document(ctx, &full_path, options.format)?;
+    match options.output {
+        Some(output) => {
+            fs::write(&output, rendered)?;
+            tracing::info!(
+                "Exported {} to {}",
+                options.file.display(),
+                output.display()
+            );
+        }
+        None => print!("{}", rendered),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_to_file() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.namespace_default = entangled::config::NamespaceDefault::None;
+        fs::write(
+            dir.path().join("doc.md"),
+            "# Doc\n\n```python #main file=out.py\n<<greet>>\n```\n\n```python #greet\nprint('hi')\n```\n",
+        )
+        .unwrap();
+
+        let output = dir.path().join("woven.md");
+        let options = ExportOptions {
+            file: PathBuf::from("doc.md"),
+            format: ExportFormat::Markdown,
+            output: Some(output.clone()),
+        };
+        export(&ctx, options).unwrap();
+
+        let woven = fs::read_to_string(output).unwrap();
+        assert!(woven.starts_with("# Doc\n"));
+        assert!(woven.contains("```python #main file=out.py\nprint('hi')\n```"));
+    }
+
+    #[test]
+    fn test_export_html() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.namespace_default = entangled::config::NamespaceDefault::None;
+        fs::write(
+            dir.path().join("doc.md"),
+            "Doc & co\n\n```python #main file=out.py\nprint('<hi>')\n```\n",
+        )
+        .unwrap();
+
+        let output = dir.path().join("doc.html");
+        let options = ExportOptions {
+            file: PathBuf::from("doc.md"),
+            format: ExportFormat::Html,
+            output: Some(output.clone()),
+        };
+        export(&ctx, options).unwrap();
+
+        let html = fs::read_to_string(output).unwrap();
+        assert!(html.contains("<p>Doc &amp; co</p>"));
+        assert!(
+            html.contains("<pre><code class=\"language-python\">print('&lt;hi&gt;')</code></pre>")
+        );
+    }
+
+    #[test]
+    fn test_export_missing_file() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let options = ExportOptions {
+            file: PathBuf::from("missing.md"),
+            format: ExportFormat::Markdown,
+            output: None,
+        };
+        assert!(export(&ctx, options).is_err());
+    }
+}
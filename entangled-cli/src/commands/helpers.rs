@@ -1,39 +1,126 @@
 //! Shared command helpers.
 
+use std::path::PathBuf;
+
 use entangled::errors::Result;
-use entangled::interface::Context;
-use entangled::io::Transaction;
+use entangled::interface::{Context, Document};
+use entangled::io::{Transaction, TransactionSummary, DEFAULT_DIFF_CONTEXT};
 
 /// Common options for transaction-based commands.
 pub struct TransactionOptions {
     pub force: bool,
     pub dry_run: bool,
     pub diff: bool,
+    /// Number of context lines in diffs (default: 3).
+    pub context: Option<usize>,
+    pub stat: bool,
+    /// Colorize diff output with ANSI escapes.
+    pub color: bool,
     pub quiet: bool,
+    /// Summarize the run as JSON instead of printing progress.
+    pub json: bool,
+}
+
+/// Prints a `--stat` summary and/or unified diffs for a transaction.
+pub fn print_changes(
+    ctx: &Context,
+    transaction: &Transaction,
+    diff: bool,
+    stat: bool,
+    context: Option<usize>,
+    color: bool,
+) {
+    if stat {
+        let stats = transaction.diff_stats_in(ctx.file_cache.as_ref());
+        let (mut additions, mut deletions) = (0, 0);
+        for s in &stats {
+            let path = s.path.strip_prefix(&ctx.base_dir).unwrap_or(&s.path);
+            println!(" {} | +{} -{}", path.display(), s.additions, s.deletions);
+            additions += s.additions;
+            deletions += s.deletions;
+        }
+        println!(
+            " {} files changed, {} insertions(+), {} deletions(-)",
+            stats.len(),
+            additions,
+            deletions
+        );
+    }
+
+    if diff {
+        let context = context.unwrap_or(DEFAULT_DIFF_CONTEXT);
+        for diff in transaction.diffs_in(ctx.file_cache.as_ref(), context) {
+            if color {
+                println!("{}", colorize_diff(&diff));
+            } else {
+                println!("{}", diff);
+            }
+        }
+    }
 }
 
-/// Runs a transaction with common option handling (diff, dry-run, force, quiet).
+/// Colors a unified diff for terminal display.
 ///
-/// Returns Ok(()) after handling the transaction according to the options.
+/// File headers are bold, hunk headers cyan, additions green and removals red.
+pub fn colorize_diff(diff: &str) -> String {
+    const RESET: &str = "\x1b[0m";
+
+    diff.lines()
+        .map(|line| {
+            let style = if line.starts_with("+++") || line.starts_with("---") {
+                "\x1b[1m"
+            } else if line.starts_with("@@") {
+                "\x1b[36m"
+            } else if line.starts_with('+') {
+                "\x1b[32m"
+            } else if line.starts_with('-') {
+                "\x1b[31m"
+            } else {
+                return line.to_string();
+            };
+            format!("{}{}{}", style, line, RESET)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs a transaction with common option handling (diff, stat, dry-run, force, quiet).
+///
+/// With `json`, nothing is printed and the summary of the transaction, taken
+/// before it is executed, is returned; otherwise the summary is empty.
 /// `verb` is used for display (e.g., "tangle", "stitch").
 pub fn run_transaction(
     ctx: &mut Context,
     transaction: Transaction,
     options: &TransactionOptions,
     verb: &str,
-) -> Result<()> {
+) -> Result<TransactionSummary> {
+    if options.json {
+        let summary = transaction.summary_in(ctx.file_cache.as_ref());
+        if !options.dry_run && !transaction.is_empty() {
+            ctx.execute(&transaction, options.force)?;
+            ctx.save_filedb()?;
+        }
+        return Ok(summary);
+    }
+
     if transaction.is_empty() {
         if !options.quiet {
             println!("No files to {}.", verb);
         }
-        return Ok(());
+        return Ok(TransactionSummary::default());
     }
 
-    if options.diff {
-        for diff in transaction.diffs() {
-            println!("{}", diff);
-        }
-        return Ok(());
+    if options.diff || options.stat {
+        print_changes(
+            ctx,
+            &transaction,
+            options.diff,
+            options.stat,
+            options.context,
+            options.color,
+        );
+        return Ok(TransactionSummary::default());
     }
 
     if options.dry_run {
@@ -41,7 +128,7 @@ pub fn run_transaction(
         for desc in transaction.describe() {
             println!("  {}", desc);
         }
-        return Ok(());
+        return Ok(TransactionSummary::default());
     }
 
     if options.force {
@@ -56,10 +143,76 @@ pub fn run_transaction(
         let past = match verb {
             "stitch" => "Stitched",
             "tangle" => "Tangled",
+            "undo" => "Restored",
+            "rename" => "Updated",
             _ => "Processed",
         };
         println!("{} {} files.", past, transaction.len());
     }
 
-    Ok(())
+    Ok(TransactionSummary::default())
+}
+
+/// Counts the code blocks in the given source documents.
+pub fn count_blocks(ctx: &Context, files: &[PathBuf]) -> Result<usize> {
+    let mut count = 0;
+    for path in files {
+        match Document::load(path, ctx) {
+            Ok(doc) => count += doc.refs().len(),
+            // Reported by the run itself
+            Err(_) if ctx.config.keep_going => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(count)
+}
+
+/// Prints the `--json` summary of a tangle, stitch or sync run.
+///
+/// Paths are relative to the base directory and sorted. The field names
+/// are stable.
+pub fn print_summary(
+    ctx: &Context,
+    command: &str,
+    dry_run: bool,
+    summary: &TransactionSummary,
+    blocks: usize,
+) {
+    let paths = |paths: &[PathBuf]| -> Vec<String> {
+        let mut paths: Vec<String> = paths
+            .iter()
+            .map(|p| p.strip_prefix(&ctx.base_dir).unwrap_or(p))
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        paths.sort();
+        paths
+    };
+    let output = serde_json::json!({
+        "command": command,
+        "dry_run": dry_run,
+        "created": paths(&summary.created),
+        "written": paths(&summary.written),
+        "unchanged": paths(&summary.unchanged),
+        "deleted": paths(&summary.deleted),
+        "blocks": blocks,
+        "warnings": crate::warnings::take(),
+    });
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colorize_diff() {
+        let diff = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n same\n-old\n+new";
+        let colored = colorize_diff(diff);
+        let lines: Vec<&str> = colored.lines().collect();
+        assert_eq!(lines[0], "\x1b[1m--- a/f\x1b[0m");
+        assert_eq!(lines[2], "\x1b[36m@@ -1,2 +1,2 @@\x1b[0m");
+        assert_eq!(lines[3], " same");
+        assert_eq!(lines[4], "\x1b[31m-old\x1b[0m");
+        assert_eq!(lines[5], "\x1b[32m+new\x1b[0m");
+    }
 }
@@ -10,7 +10,7 @@ const DEFAULT_CONFIG: &str = r##"version = "2.0"
 source_patterns = ["**/*.md"]
 
 # Code block syntax style for .md files
-# Options: "entangled-rs" (default), "pandoc", "quarto", "knitr"
+# Options: "entangled-rs" (default), "pandoc", "quarto", "knitr", "org", "asciidoc"
 style = "entangled-rs"
 
 # How to annotate output files
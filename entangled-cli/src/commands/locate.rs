@@ -1,24 +1,56 @@
 //! Locate command implementation.
 //!
-//! Maps a line in a tangled output file back to its markdown source.
+//! Maps a line, or a range of lines, in a tangled output file back to its
+//! markdown source.
 
 use std::path::PathBuf;
 
 use entangled::errors::Result;
-use entangled::interface::{locate_source, Context};
+use entangled::interface::{
+    locate_source, locate_source_range, Context, SourceLocation, SourceRange,
+};
 
 /// Options for the locate command.
 #[derive(Debug, Clone)]
 pub struct LocateOptions {
     /// Target file path.
     pub file: PathBuf,
-    /// Line number in the target file (1-indexed).
+    /// Line number in the target file (1-indexed), or the first line of a range.
     pub line: usize,
+    /// Last line of the range (inclusive), to locate every block it spans.
+    pub end_line: Option<usize>,
+    /// Output as JSON (`null` when the line has no source mapping, an array
+    /// for a range).
+    pub json: bool,
+}
+
+/// Builds the JSON form of a location, using the same keys as the Python
+/// binding's `locate_source` dict.
+fn location_json(loc: Option<&SourceLocation>) -> serde_json::Value {
+    match loc {
+        Some(loc) => serde_json::json!({
+            "source_file": loc.source_file.display().to_string(),
+            "source_line": loc.source_line,
+            "block_id": loc.block_id.to_string(),
+        }),
+        None => serde_json::Value::Null,
+    }
+}
+
+/// Builds the JSON form of a located range.
+fn range_json(range: &SourceRange) -> serde_json::Value {
+    serde_json::json!({
+        "source_file": range.source_file.display().to_string(),
+        "first_line": range.first_line,
+        "last_line": range.last_line,
+        "block_id": range.block_id.to_string(),
+    })
 }
 
 /// Executes the locate command.
 ///
-/// Parses `file:line` and prints the corresponding markdown source location.
+/// Prints the markdown source location of `file:line`, or one source range
+/// per block spanned by `file:first-last`.
 pub fn locate(ctx: &Context, options: LocateOptions) -> Result<()> {
     let full_path = ctx.resolve_path(&options.file);
 
@@ -29,7 +61,35 @@ pub fn locate(ctx: &Context, options: LocateOptions) -> Result<()> {
         )));
     }
 
-    match locate_source(ctx, &full_path, options.line)? {
+    if let Some(end_line) = options.end_line {
+        let ranges = locate_source_range(ctx, &full_path, options.line, end_line)?;
+        if options.json {
+            let values: Vec<_> = ranges.iter().map(range_json).collect();
+            println!("{}", serde_json::Value::Array(values));
+            return Ok(());
+        }
+        if ranges.is_empty() {
+            eprintln!(
+                "No source mapping for {}:{}-{}",
+                options.file.display(),
+                options.line,
+                end_line
+            );
+        }
+        for range in ranges {
+            println!("{} ({})", range, range.block_id);
+        }
+        return Ok(());
+    }
+
+    let result = locate_source(ctx, &full_path, options.line)?;
+
+    if options.json {
+        println!("{}", location_json(result.as_ref()));
+        return Ok(());
+    }
+
+    match result {
         Some(loc) => {
             println!("{}", loc);
         }
@@ -49,6 +109,7 @@ pub fn locate(ctx: &Context, options: LocateOptions) -> Result<()> {
 mod tests {
     use super::*;
     use std::fs;
+    use std::path::Path;
     use tempfile::tempdir;
 
     #[test]
@@ -83,6 +144,8 @@ print('line3')
         let options = LocateOptions {
             file: PathBuf::from("output.py"),
             line: 2,
+            end_line: None,
+            json: false,
         };
         let result = locate_source(&ctx, &ctx.resolve_path(&options.file), options.line).unwrap();
         assert!(result.is_some());
@@ -116,4 +179,49 @@ print('hello')
             locate_source(&ctx, &ctx.resolve_path(&PathBuf::from("output.py")), 1).unwrap();
         assert!(result.is_none(), "Annotation lines should return None");
     }
+
+    #[test]
+    fn test_location_json() {
+        let loc = SourceLocation {
+            source_file: PathBuf::from("test.md"),
+            source_line: 4,
+            block_id: entangled::model::ReferenceId::parse("main[0]").unwrap(),
+        };
+        let value = location_json(Some(&loc));
+        assert_eq!(value["source_file"], "test.md");
+        assert_eq!(value["source_line"], 4);
+        assert_eq!(value["block_id"], "main[0]");
+
+        assert!(location_json(None).is_null());
+    }
+
+    #[test]
+    fn test_locate_range() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\nprint('a')\nprint('b')\n```\n",
+        )
+        .unwrap();
+        let tx = entangled::interface::tangle_documents(&ctx).unwrap();
+        tx.execute(&mut ctx.filedb).unwrap();
+
+        // The range includes both markers
+        let ranges =
+            locate_source_range(&ctx, &ctx.resolve_path(Path::new("output.py")), 1, 4).unwrap();
+        assert_eq!(ranges.len(), 1);
+        let value = range_json(&ranges[0]);
+        assert_eq!(value["source_file"], "test.md");
+        assert_eq!(value["first_line"], 2);
+        assert_eq!(value["last_line"], 3);
+
+        let options = LocateOptions {
+            file: PathBuf::from("output.py"),
+            line: 1,
+            end_line: Some(4),
+            json: true,
+        };
+        locate(&ctx, options).unwrap();
+    }
 }
@@ -0,0 +1,553 @@
+//! LSP command implementation.
+//!
+//! Runs a minimal language server over stdio so editors can jump between
+//! references, their definitions, and the markdown behind tangled files.
+//! Supports go-to-definition, document symbols, and diagnostics for
+//! undefined references and reference cycles.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use entangled::config::{ref_count, ref_name, REF_PATTERN};
+use entangled::errors::{EntangledError, Result};
+use entangled::interface::{locate_source, Context, Document};
+use entangled::model::{resolve_reference, MergePolicy, ValidationIssue};
+use entangled::readers::ParsedDocument;
+use entangled::{CodeBlock, ReferenceMap};
+
+/// JSON-RPC error code for an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code for a failed request.
+const INTERNAL_ERROR: i64 = -32603;
+/// LSP `SymbolKind` used for code blocks.
+const SYMBOL_KIND_FUNCTION: u32 = 12;
+/// LSP `DiagnosticSeverity` for errors.
+const SEVERITY_ERROR: u32 = 1;
+
+/// Executes the lsp command, serving requests on stdin until `exit`.
+pub fn lsp(ctx: &Context) -> Result<()> {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    Server::new(ctx).serve(stdin.lock(), stdout.lock())
+}
+
+/// Language server state: the context and the text of open buffers.
+struct Server<'a> {
+    ctx: &'a Context,
+    /// Unsaved text of open documents, by absolute path.
+    open: HashMap<PathBuf, String>,
+}
+
+impl<'a> Server<'a> {
+    fn new(ctx: &'a Context) -> Self {
+        Self {
+            ctx,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Reads messages until `exit` or end of input, writing every response.
+    fn serve(&mut self, mut reader: impl BufRead, mut writer: impl Write) -> Result<()> {
+        while let Some(message) = read_message(&mut reader)? {
+            if message["method"] == "exit" {
+                break;
+            }
+            for reply in self.handle(&message) {
+                write_message(&mut writer, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles one message, returning the responses and notifications to send.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        // Requests carry an id and expect exactly one response
+        if let Some(id) = message.get("id") {
+            let result = match method {
+                "initialize" => Ok(capabilities()),
+                "shutdown" => Ok(Value::Null),
+                "textDocument/definition" => self.definition(params),
+                "textDocument/documentSymbol" => self.document_symbols(params),
+                _ => {
+                    return vec![json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("Method not found: {}", method),
+                        },
+                    })]
+                }
+            };
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(e) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": INTERNAL_ERROR, "message": e.to_string() },
+                }),
+            };
+            return vec![response];
+        }
+
+        let Some(path) = document_path(params) else {
+            return Vec::new();
+        };
+        match method {
+            "textDocument/didOpen" => {
+                if let Some(text) = params["textDocument"]["text"].as_str() {
+                    self.open.insert(path.clone(), text.to_string());
+                }
+            }
+            "textDocument/didChange" => {
+                // Full document sync: the last change holds the whole text
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.open.insert(path.clone(), text.to_string());
+                }
+            }
+            "textDocument/didSave" => {}
+            "textDocument/didClose" => {
+                self.open.remove(&path);
+                return vec![publish_diagnostics(&path, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+        vec![publish_diagnostics(&path, self.diagnostics(&path))]
+    }
+
+    /// Strips the base directory from an absolute path.
+    fn relative<'p>(&self, path: &'p Path) -> &'p Path {
+        path.strip_prefix(&self.ctx.base_dir).unwrap_or(path)
+    }
+
+    /// Returns whether `path` is one of the configured source documents.
+    fn is_source(&self, path: &Path) -> Result<bool> {
+        let relative = self.relative(path);
+        Ok(self.ctx.source_files()?.iter().any(|f| f == relative))
+    }
+
+    /// Returns the current text of a document, preferring the open buffer.
+    fn text(&self, path: &Path) -> Result<String> {
+        match self.open.get(path) {
+            Some(text) => Ok(text.clone()),
+            None => Ok(self.ctx.read_file(path)?.to_string()),
+        }
+    }
+
+    /// Parses a source document, preferring the open buffer.
+    fn parse(&self, relative: &Path) -> Result<ParsedDocument> {
+        match self.open.get(&self.ctx.resolve_path(relative)) {
+            Some(text) => self.ctx.parse_cached(relative, text),
+            None => Ok(Document::load(relative, self.ctx)?.parsed),
+        }
+    }
+
+    /// Collects the code blocks of every source document.
+    fn all_refs(&self) -> Result<ReferenceMap> {
+        let mut refs = self.ctx.new_reference_map();
+        for path in self.ctx.source_files()? {
+            refs.merge(&self.parse(&path)?.refs, MergePolicy::Append)?;
+        }
+        refs.sort_by_location();
+        Ok(refs)
+    }
+
+    /// Returns the LSP location of a block's opening fence.
+    fn block_location(&self, block: &CodeBlock) -> Value {
+        let file = block.location.filename.clone().unwrap_or_default();
+        location(
+            &self.ctx.resolve_path(&file),
+            block.location.line.saturating_sub(1),
+        )
+    }
+
+    /// Answers `textDocument/definition`.
+    ///
+    /// On a `<<ref>>` line in a source document, returns the blocks that
+    /// define the reference. On a line of a tangled file, returns the markdown
+    /// line it was written from.
+    fn definition(&self, params: &Value) -> Result<Value> {
+        let Some(path) = document_path(params) else {
+            return Ok(Value::Null);
+        };
+        let line = params["position"]["line"].as_u64().unwrap_or_default() as usize;
+
+        if !self.is_source(&path)? {
+            return Ok(match locate_source(self.ctx, &path, line + 1)? {
+                Some(found) => location(
+                    &self.ctx.resolve_path(&found.source_file),
+                    found.source_line - 1,
+                ),
+                None => Value::Null,
+            });
+        }
+
+        let text = self.text(&path)?;
+        let Some(caps) = text.lines().nth(line).and_then(|l| REF_PATTERN.captures(l)) else {
+            return Ok(Value::Null);
+        };
+
+        // Resolve from the namespace of the block the reference appears in
+        let parsed = self.parse(self.relative(&path))?;
+        let namespace = parsed
+            .refs
+            .blocks()
+            .find(|b| line > b.location.line - 1 && line <= b.location.line - 1 + b.line_count())
+            .and_then(|b| b.namespace.clone());
+
+        let refs = self.all_refs()?;
+        let name = resolve_reference(&refs, &ref_name(&caps), namespace.as_deref());
+        let count = ref_count(&caps);
+        let locations: Vec<Value> = refs
+            .get_by_name(&name)
+            .into_iter()
+            .filter(|b| !b.skip && count.is_none_or(|n| b.id.count == n))
+            .map(|b| self.block_location(b))
+            .collect();
+        Ok(Value::Array(locations))
+    }
+
+    /// Answers `textDocument/documentSymbol` with one symbol per code block.
+    fn document_symbols(&self, params: &Value) -> Result<Value> {
+        let Some(path) = document_path(params) else {
+            return Ok(Value::Null);
+        };
+        if !self.is_source(&path)? {
+            return Ok(Value::Array(Vec::new()));
+        }
+
+        let parsed = self.parse(self.relative(&path))?;
+        let symbols = parsed
+            .refs
+            .iter()
+            .map(|(id, block)| {
+                json!({
+                    "name": id.to_string(),
+                    "kind": SYMBOL_KIND_FUNCTION,
+                    "location": self.block_location(block),
+                })
+            })
+            .collect();
+        Ok(Value::Array(symbols))
+    }
+
+    /// Computes diagnostics for a source document.
+    ///
+    /// Errors that stop the document from being read are reported as a
+    /// single diagnostic where possible.
+    fn diagnostics(&self, path: &Path) -> Vec<Value> {
+        let e = match self.check(path) {
+            Ok(diagnostics) => return diagnostics,
+            Err(e) => e,
+        };
+        match &e {
+            EntangledError::Parse { location, .. }
+            | EntangledError::InvalidHeader { location, .. }
+                if location.filename.as_deref() == Some(self.relative(path)) =>
+            {
+                vec![diagnostic(
+                    location.line.saturating_sub(1),
+                    0,
+                    &e.to_string(),
+                )]
+            }
+            _ => {
+                tracing::warn!("Could not check {}: {}", path.display(), e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Reports undefined references, reference cycles and annotated blocks of
+    /// unknown languages in a source document.
+    fn check(&self, path: &Path) -> Result<Vec<Value>> {
+        if !self.is_source(path)? {
+            return Ok(Vec::new());
+        }
+
+        let relative = self.relative(path);
+        // Fail with the document's own parse error, if any
+        self.parse(relative)?;
+        let refs = self.all_refs()?;
+        let diagnostics = refs
+            .validate()
+            .into_iter()
+            .chain(refs.validate_languages(&self.ctx.config))
+            .filter(|issue| {
+                issue.is_error() && issue.location().filename.as_deref() == Some(relative)
+            })
+            .map(|issue| {
+                let line = issue.location().line;
+                // Underline the reference
+                let width = match &issue {
+                    ValidationIssue::UndefinedReference { block, .. } => refs
+                        .get(block)
+                        .and_then(|b| b.source.lines().nth(line - b.location.line - 1))
+                        .map_or(0, |text| text.encode_utf16().count()),
+                    _ => 0,
+                };
+                diagnostic(line - 1, width, &issue.to_string())
+            })
+            .collect();
+
+        Ok(diagnostics)
+    }
+}
+
+/// Server capabilities sent in reply to `initialize`.
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": { "openClose": true, "change": 1, "save": true },
+            "definitionProvider": true,
+            "documentSymbolProvider": true,
+        },
+        "serverInfo": {
+            "name": "entangled",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+/// Builds an LSP location pointing at the start of a 0-based line.
+fn location(path: &Path, line: usize) -> Value {
+    json!({
+        "uri": path_to_uri(path),
+        "range": {
+            "start": { "line": line, "character": 0 },
+            "end": { "line": line, "character": 0 },
+        },
+    })
+}
+
+/// Builds an error diagnostic spanning the first `width` characters of a line.
+fn diagnostic(line: usize, width: usize, message: &str) -> Value {
+    json!({
+        "range": {
+            "start": { "line": line, "character": 0 },
+            "end": { "line": line, "character": width },
+        },
+        "severity": SEVERITY_ERROR,
+        "source": "entangled",
+        "message": message,
+    })
+}
+
+/// Builds a `textDocument/publishDiagnostics` notification.
+fn publish_diagnostics(path: &Path, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": path_to_uri(path), "diagnostics": diagnostics },
+    })
+}
+
+/// Extracts the document path from `params.textDocument.uri`.
+fn document_path(params: &Value) -> Option<PathBuf> {
+    params["textDocument"]["uri"].as_str().and_then(uri_to_path)
+}
+
+/// Converts a `file://` URI to a path, decoding percent escapes.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    let path = String::from_utf8(decoded).ok()?;
+
+    // Windows drive paths arrive as `/C:/...`
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => Some(PathBuf::from(&path[1..])),
+        _ => Some(PathBuf::from(path)),
+    }
+}
+
+/// Converts a path to a `file://` URI, percent-encoding reserved bytes.
+fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/:".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            let _ = write!(uri, "%{:02X}", byte);
+        }
+    }
+    uri
+}
+
+/// Reads one `Content-Length` framed message, or `None` at end of input.
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((key, value)) = header.split_once(':') {
+            if key.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or_default()];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+/// Writes one message with a `Content-Length` header.
+fn write_message(writer: &mut impl Write, message: &Value) -> Result<()> {
+    let body = serde_json::to_string(message)?;
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+    use tempfile::tempdir;
+
+    fn request(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params })
+    }
+
+    fn at(path: &Path, line: usize) -> Value {
+        json!({
+            "textDocument": { "uri": path_to_uri(path) },
+            "position": { "line": line, "character": 2 },
+        })
+    }
+
+    #[test]
+    fn test_message_framing() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &json!({ "method": "exit" })).unwrap();
+        write_message(&mut buffer, &json!({ "method": "shutdown" })).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        let first = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(first["method"], "exit");
+        let second = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(second["method"], "shutdown");
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_uri_round_trip() {
+        let path = Path::new("/tmp/my docs/a#b.md");
+        let uri = path_to_uri(path);
+        assert_eq!(uri, "file:///tmp/my%20docs/a%23b.md");
+        assert_eq!(uri_to_path(&uri).unwrap(), path);
+    }
+
+    #[test]
+    fn test_definition_and_symbols() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let doc = dir.path().join("doc.md");
+        fs::write(
+            &doc,
+            "```python #main file=out.py\n<<helper>>\n```\n\n```python #helper\nx = 1\n```\n",
+        )
+        .unwrap();
+
+        let mut server = Server::new(&ctx);
+        let reply = server.handle(&request("textDocument/definition", at(&doc, 1)));
+        let locations = reply[0]["result"].as_array().unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0]["uri"], path_to_uri(&doc));
+        assert_eq!(locations[0]["range"]["start"]["line"], 4);
+
+        let params = json!({ "textDocument": { "uri": path_to_uri(&doc) } });
+        let reply = server.handle(&request("textDocument/documentSymbol", params));
+        let symbols = reply[0]["result"].as_array().unwrap();
+        let names: Vec<&str> = symbols
+            .iter()
+            .map(|s| s["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["doc.md#main[0]", "doc.md#helper[0]"]);
+    }
+
+    #[test]
+    fn test_undefined_reference_diagnostic() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let doc = dir.path().join("doc.md");
+        fs::write(&doc, "").unwrap();
+
+        let text = "```python #main file=out.py\nx = 1\n<<missing>>\n```\n";
+        let mut server = Server::new(&ctx);
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": { "uri": path_to_uri(&doc), "text": text },
+            },
+        });
+        let reply = server.handle(&open);
+        assert_eq!(reply[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = reply[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 2);
+        assert!(diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("missing"));
+    }
+
+    #[test]
+    fn test_serve_until_exit() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+
+        let mut input = Vec::new();
+        write_message(&mut input, &request("initialize", json!({}))).unwrap();
+        write_message(&mut input, &json!({ "jsonrpc": "2.0", "method": "exit" })).unwrap();
+        write_message(&mut input, &request("shutdown", json!({}))).unwrap();
+
+        let mut output = Vec::new();
+        Server::new(&ctx)
+            .serve(Cursor::new(input), &mut output)
+            .unwrap();
+
+        let mut reader = Cursor::new(output);
+        let reply = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(reply["result"]["capabilities"]["definitionProvider"], true);
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+}
@@ -0,0 +1,153 @@
+//! Migrate command implementation.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use entangled::errors::{EntangledError, Result};
+use entangled::interface::migrate::migrate_python;
+
+/// Project formats that can be migrated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MigrateSource {
+    /// Python Entangled (`entangled.toml` or `pyproject.toml`, `.entangled/filedb.json`)
+    #[default]
+    Python,
+}
+
+/// Options for the migrate command.
+#[derive(Debug, Clone, Default)]
+pub struct MigrateOptions {
+    /// Format to migrate from.
+    pub from: MigrateSource,
+    /// Overwrite existing `.bak` backups.
+    pub force: bool,
+}
+
+const HEADER: &str = "# Migrated from Python Entangled by `entangled migrate`\n\n";
+
+/// Executes the migrate command.
+///
+/// Writes `entangled.toml` and the file database in this crate's formats.
+/// Files that are replaced are first renamed with a `.bak` suffix.
+pub fn migrate(base_dir: &Path, options: MigrateOptions) -> Result<()> {
+    let migration = match options.from {
+        MigrateSource::Python => migrate_python(base_dir)?,
+    };
+    for warning in &migration.warnings {
+        tracing::warn!("{}", warning);
+    }
+
+    let config_path = base_dir.join("entangled.toml");
+    let filedb_path = base_dir.join(&migration.config.filedb_path);
+    // Both files are checked before either is touched
+    let mut to_back_up = vec![config_path.clone(), filedb_path.clone()];
+    to_back_up.retain(|path| path.exists());
+    if !options.force {
+        if let Some(existing) = to_back_up
+            .iter()
+            .map(|p| backup_path(p))
+            .find(|b| b.exists())
+        {
+            return Err(EntangledError::Config(format!(
+                "{} already exists (use --force to overwrite)",
+                existing.display()
+            )));
+        }
+    }
+    for path in &to_back_up {
+        let backup = backup_path(path);
+        fs::rename(path, &backup)?;
+        println!("Saved {} as {}", path.display(), backup.display());
+    }
+
+    let toml_str = toml::to_string_pretty(&migration.config)
+        .map_err(|e| EntangledError::Other(format!("Failed to serialize config: {}", e)))?;
+    fs::write(&config_path, format!("{}{}", HEADER, toml_str))?;
+    match &migration.config_source {
+        Some(source) => println!(
+            "Created {} from {}",
+            config_path.display(),
+            source.display()
+        ),
+        None => println!("Created {} with default settings", config_path.display()),
+    }
+
+    migration.filedb.save(&filedb_path)?;
+    println!(
+        "Imported {} tracked files into {}",
+        migration.filedb.len(),
+        filedb_path.display()
+    );
+
+    Ok(())
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entangled::io::FileDB;
+    use tempfile::tempdir;
+
+    fn python_project() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("entangled.toml"),
+            "version = \"2.0\"\nwatch_list = [\"docs/*.md\"]\nhooks = [\"shebang\"]\n",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join(".entangled")).unwrap();
+        fs::write(
+            dir.path().join(".entangled/filedb.json"),
+            r#"{"version": "2.0.0",
+                "files": [{"path": "out.py", "deps": null, "modified": "2024-01-15T10:30:00", "hexdigest": "abc"}],
+                "source": [], "target": ["out.py"]}"#,
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_migrate_writes_config_and_filedb() {
+        let dir = python_project();
+        migrate(dir.path(), MigrateOptions::default()).unwrap();
+
+        let config =
+            entangled::config::read_config_file(&dir.path().join("entangled.toml")).unwrap();
+        assert_eq!(config.source_patterns, vec!["docs/*.md"]);
+        assert!(config.hooks.shebang);
+
+        let db = FileDB::load(&dir.path().join(".entangled/filedb.json")).unwrap();
+        assert_eq!(db.get(Path::new("out.py")).unwrap().hexdigest, "abc");
+
+        assert!(dir.path().join("entangled.toml.bak").exists());
+        assert!(dir.path().join(".entangled/filedb.json.bak").exists());
+    }
+
+    #[test]
+    fn test_migrate_keeps_existing_backups() {
+        let dir = python_project();
+        fs::write(dir.path().join("entangled.toml.bak"), "older").unwrap();
+
+        assert!(migrate(dir.path(), MigrateOptions::default()).is_err());
+        // Nothing was touched
+        assert!(fs::read_to_string(dir.path().join("entangled.toml"))
+            .unwrap()
+            .contains("watch_list"));
+
+        let options = MigrateOptions {
+            force: true,
+            ..Default::default()
+        };
+        migrate(dir.path(), options).unwrap();
+        assert!(fs::read_to_string(dir.path().join("entangled.toml.bak"))
+            .unwrap()
+            .contains("watch_list"));
+    }
+}
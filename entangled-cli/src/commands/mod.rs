@@ -1,22 +1,40 @@
 //! CLI command implementations.
 
+pub mod check;
 pub mod config;
+pub mod diff;
+pub mod export;
 mod helpers;
 pub mod init;
 pub mod locate;
+pub mod lsp;
+pub mod migrate;
+pub mod parse;
+pub mod rename_block;
 pub mod reset;
+pub mod reverse_locate;
 pub mod status;
 pub mod stitch;
 pub mod sync;
 pub mod tangle;
+pub mod undo;
 pub mod watch;
 
-pub use config::config;
+pub use check::{check, CheckOptions};
+pub use config::{config, ConfigOptions};
+pub use diff::{diff, DiffOptions};
+pub use export::{export, ExportOptions};
 pub use init::init;
 pub use locate::{locate, LocateOptions};
+pub use lsp::lsp;
+pub use migrate::{migrate, MigrateOptions, MigrateSource};
+pub use parse::{parse, ParseOptions};
+pub use rename_block::{rename_block, RenameBlockOptions};
 pub use reset::{reset, ResetOptions};
+pub use reverse_locate::{reverse_locate, ReverseLocateOptions};
 pub use status::{status, StatusOptions};
 pub use stitch::{stitch, StitchOptions};
 pub use sync::{sync, SyncOptions};
 pub use tangle::{tangle, TangleOptions};
+pub use undo::{undo, UndoOptions};
 pub use watch::{watch, WatchOptions};
@@ -0,0 +1,85 @@
+//! Parse command implementation.
+//!
+//! Shows the code blocks read from a single document, optionally as JSON.
+
+use std::path::PathBuf;
+
+use entangled::errors::Result;
+use entangled::interface::{Context, Document};
+
+/// Options for the parse command.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Source document path.
+    pub file: PathBuf,
+    /// Print the parsed document as JSON.
+    pub json: bool,
+}
+
+/// Executes the parse command.
+pub fn parse(ctx: &Context, options: ParseOptions) -> Result<()> {
+    let full_path = ctx.resolve_path(&options.file);
+
+    if !full_path.exists() {
+        return Err(entangled::EntangledError::Other(format!(
+            "File not found: {}",
+            full_path.display()
+        )));
+    }
+
+    let path = full_path.strip_prefix(&ctx.base_dir).unwrap_or(&full_path);
+    let document = Document::load(path, ctx)?;
+
+    if options.json {
+        println!("{}", document.parsed.to_json()?);
+        return Ok(());
+    }
+
+    for (id, block) in document.refs().iter() {
+        let mut line = format!("{} (line {})", id, block.location.line);
+        if let Some(language) = &block.language {
+            line.push_str(&format!(" {}", language));
+        }
+        for target in block.targets() {
+            line.push_str(&format!(" -> {}", target.display()));
+        }
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_json() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("doc.md"),
+            "```python #main file=out.py\nprint('hi')\n```\n",
+        )
+        .unwrap();
+
+        let options = ParseOptions {
+            file: PathBuf::from("doc.md"),
+            json: true,
+        };
+        parse(&ctx, options).unwrap();
+    }
+
+    #[test]
+    fn test_parse_missing_file() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let options = ParseOptions {
+            file: PathBuf::from("missing.md"),
+            json: false,
+        };
+        assert!(parse(&ctx, options).is_err());
+    }
+}
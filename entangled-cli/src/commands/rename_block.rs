@@ -0,0 +1,87 @@
+//! Rename-block command implementation.
+
+use entangled::errors::Result;
+use entangled::interface::{rename_block as rename_in_sources, Context};
+
+use super::helpers::{run_transaction, TransactionOptions};
+
+/// Options for the rename-block command.
+#[derive(Debug, Clone, Default)]
+pub struct RenameBlockOptions {
+    /// Name of the block to rename.
+    pub old: String,
+    /// New ID for the block.
+    pub new: String,
+    /// Force overwrite even if files have been modified externally.
+    pub force: bool,
+    /// Dry run - show what would be done without doing it.
+    pub dry_run: bool,
+    /// Show unified diffs of what would change.
+    pub diff: bool,
+    /// Number of context lines in diffs (default: 3).
+    pub context: Option<usize>,
+    /// Colorize diff output.
+    pub color: bool,
+    /// Suppress normal output.
+    pub quiet: bool,
+}
+
+/// Executes the rename-block command.
+///
+/// Rewrites the block's ID and every reference to it in the source
+/// documents. Tangled files keep the old name until the next tangle.
+pub fn rename_block(ctx: &mut Context, options: RenameBlockOptions) -> Result<()> {
+    let transaction = rename_in_sources(ctx, &options.old, &options.new)?;
+    let tx_options = TransactionOptions {
+        force: options.force,
+        dry_run: options.dry_run,
+        diff: options.diff,
+        context: options.context,
+        stat: false,
+        color: options.color,
+        quiet: options.quiet,
+        json: false,
+    };
+    run_transaction(ctx, transaction, &tx_options, "rename")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rename_block_command() {
+        let dir = tempdir().unwrap();
+        let md = dir.path().join("doc.md");
+        fs::write(
+            &md,
+            "```python #helper\npass\n```\n\n```python file=out.py\n<<helper>>\n```\n",
+        )
+        .unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let options = RenameBlockOptions {
+            old: "helper".to_string(),
+            new: "util".to_string(),
+            quiet: true,
+            ..Default::default()
+        };
+
+        rename_block(
+            &mut ctx,
+            RenameBlockOptions {
+                dry_run: true,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert!(fs::read_to_string(&md).unwrap().contains("#helper"));
+
+        rename_block(&mut ctx, options).unwrap();
+        let content = fs::read_to_string(&md).unwrap();
+        assert!(content.contains("```python #util\n"));
+        assert!(content.contains("\n<<util>>\n"));
+    }
+}
@@ -19,7 +19,7 @@ pub struct ResetOptions {
 pub fn reset(ctx: &mut Context, options: ResetOptions) -> Result<()> {
     if options.delete_files {
         // Get list of tracked files
-        let tracked: Vec<_> = ctx.filedb.tracked_files().cloned().collect();
+        let tracked: Vec<_> = ctx.filedb.tracked_files().collect();
 
         if tracked.is_empty() {
             println!("No tracked files to delete.");
@@ -56,11 +56,19 @@ pub fn reset(ctx: &mut Context, options: ResetOptions) -> Result<()> {
 
     // Clear the file database
     ctx.filedb.clear();
+    ctx.document_cache.clear();
     ctx.save_filedb()?;
 
-    // Delete the database file itself
-    if ctx.filedb_path.exists() {
-        fs::remove_file(&ctx.filedb_path)?;
+    // Delete the database file itself, the document cache and the journal
+    // of the last run
+    for path in [
+        ctx.filedb_path.clone(),
+        ctx.document_cache_path(),
+        ctx.journal_path(),
+    ] {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
     }
 
     // Try to remove the .entangled directory if empty
@@ -99,6 +107,7 @@ mod tests {
         // Reset
         let options = ResetOptions::default();
         reset(&mut ctx, options).unwrap();
+        drop(ctx);
 
         // Database should be cleared
         let reloaded = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
@@ -0,0 +1,105 @@
+//! Reverse-locate command implementation.
+//!
+//! Maps a line in a markdown source file to its positions in tangled output.
+
+use std::path::PathBuf;
+
+use entangled::errors::Result;
+use entangled::interface::{locate_target, Context};
+
+/// Options for the reverse-locate command.
+#[derive(Debug, Clone)]
+pub struct ReverseLocateOptions {
+    /// Markdown source file path.
+    pub file: PathBuf,
+    /// Line number in the source file (1-indexed).
+    pub line: usize,
+}
+
+/// Executes the reverse-locate command.
+///
+/// Prints one `target:line` per tangled position of the source line.
+pub fn reverse_locate(ctx: &Context, options: ReverseLocateOptions) -> Result<()> {
+    let full_path = ctx.resolve_path(&options.file);
+
+    if !full_path.exists() {
+        return Err(entangled::EntangledError::Other(format!(
+            "File not found: {}",
+            full_path.display()
+        )));
+    }
+
+    let locations = locate_target(ctx, &full_path, options.line)?;
+    if locations.is_empty() {
+        eprintln!(
+            "No tangled output for {}:{}",
+            options.file.display(),
+            options.line
+        );
+    }
+    for loc in locations {
+        println!("{}", loc);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reverse_locate_basic() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.namespace_default = entangled::config::NamespaceDefault::None;
+
+        fs::write(
+            dir.path().join("test.md"),
+            r#"# Title
+
+```python #main file=output.py
+print('line1')
+<<body>>
+print('line3')
+```
+
+```python #body
+print('body')
+```
+"#,
+        )
+        .unwrap();
+
+        let tx = entangled::interface::tangle_documents(&ctx).unwrap();
+        tx.execute(&mut ctx.filedb).unwrap();
+
+        // The tangled output.py looks like:
+        // 1: # ~/~ begin <<main[0]>>
+        // 2: print('line1')
+        // 3: # ~/~ begin <<body[0]>>
+        // 4: print('body')
+        // 5: # ~/~ end
+        // 6: print('line3')
+        // 7: # ~/~ end
+        let md = ctx.resolve_path(&PathBuf::from("test.md"));
+        let line_of = |n| {
+            locate_target(&ctx, &md, n)
+                .unwrap()
+                .iter()
+                .map(|l| l.target_line)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(line_of(4), vec![2]);
+        // A <<ref>> line maps to the begin marker of its expansion
+        assert_eq!(line_of(5), vec![3]);
+        assert_eq!(line_of(6), vec![6]);
+        assert_eq!(line_of(10), vec![4]);
+        // Fence and prose lines have no tangled position
+        assert!(line_of(3).is_empty());
+        assert!(line_of(1).is_empty());
+    }
+}
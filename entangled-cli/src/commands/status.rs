@@ -1,10 +1,17 @@
 //! Status command implementation.
 
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
+use entangled::config::{ref_name, REF_PATTERN};
 use entangled::errors::Result;
-use entangled::interface::{Context, Document};
-use entangled::io::FileData;
+use entangled::interface::{tangle_targets, Context, Document};
+use entangled::io::{hexdigest_str, FileData};
+use entangled::model::{resolve_reference, MergePolicy, ReferenceName};
+use entangled::ReferenceMap;
+
+/// Number of hex digits shown for hashes in verbose output.
+const SHORT_HASH_LEN: usize = 8;
 
 /// Options for the status command.
 #[derive(Debug, Clone, Default)]
@@ -15,35 +22,56 @@ pub struct StatusOptions {
     pub json: bool,
 }
 
-/// File status information.
-#[derive(Debug)]
+/// Sync state of a tangled target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileStatus {
-    /// File is up to date.
-    UpToDate,
-    /// File needs to be tangled (new or modified source).
-    NeedsTangle,
-    /// File has been modified externally.
-    ExternallyModified,
-    /// File is missing.
+    /// On-disk content matches the file database and the markdown.
+    Clean,
+    /// The markdown has changed since the last tangle.
+    Stale,
+    /// Modified externally since the last tangle; tangling would conflict.
+    Dirty,
+    /// Tracked in the file database but deleted from disk.
     Missing,
+    /// Not tracked in the file database (never tangled).
+    Untracked,
+    /// Tracked and unmodified, but the markdown could not be tangled to
+    /// tell whether it is stale.
+    Error,
 }
 
 impl FileStatus {
     fn as_str(&self) -> &'static str {
         match self {
-            Self::UpToDate => "up-to-date",
-            Self::NeedsTangle => "needs-tangle",
-            Self::ExternallyModified => "modified",
+            Self::Clean => "clean",
+            Self::Stale => "stale",
+            Self::Dirty => "dirty",
             Self::Missing => "missing",
+            Self::Untracked => "untracked",
+            Self::Error => "error",
         }
     }
 }
 
+/// Status of one target, with the details shown in verbose output.
+struct TargetStatus {
+    path: PathBuf,
+    status: FileStatus,
+    /// Data recorded in the file database at the last tangle.
+    recorded: Option<FileData>,
+    /// Hash of the file currently on disk.
+    current_digest: Option<String>,
+    /// Source files feeding the target, with the names of their blocks.
+    sources: Vec<(PathBuf, Vec<ReferenceName>)>,
+}
+
 /// Collected status data for JSON output.
 struct StatusData {
     source_files: Vec<PathBuf>,
-    targets: Vec<(PathBuf, FileStatus)>,
+    targets: Vec<TargetStatus>,
     tracked_count: usize,
+    /// Sources that failed to load or tangle, with the error messages.
+    errors: Vec<(PathBuf, String)>,
 }
 
 /// Executes the status command.
@@ -51,7 +79,7 @@ pub fn status(ctx: &Context, options: StatusOptions) -> Result<()> {
     let data = collect_status(ctx)?;
 
     if options.json {
-        print_json(&data);
+        print_json(&data, options.verbose);
     } else {
         print_human(&data, options.verbose);
     }
@@ -62,26 +90,164 @@ pub fn status(ctx: &Context, options: StatusOptions) -> Result<()> {
 fn collect_status(ctx: &Context) -> Result<StatusData> {
     let source_files = ctx.source_files()?;
 
+    // A source that fails to load is reported, and the others still
+    // classified
+    let mut errors: Vec<(PathBuf, String)> = Vec::new();
+    let mut loaded = Vec::new();
     let mut target_paths = Vec::new();
+    let mut refs = ctx.new_reference_map();
     for path in &source_files {
-        let doc = Document::load(path, ctx)?;
-        target_paths.extend(doc.targets());
+        match Document::load(path, ctx) {
+            Ok(doc) => {
+                target_paths.extend(doc.targets());
+                refs.merge(doc.refs(), MergePolicy::Append)?;
+                loaded.push(path.clone());
+            }
+            Err(e) => errors.push((path.clone(), e.to_string())),
+        }
     }
+    refs.sort_by_location();
+    target_paths.sort();
+    target_paths.dedup();
+
+    // Hash a fresh tangle so targets with changed markdown show as stale;
+    // a target that fails to tangle is blamed on the sources feeding it
+    let mut tangled: HashMap<PathBuf, Option<String>> = HashMap::new();
+    match tangle_targets(ctx, &loaded) {
+        Ok(results) => {
+            for (full_path, content) in results {
+                let digest = match content {
+                    Ok(content) => Some(hexdigest_str(&content)),
+                    Err(e) => {
+                        let target = target_paths
+                            .iter()
+                            .find(|t| ctx.resolve_path(t) == full_path);
+                        let sources = target.map(|t| target_sources(&refs, t)).unwrap_or_default();
+                        for (source, _) in sources {
+                            errors.push((source, e.to_string()));
+                        }
+                        None
+                    }
+                };
+                tangled.insert(full_path, digest);
+            }
+        }
+        Err(e) => {
+            for path in &loaded {
+                errors.push((path.clone(), e.to_string()));
+            }
+            for target in &target_paths {
+                tangled.insert(ctx.resolve_path(target), None);
+            }
+        }
+    }
+    errors.sort();
+    errors.dedup();
 
     let mut targets = Vec::new();
     for target in target_paths {
         let full_path = ctx.resolve_path(&target);
-        let status = get_file_status(&full_path, &ctx.filedb)?;
-        targets.push((target, status));
+        let status = get_file_status(&full_path, &ctx.filedb, tangled.get(&full_path))?;
+        let current_digest = if full_path.exists() {
+            Some(FileData::from_path(&full_path)?.hexdigest)
+        } else {
+            None
+        };
+        targets.push(TargetStatus {
+            status,
+            recorded: ctx.filedb.get(&full_path).cloned(),
+            current_digest,
+            sources: target_sources(&refs, &target),
+            path: target,
+        });
     }
 
     Ok(StatusData {
         source_files,
         targets,
         tracked_count: ctx.filedb.len(),
+        errors,
     })
 }
 
+/// Returns the source files whose blocks end up in `target`, each with the
+/// names of those blocks, following references from the blocks that write
+/// the target.
+fn target_sources(refs: &ReferenceMap, target: &Path) -> Vec<(PathBuf, Vec<ReferenceName>)> {
+    let mut pending: Vec<ReferenceName> = refs
+        .iter()
+        .filter(|(_, b)| b.targets().any(|t| t == target))
+        .map(|(id, _)| id.name.clone())
+        .chain(refs.get_target_name(target).cloned())
+        .collect();
+    let mut seen = HashSet::new();
+    let mut sources: Vec<(PathBuf, Vec<ReferenceName>)> = Vec::new();
+
+    while let Some(name) = pending.pop() {
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        for block in refs.get_by_name(&name) {
+            if let Some(file) = &block.location.filename {
+                match sources.iter_mut().find(|(f, _)| f == file) {
+                    Some((_, names)) if names.contains(&name) => {}
+                    Some((_, names)) => names.push(name.clone()),
+                    None => sources.push((file.clone(), vec![name.clone()])),
+                }
+            }
+            for line in block.source.lines() {
+                if let Some(caps) = REF_PATTERN.captures(line) {
+                    pending.push(resolve_reference(
+                        refs,
+                        &ref_name(&caps),
+                        block.namespace.as_deref(),
+                    ));
+                }
+            }
+        }
+    }
+
+    sources.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (_, names) in &mut sources {
+        names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    }
+    sources
+}
+
+/// Returns the first digits of a hash.
+fn short_hash(digest: &str) -> &str {
+    &digest[..digest.len().min(SHORT_HASH_LEN)]
+}
+
+/// Prints the recorded and current hashes and the sources of a target.
+fn print_target_details(target: &TargetStatus) {
+    match &target.recorded {
+        Some(recorded) => println!(
+            "      recorded: {} at {}",
+            short_hash(&recorded.hexdigest),
+            recorded.stat.mtime.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        None => println!("      recorded: -"),
+    }
+    match (&target.current_digest, &target.recorded) {
+        (Some(current), Some(recorded)) => println!(
+            "      on disk:  {} ({})",
+            short_hash(current),
+            if *current == recorded.hexdigest {
+                "matches"
+            } else {
+                "differs"
+            }
+        ),
+        (Some(current), None) => println!("      on disk:  {}", short_hash(current)),
+        (None, _) => println!("      on disk:  -"),
+    }
+    for (file, names) in &target.sources {
+        let names: Vec<&str> = names.iter().map(ReferenceName::as_str).collect();
+        println!("      from {}: {}", file.display(), names.join(", "));
+    }
+}
+
 fn print_human(data: &StatusData, verbose: bool) {
     println!("Source files: {}", data.source_files.len());
 
@@ -93,34 +259,50 @@ fn print_human(data: &StatusData, verbose: bool) {
 
     println!("\nTarget files: {}", data.targets.len());
 
-    let mut up_to_date = 0;
-    let mut needs_tangle = 0;
-    let mut modified = 0;
+    let mut clean = 0;
+    let mut stale = 0;
+    let mut dirty = 0;
     let mut missing = 0;
-
-    for (target, status) in &data.targets {
-        match status {
-            FileStatus::UpToDate => up_to_date += 1,
-            FileStatus::NeedsTangle => needs_tangle += 1,
-            FileStatus::ExternallyModified => modified += 1,
+    let mut untracked = 0;
+    let mut error = 0;
+
+    for target in &data.targets {
+        match target.status {
+            FileStatus::Clean => clean += 1,
+            FileStatus::Stale => stale += 1,
+            FileStatus::Dirty => dirty += 1,
             FileStatus::Missing => missing += 1,
+            FileStatus::Untracked => untracked += 1,
+            FileStatus::Error => error += 1,
         }
 
         if verbose {
-            println!("  {} ({})", target.display(), status.as_str());
+            println!("  {} ({})", target.path.display(), target.status.as_str());
+            print_target_details(target);
         }
     }
 
     println!("\nStatus summary:");
-    println!("  Up to date: {}", up_to_date);
-    println!("  Needs tangle: {}", needs_tangle);
-    println!("  Externally modified: {}", modified);
-    println!("  Missing: {}", missing);
+    println!("  Clean: {}", clean);
+    println!("  Stale (needs tangle): {}", stale);
+    println!("  Dirty (modified externally): {}", dirty);
+    println!("  Missing (needs tangle): {}", missing);
+    println!("  Untracked: {}", untracked);
+    if error > 0 {
+        println!("  Unknown (tangle failed): {}", error);
+    }
+
+    if !data.errors.is_empty() {
+        println!("\nErrors:");
+        for (path, message) in &data.errors {
+            println!("  {}: {}", path.display(), message);
+        }
+    }
 
     println!("\nTracked files in database: {}", data.tracked_count);
 }
 
-fn print_json(data: &StatusData) {
+fn print_json(data: &StatusData, verbose: bool) {
     let source_files: Vec<&str> = data
         .source_files
         .iter()
@@ -130,10 +312,40 @@ fn print_json(data: &StatusData) {
     let targets: Vec<serde_json::Value> = data
         .targets
         .iter()
-        .map(|(path, status)| {
+        .map(|target| {
+            let mut value = serde_json::json!({
+                "path": target.path.to_string_lossy(),
+                "state": target.status.as_str(),
+            });
+            if verbose {
+                let sources: Vec<serde_json::Value> = target
+                    .sources
+                    .iter()
+                    .map(|(file, names)| {
+                        serde_json::json!({
+                            "file": file.to_string_lossy(),
+                            "blocks": names.iter().map(ReferenceName::as_str).collect::<Vec<_>>(),
+                        })
+                    })
+                    .collect();
+                value["recorded_hash"] =
+                    serde_json::json!(target.recorded.as_ref().map(|r| r.hexdigest.as_str()));
+                value["recorded_mtime"] =
+                    serde_json::json!(target.recorded.as_ref().map(|r| r.stat.mtime.to_rfc3339()));
+                value["current_hash"] = serde_json::json!(target.current_digest);
+                value["sources"] = serde_json::json!(sources);
+            }
+            value
+        })
+        .collect();
+
+    let errors: Vec<serde_json::Value> = data
+        .errors
+        .iter()
+        .map(|(path, message)| {
             serde_json::json!({
                 "path": path.to_string_lossy(),
-                "status": status.as_str(),
+                "error": message,
             })
         })
         .collect();
@@ -142,37 +354,45 @@ fn print_json(data: &StatusData) {
         "source_files": source_files,
         "targets": targets,
         "tracked_count": data.tracked_count,
+        "errors": errors,
     });
 
     println!("{}", serde_json::to_string_pretty(&output).unwrap());
 }
 
 /// Gets the status of a target file.
-fn get_file_status(path: &std::path::Path, filedb: &entangled::io::FileDB) -> Result<FileStatus> {
+///
+/// `tangled` is the hash of freshly tangled content if the target produces
+/// any, or `None` inside if tangling it failed.
+fn get_file_status(
+    path: &std::path::Path,
+    filedb: &entangled::io::FileDB,
+    tangled: Option<&Option<String>>,
+) -> Result<FileStatus> {
+    if !filedb.is_tracked(path) {
+        return Ok(FileStatus::Untracked);
+    }
     if !path.exists() {
-        if filedb.is_tracked(path) {
-            return Ok(FileStatus::Missing);
-        } else {
-            return Ok(FileStatus::NeedsTangle);
-        }
+        return Ok(FileStatus::Missing);
     }
 
     let current = FileData::from_path(path)?;
 
-    if let Some(recorded) = filedb.get(path) {
-        if recorded.hexdigest == current.hexdigest {
-            Ok(FileStatus::UpToDate)
-        } else {
-            Ok(FileStatus::ExternallyModified)
-        }
+    if filedb.is_modified(path, &current) {
+        Ok(FileStatus::Dirty)
     } else {
-        Ok(FileStatus::NeedsTangle)
+        match tangled {
+            Some(None) => Ok(FileStatus::Error),
+            Some(Some(digest)) if *digest != current.hexdigest => Ok(FileStatus::Stale),
+            _ => Ok(FileStatus::Clean),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use entangled::interface::tangle_documents;
     use std::fs;
     use tempfile::tempdir;
 
@@ -228,4 +448,106 @@ print('hello')
         };
         status(&ctx, options).unwrap();
     }
+
+    #[test]
+    fn test_status_states() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let md = dir.path().join("test.md");
+        let out = dir.path().join("output.py");
+        let state = |ctx: &Context| collect_status(ctx).unwrap().targets[0].status;
+
+        fs::write(&md, "```python #main file=output.py\nprint('hello')\n```\n").unwrap();
+        assert_eq!(state(&ctx), FileStatus::Untracked);
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert_eq!(state(&ctx), FileStatus::Clean);
+
+        fs::write(&md, "```python #main file=output.py\nprint('bye')\n```\n").unwrap();
+        assert_eq!(state(&ctx), FileStatus::Stale);
+
+        fs::write(&out, "edited\n").unwrap();
+        assert_eq!(state(&ctx), FileStatus::Dirty);
+
+        fs::remove_file(&out).unwrap();
+        assert_eq!(state(&ctx), FileStatus::Missing);
+    }
+
+    #[test]
+    fn test_status_reports_errors_per_source() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let a = dir.path().join("a.md");
+        let b = dir.path().join("b.md");
+        fs::write(&a, "```python #a file=a.py\nx = 1\n```\n").unwrap();
+        fs::write(&b, "```python #b file=b.py\ny = 2\n```\n").unwrap();
+        let tx = tangle_documents(&ctx).unwrap();
+        ctx.execute(&tx, false).unwrap();
+
+        fs::write(&b, "```python #b file=b.py\n<<missing>>\n```\n").unwrap();
+        let data = collect_status(&ctx).unwrap();
+        let states: Vec<(&str, FileStatus)> = data
+            .targets
+            .iter()
+            .map(|t| (t.path.to_str().unwrap(), t.status))
+            .collect();
+        assert_eq!(
+            states,
+            vec![("a.py", FileStatus::Clean), ("b.py", FileStatus::Error)]
+        );
+        assert_eq!(data.errors.len(), 1);
+        assert_eq!(data.errors[0].0, Path::new("b.md"));
+        assert!(data.errors[0].1.contains("missing"));
+    }
+
+    #[test]
+    fn test_status_details() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("a.md"),
+            "```python #main file=output.py\n<<b.md#helper>>\n<<util>>\n```\n\n\
+             ```python #util\npass\n```\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("b.md"), "```python #helper\nx = 1\n```\n").unwrap();
+
+        let target = &collect_status(&ctx).unwrap().targets[0];
+        assert!(target.recorded.is_none() && target.current_digest.is_none());
+        let sources: Vec<(PathBuf, Vec<&str>)> = target
+            .sources
+            .iter()
+            .map(|(f, names)| (f.clone(), names.iter().map(|n| n.as_str()).collect()))
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                (PathBuf::from("a.md"), vec!["a.md#main", "a.md#util"]),
+                (PathBuf::from("b.md"), vec!["b.md#helper"]),
+            ]
+        );
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let target = &collect_status(&ctx).unwrap().targets[0];
+        let recorded = target.recorded.as_ref().unwrap();
+        assert_eq!(target.current_digest.as_ref(), Some(&recorded.hexdigest));
+
+        fs::write(dir.path().join("output.py"), "edited\n").unwrap();
+        let target = &collect_status(&ctx).unwrap().targets[0];
+        assert_ne!(target.current_digest.as_ref(), Some(&recorded.hexdigest));
+        status(
+            &ctx,
+            StatusOptions {
+                verbose: true,
+                json: true,
+            },
+        )
+        .unwrap();
+    }
 }
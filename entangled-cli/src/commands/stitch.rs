@@ -5,7 +5,7 @@ use std::path::PathBuf;
 use entangled::errors::Result;
 use entangled::interface::{stitch_documents, stitch_files, Context};
 
-use super::helpers::{run_transaction, TransactionOptions};
+use super::helpers::{count_blocks, print_summary, run_transaction, TransactionOptions};
 
 /// Options for the stitch command.
 #[derive(Debug, Clone, Default)]
@@ -16,12 +16,20 @@ pub struct StitchOptions {
     pub dry_run: bool,
     /// Show unified diffs of what would change.
     pub diff: bool,
+    /// Number of context lines in diffs (default: 3).
+    pub context: Option<usize>,
+    /// Show per-file added/removed line counts instead of writing.
+    pub stat: bool,
+    /// Colorize diff output.
+    pub color: bool,
     /// Suppress normal output.
     pub quiet: bool,
     /// Glob patterns to filter source files.
     pub glob: Vec<String>,
     /// Specific files to stitch (empty means all).
     pub files: Vec<PathBuf>,
+    /// Print a JSON summary of the run instead of progress messages.
+    pub json: bool,
 }
 
 /// Executes the stitch command.
@@ -30,8 +38,8 @@ pub fn stitch(ctx: &mut Context, options: StitchOptions) -> Result<()> {
 
     let has_filters = !options.files.is_empty() || !options.glob.is_empty();
 
-    let transaction = if !has_filters {
-        stitch_documents(ctx)?
+    let (transaction, sources) = if !has_filters {
+        (stitch_documents(ctx)?, ctx.source_files()?)
     } else {
         let mut selected = Vec::new();
         if !options.files.is_empty() {
@@ -42,20 +50,31 @@ pub fn stitch(ctx: &mut Context, options: StitchOptions) -> Result<()> {
         }
         selected.sort();
         selected.dedup();
-        stitch_files(ctx, &selected)?
+        (stitch_files(ctx, &selected)?, selected)
     };
 
-    run_transaction(
+    let summary = run_transaction(
         ctx,
         transaction,
         &TransactionOptions {
             force: options.force,
             dry_run: options.dry_run,
             diff: options.diff,
+            context: options.context,
+            stat: options.stat,
+            color: options.color,
             quiet: options.quiet,
+            json: options.json,
         },
         "stitch",
-    )
+    )?;
+
+    if options.json {
+        let blocks = count_blocks(ctx, &sources)?;
+        print_summary(ctx, "stitch", options.dry_run, &summary, blocks);
+    }
+
+    ctx.check_failed_documents()
 }
 
 #[cfg(test)]
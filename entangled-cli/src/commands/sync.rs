@@ -1,7 +1,12 @@
 //! Sync command implementation.
 
 use entangled::errors::Result;
-use entangled::interface::{stitch_documents, sync_documents, tangle_documents, Context};
+use entangled::interface::{
+    stitch_documents, sync_documents, sync_documents_with, tangle_documents, Context,
+};
+use entangled::io::TransactionSummary;
+
+use super::helpers::{count_blocks, print_changes, print_summary};
 
 /// Options for the sync command.
 #[derive(Debug, Clone, Default)]
@@ -12,8 +17,16 @@ pub struct SyncOptions {
     pub dry_run: bool,
     /// Show unified diffs of what would change.
     pub diff: bool,
+    /// Number of context lines in diffs (default: 3).
+    pub context: Option<usize>,
+    /// Show per-file added/removed line counts instead of writing.
+    pub stat: bool,
+    /// Colorize diff output.
+    pub color: bool,
     /// Suppress normal output.
     pub quiet: bool,
+    /// Print a JSON summary of the run instead of progress messages.
+    pub json: bool,
 }
 
 /// Executes the sync command.
@@ -22,19 +35,31 @@ pub struct SyncOptions {
 pub fn sync(ctx: &mut Context, options: SyncOptions) -> Result<()> {
     tracing::info!("Synchronizing documents...");
 
-    // For diff/dry-run we need to compute transactions without executing
-    if options.diff || options.dry_run {
+    // For diff/stat/dry-run we need to compute transactions without executing
+    if options.diff || options.stat || options.dry_run {
         let stitch_tx = stitch_documents(ctx)?;
         let tangle_tx = tangle_documents(ctx)?;
 
-        if options.diff {
-            for diff in stitch_tx.diffs() {
-                println!("{}", diff);
-            }
-            for diff in tangle_tx.diffs() {
-                println!("{}", diff);
+        if options.json {
+            let mut summary = stitch_tx.summary_in(ctx.file_cache.as_ref());
+            summary.extend(tangle_tx.summary_in(ctx.file_cache.as_ref()));
+            let blocks = count_blocks(ctx, &ctx.source_files()?)?;
+            print_summary(ctx, "sync", true, &summary, blocks);
+            return ctx.check_failed_documents();
+        }
+
+        if options.diff || options.stat {
+            for tx in [&stitch_tx, &tangle_tx] {
+                print_changes(
+                    ctx,
+                    tx,
+                    options.diff,
+                    options.stat,
+                    options.context,
+                    options.color,
+                );
             }
-            return Ok(());
+            return ctx.check_failed_documents();
         }
 
         // dry_run
@@ -58,10 +83,19 @@ pub fn sync(ctx: &mut Context, options: SyncOptions) -> Result<()> {
                 }
             }
         }
-        return Ok(());
+        return ctx.check_failed_documents();
     }
 
     // Normal execution -- delegate to library
+    if options.json {
+        let mut summary = TransactionSummary::default();
+        sync_documents_with(ctx, options.force, |ctx, tx| {
+            summary.extend(tx.summary_in(ctx.file_cache.as_ref()));
+        })?;
+        let blocks = count_blocks(ctx, &ctx.source_files()?)?;
+        print_summary(ctx, "sync", false, &summary, blocks);
+        return Ok(());
+    }
     sync_documents(ctx, options.force)?;
 
     if !options.quiet {
@@ -1,11 +1,18 @@
 //! Tangle command implementation.
 
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
-use entangled::errors::Result;
-use entangled::interface::{tangle_documents, tangle_files, Context};
+use entangled::config::AnnotationMethod;
+use entangled::errors::{EntangledError, Result};
+use entangled::interface::{
+    prune_filedb, record_source_state, tangle_content, tangle_documents,
+    tangle_documents_incremental, tangle_files, Context,
+};
+use entangled::model::ReferenceName;
+use entangled::readers::parse_markdown;
 
-use super::helpers::{run_transaction, TransactionOptions};
+use super::helpers::{count_blocks, print_summary, run_transaction, TransactionOptions};
 
 /// Options for the tangle command.
 #[derive(Debug, Clone, Default)]
@@ -16,22 +23,93 @@ pub struct TangleOptions {
     pub dry_run: bool,
     /// Show unified diffs of what would change.
     pub diff: bool,
+    /// Number of context lines in diffs (default: 3).
+    pub context: Option<usize>,
+    /// Show per-file added/removed line counts instead of writing.
+    pub stat: bool,
+    /// Colorize diff output.
+    pub color: bool,
     /// Suppress normal output.
     pub quiet: bool,
     /// Glob patterns to filter source files.
     pub glob: Vec<String>,
     /// Specific files to tangle (empty means all).
     pub files: Vec<PathBuf>,
+    /// Read a single markdown document from stdin instead of source files.
+    pub stdin: bool,
+    /// Tangle everything, even if no source changed since the last run,
+    /// parsing every document again.
+    pub full: bool,
+    /// Print a JSON summary of the run instead of progress messages.
+    pub json: bool,
+    /// Print the expansion of this block to stdout instead of writing files.
+    pub block: Option<String>,
+    /// Force annotated (`Some(true)`) or naked (`Some(false)`) `block` output,
+    /// instead of the configured annotation method.
+    pub annotate: Option<bool>,
+}
+
+/// Returns true if the path is the conventional `-` placeholder for stdin.
+fn is_stdin_path(path: &Path) -> bool {
+    path.as_os_str() == "-"
 }
 
 /// Executes the tangle command.
 pub fn tangle(ctx: &mut Context, options: TangleOptions) -> Result<()> {
+    tangle_with_input(ctx, options, &mut std::io::stdin())
+}
+
+/// Executes the tangle command, reading a piped document from `input`.
+fn tangle_with_input(
+    ctx: &mut Context,
+    options: TangleOptions,
+    input: &mut dyn Read,
+) -> Result<()> {
+    if let Some(name) = &options.block {
+        let method = options.annotate.map(|annotate| {
+            if annotate {
+                AnnotationMethod::Standard
+            } else {
+                AnnotationMethod::Naked
+            }
+        });
+        let content = ctx.tangle_block_with(&ReferenceName::new(name), method)?;
+        println!("{}", content);
+        return Ok(());
+    }
+
     tracing::info!("Tangling documents...");
 
+    let read_stdin = options.stdin || options.files.iter().any(|f| is_stdin_path(f));
     let has_filters = !options.files.is_empty() || !options.glob.is_empty();
+    // Code blocks in the documents read, for the JSON summary
+    let mut blocks = 0;
 
-    let transaction = if !has_filters {
-        tangle_documents(ctx)?
+    let transaction = if read_stdin {
+        if options.files.iter().any(|f| !is_stdin_path(f)) || !options.glob.is_empty() {
+            return Err(EntangledError::Config(
+                "Reading from stdin cannot be combined with source files or --glob".to_string(),
+            ));
+        }
+        let mut content = String::new();
+        input.read_to_string(&mut content)?;
+        if options.json {
+            blocks = parse_markdown(&content, None, &ctx.config)?.refs.len();
+        }
+        tangle_content(ctx, &content)?
+    } else if !has_filters {
+        if options.full {
+            // A complete rebuild parses every document again
+            ctx.forget_parsed_documents();
+        }
+        if options.json {
+            blocks = count_blocks(ctx, &ctx.source_files()?)?;
+        }
+        if options.full {
+            tangle_documents(ctx)?
+        } else {
+            tangle_documents_incremental(ctx)?
+        }
     } else {
         let mut selected = Vec::new();
         if !options.files.is_empty() {
@@ -42,20 +120,46 @@ pub fn tangle(ctx: &mut Context, options: TangleOptions) -> Result<()> {
         }
         selected.sort();
         selected.dedup();
+        if options.json {
+            blocks = count_blocks(ctx, &selected)?;
+        }
         tangle_files(ctx, &selected)?
     };
 
-    run_transaction(
+    let writes_files =
+        !transaction.is_empty() && !options.dry_run && !options.diff && !options.stat;
+
+    let summary = run_transaction(
         ctx,
         transaction,
         &TransactionOptions {
             force: options.force,
             dry_run: options.dry_run,
             diff: options.diff,
+            context: options.context,
+            stat: options.stat,
+            color: options.color,
             quiet: options.quiet,
+            json: options.json,
         },
         "tangle",
-    )
+    )?;
+
+    if writes_files {
+        // Only a run over all sources describes the state of every target
+        if !read_stdin && !has_filters && !ctx.has_skipped_documents() {
+            record_source_state(ctx)?;
+            prune_filedb(ctx);
+            ctx.save_filedb()?;
+        }
+        ctx.run_post_tangle_command()?;
+    }
+
+    if options.json {
+        print_summary(ctx, "tangle", options.dry_run, &summary, blocks);
+    }
+
+    ctx.check_failed_documents()
 }
 
 #[cfg(test)]
@@ -91,6 +195,33 @@ print('hello')
         assert!(content.contains("print('hello')"));
     }
 
+    #[test]
+    fn test_tangle_block_does_not_write() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.namespace_default = entangled::config::NamespaceDefault::None;
+
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\nprint('hello')\n```\n",
+        )
+        .unwrap();
+
+        let options = TangleOptions {
+            block: Some("main".to_string()),
+            annotate: Some(false),
+            ..Default::default()
+        };
+        tangle(&mut ctx, options).unwrap();
+        assert!(!dir.path().join("output.py").exists());
+
+        let options = TangleOptions {
+            block: Some("missing".to_string()),
+            ..Default::default()
+        };
+        assert!(tangle(&mut ctx, options).is_err());
+    }
+
     #[test]
     fn test_tangle_glob() {
         let dir = tempdir().unwrap();
@@ -119,6 +250,25 @@ print('hello')
         assert!(!dir.path().join("b.py").exists());
     }
 
+    #[test]
+    fn test_tangle_keep_going() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("a.md"),
+            "```python #main file=a.py\nprint('a')\n```\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("b.md"), "```{.python file=b.py}\n```\n").unwrap();
+
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.keep_going = true;
+        let error = tangle(&mut ctx, TangleOptions::default()).unwrap_err();
+
+        assert!(error.to_string().contains("b.md"));
+        assert!(dir.path().join("a.py").exists());
+        assert!(ctx.filedb.last_tangle.is_none());
+    }
+
     #[test]
     fn test_tangle_glob_no_match() {
         let dir = tempdir().unwrap();
@@ -133,6 +283,41 @@ print('hello')
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_tangle_stdin_rejects_other_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("test.md"), "# hello\n").unwrap();
+
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let options = TangleOptions {
+            files: vec![PathBuf::from("-"), PathBuf::from("test.md")],
+            ..Default::default()
+        };
+        let result = tangle(&mut ctx, options);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_tangle_stdin() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.annotation = AnnotationMethod::Naked;
+
+        let mut input = "```python #main file=out.py\nprint('piped')\n```\n".as_bytes();
+        let options = TangleOptions {
+            files: vec![PathBuf::from("-")],
+            ..Default::default()
+        };
+        tangle_with_input(&mut ctx, options, &mut input).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("out.py")).unwrap(),
+            "print('piped')"
+        );
+        // A piped document says nothing about the other sources
+        assert!(ctx.filedb.last_tangle.is_none());
+    }
+
     #[test]
     fn test_tangle_dry_run() {
         let dir = tempdir().unwrap();
@@ -157,4 +342,123 @@ print('hello')
         // Output should NOT be created
         assert!(!dir.path().join("output.py").exists());
     }
+
+    #[test]
+    fn test_tangle_stat_does_not_write() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\nprint('hello')\n```\n",
+        )
+        .unwrap();
+
+        let options = TangleOptions {
+            diff: true,
+            context: Some(0),
+            stat: true,
+            ..Default::default()
+        };
+        tangle(&mut ctx, options).unwrap();
+
+        assert!(!dir.path().join("output.py").exists());
+    }
+
+    #[test]
+    fn test_tangle_json_writes_files() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\nprint('hello')\n```\n",
+        )
+        .unwrap();
+
+        let options = TangleOptions {
+            json: true,
+            ..Default::default()
+        };
+        tangle(&mut ctx, options).unwrap();
+
+        assert!(dir.path().join("output.py").exists());
+        assert!(ctx.filedb.is_tracked(&dir.path().join("output.py")));
+    }
+
+    #[test]
+    fn test_tangle_incremental_skips_unchanged() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let md = dir.path().join("test.md");
+        let out = dir.path().join("output.py");
+
+        fs::write(&md, "```python #main file=output.py\nprint('hello')\n```\n").unwrap();
+        tangle(&mut ctx, TangleOptions::default()).unwrap();
+        assert!(ctx.filedb.last_tangle.is_some());
+        assert!(tangle_documents_incremental(&ctx).unwrap().is_empty());
+
+        // A deleted target invalidates the recorded state
+        fs::remove_file(&out).unwrap();
+        assert!(!tangle_documents_incremental(&ctx).unwrap().is_empty());
+        tangle(&mut ctx, TangleOptions::default()).unwrap();
+        assert!(out.exists());
+
+        // So does a changed source
+        fs::write(&md, "```python #main file=output.py\nprint('bye')\n```\n").unwrap();
+        tangle(&mut ctx, TangleOptions::default()).unwrap();
+        assert!(fs::read_to_string(&out).unwrap().contains("bye"));
+
+        // --full always produces the transaction
+        let options = TangleOptions {
+            full: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        tangle(&mut ctx, options).unwrap();
+        assert!(!tangle_documents(&ctx).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tangle_reuses_recorded_documents() {
+        let dir = tempdir().unwrap();
+        let a_py = dir.path().join("a.py");
+        let b_md = dir.path().join("b.md");
+        fs::write(dir.path().join("a.md"), "```python file=a.py\nx = 1\n```\n").unwrap();
+        fs::write(&b_md, "```python file=b.py\ny = 1\n```\n").unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        tangle(&mut ctx, TangleOptions::default()).unwrap();
+        assert_eq!(ctx.document_cache.len(), 2);
+        // Block sources go to the document cache; the database keeps hashes
+        let filedb = fs::read_to_string(&ctx.filedb_path).unwrap();
+        assert!(!filedb.contains("x = 1"));
+        assert!(fs::read_to_string(ctx.document_cache_path())
+            .unwrap()
+            .contains("x = 1"));
+        drop(ctx);
+
+        // Doctor the recorded copy of a.md to see whether it is parsed again
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        for record in ctx.document_cache.documents.values_mut() {
+            for block in &mut record.blocks {
+                block.source = block.source.replace("x = 1", "x = 99");
+            }
+        }
+
+        // Only the changed b.md is parsed
+        fs::write(&b_md, "```python file=b.py\ny = 2\n```\n").unwrap();
+        tangle(&mut ctx, TangleOptions::default()).unwrap();
+        assert!(fs::read_to_string(&a_py).unwrap().contains("x = 99"));
+        assert!(fs::read_to_string(dir.path().join("b.py"))
+            .unwrap()
+            .contains("y = 2"));
+
+        // --full parses everything again
+        let options = TangleOptions {
+            full: true,
+            ..Default::default()
+        };
+        tangle(&mut ctx, options).unwrap();
+        assert!(fs::read_to_string(&a_py).unwrap().contains("x = 1"));
+    }
 }
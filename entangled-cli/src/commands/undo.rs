@@ -0,0 +1,168 @@
+//! Undo command implementation.
+//!
+//! Restores the files changed by the last run from the journal kept next to
+//! the file database.
+
+use entangled::errors::Result;
+use entangled::interface::Context;
+
+use super::helpers::{run_transaction, TransactionOptions};
+
+/// Options for the undo command.
+#[derive(Debug, Clone, Default)]
+pub struct UndoOptions {
+    /// Restore files even if they were modified since the last run.
+    pub force: bool,
+    /// Dry run - show what would be restored without doing it.
+    pub dry_run: bool,
+    /// Show unified diffs of what would be restored.
+    pub diff: bool,
+    /// Number of context lines in diffs (default: 3).
+    pub context: Option<usize>,
+    /// Colorize diff output.
+    pub color: bool,
+    /// Suppress normal output.
+    pub quiet: bool,
+}
+
+/// Executes the undo command.
+///
+/// The restore is itself journaled, so running undo twice redoes the run.
+pub fn undo(ctx: &mut Context, options: UndoOptions) -> Result<()> {
+    let Some(transaction) = ctx.undo_transaction()? else {
+        if !options.quiet {
+            println!("Nothing to undo.");
+        }
+        return Ok(());
+    };
+
+    let tx_options = TransactionOptions {
+        force: options.force,
+        dry_run: options.dry_run,
+        diff: options.diff,
+        context: options.context,
+        stat: false,
+        color: options.color,
+        quiet: options.quiet,
+        json: false,
+    };
+    run_transaction(ctx, transaction, &tx_options, "undo")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{sync, tangle, SyncOptions, TangleOptions};
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn quiet() -> UndoOptions {
+        UndoOptions {
+            quiet: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_undo_restores_last_tangle() {
+        let dir = tempdir().unwrap();
+        let md = dir.path().join("doc.md");
+        let out = dir.path().join("out.py");
+        fs::write(&md, "```python file=out.py\nx = 1\n```\n").unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+
+        let tangle_options = TangleOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        tangle(&mut ctx, tangle_options.clone()).unwrap();
+        let first = fs::read_to_string(&out).unwrap();
+
+        fs::write(&md, "```python file=out.py\nx = 2\n```\n").unwrap();
+        tangle(&mut ctx, tangle_options).unwrap();
+        assert!(fs::read_to_string(&out).unwrap().contains("x = 2"));
+
+        undo(&mut ctx, quiet()).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), first);
+
+        // Undoing the undo redoes the tangle
+        undo(&mut ctx, quiet()).unwrap();
+        assert!(fs::read_to_string(&out).unwrap().contains("x = 2"));
+    }
+
+    #[test]
+    fn test_undo_dry_run_and_created_files() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("out.py");
+        fs::write(
+            dir.path().join("doc.md"),
+            "```python file=out.py\nx = 1\n```\n",
+        )
+        .unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+
+        // Nothing journaled yet
+        undo(&mut ctx, quiet()).unwrap();
+
+        tangle(
+            &mut ctx,
+            TangleOptions {
+                quiet: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(out.exists());
+
+        let dry_run = UndoOptions {
+            dry_run: true,
+            ..quiet()
+        };
+        undo(&mut ctx, dry_run).unwrap();
+        assert!(out.exists());
+
+        undo(&mut ctx, quiet()).unwrap();
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn test_undo_restores_whole_sync() {
+        let dir = tempdir().unwrap();
+        let a_md = dir.path().join("a.md");
+        let a_py = dir.path().join("a.py");
+        let b_py = dir.path().join("b.py");
+        fs::write(&a_md, "```python file=a.py\nx = 1\n```\n").unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        tangle(
+            &mut ctx,
+            TangleOptions {
+                quiet: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // a.py is stitched back into a.md, the new b.md is tangled into b.py
+        let a_py_edited = fs::read_to_string(&a_py).unwrap().replace("x = 1", "x = 2");
+        fs::write(&a_py, &a_py_edited).unwrap();
+        fs::write(dir.path().join("b.md"), "```python file=b.py\ny = 1\n```\n").unwrap();
+        sync(
+            &mut ctx,
+            SyncOptions {
+                quiet: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(fs::read_to_string(&a_md).unwrap().contains("x = 2"));
+        assert!(b_py.exists());
+
+        undo(&mut ctx, quiet()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&a_md).unwrap(),
+            "```python file=a.py\nx = 1\n```\n"
+        );
+        assert!(!b_py.exists());
+    }
+}
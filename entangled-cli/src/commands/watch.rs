@@ -1,14 +1,15 @@
 //! Watch command implementation.
 
-use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::time::Duration;
 
-use entangled::config::builtin_languages;
+use entangled::config::{find_config_file, read_config_file};
 use entangled::errors::{EntangledError, Result};
 use entangled::interface::{sync_documents, Context};
+use entangled::io::{FileDB, TEMP_FILE_PREFIX};
+use entangled::Style;
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 
 /// Options for the watch command.
@@ -16,51 +17,132 @@ use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 pub struct WatchOptions {
     /// Debounce delay in milliseconds.
     pub debounce_ms: u64,
+    /// Config file to reload on change; discovered from the base directory
+    /// when not set.
+    pub config_path: Option<PathBuf>,
+    /// Style override from the command line, re-applied after a reload.
+    pub style: Option<Style>,
+    /// Whether `--skip-invalid` was given, re-applied after a reload.
+    pub skip_invalid: bool,
+    /// Whether `--keep-going` was given, re-applied after a reload.
+    pub keep_going: bool,
 }
 
-/// Collects all relevant file extensions from config and built-in languages.
-///
-/// This includes extensions from source patterns (e.g. "md", "qmd", "Rmd")
-/// and all language identifiers that could be file extensions.
-fn relevant_extensions(ctx: &Context) -> HashSet<String> {
-    let mut exts = HashSet::new();
-
-    // Extract extensions from source patterns (e.g. "**/*.md" -> "md")
-    for pattern in &ctx.config.source_patterns {
-        if let Some(ext) = pattern.rsplit('.').next() {
-            exts.insert(ext.to_string());
-        }
+/// Decides which file-system events should trigger a sync.
+struct EventFilter {
+    base_dir: PathBuf,
+    filedb_path: PathBuf,
+    journal_path: PathBuf,
+    document_cache_path: PathBuf,
+    source_patterns: Vec<glob::Pattern>,
+    exclude_patterns: Vec<glob::Pattern>,
+}
+
+impl EventFilter {
+    fn new(ctx: &Context) -> Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| glob::Pattern::new(p))
+                .collect::<std::result::Result<Vec<_>, _>>()
+        };
+        let mut exclude_patterns = compile(&ctx.config.exclude_patterns)?;
+        exclude_patterns.extend(compile(&ctx.config.watch.exclude)?);
+
+        Ok(Self {
+            base_dir: ctx.base_dir.clone(),
+            filedb_path: ctx.filedb_path.clone(),
+            journal_path: ctx.journal_path(),
+            document_cache_path: ctx.document_cache_path(),
+            source_patterns: compile(&ctx.config.source_patterns)?,
+            exclude_patterns,
+        })
     }
 
-    // Add all language names and identifiers as potential extensions
-    for lang in &ctx.config.languages {
-        exts.insert(lang.name.clone());
-        for id in &lang.identifiers {
-            exts.insert(id.clone());
+    /// Returns true if a change to `path` should trigger a sync.
+    ///
+    /// The file database, its journal and document cache and atomic-write temp
+    /// files are written by sync itself and are always ignored, so syncing
+    /// cannot retrigger itself.
+    /// Otherwise only source documents and files tracked in `filedb` count.
+    fn is_relevant(&self, path: &Path, filedb: &FileDB) -> bool {
+        if path == self.filedb_path || path == self.journal_path || path == self.document_cache_path
+        {
+            return false;
         }
-    }
-    for lang in builtin_languages() {
-        exts.insert(lang.name.clone());
-        for id in &lang.identifiers {
-            exts.insert(id.clone());
+        let is_temp = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .is_some_and(|name| name.starts_with(TEMP_FILE_PREFIX));
+        if is_temp {
+            return false;
         }
+
+        let relative = path.strip_prefix(&self.base_dir).unwrap_or(path);
+        if self
+            .exclude_patterns
+            .iter()
+            .any(|p| p.matches_path(relative))
+        {
+            return false;
+        }
+        filedb.is_tracked(path)
+            || self
+                .source_patterns
+                .iter()
+                .any(|p| p.matches_path(relative))
     }
+}
 
-    exts
+/// Returns true if `path` refers to the config file at `config_path`.
+fn is_config_file(path: &Path, config_path: &Path) -> bool {
+    if path == config_path {
+        return true;
+    }
+    path.file_name() == config_path.file_name()
+        && path.canonicalize().ok().as_deref() == Some(config_path)
 }
 
-/// Checks whether a path matches any of the exclude patterns.
-fn is_excluded(path: &Path, base_dir: &Path, exclude_patterns: &[String]) -> bool {
-    let relative = path.strip_prefix(base_dir).unwrap_or(path);
-    let rel_str = relative.to_string_lossy();
-    for pattern in exclude_patterns {
-        if let Ok(glob) = glob::Pattern::new(pattern) {
-            if glob.matches(&rel_str) {
-                return true;
-            }
+/// Re-reads the config file and rebuilds the context from it.
+///
+/// On a read, parse or path expansion error the previous configuration is
+/// kept and false is returned.
+fn reload_config(ctx: &mut Context, path: &Path, options: &WatchOptions) -> bool {
+    let reloaded = read_config_file(path).and_then(|mut config| {
+        if let Some(style) = options.style {
+            config.style = style;
+        }
+        if options.skip_invalid {
+            config.skip_invalid = true;
+        }
+        if options.keep_going {
+            config.keep_going = true;
+        }
+        ctx.set_config(config)
+    });
+    match reloaded {
+        Ok(()) => {
+            tracing::info!("Configuration reloaded from {}", path.display());
+            true
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Failed to reload {}, keeping previous configuration: {}",
+                path.display(),
+                e
+            );
+            false
         }
     }
-    false
+}
+
+/// Brings everything up to date before watching, catching changes made
+/// while the watcher was not running.
+fn initial_sync(ctx: &mut Context) {
+    match sync_documents(ctx, false) {
+        Ok(()) => tracing::info!("Initial sync complete"),
+        Err(e) => eprintln!("Initial sync error: {}", e),
+    }
 }
 
 /// Executes the watch command.
@@ -71,22 +153,19 @@ pub fn watch(ctx: &mut Context, options: WatchOptions) -> Result<()> {
         ctx.config.watch.debounce_ms
     };
 
-    let exts = relevant_extensions(ctx);
-    let exclude_patterns = ctx.config.watch.exclude.clone();
-    let base_dir = ctx.base_dir.clone();
-    tracing::debug!("Watching for extensions: {:?}", exts);
-    if !exclude_patterns.is_empty() {
-        tracing::debug!("Exclude patterns: {:?}", exclude_patterns);
+    let mut filter = EventFilter::new(ctx)?;
+    let config_path = options
+        .config_path
+        .clone()
+        .or_else(|| find_config_file(&ctx.base_dir))
+        .map(|p| p.canonicalize().unwrap_or(p));
+    if !ctx.config.watch.exclude.is_empty() {
+        tracing::debug!("Exclude patterns: {:?}", ctx.config.watch.exclude);
     }
 
     println!("Watching for changes (debounce: {}ms)...", debounce);
     println!("Press Ctrl+C to stop.");
 
-    // Initial sync
-    if let Err(e) = sync_documents(ctx, false) {
-        eprintln!("Initial sync error: {}", e);
-    }
-
     let (tx, rx) = channel();
 
     let mut watcher = RecommendedWatcher::new(
@@ -115,21 +194,42 @@ pub fn watch(ctx: &mut Context, options: WatchOptions) -> Result<()> {
         }
     }
 
+    // Watch the config file's directory if it lies outside the base directory
+    if let Some(dir) = config_path.as_deref().and_then(Path::parent) {
+        let base = ctx.base_dir.canonicalize().unwrap_or(ctx.base_dir.clone());
+        if !dir.starts_with(&base) {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| EntangledError::Watch(e.to_string()))?;
+        }
+    }
+
+    // Initial sync, once the watcher is running so that changes made while
+    // it runs are not missed
+    initial_sync(ctx);
+
     // Event loop
     loop {
         match rx.recv() {
             Ok(event) => {
                 let paths: Vec<&PathBuf> = event.paths.iter().collect();
 
-                // Check extension relevance and exclude patterns
-                let relevant = paths.iter().any(|p| {
-                    let ext_ok = p
-                        .extension()
-                        .and_then(OsStr::to_str)
-                        .map(|e| exts.contains(e))
-                        .unwrap_or(false);
-                    ext_ok && !is_excluded(p, &base_dir, &exclude_patterns)
-                });
+                // Rebuild the context before syncing if the config changed
+                let changed_config = config_path
+                    .as_deref()
+                    .filter(|cp| paths.iter().any(|p| is_config_file(p, cp)));
+                let reloaded = match changed_config {
+                    Some(cp) => reload_config(ctx, cp, &options),
+                    None => false,
+                };
+                if reloaded {
+                    match EventFilter::new(ctx) {
+                        Ok(f) => filter = f,
+                        Err(e) => eprintln!("Invalid patterns in configuration: {}", e),
+                    }
+                }
+
+                let relevant = reloaded || paths.iter().any(|p| filter.is_relevant(p, &ctx.filedb));
 
                 if relevant {
                     tracing::debug!("File changed: {:?}", paths);
@@ -147,8 +247,91 @@ pub fn watch(ctx: &mut Context, options: WatchOptions) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
 
     // Watch is difficult to test in unit tests due to its blocking nature
     // Integration tests would be more appropriate
+
+    #[test]
+    fn test_event_filter() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.watch.exclude = vec!["drafts/**".to_string()];
+        let filter = EventFilter::new(&ctx).unwrap();
+        let mut filedb = FileDB::new();
+        let target = dir.path().join("src/main.py");
+        filedb.record(
+            target.clone(),
+            entangled::io::FileData::from_content("", chrono::Utc::now()),
+        );
+
+        let relevant = |p: &Path| filter.is_relevant(p, &filedb);
+        assert!(relevant(&dir.path().join("docs/guide.md")));
+        assert!(relevant(&target));
+        assert!(!relevant(&ctx.filedb_path));
+        assert!(!relevant(&ctx.journal_path()));
+        assert!(!relevant(&ctx.document_cache_path()));
+        assert!(!relevant(&dir.path().join("src/.entangled-tmp-42-0")));
+        assert!(!relevant(&dir.path().join("drafts/wip.md")));
+        assert!(!relevant(&dir.path().join("node_modules/pkg/README.md")));
+        assert!(!relevant(&dir.path().join("src/untracked.py")));
+    }
+
+    #[test]
+    fn test_initial_sync_catches_up() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\nprint('hello')\n```\n",
+        )
+        .unwrap();
+
+        initial_sync(&mut ctx);
+        assert!(dir.path().join("output.py").exists());
+    }
+
+    #[test]
+    fn test_reload_config() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let path = dir.path().join("entangled.toml");
+
+        fs::write(&path, "annotation = \"naked\"\n[hooks]\nshebang = true\n").unwrap();
+        let options = WatchOptions {
+            style: Some(Style::Pandoc),
+            skip_invalid: true,
+            keep_going: true,
+            ..Default::default()
+        };
+        assert!(reload_config(&mut ctx, &path, &options));
+        assert_eq!(
+            ctx.config.annotation,
+            entangled::config::AnnotationMethod::Naked
+        );
+        assert_eq!(ctx.config.style, Style::Pandoc);
+        assert!(ctx.config.skip_invalid);
+        assert!(ctx.config.keep_going);
+        assert!(ctx.config.hooks.shebang);
+
+        fs::write(&path, "annotation = [not toml").unwrap();
+        assert!(!reload_config(&mut ctx, &path, &WatchOptions::default()));
+        assert_eq!(
+            ctx.config.annotation,
+            entangled::config::AnnotationMethod::Naked
+        );
+    }
+
+    #[test]
+    fn test_is_config_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("entangled.toml");
+        fs::write(&path, "").unwrap();
+        let canonical = path.canonicalize().unwrap();
+
+        assert!(is_config_file(&path, &canonical));
+        assert!(!is_config_file(&dir.path().join("other.toml"), &canonical));
+    }
 }
@@ -3,12 +3,16 @@
 use std::path::PathBuf;
 use std::process::ExitCode;
 
-use clap::{Parser, Subcommand};
+use std::io::IsTerminal;
+
+use clap::{ColorChoice, Parser, Subcommand};
+use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
 mod commands;
+mod warnings;
 
-use entangled::interface::Context;
+use entangled::interface::{Context, ExportFormat};
 use entangled::Style;
 
 #[derive(Parser)]
@@ -41,10 +45,22 @@ struct Cli {
     #[arg(short, long, global = true)]
     quiet: bool,
 
+    /// When to use colors: auto (terminal and no NO_COLOR), always, never
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
     /// Code block syntax style (overrides config file)
     #[arg(short, long, global = true, value_enum)]
     style: Option<Style>,
 
+    /// Skip source files that are not valid UTF-8 instead of failing
+    #[arg(long, global = true)]
+    skip_invalid: bool,
+
+    /// Skip documents that fail to parse, process the rest, then fail
+    #[arg(short = 'k', long, global = true)]
+    keep_going: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -65,11 +81,43 @@ enum Commands {
         #[arg(short, long)]
         diff: bool,
 
-        /// Glob patterns to filter source files
+        /// Number of context lines shown around changes with --diff
+        #[arg(short = 'U', long, value_name = "N")]
+        context: Option<usize>,
+
+        /// Show per-file added/removed line counts of what would change
+        #[arg(long)]
+        stat: bool,
+
+        /// Glob patterns to filter source files (`!PATTERN` excludes)
         #[arg(short = 'g', long = "glob")]
         glob: Vec<String>,
 
-        /// Specific files to tangle
+        /// Read a markdown document from stdin (same as passing `-` as FILE)
+        #[arg(long)]
+        stdin: bool,
+
+        /// Tangle all documents even if no source changed since the last run
+        #[arg(long, alias = "no-incremental")]
+        full: bool,
+
+        /// Print a JSON summary of the run instead of progress messages
+        #[arg(long, conflicts_with_all = ["diff", "stat"])]
+        json: bool,
+
+        /// Print the expansion of a single named block to stdout
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["diff", "stat", "json", "stdin"])]
+        block: Option<String>,
+
+        /// Annotate the --block output, whatever the configured mode
+        #[arg(long, requires = "block")]
+        annotate: bool,
+
+        /// Print the --block output without annotations, whatever the configured mode
+        #[arg(long, requires = "block", conflicts_with = "annotate")]
+        no_annotate: bool,
+
+        /// Specific files to tangle (`-` reads from stdin)
         #[arg(value_name = "FILE")]
         files: Vec<PathBuf>,
     },
@@ -88,10 +136,22 @@ enum Commands {
         #[arg(short, long)]
         diff: bool,
 
-        /// Glob patterns to filter source files
+        /// Number of context lines shown around changes with --diff
+        #[arg(short = 'U', long, value_name = "N")]
+        context: Option<usize>,
+
+        /// Show per-file added/removed line counts of what would change
+        #[arg(long)]
+        stat: bool,
+
+        /// Glob patterns to filter source files (`!PATTERN` excludes)
         #[arg(short = 'g', long = "glob")]
         glob: Vec<String>,
 
+        /// Print a JSON summary of the run instead of progress messages
+        #[arg(long, conflicts_with_all = ["diff", "stat"])]
+        json: bool,
+
         /// Specific files to stitch
         #[arg(value_name = "FILE")]
         files: Vec<PathBuf>,
@@ -110,6 +170,29 @@ enum Commands {
         /// Show unified diffs of what would change
         #[arg(short, long)]
         diff: bool,
+
+        /// Number of context lines shown around changes with --diff
+        #[arg(short = 'U', long, value_name = "N")]
+        context: Option<usize>,
+
+        /// Show per-file added/removed line counts of what would change
+        #[arg(long)]
+        stat: bool,
+
+        /// Print a JSON summary of the run instead of progress messages
+        #[arg(long, conflicts_with_all = ["diff", "stat"])]
+        json: bool,
+    },
+
+    /// Show how tangled files on disk differ from a fresh tangle
+    Diff {
+        /// Number of context lines shown around changes
+        #[arg(short = 'U', long, value_name = "N")]
+        context: Option<usize>,
+
+        /// Target files to compare (all targets if omitted)
+        #[arg(value_name = "FILE")]
+        files: Vec<PathBuf>,
     },
 
     /// Watch for changes and sync automatically
@@ -119,7 +202,7 @@ enum Commands {
         debounce: u64,
     },
 
-    /// Show status of files
+    /// Show the sync state of each tangled file
     Status {
         /// Show verbose output
         #[arg(short, long)]
@@ -130,6 +213,53 @@ enum Commands {
         json: bool,
     },
 
+    /// Check references and block languages without tangling
+    Check,
+
+    /// Rename a code block and every reference to it
+    RenameBlock {
+        /// Name of the block to rename (qualify it as `file.md#id` if ambiguous)
+        old: String,
+
+        /// New ID for the block
+        new: String,
+
+        /// Force overwrite even if files have been modified externally
+        #[arg(short, long)]
+        force: bool,
+
+        /// Dry run - show what would be done without doing it
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Show unified diffs of what would change
+        #[arg(short, long)]
+        diff: bool,
+
+        /// Number of context lines shown around changes with --diff
+        #[arg(short = 'U', long, value_name = "N")]
+        context: Option<usize>,
+    },
+
+    /// Restore the files changed by the last run
+    Undo {
+        /// Restore files even if they were modified since the last run
+        #[arg(short, long)]
+        force: bool,
+
+        /// Dry run - show what would be restored without doing it
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Show unified diffs of what would be restored
+        #[arg(short, long)]
+        diff: bool,
+
+        /// Number of context lines shown around changes with --diff
+        #[arg(short = 'U', long, value_name = "N")]
+        context: Option<usize>,
+    },
+
     /// Reset the file database
     Reset {
         /// Also delete tangled files
@@ -142,17 +272,107 @@ enum Commands {
     },
 
     /// Show effective resolved configuration
-    Config,
+    Config {
+        /// Show where each setting came from (config file, flag or default)
+        #[arg(long)]
+        explain: bool,
+    },
 
     /// Initialize a new entangled project
     Init,
 
+    /// Convert a project from another Entangled implementation
+    Migrate {
+        /// Implementation the project was created with
+        #[arg(long, value_enum, default_value_t = commands::MigrateSource::Python)]
+        from: commands::MigrateSource,
+
+        /// Overwrite existing .bak backups
+        #[arg(short, long)]
+        force: bool,
+    },
+
     /// Map a tangled file line back to its markdown source
     Locate {
-        /// Location in format file:line (e.g., output.py:42)
+        /// Location in format file:line or file:first-last (e.g., output.py:40-55)
         #[arg(value_name = "FILE:LINE")]
         location: String,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
     },
+
+    /// Map a markdown source line to its positions in tangled files
+    ReverseLocate {
+        /// Location in format file:line (e.g., README.md:42)
+        #[arg(value_name = "FILE:LINE")]
+        location: String,
+    },
+
+    /// Render a document with its code blocks expanded
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "md")]
+        format: ExportFormat,
+
+        /// Write to a file instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Markdown document to export
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Show the code blocks parsed from a document
+    Parse {
+        /// Print the parsed document as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Source document to parse
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Run a language server over stdio for editors
+    Lsp,
+}
+
+/// Parses a `file:line` location argument (line numbers are 1-indexed).
+fn parse_location(location: &str) -> std::result::Result<(PathBuf, usize), String> {
+    match location.rsplit_once(':') {
+        Some((f, l)) => match l.parse::<usize>() {
+            Ok(n) if n > 0 => Ok((PathBuf::from(f), n)),
+            _ => Err(format!(
+                "Invalid line number in '{}'. Expected format: file:line",
+                location
+            )),
+        },
+        None => Err("Expected format: file:line (e.g., output.py:42)".to_string()),
+    }
+}
+
+/// Parses a `file:line` or `file:first-last` location argument; the range is
+/// inclusive.
+fn parse_location_range(
+    location: &str,
+) -> std::result::Result<(PathBuf, usize, Option<usize>), String> {
+    if let Some((f, lines)) = location.rsplit_once(':') {
+        if let Some((first, last)) = lines.split_once('-') {
+            return match (first.parse::<usize>(), last.parse::<usize>()) {
+                (Ok(first), Ok(last)) if first > 0 && last >= first => {
+                    Ok((PathBuf::from(f), first, Some(last)))
+                }
+                _ => Err(format!(
+                    "Invalid line range in '{}'. Expected format: file:first-last",
+                    location
+                )),
+            };
+        }
+    }
+    parse_location(location).map(|(file, line)| (file, line, None))
 }
 
 fn main() -> ExitCode {
@@ -167,13 +387,34 @@ fn main() -> ExitCode {
         EnvFilter::new("info")
     };
 
-    // Respect NO_COLOR convention (https://no-color.org/)
+    // Respect NO_COLOR convention (https://no-color.org/) unless --color overrides it
     let no_color = std::env::var_os("NO_COLOR").is_some();
+    let (log_color, diff_color) = match cli.color {
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+        ColorChoice::Auto => (!no_color, !no_color && std::io::stdout().is_terminal()),
+    };
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
+    // JSON summaries and the language server own stdout, so logs go to
+    // stderr; warnings are also collected for the summary
+    let owns_stdout = matches!(
+        cli.command,
+        Commands::Tangle { json: true, .. }
+            | Commands::Stitch { json: true, .. }
+            | Commands::Sync { json: true, .. }
+            | Commands::Lsp
+    );
+    let log_layer = tracing_subscriber::fmt::layer()
         .with_target(false)
-        .with_ansi(!no_color)
+        .with_ansi(log_color);
+    let log_layer = if owns_stdout {
+        log_layer.with_writer(std::io::stderr).boxed()
+    } else {
+        log_layer.boxed()
+    };
+    tracing_subscriber::registry()
+        .with(log_layer.with_filter(filter))
+        .with(warnings::WarningCollector)
         .init();
 
     // Determine working directory
@@ -193,6 +434,17 @@ fn main() -> ExitCode {
         };
     }
 
+    // Migrate before context creation: the old config may not parse
+    if let Commands::Migrate { from, force } = cli.command {
+        return match commands::migrate(&base_dir, commands::MigrateOptions { from, force }) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
     // Read configuration from file or use defaults
     let mut config = match cli.config {
         Some(ref path) => {
@@ -221,9 +473,35 @@ fn main() -> ExitCode {
     if let Some(style) = cli.style {
         config.style = style;
     }
+    if cli.skip_invalid {
+        config.skip_invalid = true;
+    }
+    if cli.keep_going {
+        config.keep_going = true;
+    }
 
+    // Commands that only read may run alongside another process, and never
+    // lock or create the database directory; the database is replaced
+    // atomically, so they cannot see a partial write
+    let read_only = matches!(
+        cli.command,
+        Commands::Status { .. }
+            | Commands::Check
+            | Commands::Diff { .. }
+            | Commands::Config { .. }
+            | Commands::Locate { .. }
+            | Commands::ReverseLocate { .. }
+            | Commands::Export { .. }
+            | Commands::Parse { .. }
+            | Commands::Lsp
+    );
     // Create context
-    let mut ctx = match Context::new(config, base_dir) {
+    let created = if read_only {
+        Context::new_unlocked(config, base_dir)
+    } else {
+        Context::new(config, base_dir)
+    };
+    let mut ctx = match created {
         Ok(ctx) => ctx,
         Err(e) => {
             eprintln!("Error initializing: {}", e);
@@ -237,16 +515,36 @@ fn main() -> ExitCode {
             force,
             dry_run,
             diff,
+            context,
+            stat,
             glob,
+            stdin,
+            full,
+            json,
+            block,
+            annotate,
+            no_annotate,
             files,
         } => {
             let options = commands::TangleOptions {
                 force,
                 dry_run,
                 diff,
+                context,
+                stat,
+                color: diff_color,
                 quiet: cli.quiet,
                 glob,
                 files,
+                stdin,
+                full,
+                json,
+                block,
+                annotate: match (annotate, no_annotate) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
             };
             commands::tangle(&mut ctx, options)
         }
@@ -255,16 +553,23 @@ fn main() -> ExitCode {
             force,
             dry_run,
             diff,
+            context,
+            stat,
             glob,
+            json,
             files,
         } => {
             let options = commands::StitchOptions {
                 force,
                 dry_run,
                 diff,
+                context,
+                stat,
+                color: diff_color,
                 quiet: cli.quiet,
                 glob,
                 files,
+                json,
             };
             commands::stitch(&mut ctx, options)
         }
@@ -273,19 +578,40 @@ fn main() -> ExitCode {
             force,
             dry_run,
             diff,
+            context,
+            stat,
+            json,
         } => {
             let options = commands::SyncOptions {
                 force,
                 dry_run,
                 diff,
+                context,
+                stat,
+                color: diff_color,
                 quiet: cli.quiet,
+                json,
             };
             commands::sync(&mut ctx, options)
         }
 
+        Commands::Diff { context, files } => {
+            let options = commands::DiffOptions {
+                files,
+                context,
+                color: diff_color,
+                quiet: cli.quiet,
+            };
+            commands::diff(&ctx, options)
+        }
+
         Commands::Watch { debounce } => {
             let options = commands::WatchOptions {
                 debounce_ms: debounce,
+                config_path: cli.config.clone(),
+                style: cli.style,
+                skip_invalid: cli.skip_invalid,
+                keep_going: cli.keep_going,
             };
             commands::watch(&mut ctx, options)
         }
@@ -295,6 +621,49 @@ fn main() -> ExitCode {
             commands::status(&ctx, options)
         }
 
+        Commands::Check => {
+            let options = commands::CheckOptions { quiet: cli.quiet };
+            commands::check(&ctx, options)
+        }
+
+        Commands::RenameBlock {
+            old,
+            new,
+            force,
+            dry_run,
+            diff,
+            context,
+        } => {
+            let options = commands::RenameBlockOptions {
+                old,
+                new,
+                force,
+                dry_run,
+                diff,
+                context,
+                color: diff_color,
+                quiet: cli.quiet,
+            };
+            commands::rename_block(&mut ctx, options)
+        }
+
+        Commands::Undo {
+            force,
+            dry_run,
+            diff,
+            context,
+        } => {
+            let options = commands::UndoOptions {
+                force,
+                dry_run,
+                diff,
+                context,
+                color: diff_color,
+                quiet: cli.quiet,
+            };
+            commands::undo(&mut ctx, options)
+        }
+
         Commands::Reset {
             delete_files,
             force,
@@ -306,36 +675,78 @@ fn main() -> ExitCode {
             commands::reset(&mut ctx, options)
         }
 
-        Commands::Config => commands::config(&ctx),
-
-        Commands::Locate { location } => {
-            let (file, line) = match location.rsplit_once(':') {
-                Some((f, l)) => match l.parse::<usize>() {
-                    Ok(n) if n > 0 => (PathBuf::from(f), n),
-                    _ => {
-                        eprintln!(
-                            "Invalid line number in '{}'. Expected format: file:line",
-                            location
-                        );
-                        return ExitCode::FAILURE;
-                    }
-                },
-                None => {
-                    eprintln!("Expected format: file:line (e.g., output.py:42)");
+        Commands::Config { explain } => {
+            let options = commands::ConfigOptions {
+                explain,
+                config_path: cli.config.clone(),
+                style: cli.style,
+                skip_invalid: cli.skip_invalid,
+                keep_going: cli.keep_going,
+            };
+            commands::config(&ctx, options)
+        }
+
+        Commands::Locate { location, json } => {
+            let (file, line, end_line) = match parse_location_range(&location) {
+                Ok(parsed) => parsed,
+                Err(msg) => {
+                    eprintln!("{}", msg);
                     return ExitCode::FAILURE;
                 }
             };
-            let options = commands::LocateOptions { file, line };
+            let options = commands::LocateOptions {
+                file,
+                line,
+                end_line,
+                json,
+            };
             commands::locate(&ctx, options)
         }
 
-        Commands::Init => unreachable!("handled before context creation"),
+        Commands::ReverseLocate { location } => {
+            let (file, line) = match parse_location(&location) {
+                Ok(parsed) => parsed,
+                Err(msg) => {
+                    eprintln!("{}", msg);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let options = commands::ReverseLocateOptions { file, line };
+            commands::reverse_locate(&ctx, options)
+        }
+
+        Commands::Export {
+            format,
+            output,
+            file,
+        } => {
+            let options = commands::ExportOptions {
+                file,
+                format,
+                output,
+            };
+            commands::export(&ctx, options)
+        }
+
+        Commands::Parse { json, file } => {
+            let options = commands::ParseOptions { file, json };
+            commands::parse(&ctx, options)
+        }
+
+        Commands::Lsp => commands::lsp(&ctx),
+
+        Commands::Init | Commands::Migrate { .. } => {
+            unreachable!("handled before context creation")
+        }
     };
 
     match result {
         Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
             eprintln!("Error: {}", e);
+            if let Some(snippet) = e.snippet() {
+                eprintln!("{}", snippet);
+            }
             ExitCode::from(e.exit_code())
         }
     }
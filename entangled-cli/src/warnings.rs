@@ -0,0 +1,50 @@
+//! Collection of logged warnings for machine-readable output.
+
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Tracing layer that records the message of every warning.
+///
+/// Installed alongside the log output, independently of its level filter,
+/// so `--quiet` runs still report warnings in `--json` summaries.
+pub struct WarningCollector;
+
+impl<S: Subscriber> Layer<S> for WarningCollector {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::WARN {
+            return;
+        }
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        WARNINGS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(message.0);
+    }
+}
+
+/// Extracts the `message` field of an event.
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        }
+    }
+}
+
+/// Returns the warnings recorded so far, clearing them.
+pub fn take() -> Vec<String> {
+    std::mem::take(
+        &mut *WARNINGS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    )
+}
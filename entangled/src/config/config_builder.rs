@@ -0,0 +1,310 @@
+//! Fluent construction of [`Config`] values.
+
+use std::path::PathBuf;
+
+use super::annotation_method::AnnotationMethod;
+use super::config_data::{Config, HooksConfig, WatchConfig};
+use super::filedb_format::FileDbFormat;
+use super::language::Language;
+use super::markers::Markers;
+use super::namespace_default::NamespaceDefault;
+use crate::style::Style;
+
+/// Builder for [`Config`], starting from the defaults.
+///
+/// ```
+/// use entangled::config::{AnnotationMethod, Config, NamespaceDefault};
+/// use entangled::Style;
+///
+/// let config = Config::builder()
+///     .annotation(AnnotationMethod::Naked)
+///     .namespace_default(NamespaceDefault::None)
+///     .style(Style::Pandoc)
+///     .build();
+/// assert_eq!(config.style, Style::Pandoc);
+/// ```
+#[derive(Debug, Clone, Default)]
+#[must_use]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Creates a builder holding the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the configuration format version.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.config.version = version.into();
+        self
+    }
+
+    /// Replaces the glob patterns for source files.
+    pub fn source_patterns<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.source_patterns = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Replaces the glob patterns for files left out of source matches.
+    pub fn exclude_patterns<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.exclude_patterns = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the directory tangled files are written under.
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.output_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets how tangled output is annotated.
+    pub fn annotation(mut self, annotation: AnnotationMethod) -> Self {
+        self.config.annotation = annotation;
+        self
+    }
+
+    /// Sets the default namespace for block IDs.
+    pub fn namespace_default(mut self, namespace: NamespaceDefault) -> Self {
+        self.config.namespace_default = namespace;
+        self
+    }
+
+    /// Sets the annotation markers.
+    pub fn markers(mut self, markers: Markers) -> Self {
+        self.config.markers = markers;
+        self
+    }
+
+    /// Adds a custom language definition.
+    pub fn language(mut self, language: Language) -> Self {
+        self.config.languages.push(language);
+        self
+    }
+
+    /// Sets the watch configuration.
+    pub fn watch(mut self, watch: WatchConfig) -> Self {
+        self.config.watch = watch;
+        self
+    }
+
+    /// Sets the hook configuration.
+    pub fn hooks(mut self, hooks: HooksConfig) -> Self {
+        self.config.hooks = hooks;
+        self
+    }
+
+    /// Sets the file database path.
+    pub fn filedb_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.filedb_path = path.into();
+        self
+    }
+
+    /// Sets how the file database is serialized.
+    pub fn filedb_format(mut self, format: FileDbFormat) -> Self {
+        self.config.filedb_format = format;
+        self
+    }
+
+    /// Sets the code block syntax style.
+    pub fn style(mut self, style: Style) -> Self {
+        self.config.style = style;
+        self
+    }
+
+    /// Sets whether `#|` option lines are stripped from tangled output.
+    pub fn strip_quarto_options(mut self, strip: bool) -> Self {
+        self.config.strip_quarto_options = strip;
+        self
+    }
+
+    /// Replaces the Quarto option keys whose `#|` lines survive stripping.
+    pub fn strip_quarto_options_except<I, S>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.strip_quarto_options_except = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Adds a language whose targets are tangled without annotations.
+    pub fn no_annotation_language(mut self, language: impl Into<String>) -> Self {
+        self.config.no_annotation_languages.push(language.into());
+        self
+    }
+
+    /// Sets whether `#line` directives are emitted for languages that support them.
+    pub fn line_directives(mut self, enabled: bool) -> Self {
+        self.config.line_directives = enabled;
+        self
+    }
+
+    /// Sets whether block names defined in several files are warned about.
+    pub fn warn_duplicate_ids(mut self, warn: bool) -> Self {
+        self.config.warn_duplicate_ids = warn;
+        self
+    }
+
+    /// Sets whether block names defined in several files are an error.
+    pub fn deny_duplicate_ids(mut self, deny: bool) -> Self {
+        self.config.deny_duplicate_ids = deny;
+        self
+    }
+
+    /// Sets whether targets may point outside the project directory.
+    pub fn allow_escaping_targets(mut self, allow: bool) -> Self {
+        self.config.allow_escaping_targets = allow;
+        self
+    }
+
+    /// Sets the directory files are backed up to before being overwritten.
+    pub fn backup_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.backup_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets whether tangled targets are streamed to disk.
+    pub fn stream_writes(mut self, stream: bool) -> Self {
+        self.config.stream_writes = stream;
+        self
+    }
+
+    /// Sets whether block names match regardless of case.
+    pub fn case_insensitive_names(mut self, enabled: bool) -> Self {
+        self.config.case_insensitive_names = enabled;
+        self
+    }
+
+    /// Sets the escape that makes a reference-like line literal (empty disables it).
+    pub fn reference_escape(mut self, escape: impl Into<String>) -> Self {
+        self.config.reference_escape = escape.into();
+        self
+    }
+
+    /// Sets whether indented code blocks after a `<!-- file: path -->` comment are read.
+    pub fn indented_blocks(mut self, enabled: bool) -> Self {
+        self.config.indented_blocks = enabled;
+        self
+    }
+
+    /// Sets whether fenced code blocks inside blockquotes are read.
+    pub fn quoted_blocks(mut self, enabled: bool) -> Self {
+        self.config.quoted_blocks = enabled;
+        self
+    }
+
+    /// Sets whether full tangles delete targets they no longer produce.
+    pub fn prune(mut self, enabled: bool) -> Self {
+        self.config.prune = enabled;
+        self
+    }
+
+    /// Sets whether Quarto-style documents tangle into their project's `output-dir`.
+    pub fn quarto_project_integration(mut self, enabled: bool) -> Self {
+        self.config.quarto_project_integration = enabled;
+        self
+    }
+
+    /// Sets whether source files that are not valid UTF-8 are skipped with a warning.
+    pub fn skip_invalid(mut self, enabled: bool) -> Self {
+        self.config.skip_invalid = enabled;
+        self
+    }
+
+    /// Sets whether documents that fail to parse are skipped with a warning.
+    pub fn keep_going(mut self, enabled: bool) -> Self {
+        self.config.keep_going = enabled;
+        self
+    }
+
+    /// Sets whether `<<file:path>>` references include files.
+    pub fn file_includes(mut self, enabled: bool) -> Self {
+        self.config.file_includes = enabled;
+        self
+    }
+
+    /// Sets whether tangled targets are normalized.
+    pub fn normalize_output(mut self, enabled: bool) -> Self {
+        self.config.normalize_output = enabled;
+        self
+    }
+
+    /// Adds a custom setting kept in [`Config::extra`].
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<toml::Value>) -> Self {
+        self.config.extra.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns the configuration.
+    #[must_use]
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+impl From<Config> for ConfigBuilder {
+    /// Starts from an existing configuration instead of the defaults.
+    fn from(config: Config) -> Self {
+        Self { config }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Comment;
+
+    #[test]
+    fn test_builder_defaults() {
+        let built = Config::builder().build();
+        assert_eq!(built.fingerprint(), Config::default().fingerprint());
+    }
+
+    #[test]
+    fn test_builder_sets_fields() {
+        let config = Config::builder()
+            .annotation(AnnotationMethod::Naked)
+            .namespace_default(NamespaceDefault::None)
+            .style(Style::Pandoc)
+            .source_patterns(["docs/**/*.md"])
+            .output_dir("src")
+            .language(Language::new("mylang", Comment::line("##")))
+            .no_annotation_language("json")
+            .case_insensitive_names(true)
+            .extra("custom", "value")
+            .build();
+
+        assert_eq!(config.annotation, AnnotationMethod::Naked);
+        assert_eq!(config.namespace_default, NamespaceDefault::None);
+        assert_eq!(config.style, Style::Pandoc);
+        assert_eq!(config.source_patterns, vec!["docs/**/*.md"]);
+        assert_eq!(config.output_dir, Some(PathBuf::from("src")));
+        assert_eq!(config.languages[0].name, "mylang");
+        assert_eq!(config.no_annotation_languages, vec!["json"]);
+        assert!(config.case_insensitive_names);
+        assert_eq!(
+            config.extra.get("custom"),
+            Some(&toml::Value::from("value"))
+        );
+        // Untouched fields keep their defaults
+        assert!(config.warn_duplicate_ids);
+    }
+
+    #[test]
+    fn test_builder_from_config() {
+        let base = Config::builder().stream_writes(true).build();
+        let config = ConfigBuilder::from(base).line_directives(true).build();
+        assert!(config.stream_writes);
+        assert!(config.line_directives);
+    }
+}
@@ -6,9 +6,13 @@ use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 
 use super::annotation_method::AnnotationMethod;
+use super::config_builder::ConfigBuilder;
+use super::expand::expand_path;
+use super::filedb_format::FileDbFormat;
 use super::language::Language;
 use super::markers::Markers;
 use super::namespace_default::NamespaceDefault;
+use crate::errors::Result;
 use crate::style::Style;
 
 /// Main configuration structure for Entangled.
@@ -22,6 +26,10 @@ pub struct Config {
     #[serde(default = "default_source_patterns")]
     pub source_patterns: Vec<String>,
 
+    /// Glob patterns for files to leave out of `source_patterns` matches.
+    #[serde(default = "default_exclude_patterns")]
+    pub exclude_patterns: Vec<String>,
+
     /// Directory for generated/tangled files.
     #[serde(default)]
     pub output_dir: Option<PathBuf>,
@@ -54,6 +62,10 @@ pub struct Config {
     #[serde(default = "default_filedb_path")]
     pub filedb_path: PathBuf,
 
+    /// How the file database is serialized.
+    #[serde(default)]
+    pub filedb_format: FileDbFormat,
+
     /// Code block syntax style.
     #[serde(default)]
     pub style: Style,
@@ -62,6 +74,90 @@ pub struct Config {
     #[serde(default = "default_strip_quarto_options")]
     pub strip_quarto_options: bool,
 
+    /// Quarto option keys whose `#|` lines are kept when stripping.
+    #[serde(default)]
+    pub strip_quarto_options_except: Vec<String>,
+
+    /// Languages whose targets are always tangled without annotations.
+    #[serde(default)]
+    pub no_annotation_languages: Vec<String>,
+
+    /// Emit `#line` directives for languages that support them (C, C++, CUDA).
+    #[serde(default)]
+    pub line_directives: bool,
+
+    /// Whether to warn when the same block name is defined in several source files.
+    #[serde(default = "default_warn_duplicate_ids")]
+    pub warn_duplicate_ids: bool,
+
+    /// Whether a block name defined in several source files is an error
+    /// instead of a warning.
+    #[serde(default)]
+    pub deny_duplicate_ids: bool,
+
+    /// Whether `file=` targets and `<<file:path>>` includes may be absolute or
+    /// escape the project directory.
+    #[serde(default)]
+    pub allow_escaping_targets: bool,
+
+    /// Directory where files are backed up before being overwritten or deleted.
+    #[serde(default)]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Whether to stream tangled targets to disk instead of building them in memory.
+    #[serde(default)]
+    pub stream_writes: bool,
+
+    /// Whether block names and references match regardless of case.
+    #[serde(default)]
+    pub case_insensitive_names: bool,
+
+    /// Escape that, written before `<<` on a line that would otherwise be a
+    /// reference, makes tangle emit the line literally. Empty disables it.
+    #[serde(default = "default_reference_escape")]
+    pub reference_escape: String,
+
+    /// Whether an indented code block following a `<!-- file: path -->`
+    /// comment is read as a block targeting that file.
+    #[serde(default)]
+    pub indented_blocks: bool,
+
+    /// Whether fenced code blocks inside blockquotes (`> ```python`) are
+    /// read, with the quote markers stripped from their content.
+    #[serde(default)]
+    pub quoted_blocks: bool,
+
+    /// Whether a full tangle deletes tracked targets it no longer produces
+    /// and forgets tracked files that no longer exist.
+    #[serde(default = "default_prune")]
+    pub prune: bool,
+
+    /// Whether tangled files of Quarto-style documents are placed in the
+    /// `output-dir` of the enclosing Quarto project (`_quarto.yml`).
+    #[serde(default)]
+    pub quarto_project_integration: bool,
+
+    /// Whether source files that are not valid UTF-8 are skipped with a
+    /// warning instead of failing the run. Binary files are always skipped.
+    #[serde(default)]
+    pub skip_invalid: bool,
+
+    /// Whether a source document that fails to parse is skipped with a
+    /// warning, so that the remaining documents are still processed. The
+    /// failures are collected by the [`Context`](crate::interface::Context).
+    #[serde(default)]
+    pub keep_going: bool,
+
+    /// Whether a `<<file:path>>` reference that names no block includes the
+    /// contents of the file at `path`, relative to the project directory.
+    #[serde(default)]
+    pub file_includes: bool,
+
+    /// Whether tangled targets have trailing whitespace removed from every
+    /// line and end in exactly one newline.
+    #[serde(default)]
+    pub normalize_output: bool,
+
     /// Additional custom settings.
     #[serde(default, flatten)]
     pub extra: HashMap<String, toml::Value>,
@@ -79,6 +175,15 @@ fn default_source_patterns() -> Vec<String> {
     ]
 }
 
+fn default_exclude_patterns() -> Vec<String> {
+    vec![
+        "**/node_modules/**".to_string(),
+        "**/target/**".to_string(),
+        "**/.git/**".to_string(),
+        "**/.venv/**".to_string(),
+    ]
+}
+
 fn default_filedb_path() -> PathBuf {
     PathBuf::from(".entangled/filedb.json")
 }
@@ -87,11 +192,24 @@ fn default_strip_quarto_options() -> bool {
     true
 }
 
+fn default_warn_duplicate_ids() -> bool {
+    true
+}
+
+fn default_reference_escape() -> String {
+    "\\".to_string()
+}
+
+fn default_prune() -> bool {
+    true
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             version: default_version(),
             source_patterns: default_source_patterns(),
+            exclude_patterns: default_exclude_patterns(),
             output_dir: None,
             annotation: AnnotationMethod::default(),
             namespace_default: NamespaceDefault::default(),
@@ -100,20 +218,53 @@ impl Default for Config {
             watch: WatchConfig::default(),
             hooks: HooksConfig::default(),
             filedb_path: default_filedb_path(),
+            filedb_format: FileDbFormat::default(),
             style: Style::default(),
             strip_quarto_options: default_strip_quarto_options(),
+            strip_quarto_options_except: Vec::new(),
+            no_annotation_languages: Vec::new(),
+            line_directives: false,
+            warn_duplicate_ids: default_warn_duplicate_ids(),
+            deny_duplicate_ids: false,
+            allow_escaping_targets: false,
+            backup_dir: None,
+            stream_writes: false,
+            case_insensitive_names: false,
+            reference_escape: default_reference_escape(),
+            indented_blocks: false,
+            quoted_blocks: false,
+            prune: default_prune(),
+            quarto_project_integration: false,
+            skip_invalid: false,
+            keep_going: false,
+            file_includes: false,
+            normalize_output: false,
             extra: HashMap::new(),
         }
     }
 }
 
 impl Config {
+    /// Returns a hash of the configuration, stable across runs.
+    ///
+    /// Used to detect configuration changes between incremental tangles.
+    pub fn fingerprint(&self) -> String {
+        // Going through `Value` sorts map keys, so `HashMap` order doesn't matter
+        let value = serde_json::to_value(self).unwrap_or_default();
+        crate::io::hexdigest_str(&value.to_string())
+    }
+
     /// Creates a new default configuration.
     #[must_use]
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Returns a builder starting from the default configuration.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
     /// Looks up a language by identifier, checking custom languages first.
     pub fn find_language(&self, identifier: &str) -> Option<Language> {
         // Check custom languages first
@@ -124,6 +275,27 @@ impl Config {
         super::templates::find_language(identifier)
     }
 
+    /// Returns the annotation method to use for a block of the given language.
+    ///
+    /// Languages listed in `no_annotation_languages` (by identifier or by
+    /// language name) fall back to naked output.
+    pub fn annotation_for(&self, language: Option<&str>) -> AnnotationMethod {
+        let Some(identifier) = language else {
+            return self.annotation;
+        };
+        let disabled = self.no_annotation_languages.iter().any(|l| {
+            l == identifier
+                || self
+                    .find_language(identifier)
+                    .is_some_and(|lang| lang.name == *l)
+        });
+        if disabled {
+            AnnotationMethod::Naked
+        } else {
+            self.annotation
+        }
+    }
+
     /// Returns all source patterns.
     pub fn source_patterns(&self) -> &[String] {
         &self.source_patterns
@@ -133,6 +305,30 @@ impl Config {
     pub fn output_dir(&self) -> Option<&Path> {
         self.output_dir.as_deref()
     }
+
+    /// Returns the output directory with `~` and environment variables expanded.
+    ///
+    /// The stored `output_dir` is left as written; an unset variable is a
+    /// [`Config`](crate::errors::EntangledError::Config) error.
+    pub fn expanded_output_dir(&self) -> Result<Option<PathBuf>> {
+        self.output_dir
+            .as_deref()
+            .map(|dir| expand_path(dir, "output_dir"))
+            .transpose()
+    }
+
+    /// Returns the file database path with `~` and environment variables expanded.
+    pub fn expanded_filedb_path(&self) -> Result<PathBuf> {
+        expand_path(&self.filedb_path, "filedb_path")
+    }
+
+    /// Returns the backup directory with `~` and environment variables expanded.
+    pub fn expanded_backup_dir(&self) -> Result<Option<PathBuf>> {
+        self.backup_dir
+            .as_deref()
+            .map(|dir| expand_path(dir, "backup_dir"))
+            .transpose()
+    }
 }
 
 /// Watch mode configuration.
@@ -176,6 +372,33 @@ pub struct HooksConfig {
     #[serde(default)]
     pub spdx_license: bool,
 
+    /// License identifier used to generate SPDX headers (e.g. `MIT`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spdx_license_id: Option<String>,
+
+    /// Prepend a "generated file" warning banner to tangled files.
+    #[serde(default)]
+    pub generated_banner: bool,
+
+    /// Banner text template; `{source}` is replaced by the markdown source path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated_banner_template: Option<String>,
+
+    /// Formatter commands keyed by language, e.g. `rust = "rustfmt --emit stdout"`.
+    ///
+    /// Tangled content is piped through the command and replaced by its
+    /// stdout. Only targets tangled without annotations are formatted.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub format: HashMap<String, String>,
+
+    /// Shell command to run in the project directory after a successful tangle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_tangle: Option<String>,
+
+    /// Shell command to run in the project directory after a successful sync.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_sync: Option<String>,
+
     /// Absorb unknown hook keys (forward-compat with Python Entangled configs).
     #[serde(default, flatten)]
     pub extra: HashMap<String, toml::Value>,
@@ -194,6 +417,21 @@ mod tests {
             vec!["**/*.md", "**/*.qmd", "**/*.Rmd"]
         );
         assert_eq!(config.annotation, AnnotationMethod::Standard);
+        assert!(config.warn_duplicate_ids);
+    }
+
+    #[test]
+    fn test_annotation_for_disabled_language() {
+        let config = Config {
+            no_annotation_languages: vec!["json".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(config.annotation_for(Some("json")), AnnotationMethod::Naked);
+        assert_eq!(
+            config.annotation_for(Some("python")),
+            AnnotationMethod::Standard
+        );
+        assert_eq!(config.annotation_for(None), AnnotationMethod::Standard);
     }
 
     #[test]
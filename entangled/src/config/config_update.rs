@@ -6,9 +6,11 @@ use serde::{Deserialize, Serialize};
 
 use super::annotation_method::AnnotationMethod;
 use super::config_data::{Config, HooksConfig, WatchConfig};
-use super::language::Language;
+use super::filedb_format::FileDbFormat;
+use super::language::{Language, LanguageUpdate};
 use super::markers::Markers;
 use super::namespace_default::NamespaceDefault;
+use super::templates::builtin_languages;
 use crate::style::Style;
 
 /// Partial configuration update that can be merged into a Config.
@@ -24,6 +26,10 @@ pub struct ConfigUpdate {
     #[serde(default)]
     pub source_patterns: Option<Vec<String>>,
 
+    /// Glob patterns for files to leave out of `source_patterns` matches.
+    #[serde(default)]
+    pub exclude_patterns: Option<Vec<String>>,
+
     /// Directory for generated/tangled files.
     #[serde(default)]
     pub output_dir: Option<PathBuf>,
@@ -40,9 +46,9 @@ pub struct ConfigUpdate {
     #[serde(default)]
     pub markers: Option<Markers>,
 
-    /// Language configurations.
+    /// Language configurations, merged onto known languages of the same name.
     #[serde(default)]
-    pub languages: Option<Vec<Language>>,
+    pub languages: Option<Vec<LanguageUpdate>>,
 
     /// Watch configuration.
     #[serde(default)]
@@ -56,6 +62,10 @@ pub struct ConfigUpdate {
     #[serde(default)]
     pub filedb_path: Option<PathBuf>,
 
+    /// File database serialization format.
+    #[serde(default)]
+    pub filedb_format: Option<FileDbFormat>,
+
     /// Code block syntax style.
     #[serde(default)]
     pub style: Option<Style>,
@@ -63,6 +73,78 @@ pub struct ConfigUpdate {
     /// Whether to strip #| comment lines from tangled output.
     #[serde(default)]
     pub strip_quarto_options: Option<bool>,
+
+    /// Quarto option keys kept when stripping.
+    #[serde(default)]
+    pub strip_quarto_options_except: Option<Vec<String>>,
+
+    /// Languages whose targets are always tangled without annotations.
+    #[serde(default)]
+    pub no_annotation_languages: Option<Vec<String>>,
+
+    /// Emit `#line` directives for languages that support them.
+    #[serde(default)]
+    pub line_directives: Option<bool>,
+
+    /// Whether to warn about block names defined in several source files.
+    #[serde(default)]
+    pub warn_duplicate_ids: Option<bool>,
+
+    /// Whether block names defined in several source files are an error.
+    #[serde(default)]
+    pub deny_duplicate_ids: Option<bool>,
+
+    /// Whether `file=` targets may escape the project directory.
+    #[serde(default)]
+    pub allow_escaping_targets: Option<bool>,
+
+    /// Directory for backups of overwritten files.
+    #[serde(default)]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Whether to stream tangled targets to disk.
+    #[serde(default)]
+    pub stream_writes: Option<bool>,
+
+    /// Whether block names match regardless of case.
+    #[serde(default)]
+    pub case_insensitive_names: Option<bool>,
+
+    /// Escape for lines that look like references.
+    #[serde(default)]
+    pub reference_escape: Option<String>,
+
+    /// Whether indented code blocks after a file directive are read.
+    #[serde(default)]
+    pub indented_blocks: Option<bool>,
+
+    /// Whether fenced code blocks inside blockquotes are read.
+    #[serde(default)]
+    pub quoted_blocks: Option<bool>,
+
+    /// Whether full tangles prune stale targets.
+    #[serde(default)]
+    pub prune: Option<bool>,
+
+    /// Whether Quarto project output directories are honored.
+    #[serde(default)]
+    pub quarto_project_integration: Option<bool>,
+
+    /// Whether sources that are not valid UTF-8 are skipped.
+    #[serde(default)]
+    pub skip_invalid: Option<bool>,
+
+    /// Whether documents that fail to parse are skipped.
+    #[serde(default)]
+    pub keep_going: Option<bool>,
+
+    /// Whether `<<file:path>>` references include files.
+    #[serde(default)]
+    pub file_includes: Option<bool>,
+
+    /// Whether tangled targets are normalized.
+    #[serde(default)]
+    pub normalize_output: Option<bool>,
 }
 
 impl ConfigUpdate {
@@ -80,34 +162,74 @@ impl ConfigUpdate {
             source_patterns: self
                 .source_patterns
                 .unwrap_or_else(|| base.source_patterns.clone()),
+            exclude_patterns: self
+                .exclude_patterns
+                .unwrap_or_else(|| base.exclude_patterns.clone()),
             output_dir: self.output_dir.or_else(|| base.output_dir.clone()),
             annotation: self.annotation.unwrap_or(base.annotation),
             namespace_default: self.namespace_default.unwrap_or(base.namespace_default),
             markers: self.markers.unwrap_or_else(|| base.markers.clone()),
-            languages: merge_languages(
-                &base.languages,
-                self.languages.as_ref().unwrap_or(&Vec::new()),
-            ),
+            languages: merge_languages(&base.languages, self.languages.unwrap_or_default()),
             watch: self.watch.unwrap_or_else(|| base.watch.clone()),
             hooks: merge_hooks(&base.hooks, self.hooks.as_ref()),
             filedb_path: self.filedb_path.unwrap_or_else(|| base.filedb_path.clone()),
+            filedb_format: self.filedb_format.unwrap_or(base.filedb_format),
             style: self.style.unwrap_or(base.style),
             strip_quarto_options: self
                 .strip_quarto_options
                 .unwrap_or(base.strip_quarto_options),
+            strip_quarto_options_except: self
+                .strip_quarto_options_except
+                .unwrap_or_else(|| base.strip_quarto_options_except.clone()),
+            no_annotation_languages: self
+                .no_annotation_languages
+                .unwrap_or_else(|| base.no_annotation_languages.clone()),
+            line_directives: self.line_directives.unwrap_or(base.line_directives),
+            warn_duplicate_ids: self.warn_duplicate_ids.unwrap_or(base.warn_duplicate_ids),
+            deny_duplicate_ids: self.deny_duplicate_ids.unwrap_or(base.deny_duplicate_ids),
+            allow_escaping_targets: self
+                .allow_escaping_targets
+                .unwrap_or(base.allow_escaping_targets),
+            backup_dir: self.backup_dir.or_else(|| base.backup_dir.clone()),
+            stream_writes: self.stream_writes.unwrap_or(base.stream_writes),
+            case_insensitive_names: self
+                .case_insensitive_names
+                .unwrap_or(base.case_insensitive_names),
+            reference_escape: self
+                .reference_escape
+                .unwrap_or_else(|| base.reference_escape.clone()),
+            indented_blocks: self.indented_blocks.unwrap_or(base.indented_blocks),
+            quoted_blocks: self.quoted_blocks.unwrap_or(base.quoted_blocks),
+            prune: self.prune.unwrap_or(base.prune),
+            quarto_project_integration: self
+                .quarto_project_integration
+                .unwrap_or(base.quarto_project_integration),
+            skip_invalid: self.skip_invalid.unwrap_or(base.skip_invalid),
+            keep_going: self.keep_going.unwrap_or(base.keep_going),
+            file_includes: self.file_includes.unwrap_or(base.file_includes),
+            normalize_output: self.normalize_output.unwrap_or(base.normalize_output),
             extra: base.extra.clone(),
         }
     }
 }
 
 /// Merge language lists, with update languages overriding base languages of the same name.
-fn merge_languages(base: &[Language], update: &[Language]) -> Vec<Language> {
+///
+/// An update entry is merged field-by-field onto the base entry of the same
+/// name, or onto the built-in language when the base has none, so that an
+/// entry only needs the fields it changes.
+fn merge_languages(base: &[Language], update: Vec<LanguageUpdate>) -> Vec<Language> {
     let mut result = base.to_vec();
 
     for lang in update {
+        let known = result
+            .iter()
+            .find(|l| l.name == lang.name)
+            .or_else(|| builtin_languages().iter().find(|l| l.name == lang.name));
+        let merged = lang.merge_onto(known);
         // Remove any existing language with the same name
-        result.retain(|l| l.name != lang.name);
-        result.push(lang.clone());
+        result.retain(|l| l.name != merged.name);
+        result.push(merged);
     }
 
     result
@@ -119,6 +241,22 @@ fn merge_hooks(base: &HooksConfig, update: Option<&HooksConfig>) -> HooksConfig
         Some(u) => HooksConfig {
             shebang: u.shebang || base.shebang,
             spdx_license: u.spdx_license || base.spdx_license,
+            spdx_license_id: u
+                .spdx_license_id
+                .clone()
+                .or_else(|| base.spdx_license_id.clone()),
+            generated_banner: u.generated_banner || base.generated_banner,
+            generated_banner_template: u
+                .generated_banner_template
+                .clone()
+                .or_else(|| base.generated_banner_template.clone()),
+            format: {
+                let mut merged = base.format.clone();
+                merged.extend(u.format.clone());
+                merged
+            },
+            post_tangle: u.post_tangle.clone().or_else(|| base.post_tangle.clone()),
+            post_sync: u.post_sync.clone().or_else(|| base.post_sync.clone()),
             extra: {
                 let mut merged = base.extra.clone();
                 merged.extend(u.extra.clone());
@@ -167,11 +305,11 @@ mod tests {
     fn test_merge_languages() {
         let base_langs = vec![Language::new("python", Comment::line("#"))];
         let update_langs = vec![
-            Language::new("python", Comment::line("##")), // Override
-            Language::new("rust", Comment::line("//")),   // New
+            Language::new("python", Comment::line("##")).into(), // Override
+            Language::new("rust", Comment::line("//")).into(),   // New
         ];
 
-        let merged = merge_languages(&base_langs, &update_langs);
+        let merged = merge_languages(&base_langs, update_langs);
         assert_eq!(merged.len(), 2);
 
         let python = merged.iter().find(|l| l.name == "python").unwrap();
@@ -180,6 +318,27 @@ mod tests {
         assert!(merged.iter().any(|l| l.name == "rust"));
     }
 
+    #[test]
+    fn test_language_override_keeps_builtin_identifiers() {
+        let update: ConfigUpdate =
+            toml::from_str("[[languages]]\nname = \"python\"\ncomment = \"##\"\n").unwrap();
+        let config = update.merge_into(&Config::default());
+
+        for identifier in ["python", "py", "python3"] {
+            let lang = config.find_language(identifier).unwrap();
+            assert_eq!(lang.name, "python");
+            assert_eq!(lang.comment, Comment::line("##"));
+        }
+
+        // A flag can be turned off without repeating the comment
+        let update: ConfigUpdate =
+            toml::from_str("[[languages]]\nname = \"c\"\nline_directives = false\n").unwrap();
+        let config = update.merge_into(&Config::default());
+        let c = config.find_language("c").unwrap();
+        assert!(!c.line_directives);
+        assert_eq!(c.comment, Comment::line("//"));
+    }
+
     #[test]
     fn test_from_update() {
         let update = ConfigUpdate {
@@ -190,4 +349,20 @@ mod tests {
 
         assert_eq!(config.version, "3.0");
     }
+
+    #[test]
+    fn test_merge_format_hooks() {
+        let update: ConfigUpdate = toml::from_str(
+            "[hooks.format]\nrust = \"rustfmt --emit stdout\"\npython = \"black -\"\n",
+        )
+        .unwrap();
+        let mut base = Config::default();
+        base.hooks
+            .format
+            .insert("rust".to_string(), "rustfmt".to_string());
+
+        let config = update.merge_into(&base);
+        assert_eq!(config.hooks.format["rust"], "rustfmt --emit stdout");
+        assert_eq!(config.hooks.format["python"], "black -");
+    }
 }
@@ -0,0 +1,125 @@
+//! Expansion of `~` and environment variables in configured paths.
+
+use std::path::{Path, PathBuf};
+
+use crate::errors::{EntangledError, Result};
+
+/// Expands a leading `~` and `$VAR`/`${VAR}` references in `path`.
+///
+/// `~` is only expanded as the whole first component (`~` or `~/...`). A `$`
+/// that is not followed by a variable name is kept as written. Referencing an
+/// unset variable is a configuration error naming `field`, so a missing
+/// variable never silently turns into an empty path segment.
+pub fn expand_path(path: &Path, field: &str) -> Result<PathBuf> {
+    // Non-UTF-8 paths cannot contain the syntax; keep them as they are
+    let Some(raw) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+    if !raw.contains(['$', '~']) {
+        return Ok(path.to_path_buf());
+    }
+
+    let lookup = |name: &str| {
+        std::env::var(name).map_err(|_| {
+            EntangledError::Config(format!(
+                "{} = \"{}\" uses ${}, which is not set",
+                field, raw, name
+            ))
+        })
+    };
+
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    if rest == "~" || rest.starts_with("~/") {
+        out.push_str(&home_dir(field, raw)?);
+        rest = &rest[1..];
+    }
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        if let Some(braced) = after.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                return Err(EntangledError::Config(format!(
+                    "{} = \"{}\" has an unclosed ${{",
+                    field, raw
+                )));
+            };
+            out.push_str(&lookup(&braced[..end])?);
+            rest = &braced[end + 1..];
+        } else {
+            let len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            if len == 0 {
+                out.push('$');
+            } else {
+                out.push_str(&lookup(&after[..len])?);
+            }
+            rest = &after[len..];
+        }
+    }
+    out.push_str(rest);
+
+    Ok(PathBuf::from(out))
+}
+
+fn home_dir(field: &str, raw: &str) -> Result<String> {
+    std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .map_err(|_| {
+            EntangledError::Config(format!(
+                "{} = \"{}\" starts with ~, but the home directory is unknown",
+                field, raw
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(s: &str) -> Result<PathBuf> {
+        expand_path(Path::new(s), "output_dir")
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        std::env::set_var("ENTANGLED_TEST_BUILD", "/tmp/build");
+        assert_eq!(
+            expand("$ENTANGLED_TEST_BUILD/gen").unwrap(),
+            PathBuf::from("/tmp/build/gen")
+        );
+        assert_eq!(
+            expand("${ENTANGLED_TEST_BUILD}_x/gen").unwrap(),
+            PathBuf::from("/tmp/build_x/gen")
+        );
+        // Not followed by a name: kept literally
+        assert_eq!(expand("cost$/a$").unwrap(), PathBuf::from("cost$/a$"));
+        assert_eq!(expand("plain/path").unwrap(), PathBuf::from("plain/path"));
+    }
+
+    #[test]
+    fn test_expand_home() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(expand("~").unwrap(), PathBuf::from(&home));
+        assert_eq!(
+            expand("~/gen").unwrap(),
+            PathBuf::from(format!("{}/gen", home))
+        );
+        // Only a leading ~ is expanded
+        assert_eq!(expand("a/~/b").unwrap(), PathBuf::from("a/~/b"));
+        assert_eq!(expand("~user/x").unwrap(), PathBuf::from("~user/x"));
+    }
+
+    #[test]
+    fn test_unset_variable_is_an_error() {
+        let err = expand("$ENTANGLED_TEST_SURELY_UNSET/gen").unwrap_err();
+        assert!(matches!(err, EntangledError::Config(_)));
+        assert!(err.to_string().contains(
+            "output_dir = \"$ENTANGLED_TEST_SURELY_UNSET/gen\" uses $ENTANGLED_TEST_SURELY_UNSET"
+        ));
+
+        assert!(expand("${ENTANGLED_TEST_BUILD").is_err());
+    }
+}
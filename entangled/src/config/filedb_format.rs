@@ -0,0 +1,41 @@
+//! File database format configuration.
+
+use serde::{Deserialize, Serialize};
+
+/// How the file database is serialized on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileDbFormat {
+    /// Pretty-printed JSON, with files sorted by path.
+    #[default]
+    Json,
+
+    /// TOML, with files sorted by path.
+    Toml,
+
+    /// Compact binary (CBOR).
+    Binary,
+}
+
+impl FileDbFormat {
+    /// Returns every format, the default first.
+    pub fn all() -> [FileDbFormat; 3] {
+        [FileDbFormat::Json, FileDbFormat::Toml, FileDbFormat::Binary]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serde() {
+        let toml: FileDbFormat = serde_json::from_str("\"toml\"").unwrap();
+        assert_eq!(toml, FileDbFormat::Toml);
+        assert_eq!(
+            serde_json::to_string(&FileDbFormat::Binary).unwrap(),
+            "\"binary\""
+        );
+        assert_eq!(FileDbFormat::default(), FileDbFormat::Json);
+    }
+}
@@ -34,6 +34,59 @@ impl Comment {
         }
     }
 
+    /// Comments out a single line.
+    ///
+    /// Like [`Comment::wrap`], except that an empty line in a line-comment
+    /// language becomes the bare prefix, without trailing whitespace.
+    pub fn wrap_line(&self, line: &str) -> String {
+        match self {
+            Comment::Line(prefix) if line.is_empty() => prefix.clone(),
+            _ => self.wrap(line),
+        }
+    }
+
+    /// Wraps several lines of text in a comment.
+    ///
+    /// Line comments prefix every line, as by [`Comment::wrap_line`]. A
+    /// single line in a block comment is wrapped like [`Comment::wrap`]; several lines get the delimiters on
+    /// lines of their own, with a ` * ` continuation when the opening
+    /// delimiter ends in `*` (`/*`, `(*`) and an indent otherwise:
+    ///
+    /// ```text
+    /// /*
+    ///  * first
+    ///  * second
+    ///  */
+    /// ```
+    pub fn wrap_block(&self, lines: &[&str]) -> String {
+        let (open, close) = match self {
+            Comment::Block { open, close } if lines.len() > 1 => (open, close),
+            _ => {
+                return lines
+                    .iter()
+                    .map(|line| self.wrap_line(line))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        };
+
+        let (continuation, last) = if open.ends_with('*') {
+            (" * ", format!(" {}", close))
+        } else {
+            ("  ", close.clone())
+        };
+        let mut wrapped = vec![open.clone()];
+        wrapped.extend(lines.iter().map(|line| {
+            if line.is_empty() {
+                continuation.trim_end().to_string()
+            } else {
+                format!("{}{}", continuation, line)
+            }
+        }));
+        wrapped.push(last);
+        wrapped.join("\n")
+    }
+
     /// Returns the comment prefix for annotation markers.
     pub fn prefix(&self) -> &str {
         match self {
@@ -61,6 +114,10 @@ pub struct Language {
 
     /// Comment style
     pub comment: Comment,
+
+    /// Whether the compiler understands `#line <n> "<file>"` directives
+    #[serde(default)]
+    pub line_directives: bool,
 }
 
 impl Language {
@@ -70,6 +127,7 @@ impl Language {
             name: name.into(),
             identifiers: Vec::new(),
             comment,
+            line_directives: false,
         }
     }
 
@@ -79,12 +137,76 @@ impl Language {
         self
     }
 
+    /// Marks the language as supporting `#line` directives.
+    pub fn with_line_directives(mut self) -> Self {
+        self.line_directives = true;
+        self
+    }
+
     /// Checks if this language matches a given identifier.
     pub fn matches(&self, identifier: &str) -> bool {
         self.name == identifier || self.identifiers.iter().any(|id| id == identifier)
     }
 }
 
+/// A `[[languages]]` entry of a configuration update, with every field but
+/// the name optional.
+///
+/// An entry for a language that is already known, built in or configured
+/// earlier, is merged onto it field by field, so it only needs the fields it
+/// changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LanguageUpdate {
+    /// Language identifier (e.g., "python", "rust")
+    pub name: String,
+
+    /// File extensions for this language
+    #[serde(default)]
+    pub identifiers: Option<Vec<String>>,
+
+    /// Comment style
+    #[serde(default)]
+    pub comment: Option<Comment>,
+
+    /// Whether the compiler understands `#line <n> "<file>"` directives
+    #[serde(default)]
+    pub line_directives: Option<bool>,
+}
+
+impl LanguageUpdate {
+    /// Applies this entry to `base`, the known language of the same name,
+    /// if any. Fields left unset keep the value of `base`; a new language
+    /// without a `comment` uses the default `#`.
+    pub fn merge_onto(self, base: Option<&Language>) -> Language {
+        Language {
+            identifiers: self
+                .identifiers
+                .or_else(|| base.map(|b| b.identifiers.clone()))
+                .unwrap_or_default(),
+            comment: self
+                .comment
+                .or_else(|| base.map(|b| b.comment.clone()))
+                .unwrap_or_default(),
+            line_directives: self
+                .line_directives
+                .or_else(|| base.map(|b| b.line_directives))
+                .unwrap_or(false),
+            name: self.name,
+        }
+    }
+}
+
+impl From<Language> for LanguageUpdate {
+    fn from(language: Language) -> Self {
+        Self {
+            name: language.name,
+            identifiers: Some(language.identifiers),
+            comment: Some(language.comment),
+            line_directives: Some(language.line_directives),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -103,6 +225,34 @@ mod tests {
         assert_eq!(comment.prefix(), "/*");
     }
 
+    #[test]
+    fn test_wrap_line() {
+        assert_eq!(Comment::line("//").wrap_line("text"), "// text");
+        assert_eq!(Comment::line("//").wrap_line(""), "//");
+        assert_eq!(Comment::block("/*", "*/").wrap_line("text"), "/* text */");
+    }
+
+    #[test]
+    fn test_wrap_block() {
+        let lines = ["first", "", "second"];
+        assert_eq!(
+            Comment::line("#").wrap_block(&lines),
+            "# first\n#\n# second"
+        );
+        assert_eq!(
+            Comment::block("/*", "*/").wrap_block(&lines),
+            "/*\n * first\n *\n * second\n */"
+        );
+        assert_eq!(
+            Comment::block("<!--", "-->").wrap_block(&lines),
+            "<!--\n  first\n\n  second\n-->"
+        );
+        assert_eq!(
+            Comment::block("/*", "*/").wrap_block(&["only"]),
+            "/* only */"
+        );
+    }
+
     #[test]
     fn test_language_matches() {
         let lang = Language::new("python", Comment::line("#"))
@@ -114,6 +264,33 @@ mod tests {
         assert!(!lang.matches("rust"));
     }
 
+    #[test]
+    fn test_language_update_merge_onto() {
+        let builtin = Language::new("c", Comment::block("/*", "*/"))
+            .with_identifiers(vec!["h".to_string()])
+            .with_line_directives();
+
+        let update: LanguageUpdate = toml::from_str("name = \"c\"\ncomment = \"//\"").unwrap();
+        let merged = update.merge_onto(Some(&builtin));
+        assert_eq!(merged.comment, Comment::line("//"));
+        assert_eq!(merged.identifiers, vec!["h".to_string()]);
+        assert!(merged.line_directives);
+
+        // Turning a flag off and replacing identifiers, without a comment
+        let update: LanguageUpdate =
+            toml::from_str("name = \"c\"\nidentifiers = []\nline_directives = false").unwrap();
+        let merged = update.merge_onto(Some(&builtin));
+        assert_eq!(merged.comment, builtin.comment);
+        assert!(merged.identifiers.is_empty());
+        assert!(!merged.line_directives);
+
+        let update: LanguageUpdate = toml::from_str("name = \"mylang\"").unwrap();
+        assert_eq!(
+            update.merge_onto(None),
+            Language::new("mylang", Comment::default())
+        );
+    }
+
     #[test]
     fn test_comment_serde() {
         let line: Comment = serde_json::from_str("\"#\"").unwrap();
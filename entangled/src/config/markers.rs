@@ -1,8 +1,13 @@
 //! Annotation markers for tangled code.
 
 use once_cell::sync::Lazy;
-use regex::Regex;
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::path::Path;
+
+use crate::errors::{EntangledError, Result};
+use crate::io::normalize_separators;
 
 /// Marker patterns for annotated code blocks.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -22,6 +27,28 @@ pub struct Markers {
     /// The word used to mark the end of a block.
     #[serde(default = "default_end")]
     pub end: String,
+
+    /// Sigil between the comment prefix and the begin/end word.
+    #[serde(default = "default_sigil")]
+    pub sigil: String,
+
+    /// Whether begin markers end with a fingerprint of the block's source
+    /// (`hash:ab12cd34`), which stitch checks to find the block to update.
+    #[serde(default)]
+    pub fingerprints: bool,
+
+    /// Whether begin markers record the markdown file the block was tangled
+    /// from (`from:docs/a.md`), which stitch and `locate_source` use to tell
+    /// blocks with the same ID in different files apart.
+    #[serde(default)]
+    pub source_paths: bool,
+
+    /// Whether leaf blocks (blocks without references) are followed by a
+    /// single compact marker recording their line count
+    /// (`end <<name[0]>> lines:3`) instead of being wrapped in begin and end
+    /// markers.
+    #[serde(default)]
+    pub compact: bool,
 }
 
 fn default_open() -> String {
@@ -40,6 +67,10 @@ fn default_end() -> String {
     "end".to_string()
 }
 
+fn default_sigil() -> String {
+    ANNOTATION_PREFIX.to_string()
+}
+
 impl Default for Markers {
     fn default() -> Self {
         Self {
@@ -47,6 +78,10 @@ impl Default for Markers {
             close: default_close(),
             begin: default_begin(),
             end: default_end(),
+            sigil: default_sigil(),
+            fingerprints: false,
+            source_paths: false,
+            compact: false,
         }
     }
 }
@@ -59,9 +94,37 @@ impl Markers {
             close: close.to_string(),
             begin: begin.to_string(),
             end: end.to_string(),
+            sigil: default_sigil(),
+            fingerprints: false,
+            source_paths: false,
+            compact: false,
         }
     }
 
+    /// Sets the sigil written between the comment prefix and the marker word.
+    pub fn with_sigil(mut self, sigil: impl Into<String>) -> Self {
+        self.sigil = sigil.into();
+        self
+    }
+
+    /// Sets whether begin markers carry a fingerprint of the block's source.
+    pub fn with_fingerprints(mut self, fingerprints: bool) -> Self {
+        self.fingerprints = fingerprints;
+        self
+    }
+
+    /// Sets whether begin markers record the block's markdown file.
+    pub fn with_source_paths(mut self, source_paths: bool) -> Self {
+        self.source_paths = source_paths;
+        self
+    }
+
+    /// Sets whether leaf blocks get a single compact marker.
+    pub fn with_compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
     /// Formats a begin marker for the given reference.
     pub fn format_begin(&self, reference: &str) -> String {
         format!("{} {}{}{}", self.begin, self.open, reference, self.close)
@@ -72,10 +135,22 @@ impl Markers {
         self.end.clone()
     }
 
+    /// Formats a compact marker for a reference to a block of `lines` lines.
+    pub fn format_compact(&self, reference: &str, lines: usize) -> String {
+        format!(
+            "{} {}{}{} lines:{}",
+            self.end, self.open, reference, self.close, lines
+        )
+    }
+
     /// Creates a regex pattern for matching begin markers.
+    ///
+    /// A source path and a trailing fingerprint, if any, are captured as
+    /// `from` and `hash`; they are recognized whether or not `source_paths`
+    /// and `fingerprints` are set. Use [`begin_source_file`] to read the path.
     pub fn begin_pattern(&self) -> String {
         format!(
-            r"^\s*{}\s+{}(?P<ref>[^{}]+){}",
+            r#"^\s*{}\s+{}(?P<ref>[^{}]+){}(?:\s+from:(?P<from>"[^"]*"|\S+))?(?:\s+hash:(?P<hash>[0-9a-f]+))?"#,
             regex::escape(&self.begin),
             regex::escape(&self.open),
             regex::escape(&self.close.chars().next().unwrap_or('>').to_string()),
@@ -87,13 +162,196 @@ impl Markers {
     pub fn end_pattern(&self) -> String {
         format!(r"^\s*{}\s*$", regex::escape(&self.end))
     }
+
+    /// Creates a regex pattern for matching compact markers.
+    ///
+    /// The line count is captured as `lines`; a source path and fingerprint
+    /// are captured as in [`Markers::begin_pattern`].
+    pub fn compact_pattern(&self) -> String {
+        format!(
+            r#"^\s*{}\s+{}(?P<ref>[^{}]+){}\s+lines:(?P<lines>\d+)(?:\s+from:(?P<from>"[^"]*"|\S+))?(?:\s+hash:(?P<hash>[0-9a-f]+))?"#,
+            regex::escape(&self.end),
+            regex::escape(&self.open),
+            regex::escape(&self.close.chars().next().unwrap_or('>').to_string()),
+            regex::escape(&self.close)
+        )
+    }
+
+    /// Checks that annotations written with these markers can be read back.
+    ///
+    /// A sample begin, end and compact line are formatted with
+    /// [`annotation_begin`], [`annotation_end`] and [`annotation_compact`] and
+    /// matched against the [`AnnotationPatterns`] built from these markers:
+    /// the begin and compact lines must yield the reference they were written
+    /// with, and no line may be mistaken for another kind.
+    pub fn validate(&self) -> Result<()> {
+        let invalid =
+            |reason: String| Err(EntangledError::Config(format!("Invalid markers: {reason}")));
+
+        for (field, value) in [
+            ("open", &self.open),
+            ("close", &self.close),
+            ("begin", &self.begin),
+            ("end", &self.end),
+            ("sigil", &self.sigil),
+        ] {
+            if value.trim().is_empty() {
+                return invalid(format!("`{field}` must not be empty"));
+            }
+        }
+        if self.sigil.contains(char::is_whitespace) {
+            return invalid("`sigil` must not contain whitespace".to_string());
+        }
+
+        let patterns = AnnotationPatterns::new(self)?;
+        let reference = "doc.md#name[0]";
+        let begin_line = annotation_begin("#", self, reference);
+        let end_line = annotation_end("#", self);
+        let compact_line = annotation_compact("#", self, reference, 1);
+
+        match patterns.begin.captures(&begin_line) {
+            Some(caps) if &caps["ref"] == reference => {}
+            Some(caps) => {
+                return invalid(format!(
+                    "begin marker `{begin_line}` reads back as reference `{}`",
+                    &caps["ref"]
+                ))
+            }
+            None => return invalid(format!("begin marker `{begin_line}` cannot be parsed")),
+        }
+        if !patterns.end.is_match(&end_line) {
+            return invalid(format!("end marker `{end_line}` cannot be parsed"));
+        }
+        if patterns.end.is_match(&begin_line) || patterns.begin.is_match(&end_line) {
+            return invalid(format!(
+                "begin marker `{begin_line}` and end marker `{end_line}` are ambiguous"
+            ));
+        }
+        match patterns.compact.captures(&compact_line) {
+            Some(caps) if &caps["ref"] == reference => {}
+            _ => return invalid(format!("compact marker `{compact_line}` cannot be parsed")),
+        }
+        if patterns.begin.is_match(&compact_line)
+            || patterns.end.is_match(&compact_line)
+            || patterns.compact.is_match(&begin_line)
+            || patterns.compact.is_match(&end_line)
+        {
+            return invalid(format!(
+                "compact marker `{compact_line}` is ambiguous with begin or end markers"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Compiled regexes that recognize annotation lines in tangled files.
+///
+/// Built from a [`Markers`] configuration, so files tangled with custom
+/// markers or a custom sigil can be read back. `begin` captures the comment
+/// `prefix` and the reference (`ref`); `end` matches a whole end line.
+#[derive(Debug, Clone)]
+pub struct AnnotationPatterns {
+    /// Matches `<prefix> <sigil> <begin> <open><ref><close>`.
+    pub begin: Regex,
+    /// Matches `<prefix> <sigil> <end>`.
+    pub end: Regex,
+    /// Matches `<prefix> <sigil> <end> <open><ref><close> lines:<n>`.
+    pub compact: Regex,
+}
+
+impl AnnotationPatterns {
+    /// Compiles the patterns for the given markers.
+    pub fn new(markers: &Markers) -> Result<Self> {
+        let lead = format!(
+            r"^\s*(?P<prefix>\S+)\s+{}\s+",
+            regex::escape(&markers.sigil)
+        );
+        let begin = markers.begin_pattern();
+        let end = markers.end_pattern();
+        let compact = markers.compact_pattern();
+        Ok(Self {
+            begin: Regex::new(&format!("{}{}", lead, &begin[r"^\s*".len()..]))?,
+            end: Regex::new(&format!("{}{}", lead, &end[r"^\s*".len()..]))?,
+            compact: Regex::new(&format!("{}{}", lead, &compact[r"^\s*".len()..]))?,
+        })
+    }
+}
+
+impl Default for AnnotationPatterns {
+    fn default() -> Self {
+        static DEFAULT: Lazy<AnnotationPatterns> =
+            Lazy::new(|| AnnotationPatterns::new(&Markers::default()).unwrap());
+        DEFAULT.clone()
+    }
 }
 
 /// Reference pattern for detecting noweb-style references like `<<refname>>`.
-pub static REF_PATTERN: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^(?P<indent>\s*)<<(?P<refname>[\w:/_.-]+)>>\s*$").unwrap());
+///
+/// Names may contain single spaces between words (`<<my block name>>`) or be
+/// quoted (`<<"my block name">>`); use [`ref_name`] to read the name from a match.
+/// An optional `[n]` suffix (`<<refname[2]>>`) selects a single block of the
+/// name; use [`ref_count`] to read it.
+pub static REF_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"^(?P<indent>\s*)<<(?P<refname>[\w:/_.#-]+(?: [\w:/_.#-]+)*|"(?:[^"\\]|\\.)+")(?:\[(?P<count>\d+)\])?>>\s*$"#,
+    )
+    .unwrap()
+});
 
-/// Annotation prefix pattern.
+/// Returns `line` without `escape` if it is an escaped reference, such as
+/// `\<<name>>`: a line that matches [`REF_PATTERN`] once the escape in front
+/// of `<<` is removed. Returns `None` for other lines or an empty `escape`.
+pub fn unescape_reference(line: &str, escape: &str) -> Option<String> {
+    if escape.is_empty() {
+        return None;
+    }
+    let rest = line.trim_start();
+    let indent = &line[..line.len() - rest.len()];
+    let unescaped = format!("{}{}", indent, rest.strip_prefix(escape)?);
+    REF_PATTERN.is_match(&unescaped).then_some(unescaped)
+}
+
+/// Inserts `escape` in front of `<<` if `line` matches [`REF_PATTERN`]; the
+/// inverse of [`unescape_reference`].
+pub fn escape_reference(line: &str, escape: &str) -> Option<String> {
+    if escape.is_empty() {
+        return None;
+    }
+    let caps = REF_PATTERN.captures(line)?;
+    let indent = &caps["indent"];
+    Some(format!("{}{}{}", indent, escape, &line[indent.len()..]))
+}
+
+/// Returns the block count from a [`REF_PATTERN`] match of `<<refname[n]>>`,
+/// or `None` for a reference to every block of the name.
+pub fn ref_count(caps: &Captures<'_>) -> Option<usize> {
+    caps.name("count").and_then(|m| m.as_str().parse().ok())
+}
+
+/// Returns the reference name from a [`REF_PATTERN`] match, with any quotes
+/// and backslash escapes removed.
+pub fn ref_name<'a>(caps: &Captures<'a>) -> Cow<'a, str> {
+    let raw = caps.name("refname").map_or("", |m| m.as_str());
+    match raw.strip_prefix('"').and_then(|r| r.strip_suffix('"')) {
+        Some(quoted) if quoted.contains('\\') => {
+            let mut name = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                name.push(if c == '\\' {
+                    chars.next().unwrap_or(c)
+                } else {
+                    c
+                });
+            }
+            Cow::Owned(name)
+        }
+        Some(quoted) => Cow::Borrowed(quoted),
+        None => Cow::Borrowed(raw),
+    }
+}
+
+/// Default annotation sigil, written between the comment prefix and the
+/// begin/end word.
 pub static ANNOTATION_PREFIX: &str = "~/~";
 
 /// Creates a full annotation begin marker.
@@ -101,17 +359,121 @@ pub fn annotation_begin(comment_prefix: &str, markers: &Markers, reference: &str
     format!(
         "{} {} {}",
         comment_prefix,
-        ANNOTATION_PREFIX,
+        markers.sigil,
         markers.format_begin(reference)
     )
 }
 
+/// Number of hex digits in a block fingerprint.
+const FINGERPRINT_LEN: usize = 8;
+
+/// Returns the short hash of a block's source written in begin markers.
+pub fn fingerprint(source: &str) -> String {
+    let mut digest = crate::io::hexdigest_str(source);
+    digest.truncate(FINGERPRINT_LEN);
+    digest
+}
+
+/// Matches the fingerprint field at the end of a marker line.
+static FINGERPRINT_FIELD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!(r"(?m) hash:[0-9a-f]{{{}}}(\r?)$", FINGERPRINT_LEN)).unwrap());
+
+/// Returns `content` with the fingerprint fields of its markers removed.
+///
+/// Two tangles of a target that differ only here differ only in blocks
+/// whose source changed, such as blocks just stitched from the target.
+pub fn strip_fingerprints(content: &str) -> Cow<'_, str> {
+    FINGERPRINT_FIELD.replace_all(content, "$1")
+}
+
+/// Creates the begin marker for a block with the given source, read from
+/// `source_file`.
+///
+/// Like [`annotation_begin`], followed by the block's markdown file when the
+/// markers have `source_paths` set and the file is known, then by the
+/// fingerprint of `source` when they have `fingerprints` set.
+pub fn block_annotation_begin(
+    comment_prefix: &str,
+    markers: &Markers,
+    reference: &str,
+    source: &str,
+    source_file: Option<&Path>,
+) -> String {
+    let mut begin = annotation_begin(comment_prefix, markers, reference);
+    push_block_fields(&mut begin, markers, source, source_file);
+    begin
+}
+
+/// Creates a full compact marker for a block of `lines` lines.
+pub fn annotation_compact(
+    comment_prefix: &str,
+    markers: &Markers,
+    reference: &str,
+    lines: usize,
+) -> String {
+    format!(
+        "{} {} {}",
+        comment_prefix,
+        markers.sigil,
+        markers.format_compact(reference, lines)
+    )
+}
+
+/// Creates the compact marker written after a leaf block with the given
+/// source, read from `source_file`.
+///
+/// Like [`annotation_compact`], followed by the same source file and
+/// fingerprint fields as [`block_annotation_begin`].
+pub fn block_annotation_compact(
+    comment_prefix: &str,
+    markers: &Markers,
+    reference: &str,
+    source: &str,
+    source_file: Option<&Path>,
+) -> String {
+    let lines = source.lines().count();
+    let mut compact = annotation_compact(comment_prefix, markers, reference, lines);
+    push_block_fields(&mut compact, markers, source, source_file);
+    compact
+}
+
+/// Appends the source file and fingerprint fields enabled in `markers`.
+fn push_block_fields(
+    line: &mut String,
+    markers: &Markers,
+    source: &str,
+    source_file: Option<&Path>,
+) {
+    if let Some(path) = source_file.filter(|_| markers.source_paths) {
+        let path = normalize_separators(path).display().to_string();
+        if path.contains(|c: char| c.is_whitespace() || c == '"') {
+            line.push_str(&format!(" from:\"{}\"", path.replace('"', "")));
+        } else {
+            line.push_str(&format!(" from:{}", path));
+        }
+    }
+    if markers.fingerprints {
+        line.push_str(&format!(" hash:{}", fingerprint(source)));
+    }
+}
+
+/// Returns the markdown file recorded in a begin or compact marker matched
+/// by [`AnnotationPatterns`], without quotes.
+pub fn begin_source_file<'a>(caps: &Captures<'a>) -> Option<&'a str> {
+    let raw = caps.name("from")?.as_str();
+    Some(
+        raw.strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+            .unwrap_or(raw),
+    )
+}
+
 /// Creates a full annotation end marker.
 pub fn annotation_end(comment_prefix: &str, markers: &Markers) -> String {
     format!(
         "{} {} {}",
         comment_prefix,
-        ANNOTATION_PREFIX,
+        markers.sigil,
         markers.format_end()
     )
 }
@@ -148,6 +510,104 @@ mod tests {
         assert_eq!(result, "# ~/~ begin <<file#main[0]>>");
     }
 
+    #[test]
+    fn test_strip_fingerprints() {
+        let content = "# ~/~ begin <<main[0]>> hash:0123abcd\r\nx = 1\r\n# ~/~ end\r\n";
+        assert_eq!(
+            strip_fingerprints(content),
+            "# ~/~ begin <<main[0]>>\r\nx = 1\r\n# ~/~ end\r\n"
+        );
+        assert_eq!(strip_fingerprints("x = 1 # hash:y"), "x = 1 # hash:y");
+    }
+
+    #[test]
+    fn test_block_annotation_begin_fingerprint() {
+        let markers = Markers::default();
+        assert_eq!(
+            block_annotation_begin("#", &markers, "main[0]", "x = 1", None),
+            "# ~/~ begin <<main[0]>>"
+        );
+
+        let markers = markers.with_fingerprints(true);
+        let line = block_annotation_begin("#", &markers, "main[0]", "x = 1", None);
+        assert_eq!(
+            line,
+            format!("# ~/~ begin <<main[0]>> hash:{}", fingerprint("x = 1"))
+        );
+        assert_eq!(fingerprint("x = 1").len(), 8);
+
+        let caps = AnnotationPatterns::new(&markers)
+            .unwrap()
+            .begin
+            .captures(&line)
+            .unwrap();
+        assert_eq!(&caps["ref"], "main[0]");
+        assert_eq!(&caps["hash"], fingerprint("x = 1"));
+    }
+
+    #[test]
+    fn test_block_annotation_begin_source_file() {
+        let path = Path::new("docs/a.md");
+        let markers = Markers::default();
+        assert_eq!(
+            block_annotation_begin("#", &markers, "main[0]", "x = 1", Some(path)),
+            "# ~/~ begin <<main[0]>>"
+        );
+
+        let markers = markers.with_source_paths(true).with_fingerprints(true);
+        let patterns = AnnotationPatterns::new(&markers).unwrap();
+        let line = block_annotation_begin("#", &markers, "main[0]", "x = 1", Some(path));
+        assert_eq!(
+            line,
+            format!(
+                "# ~/~ begin <<main[0]>> from:docs/a.md hash:{}",
+                fingerprint("x = 1")
+            )
+        );
+        let caps = patterns.begin.captures(&line).unwrap();
+        assert_eq!(&caps["ref"], "main[0]");
+        assert_eq!(begin_source_file(&caps), Some("docs/a.md"));
+        assert_eq!(&caps["hash"], fingerprint("x = 1"));
+
+        // Paths with spaces are quoted
+        let line = block_annotation_begin(
+            "#",
+            &markers,
+            "main[0]",
+            "",
+            Some(Path::new("my docs/a.md")),
+        );
+        let caps = patterns.begin.captures(&line).unwrap();
+        assert_eq!(begin_source_file(&caps), Some("my docs/a.md"));
+
+        // Unknown files are left out
+        let line = block_annotation_begin("#", &markers, "main[0]", "", None);
+        assert!(begin_source_file(&patterns.begin.captures(&line).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_block_annotation_compact() {
+        let markers = Markers::default()
+            .with_compact(true)
+            .with_fingerprints(true);
+        let patterns = AnnotationPatterns::new(&markers).unwrap();
+        let line = block_annotation_compact("#", &markers, "a.md#x[1]", "x = 1\ny = 2", None);
+        assert_eq!(
+            line,
+            format!(
+                "# ~/~ end <<a.md#x[1]>> lines:2 hash:{}",
+                fingerprint("x = 1\ny = 2")
+            )
+        );
+
+        let caps = patterns.compact.captures(&line).unwrap();
+        assert_eq!(&caps["ref"], "a.md#x[1]");
+        assert_eq!(&caps["lines"], "2");
+        assert!(!patterns.begin.is_match(&line));
+        assert!(!patterns.end.is_match(&line));
+        assert!(!patterns.compact.is_match("# ~/~ end"));
+    }
+
     #[test]
     fn test_annotation_end() {
         let markers = Markers::default();
@@ -155,6 +615,51 @@ mod tests {
         assert_eq!(result, "# ~/~ end");
     }
 
+    #[test]
+    fn test_custom_sigil() {
+        let markers = Markers::new("{{", "}}", "start", "stop").with_sigil("@@");
+        let begin = annotation_begin("//", &markers, "a.md#x[1]");
+        let end = annotation_end("//", &markers);
+        assert_eq!(begin, "// @@ start {{a.md#x[1]}}");
+        assert_eq!(end, "// @@ stop");
+
+        let patterns = AnnotationPatterns::new(&markers).unwrap();
+        let indented = format!("    {}", begin);
+        let caps = patterns.begin.captures(&indented).unwrap();
+        assert_eq!(&caps["prefix"], "//");
+        assert_eq!(&caps["ref"], "a.md#x[1]");
+        assert!(patterns.end.is_match(&end));
+
+        // Default markers are not recognized with a different sigil
+        let defaults = AnnotationPatterns::default();
+        assert!(!defaults.begin.is_match(&begin));
+        assert!(defaults.begin.is_match("# ~/~ begin <<a[0]>>"));
+        assert!(!patterns.begin.is_match("# ~/~ begin <<a[0]>>"));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Markers::default().validate().is_ok());
+        assert!(Markers::new("{{", "}}", "start", "stop").validate().is_ok());
+
+        // Empty fields
+        assert!(Markers::new("", ">>", "begin", "end").validate().is_err());
+        assert!(Markers::default().with_sigil("").validate().is_err());
+        assert!(Markers::default().with_sigil("~ ~").validate().is_err());
+        assert!(Markers::new("<<", ">>", "begin", " ").validate().is_err());
+
+        // The close marker's first character occurs in every reference ID
+        let err = Markers::new("<<", "]", "begin", "end")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("doc.md#name[0"));
+
+        // Begin and end cannot be told apart
+        assert!(Markers::new("<<", ">>", "mark", "mark <<x>>")
+            .validate()
+            .is_err());
+    }
+
     #[test]
     fn test_ref_pattern() {
         let caps = REF_PATTERN.captures("    <<some_ref>>").unwrap();
@@ -169,9 +674,69 @@ mod tests {
         assert!(REF_PATTERN.captures("<<>>").is_none());
     }
 
+    #[test]
+    fn test_reference_escape() {
+        assert_eq!(
+            unescape_reference("  \\<<not_a_ref>>", "\\").as_deref(),
+            Some("  <<not_a_ref>>")
+        );
+        assert_eq!(
+            escape_reference("  <<not_a_ref>>", "\\").as_deref(),
+            Some("  \\<<not_a_ref>>")
+        );
+
+        // Only lines that would otherwise be references
+        assert!(unescape_reference("std::cout \\<< x;", "\\").is_none());
+        assert!(unescape_reference("\\\\<<twice>>", "\\").is_none());
+        assert!(escape_reference("std::cout << x;", "\\").is_none());
+
+        // A custom escape, or none at all
+        assert_eq!(
+            unescape_reference("@@<<x>>", "@@").as_deref(),
+            Some("<<x>>")
+        );
+        assert!(unescape_reference("\\<<x>>", "").is_none());
+        assert!(escape_reference("<<x>>", "").is_none());
+    }
+
     #[test]
     fn test_ref_pattern_with_path() {
         let caps = REF_PATTERN.captures("<<path/to/file.py>>").unwrap();
         assert_eq!(&caps["refname"], "path/to/file.py");
+
+        let caps = REF_PATTERN.captures("<<docs/a.md#helper>>").unwrap();
+        assert_eq!(&caps["refname"], "docs/a.md#helper");
+    }
+
+    #[test]
+    fn test_ref_pattern_with_count() {
+        let caps = REF_PATTERN.captures("    <<helper[2]>>").unwrap();
+        assert_eq!(ref_name(&caps), "helper");
+        assert_eq!(ref_count(&caps), Some(2));
+
+        let caps = REF_PATTERN.captures("<<\"my block\"[0]>>").unwrap();
+        assert_eq!(ref_name(&caps), "my block");
+        assert_eq!(ref_count(&caps), Some(0));
+
+        let caps = REF_PATTERN.captures("<<helper>>").unwrap();
+        assert_eq!(ref_count(&caps), None);
+    }
+
+    #[test]
+    fn test_ref_pattern_with_spaces() {
+        let caps = REF_PATTERN.captures("  <<my block name>>").unwrap();
+        assert_eq!(&caps["indent"], "  ");
+        assert_eq!(ref_name(&caps), "my block name");
+
+        let caps = REF_PATTERN.captures(r#"<<"my \"block\" name">>"#).unwrap();
+        assert_eq!(ref_name(&caps), r#"my "block" name"#);
+
+        let caps = REF_PATTERN.captures("<<größe>>").unwrap();
+        assert_eq!(ref_name(&caps), "größe");
+
+        // Only single spaces between words; no leading or trailing space
+        assert!(REF_PATTERN.captures("<< name>>").is_none());
+        assert!(REF_PATTERN.captures("<<a  b>>").is_none());
+        assert!(REF_PATTERN.captures(r#"<<"">>"#).is_none());
     }
 }
@@ -1,8 +1,11 @@
 //! Configuration loading and management.
 
 mod annotation_method;
+mod config_builder;
 mod config_data;
 mod config_update;
+mod expand;
+mod filedb_format;
 mod language;
 mod markers;
 mod namespace_default;
@@ -13,10 +16,18 @@ use std::path::{Path, PathBuf};
 
 pub use crate::style::Style;
 pub use annotation_method::AnnotationMethod;
+pub use config_builder::ConfigBuilder;
 pub use config_data::{Config, HooksConfig, WatchConfig};
 pub use config_update::ConfigUpdate;
-pub use language::{Comment, Language};
-pub use markers::{annotation_begin, annotation_end, Markers, ANNOTATION_PREFIX, REF_PATTERN};
+pub use expand::expand_path;
+pub use filedb_format::FileDbFormat;
+pub use language::{Comment, Language, LanguageUpdate};
+pub use markers::{
+    annotation_begin, annotation_compact, annotation_end, begin_source_file,
+    block_annotation_begin, block_annotation_compact, escape_reference, fingerprint, ref_count,
+    ref_name, strip_fingerprints, unescape_reference, AnnotationPatterns, Markers,
+    ANNOTATION_PREFIX, REF_PATTERN,
+};
 pub use namespace_default::NamespaceDefault;
 pub use templates::{builtin_languages, find_language};
 
@@ -46,10 +57,15 @@ pub fn find_config_file(start_dir: &Path) -> Option<PathBuf> {
 }
 
 /// Reads configuration from a TOML file.
+///
+/// Custom `markers` are validated, so a configuration whose annotations could
+/// not be read back by stitch is rejected here.
 pub fn read_config_file(path: &Path) -> Result<Config> {
     let content = fs::read_to_string(path)?;
     let update: ConfigUpdate = toml::from_str(&content)?;
-    Ok(update.merge_into(&Config::default()))
+    let config = update.merge_into(&Config::default());
+    config.markers.validate()?;
+    Ok(config)
 }
 
 /// Reads configuration, searching from the given directory.
@@ -128,6 +144,19 @@ annotation = "naked"
         assert_eq!(config.annotation, AnnotationMethod::Naked);
     }
 
+    #[test]
+    fn test_read_config_file_rejects_broken_markers() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("entangled.toml");
+        fs::write(&config_path, "[markers]\nclose = \"]\"\n").unwrap();
+
+        let err = read_config_file(&config_path).unwrap_err();
+        assert!(matches!(err, crate::errors::EntangledError::Config(_)));
+        assert!(err
+            .to_string()
+            .starts_with("Configuration error: Invalid markers"));
+    }
+
     #[test]
     fn test_read_config_default() {
         let dir = tempdir().unwrap();
@@ -1,5 +1,7 @@
 //! Namespace default configuration.
 
+use std::path::Path;
+
 use serde::{Deserialize, Deserializer, Serialize};
 
 /// How to handle default namespace for code blocks without explicit naming.
@@ -14,6 +16,10 @@ pub enum NamespaceDefault {
     /// No default namespace - all blocks are in global scope.
     /// Also known as "global" in Python Entangled.
     None,
+
+    /// Use the source path relative to the base directory, so that
+    /// same-named files in different directories do not collide.
+    Path,
 }
 
 impl<'de> Deserialize<'de> for NamespaceDefault {
@@ -25,8 +31,9 @@ impl<'de> Deserialize<'de> for NamespaceDefault {
         match s.to_lowercase().as_str() {
             "file" | "private" => Ok(NamespaceDefault::File),
             "none" | "global" => Ok(NamespaceDefault::None),
+            "path" => Ok(NamespaceDefault::Path),
             _ => Err(serde::de::Error::custom(format!(
-                "unknown namespace_default: '{}' (expected 'file', 'private', 'none', or 'global')",
+                "unknown namespace_default: '{}' (expected 'file', 'private', 'path', 'none', or 'global')",
                 s
             ))),
         }
@@ -34,10 +41,22 @@ impl<'de> Deserialize<'de> for NamespaceDefault {
 }
 
 impl NamespaceDefault {
-    /// Returns the namespace prefix for a given filename.
-    pub fn prefix_for(&self, filename: &str) -> Option<String> {
+    /// Returns the namespace prefix for a source path.
+    ///
+    /// `path` should be relative to the base directory, as returned by
+    /// [`Context::source_files`](crate::interface::Context::source_files).
+    /// Path prefixes always use `/` as the separator.
+    pub fn prefix_for(&self, path: &Path) -> Option<String> {
         match self {
-            NamespaceDefault::File => Some(filename.to_string()),
+            NamespaceDefault::File => path.file_name().and_then(|n| n.to_str()).map(String::from),
+            NamespaceDefault::Path => {
+                let parts: Option<Vec<&str>> = path
+                    .components()
+                    .filter(|c| !matches!(c, std::path::Component::CurDir))
+                    .map(|c| c.as_os_str().to_str())
+                    .collect();
+                parts.map(|p| p.join("/")).filter(|p| !p.is_empty())
+            }
             NamespaceDefault::None => None,
         }
     }
@@ -55,13 +74,29 @@ mod tests {
     #[test]
     fn test_prefix_for_file() {
         let ns = NamespaceDefault::File;
-        assert_eq!(ns.prefix_for("test.md"), Some("test.md".to_string()));
+        assert_eq!(
+            ns.prefix_for(Path::new("docs/test.md")),
+            Some("test.md".to_string())
+        );
+    }
+
+    #[test]
+    fn test_prefix_for_path() {
+        let ns = NamespaceDefault::Path;
+        assert_eq!(
+            ns.prefix_for(Path::new("docs/a/util.md")),
+            Some("docs/a/util.md".to_string())
+        );
+        assert_eq!(
+            ns.prefix_for(Path::new("./util.md")),
+            Some("util.md".to_string())
+        );
     }
 
     #[test]
     fn test_prefix_for_none() {
         let ns = NamespaceDefault::None;
-        assert_eq!(ns.prefix_for("test.md"), None);
+        assert_eq!(ns.prefix_for(Path::new("test.md")), None);
     }
 
     #[test]
@@ -79,5 +114,9 @@ mod tests {
 
         let global: NamespaceDefault = serde_json::from_str("\"global\"").unwrap();
         assert_eq!(global, NamespaceDefault::None);
+
+        let path: NamespaceDefault = serde_json::from_str("\"path\"").unwrap();
+        assert_eq!(path, NamespaceDefault::Path);
+        assert_eq!(serde_json::to_string(&path).unwrap(), "\"path\"");
     }
 }
@@ -8,12 +8,22 @@ use super::language::{Comment, Language};
 static BUILTIN_LANGUAGES: Lazy<Vec<Language>> = Lazy::new(|| {
     vec![
         // C-style languages
-        Language::new("c", Comment::line("//")).with_identifiers(vec!["h".to_string()]),
-        Language::new("cpp", Comment::line("//")).with_identifiers(vec![
-            "c++".to_string(),
-            "cxx".to_string(),
-            "hpp".to_string(),
-        ]),
+        Language::new("c", Comment::line("//"))
+            .with_identifiers(vec!["h".to_string()])
+            .with_line_directives(),
+        Language::new("cpp", Comment::line("//"))
+            .with_identifiers(vec![
+                "c++".to_string(),
+                "cxx".to_string(),
+                "hpp".to_string(),
+            ])
+            .with_line_directives(),
+        Language::new("cuda", Comment::line("//"))
+            .with_identifiers(vec!["cu".to_string()])
+            .with_line_directives(),
+        Language::new("objc", Comment::line("//"))
+            .with_identifiers(vec!["objective-c".to_string()])
+            .with_line_directives(),
         Language::new("java", Comment::line("//")),
         Language::new("javascript", Comment::line("//")).with_identifiers(vec!["js".to_string()]),
         Language::new("typescript", Comment::line("//")).with_identifiers(vec!["ts".to_string()]),
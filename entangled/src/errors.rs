@@ -3,7 +3,7 @@
 use std::path::PathBuf;
 use thiserror::Error;
 
-use crate::model::ReferenceName;
+use crate::model::{ReferenceId, ReferenceName};
 use crate::text_location::TextLocation;
 
 /// Main error type for Entangled operations.
@@ -30,6 +30,13 @@ pub enum EntangledError {
         message: String,
     },
 
+    #[error("Invalid code block header at {location}: {message}")]
+    InvalidHeader {
+        location: TextLocation,
+        line: String,
+        message: String,
+    },
+
     #[error("Reference not found: {0}")]
     ReferenceNotFound(ReferenceName),
 
@@ -39,6 +46,20 @@ pub enum EntangledError {
     #[error("Duplicate reference: {0}")]
     DuplicateReference(ReferenceName),
 
+    #[error("Block {name} is defined in multiple files: {first} and {second}")]
+    DuplicateBlock {
+        name: ReferenceName,
+        first: TextLocation,
+        second: TextLocation,
+    },
+
+    #[error("Duplicate target: {} is written by both {first} and {second}", path.display())]
+    DuplicateTarget {
+        path: PathBuf,
+        first: ReferenceName,
+        second: ReferenceName,
+    },
+
     #[error("Unknown language: {0}")]
     UnknownLanguage(String),
 
@@ -63,6 +84,35 @@ pub enum EntangledError {
     #[error("Watch error: {0}")]
     Watch(String),
 
+    #[error(
+        "Cannot stitch {}: blocks {first} (lines {}-{}) and {second} (lines {}-{}) overlap",
+        path.display(), first_lines.0, first_lines.1, second_lines.0, second_lines.1
+    )]
+    OverlappingStitch {
+        path: PathBuf,
+        first: ReferenceId,
+        first_lines: (usize, usize),
+        second: ReferenceId,
+        second_lines: (usize, usize),
+    },
+
+    #[error(
+        "File database version {found} is newer than this entangled supports ({supported}); upgrade entangled"
+    )]
+    UnsupportedFileDb { found: String, supported: String },
+
+    #[error("{} is not valid UTF-8 (invalid byte at offset {offset})", path.display())]
+    InvalidUtf8 {
+        path: PathBuf,
+        offset: usize,
+        /// Whether the content looks like a binary file rather than text in
+        /// another encoding.
+        binary: bool,
+    },
+
+    #[error("Hook command `{command}` failed: {status}")]
+    HookCommand { command: String, status: String },
+
     #[error("{0}")]
     Other(String),
 }
@@ -83,16 +133,50 @@ impl EntangledError {
             | Self::JsonParse(_)
             | Self::YamlParse(_)
             | Self::InvalidProperty(_)
+            | Self::InvalidHeader { .. }
             | Self::MissingProperty(_)
-            | Self::GlobPattern(_) => 2,
+            | Self::GlobPattern(_)
+            | Self::UnsupportedFileDb { .. }
+            | Self::InvalidUtf8 { .. } => 2,
             Self::Io(_) | Self::Watch(_) => 3,
             Self::ReferenceNotFound(_)
             | Self::CycleDetected(_)
             | Self::DuplicateReference(_)
+            | Self::DuplicateBlock { .. }
+            | Self::DuplicateTarget { .. }
             | Self::UnknownLanguage(_) => 4,
-            Self::Parse { .. } | Self::Transaction(_) | Self::Regex(_) | Self::Other(_) => 5,
+            Self::Parse { .. }
+            | Self::Transaction(_)
+            | Self::Regex(_)
+            | Self::HookCommand { .. }
+            | Self::OverlappingStitch { .. }
+            | Self::Other(_) => 5,
         }
     }
+
+    /// Renders the offending source line with a caret under the failing
+    /// position, in the style of rustc diagnostics.
+    ///
+    /// For [`InvalidHeader`](Self::InvalidHeader), `line` is the opening
+    /// fence and `location.column` the position the parser stopped at.
+    ///
+    /// Returns `None` for errors that carry no source line.
+    pub fn snippet(&self) -> Option<String> {
+        let Self::InvalidHeader { location, line, .. } = self else {
+            return None;
+        };
+        let number = location.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // Keep tabs so that the caret lines up with the source
+        let pad: String = line
+            .chars()
+            .take(location.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        Some(format!(
+            "{gutter}--> {location}\n{gutter} |\n{number} | {line}\n{gutter} | {pad}^"
+        ))
+    }
 }
 
 /// Result type alias for Entangled operations.
@@ -0,0 +1,105 @@
+//! Project-level shell command hooks.
+//!
+//! Unlike the [`Hook`](super::Hook) trait, which processes individual code
+//! blocks, command hooks run a shell command once after a successful
+//! tangle or sync (e.g. `post_sync = "cargo check"`).
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::errors::{EntangledError, Result};
+
+/// Runs a shell command in the given directory.
+///
+/// The command's stdout and stderr are inherited, so output is streamed to
+/// the terminal as it runs. A non-zero exit status is reported as
+/// [`EntangledError::HookCommand`].
+pub fn run_command(command: &str, dir: &Path) -> Result<()> {
+    tracing::info!("Running hook command: {}", command);
+
+    let status = shell(command).current_dir(dir).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(EntangledError::HookCommand {
+            command: command.to_string(),
+            status: status.to_string(),
+        })
+    }
+}
+
+/// Runs a shell command in the given directory with `input` on stdin and
+/// returns its stdout.
+///
+/// Stderr is inherited. A non-zero exit status is reported as
+/// [`EntangledError::HookCommand`].
+pub(crate) fn pipe_command(command: &str, dir: &Path, input: &str) -> Result<String> {
+    let mut child = shell(command)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Written from another thread so that a command filling its stdout pipe
+    // before reading all of stdin cannot deadlock
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    // A command may exit without reading its input; its status tells
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(EntangledError::HookCommand {
+            command: command.to_string(),
+            status: output.status.to_string(),
+        });
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| EntangledError::Other(format!("Output of `{}` is not UTF-8", command)))
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_run_command_in_dir() {
+        let dir = tempdir().unwrap();
+        run_command("touch ran.txt", dir.path()).unwrap();
+        assert!(dir.path().join("ran.txt").exists());
+    }
+
+    #[test]
+    fn test_run_command_failure() {
+        let dir = tempdir().unwrap();
+        let result = run_command("exit 3", dir.path());
+        assert!(matches!(result, Err(EntangledError::HookCommand { .. })));
+    }
+
+    #[test]
+    fn test_pipe_command() {
+        let dir = tempdir().unwrap();
+        let output = pipe_command("tr a-z A-Z", dir.path(), "hello\n").unwrap();
+        assert_eq!(output, "HELLO\n");
+
+        let result = pipe_command("cat; exit 2", dir.path(), "x");
+        assert!(matches!(result, Err(EntangledError::HookCommand { .. })));
+    }
+}
@@ -0,0 +1,185 @@
+//! Formatter hook piping tangled output through a shell command.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::config::{AnnotationMethod, Config};
+use crate::errors::Result;
+use crate::model::CodeBlock;
+
+use super::command::pipe_command;
+use super::{Hook, PostTangleResult, PreTangleResult};
+
+/// Hook that formats tangled files with a per-language command.
+///
+/// The assembled content is piped through the command configured for the
+/// language of the target's block (e.g. `rustfmt --emit stdout` or
+/// `black -`), and its stdout replaces the content.
+///
+/// Formatting changes the line layout, which breaks annotation markers and
+/// stitching, so only targets tangled without annotations (naked) are
+/// formatted. Annotated targets are written unformatted, with a warning.
+#[derive(Debug, Clone)]
+pub struct FormatHook {
+    commands: HashMap<String, String>,
+    config: Config,
+    base_dir: PathBuf,
+}
+
+impl FormatHook {
+    /// Creates a formatter hook for the commands in `config.hooks.format`,
+    /// run in `base_dir`.
+    pub fn new(config: &Config, base_dir: PathBuf) -> Self {
+        Self {
+            commands: config.hooks.format.clone(),
+            config: config.clone(),
+            base_dir,
+        }
+    }
+
+    /// Returns the command configured for a language identifier.
+    ///
+    /// A key matches the identifier itself or, for a known language, its
+    /// name or any of its other identifiers.
+    fn command_for(&self, language: &str) -> Option<&str> {
+        if let Some(command) = self.commands.get(language) {
+            return Some(command);
+        }
+        let lang = self.config.find_language(language)?;
+        self.commands
+            .iter()
+            .find(|(key, _)| lang.matches(key))
+            .map(|(_, command)| command.as_str())
+    }
+}
+
+impl Hook for FormatHook {
+    fn name(&self) -> &str {
+        "format"
+    }
+
+    /// Runs after the built-in hooks.
+    fn priority(&self) -> i32 {
+        100
+    }
+
+    fn pre_tangle(&self, _block: &CodeBlock) -> Result<Option<PreTangleResult>> {
+        Ok(None)
+    }
+
+    fn post_tangle(&self, content: &str, block: &CodeBlock) -> Result<Option<PostTangleResult>> {
+        let method = self.config.annotation_for(block.language.as_deref());
+        self.post_tangle_annotated(content, block, method)
+    }
+
+    fn post_tangle_annotated(
+        &self,
+        content: &str,
+        block: &CodeBlock,
+        method: AnnotationMethod,
+    ) -> Result<Option<PostTangleResult>> {
+        let (Some(target), Some(language)) = (block.target.as_ref(), block.language.as_deref())
+        else {
+            return Ok(None);
+        };
+        let Some(command) = self.command_for(language) else {
+            return Ok(None);
+        };
+        if method != AnnotationMethod::Naked {
+            tracing::warn!(
+                "Not formatting {}: formatting breaks annotations; tangle {} without them \
+                 (annotation = \"naked\" or no_annotation_languages)",
+                target.display(),
+                language
+            );
+            return Ok(None);
+        }
+
+        let formatted = pipe_command(command, &self.base_dir, content)?;
+        // Keep the final-newline convention of the unformatted content
+        let formatted = match formatted.strip_suffix('\n') {
+            Some(stripped) if !content.ends_with('\n') => stripped.to_string(),
+            _ => formatted,
+        };
+        Ok(Some(PostTangleResult {
+            prefix: None,
+            content: formatted,
+            suffix: None,
+        }))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+    use tempfile::tempdir;
+
+    fn config(format: &[(&str, &str)], annotation: AnnotationMethod) -> Config {
+        let mut config = Config {
+            annotation,
+            ..Default::default()
+        };
+        config.hooks.format = format
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        config
+    }
+
+    #[test]
+    fn test_formats_naked_target() {
+        let dir = tempdir().unwrap();
+        let config = config(&[("python", "tr a-z A-Z")], AnnotationMethod::Naked);
+        let hook = FormatHook::new(&config, dir.path().to_path_buf());
+        let block = test_utils::make_block_lang("test", "x = 1", "python")
+            .with_target(PathBuf::from("out.py"));
+
+        let result = hook.post_tangle("x = 1", &block).unwrap().unwrap();
+        assert_eq!(result.content, "X = 1");
+    }
+
+    #[test]
+    fn test_matches_language_identifiers() {
+        let dir = tempdir().unwrap();
+        let config = config(&[("python", "tr a-z A-Z")], AnnotationMethod::Naked);
+        let hook = FormatHook::new(&config, dir.path().to_path_buf());
+
+        assert_eq!(hook.command_for("python"), Some("tr a-z A-Z"));
+        assert_eq!(hook.command_for("py"), Some("tr a-z A-Z"));
+        assert_eq!(hook.command_for("rust"), None);
+    }
+
+    #[test]
+    fn test_skips_annotated_target() {
+        let dir = tempdir().unwrap();
+        let config = config(&[("python", "tr a-z A-Z")], AnnotationMethod::Standard);
+        let hook = FormatHook::new(&config, dir.path().to_path_buf());
+        let block = test_utils::make_block_lang("test", "x = 1", "python")
+            .with_target(PathBuf::from("out.py"));
+
+        assert!(hook.post_tangle("x = 1", &block).unwrap().is_none());
+
+        // The annotation actually used decides, as with `--no-annotate`
+        let result = hook
+            .post_tangle_annotated("x = 1", &block, AnnotationMethod::Naked)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.content, "X = 1");
+        assert!(hook
+            .post_tangle_annotated("x = 1", &block, AnnotationMethod::Standard)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_failing_command() {
+        let dir = tempdir().unwrap();
+        let config = config(&[("python", "exit 1")], AnnotationMethod::Naked);
+        let hook = FormatHook::new(&config, dir.path().to_path_buf());
+        let block = test_utils::make_block_lang("test", "x = 1", "python")
+            .with_target(PathBuf::from("out.py"));
+
+        assert!(hook.post_tangle("x = 1", &block).is_err());
+    }
+}
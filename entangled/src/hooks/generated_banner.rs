@@ -0,0 +1,164 @@
+//! "Generated file" warning banner hook.
+
+use std::path::PathBuf;
+
+use crate::config::Language;
+use crate::errors::Result;
+use crate::model::CodeBlock;
+
+use super::{comment_for_block, Hook, PostTangleResult, PreTangleResult};
+
+/// Default banner text; `{source}` is replaced by the originating markdown path.
+pub const DEFAULT_BANNER_TEMPLATE: &str =
+    "This file was generated by entangled from {source}; edits will be overwritten";
+
+/// Hook that prepends a "do not edit" banner to tangled files.
+///
+/// The banner is commented in the style of the block's language and names
+/// the markdown file the target was tangled from.
+#[derive(Debug, Clone)]
+pub struct GeneratedBannerHook {
+    template: String,
+    languages: Vec<Language>,
+    base_dir: Option<PathBuf>,
+}
+
+impl Default for GeneratedBannerHook {
+    fn default() -> Self {
+        Self {
+            template: DEFAULT_BANNER_TEMPLATE.to_string(),
+            languages: Vec::new(),
+            base_dir: None,
+        }
+    }
+}
+
+impl GeneratedBannerHook {
+    /// Creates a new banner hook with the default template.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the banner template. `{source}` is replaced by the source path.
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Sets custom language definitions consulted before the built-ins.
+    pub fn with_languages(mut self, languages: Vec<Language>) -> Self {
+        self.languages = languages;
+        self
+    }
+
+    /// Sets the directory source paths are shown relative to.
+    pub fn with_base_dir(mut self, base_dir: PathBuf) -> Self {
+        self.base_dir = Some(base_dir);
+        self
+    }
+
+    /// Renders the banner text for a block.
+    fn render(&self, block: &CodeBlock) -> String {
+        let source = match block.location.filename.as_deref() {
+            Some(path) => {
+                let relative = self
+                    .base_dir
+                    .as_deref()
+                    .and_then(|base| path.strip_prefix(base).ok())
+                    .unwrap_or(path);
+                relative.display().to_string()
+            }
+            None => "stdin".to_string(),
+        };
+        self.template.replace("{source}", &source)
+    }
+
+    /// Renders the banner for a block, commented in its language's style.
+    fn banner(&self, block: &CodeBlock) -> String {
+        let text = self.render(block);
+        comment_for_block(&self.languages, block).wrap_block(&text.lines().collect::<Vec<_>>())
+    }
+}
+
+impl Hook for GeneratedBannerHook {
+    fn name(&self) -> &str {
+        "generated_banner"
+    }
+
+    /// Runs after the shebang and SPDX license hooks.
+    fn priority(&self) -> i32 {
+        -10
+    }
+
+    fn pre_tangle(&self, _block: &CodeBlock) -> Result<Option<PreTangleResult>> {
+        Ok(None)
+    }
+
+    fn post_tangle(&self, content: &str, block: &CodeBlock) -> Result<Option<PostTangleResult>> {
+        if !block.has_target() {
+            return Ok(None);
+        }
+
+        Ok(Some(PostTangleResult {
+            prefix: Some(self.banner(block)),
+            content: content.to_string(),
+            suffix: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+    use crate::text_location::TextLocation;
+
+    fn block_from(source: &str, language: &str, target: &str) -> CodeBlock {
+        let mut block = test_utils::make_block_lang("test", "code", language)
+            .with_target(PathBuf::from(target));
+        block.location = TextLocation::file_line(PathBuf::from(source), 3);
+        block
+    }
+
+    #[test]
+    fn test_default_banner() {
+        let hook = GeneratedBannerHook::new().with_base_dir(PathBuf::from("/project"));
+        let block = block_from("/project/docs/guide.md", "python", "out.py");
+
+        let result = hook.post_tangle("code", &block).unwrap().unwrap();
+        assert_eq!(
+            result.prefix.as_deref(),
+            Some(
+                "# This file was generated by entangled from docs/guide.md; edits will be overwritten"
+            )
+        );
+    }
+
+    #[test]
+    fn test_custom_template_block_comment() {
+        let hook = GeneratedBannerHook::new().with_template("DO NOT EDIT ({source})");
+        let block = block_from("doc.md", "css", "style.css");
+
+        let result = hook.post_tangle("code", &block).unwrap().unwrap();
+        assert_eq!(result.prefix.as_deref(), Some("/* DO NOT EDIT (doc.md) */"));
+    }
+
+    #[test]
+    fn test_multiline_template_block_comment() {
+        let hook = GeneratedBannerHook::new().with_template("Generated from {source}\nDo not edit");
+        let block = block_from("doc.md", "css", "style.css");
+
+        let result = hook.post_tangle("code", &block).unwrap().unwrap();
+        assert_eq!(
+            result.prefix.as_deref(),
+            Some("/*\n * Generated from doc.md\n * Do not edit\n */")
+        );
+    }
+
+    #[test]
+    fn test_no_target() {
+        let hook = GeneratedBannerHook::new();
+        let block = test_utils::make_block("test", "code");
+        assert!(hook.post_tangle("code", &block).unwrap().is_none());
+    }
+}
@@ -1,28 +1,91 @@
 //! Hooks for extending Entangled functionality.
 
+mod command;
+mod format;
+mod generated_banner;
 mod shebang;
 mod spdx_license;
 
+pub use command::run_command;
+pub use format::FormatHook;
+pub use generated_banner::GeneratedBannerHook;
 pub use shebang::ShebangHook;
 pub use spdx_license::SpdxLicenseHook;
 
+use std::borrow::Cow;
+
+use crate::config::{AnnotationMethod, Comment, Language};
 use crate::errors::Result;
 use crate::model::CodeBlock;
 
+/// Resolves the comment style for a block's language.
+///
+/// Custom languages are consulted before the built-ins; unknown languages
+/// fall back to `#`.
+pub(crate) fn comment_for_block(languages: &[Language], block: &CodeBlock) -> Comment {
+    block
+        .language
+        .as_deref()
+        .and_then(|id| {
+            languages
+                .iter()
+                .find(|l| l.matches(id))
+                .cloned()
+                .or_else(|| crate::config::find_language(id))
+        })
+        .map(|l| l.comment)
+        .unwrap_or_else(|| Comment::line("#"))
+}
+
 /// A hook that can process code blocks.
 pub trait Hook: Send + Sync {
     /// Returns the name of this hook.
     fn name(&self) -> &str;
 
+    /// Returns the priority of this hook: hooks run in ascending priority,
+    /// so the prefix of a lower one comes first. Hooks of equal priority run
+    /// in the order they were added.
+    ///
+    /// The built-in hooks run before custom ones: shebang (-100), SPDX
+    /// license (-20), then generated banner (-10).
+    fn priority(&self) -> i32 {
+        0
+    }
+
     /// Processes a code block before tangling.
     ///
-    /// Returns modified content if the hook made changes.
+    /// Returns modified content if the hook made changes. The result is
+    /// what is tangled between the block's markers; the block passed to
+    /// `post_tangle` and `pre_stitch` keeps its markdown source.
     fn pre_tangle(&self, block: &CodeBlock) -> Result<Option<PreTangleResult>>;
 
     /// Processes tangled output before writing.
     ///
     /// Returns modified content and optional prefix/suffix.
     fn post_tangle(&self, content: &str, block: &CodeBlock) -> Result<Option<PostTangleResult>>;
+
+    /// Processes tangled output annotated with `method` rather than the
+    /// configured annotation method, as by `tangle --block --annotate`.
+    ///
+    /// The default implementation ignores `method` and calls
+    /// [`post_tangle`](Hook::post_tangle).
+    fn post_tangle_annotated(
+        &self,
+        content: &str,
+        block: &CodeBlock,
+        _method: AnnotationMethod,
+    ) -> Result<Option<PostTangleResult>> {
+        self.post_tangle(content, block)
+    }
+
+    /// Processes a block's content read from a tangled file before stitch
+    /// compares it with the markdown `block`.
+    ///
+    /// Returns modified content if the hook made changes, e.g. to strip
+    /// what its `post_tangle` added so that it is not stitched back.
+    fn pre_stitch(&self, _content: &str, _block: &CodeBlock) -> Result<Option<String>> {
+        Ok(None)
+    }
 }
 
 /// Result of pre-tangle hook processing.
@@ -67,9 +130,13 @@ impl HookRegistry {
         Self { hooks: Vec::new() }
     }
 
-    /// Adds a hook to the registry.
+    /// Adds a hook to the registry, after the hooks of lower or equal
+    /// priority.
     pub fn add<H: Hook + 'static>(&mut self, hook: H) {
-        self.hooks.push(Box::new(hook));
+        let index = self
+            .hooks
+            .partition_point(|h| h.priority() <= hook.priority());
+        self.hooks.insert(index, Box::new(hook));
     }
 
     /// Returns the number of registered hooks.
@@ -82,25 +149,45 @@ impl HookRegistry {
         self.hooks.is_empty()
     }
 
-    /// Runs all pre-tangle hooks on a block.
+    /// Runs all pre-tangle hooks on a block, in priority order. Each hook
+    /// sees the source left by the hooks before it, so the last result holds
+    /// the source to tangle.
     pub fn run_pre_tangle(&self, block: &CodeBlock) -> Result<Vec<PreTangleResult>> {
+        let mut current = Cow::Borrowed(block);
         let mut results = Vec::new();
         for hook in &self.hooks {
-            if let Some(result) = hook.pre_tangle(block)? {
+            if let Some(result) = hook.pre_tangle(&current)? {
+                current.to_mut().source = result.source.clone();
                 results.push(result);
             }
         }
         Ok(results)
     }
 
-    /// Runs all post-tangle hooks on content.
+    /// Runs all post-tangle hooks on content, in priority order, stacking
+    /// their prefixes and suffixes in the same order.
     pub fn run_post_tangle(&self, content: &str, block: &CodeBlock) -> Result<String> {
+        self.run_post_tangle_annotated(content, block, None)
+    }
+
+    /// Like [`run_post_tangle`](Self::run_post_tangle), for content
+    /// annotated with `method` in place of the configured method.
+    pub fn run_post_tangle_annotated(
+        &self,
+        content: &str,
+        block: &CodeBlock,
+        method: Option<AnnotationMethod>,
+    ) -> Result<String> {
         let mut current = content.to_string();
         let mut prefix_parts = Vec::new();
         let mut suffix_parts = Vec::new();
 
         for hook in &self.hooks {
-            if let Some(result) = hook.post_tangle(&current, block)? {
+            let result = match method {
+                Some(method) => hook.post_tangle_annotated(&current, block, method)?,
+                None => hook.post_tangle(&current, block)?,
+            };
+            if let Some(result) = result {
                 if let Some(p) = result.prefix {
                     prefix_parts.push(p);
                 }
@@ -124,6 +211,19 @@ impl HookRegistry {
 
         Ok(final_content)
     }
+
+    /// Runs all pre-stitch hooks on content, in reverse priority order, so
+    /// that the changes made by `run_pre_tangle` and `run_post_tangle` are
+    /// undone last to first.
+    pub fn run_pre_stitch(&self, content: &str, block: &CodeBlock) -> Result<String> {
+        let mut current = content.to_string();
+        for hook in self.hooks.iter().rev() {
+            if let Some(result) = hook.pre_stitch(&current, block)? {
+                current = result;
+            }
+        }
+        Ok(current)
+    }
 }
 
 #[cfg(test)]
@@ -133,6 +233,16 @@ mod tests {
 
     struct TestHook {
         prefix: String,
+        priority: i32,
+    }
+
+    impl TestHook {
+        fn new(prefix: &str) -> Self {
+            Self {
+                prefix: prefix.to_string(),
+                priority: 0,
+            }
+        }
     }
 
     impl Hook for TestHook {
@@ -140,8 +250,15 @@ mod tests {
             "test"
         }
 
-        fn pre_tangle(&self, _block: &CodeBlock) -> Result<Option<PreTangleResult>> {
-            Ok(None)
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+
+        fn pre_tangle(&self, block: &CodeBlock) -> Result<Option<PreTangleResult>> {
+            Ok(Some(PreTangleResult {
+                source: format!("{}\n{}", self.prefix, block.source),
+                metadata: Vec::new(),
+            }))
         }
 
         fn post_tangle(
@@ -155,6 +272,13 @@ mod tests {
                 suffix: None,
             }))
         }
+
+        fn pre_stitch(&self, content: &str, _block: &CodeBlock) -> Result<Option<String>> {
+            Ok(content
+                .strip_prefix(&self.prefix)
+                .and_then(|rest| rest.strip_prefix('\n'))
+                .map(str::to_string))
+        }
     }
 
     #[test]
@@ -162,18 +286,14 @@ mod tests {
         let mut registry = HookRegistry::new();
         assert!(registry.is_empty());
 
-        registry.add(TestHook {
-            prefix: "# Header".to_string(),
-        });
+        registry.add(TestHook::new("# Header"));
         assert_eq!(registry.len(), 1);
     }
 
     #[test]
     fn test_post_tangle() {
         let mut registry = HookRegistry::new();
-        registry.add(TestHook {
-            prefix: "#!/usr/bin/env python".to_string(),
-        });
+        registry.add(TestHook::new("#!/usr/bin/env python"));
 
         let block = test_utils::make_block("test", "code");
         let result = registry.run_post_tangle("print('hello')", &block).unwrap();
@@ -181,4 +301,42 @@ mod tests {
         assert!(result.starts_with("#!/usr/bin/env python\n"));
         assert!(result.contains("print('hello')"));
     }
+
+    #[test]
+    fn test_post_tangle_priority_order() {
+        let mut registry = HookRegistry::new();
+        registry.add(TestHook::new("# second"));
+        registry.add(TestHook {
+            priority: 5,
+            ..TestHook::new("# last")
+        });
+        registry.add(TestHook {
+            priority: -1,
+            ..TestHook::new("# first")
+        });
+        registry.add(TestHook::new("# third"));
+
+        let block = test_utils::make_block("test", "code");
+        let result = registry.run_post_tangle("code", &block).unwrap();
+        assert_eq!(result, "# first\n# second\n# third\n# last\ncode");
+    }
+
+    #[test]
+    fn test_pre_stitch_undoes_pre_tangle() {
+        let mut registry = HookRegistry::new();
+        registry.add(TestHook::new("# second"));
+        registry.add(TestHook {
+            priority: -1,
+            ..TestHook::new("# first")
+        });
+
+        let block = test_utils::make_block("test", "code");
+        let results = registry.run_pre_tangle(&block).unwrap();
+        // Each hook sees the source left by the one before it
+        let tangled = &results.last().unwrap().source;
+        assert_eq!(tangled, "# second\n# first\ncode");
+        assert_eq!(registry.run_pre_stitch(tangled, &block).unwrap(), "code");
+        // Content without the prefixes is left alone
+        assert_eq!(registry.run_pre_stitch("code", &block).unwrap(), "code");
+    }
 }
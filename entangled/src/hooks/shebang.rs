@@ -5,17 +5,60 @@ use crate::model::CodeBlock;
 
 use super::{Hook, PostTangleResult, PreTangleResult};
 
+/// Script extensions a shebang is inserted for by default.
+const DEFAULT_SCRIPT_EXTENSIONS: &[&str] = &[
+    "sh", "bash", "zsh", "fish", "py", "rb", "pl", "php", "lua", "js", "R", "r", "tcl", "awk",
+];
+
 /// Hook that extracts shebang lines from code blocks.
 ///
 /// If the first line of a code block is a shebang (`#!...`),
-/// it will be moved to the beginning of the tangled output.
-#[derive(Debug, Clone, Default)]
-pub struct ShebangHook;
+/// it will be moved to the beginning of the tangled output: `pre_tangle`
+/// takes it out of the block, `post_tangle` writes it on the first line and
+/// `pre_stitch` puts it back into the block's content.
+///
+/// The hook is idempotent: nothing is inserted when the assembled output
+/// already starts with a `#!` line. It only fires for targets without an
+/// extension or with one of the configured script extensions.
+#[derive(Debug, Clone)]
+pub struct ShebangHook {
+    extensions: Vec<String>,
+}
+
+impl Default for ShebangHook {
+    fn default() -> Self {
+        Self {
+            extensions: DEFAULT_SCRIPT_EXTENSIONS
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+        }
+    }
+}
 
 impl ShebangHook {
     /// Creates a new shebang hook.
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    /// Sets the target extensions (without leading dot) the hook applies to.
+    ///
+    /// Targets without an extension are always eligible.
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Returns true if the block's (primary) target file should receive a shebang.
+    fn applies_to(&self, block: &CodeBlock) -> bool {
+        match block.target.as_ref() {
+            Some(target) => match target.extension().and_then(|e| e.to_str()) {
+                Some(ext) => self.extensions.iter().any(|e| e == ext),
+                None => true,
+            },
+            None => false,
+        }
     }
 
     /// Extracts a shebang line from content.
@@ -40,7 +83,15 @@ impl Hook for ShebangHook {
         "shebang"
     }
 
+    /// Runs first, since the shebang must stay on the first line.
+    fn priority(&self) -> i32 {
+        -100
+    }
+
     fn pre_tangle(&self, block: &CodeBlock) -> Result<Option<PreTangleResult>> {
+        if !self.applies_to(block) {
+            return Ok(None);
+        }
         if let Some((shebang, rest)) = Self::extract_shebang(&block.source) {
             Ok(Some(PreTangleResult {
                 source: rest.to_string(),
@@ -52,19 +103,38 @@ impl Hook for ShebangHook {
     }
 
     fn post_tangle(&self, content: &str, block: &CodeBlock) -> Result<Option<PostTangleResult>> {
+        // Only add shebang for script-like file targets
+        if !self.applies_to(block) {
+            return Ok(None);
+        }
+        // Output already starts with a shebang (e.g. naked mode): don't stack another
+        if Self::extract_shebang(content).is_some() {
+            return Ok(None);
+        }
         // Check if the original block had a shebang
         if let Some((shebang, _)) = Self::extract_shebang(&block.source) {
-            // Only add shebang if this is a file target
-            if block.has_target() {
-                return Ok(Some(PostTangleResult {
-                    prefix: Some(shebang.to_string()),
-                    content: content.to_string(),
-                    suffix: None,
-                }));
-            }
+            return Ok(Some(PostTangleResult {
+                prefix: Some(shebang.to_string()),
+                content: content.to_string(),
+                suffix: None,
+            }));
         }
         Ok(None)
     }
+
+    /// Restores the block's shebang, taken out by `pre_tangle`, in front of
+    /// its content.
+    fn pre_stitch(&self, content: &str, block: &CodeBlock) -> Result<Option<String>> {
+        if !self.applies_to(block) {
+            return Ok(None);
+        }
+        match Self::extract_shebang(&block.source) {
+            Some((shebang, _)) if content.lines().next() != Some(shebang) => {
+                Ok(Some(format!("{}\n{}", shebang, content)))
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -90,7 +160,7 @@ mod tests {
     #[test]
     fn test_pre_tangle() {
         let hook = ShebangHook::new();
-        let block = test_utils::make_block("test", "#!/bin/bash\necho hello");
+        let block = test_utils::make_block_with_target("test", "#!/bin/bash\necho hello", "run.sh");
 
         let result = hook.pre_tangle(&block).unwrap().unwrap();
         assert_eq!(result.source, "echo hello");
@@ -98,6 +168,25 @@ mod tests {
             result.metadata[0],
             ("shebang".to_string(), "#!/bin/bash".to_string())
         );
+
+        // Blocks that get no shebang written keep theirs
+        let block = test_utils::make_block("test", "#!/bin/bash\necho hello");
+        assert!(hook.pre_tangle(&block).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pre_stitch_restores_shebang() {
+        let hook = ShebangHook::new();
+        let block = test_utils::make_block_with_target("test", "#!/bin/sh\necho hi", "run.sh");
+
+        assert_eq!(
+            hook.pre_stitch("echo bye", &block).unwrap().as_deref(),
+            Some("#!/bin/sh\necho bye")
+        );
+        assert!(hook
+            .pre_stitch("#!/bin/sh\necho hi", &block)
+            .unwrap()
+            .is_none());
     }
 
     #[test]
@@ -122,4 +211,48 @@ mod tests {
         let result = hook.post_tangle("print('hello')", &block).unwrap();
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_post_tangle_content_already_has_shebang() {
+        let hook = ShebangHook::new();
+        let block = test_utils::make_block_with_target(
+            "test",
+            "#!/usr/bin/env python3\nprint('hello')",
+            "script.py",
+        );
+
+        // Naked output keeps the block's own shebang on the first line
+        let content = "#!/usr/bin/env python3\nprint('hello')";
+        assert!(hook.post_tangle(content, &block).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_post_tangle_block_without_shebang() {
+        let hook = ShebangHook::new();
+        let block = test_utils::make_block_with_target("test", "print('hello')", "script.py");
+
+        assert!(hook
+            .post_tangle("print('hello')", &block)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_post_tangle_extension_filter() {
+        let block = test_utils::make_block_with_target("test", "#!/bin/sh\necho hi", "data.txt");
+        assert!(ShebangHook::new()
+            .post_tangle("echo hi", &block)
+            .unwrap()
+            .is_none());
+
+        let hook = ShebangHook::new().with_extensions(vec!["txt".to_string()]);
+        assert!(hook.post_tangle("echo hi", &block).unwrap().is_some());
+
+        // Targets without an extension are always eligible
+        let block = test_utils::make_block_with_target("test", "#!/bin/sh\necho hi", "bin/run");
+        assert!(ShebangHook::new()
+            .post_tangle("echo hi", &block)
+            .unwrap()
+            .is_some());
+    }
 }
@@ -3,10 +3,11 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
 
+use crate::config::Language;
 use crate::errors::Result;
 use crate::model::CodeBlock;
 
-use super::{Hook, PostTangleResult, PreTangleResult};
+use super::{comment_for_block, Hook, PostTangleResult, PreTangleResult};
 
 /// Pattern for SPDX license identifiers.
 static SPDX_PATTERN: Lazy<Regex> =
@@ -14,22 +15,61 @@ static SPDX_PATTERN: Lazy<Regex> =
 
 /// Hook that extracts SPDX license headers from code blocks.
 ///
-/// Recognizes SPDX-License-Identifier comments at the beginning of code blocks
-/// and ensures they appear at the top of tangled output files.
+/// Recognizes SPDX-License-Identifier comments at the beginning of code blocks,
+/// after a shebang if there is one, and moves them to the top of tangled
+/// output files; stitch puts them back into the block.
+///
+/// With a configured license identifier, blocks that carry no SPDX line of
+/// their own get a generated header, commented in the style of the block's
+/// language (falling back to `#` for unknown languages).
 #[derive(Debug, Clone, Default)]
-pub struct SpdxLicenseHook;
+pub struct SpdxLicenseHook {
+    license_id: Option<String>,
+    languages: Vec<Language>,
+}
 
 impl SpdxLicenseHook {
     /// Creates a new SPDX license hook.
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    /// Sets the license identifier (e.g. `MIT`) used to generate headers.
+    pub fn with_license_id(mut self, license_id: impl Into<String>) -> Self {
+        self.license_id = Some(license_id.into());
+        self
     }
 
-    /// Extracts SPDX license lines from the beginning of content.
+    /// Sets custom language definitions consulted before the built-ins.
+    pub fn with_languages(mut self, languages: Vec<Language>) -> Self {
+        self.languages = languages;
+        self
+    }
+
+    /// Splits a leading shebang line off `content`.
+    fn split_shebang(content: &str) -> (Option<&str>, &str) {
+        match content.split_once('\n') {
+            Some((first, rest)) if first.starts_with("#!") => (Some(first), rest),
+            None if content.starts_with("#!") => (Some(content), ""),
+            _ => (None, content),
+        }
+    }
+
+    /// Returns the SPDX header lines at the beginning of `content`, after a
+    /// shebang, together with the blank lines around them.
+    fn header_lines(content: &str) -> Vec<&str> {
+        let (_, body) = Self::split_shebang(content);
+        body.lines()
+            .take_while(|line| SPDX_PATTERN.is_match(line) || line.trim().is_empty())
+            .collect()
+    }
+
+    /// Extracts SPDX license lines from the beginning of content, after a
+    /// shebang.
     fn extract_spdx_lines(content: &str) -> Vec<String> {
         let mut spdx_lines = Vec::new();
 
-        for line in content.lines() {
+        for line in Self::split_shebang(content).1.lines() {
             if SPDX_PATTERN.is_match(line) {
                 spdx_lines.push(line.to_string());
             } else if !line.trim().is_empty() {
@@ -41,20 +81,15 @@ impl SpdxLicenseHook {
         spdx_lines
     }
 
-    /// Removes SPDX lines from the beginning of content.
+    /// Removes SPDX lines from the beginning of content, keeping a shebang.
     fn remove_spdx_prefix(content: &str) -> String {
-        let lines: Vec<&str> = content.lines().collect();
-        let mut skip = 0;
-
-        for line in &lines {
-            if SPDX_PATTERN.is_match(line) || line.trim().is_empty() {
-                skip += 1;
-            } else {
-                break;
-            }
+        let (shebang, body) = Self::split_shebang(content);
+        let skip = Self::header_lines(content).len();
+        let rest = body.lines().skip(skip).collect::<Vec<_>>().join("\n");
+        match shebang {
+            Some(shebang) => format!("{}\n{}", shebang, rest),
+            None => rest,
         }
-
-        lines[skip..].join("\n")
     }
 }
 
@@ -63,7 +98,15 @@ impl Hook for SpdxLicenseHook {
         "spdx_license"
     }
 
+    /// Runs after the shebang hook.
+    fn priority(&self) -> i32 {
+        -20
+    }
+
     fn pre_tangle(&self, block: &CodeBlock) -> Result<Option<PreTangleResult>> {
+        if !block.has_target() {
+            return Ok(None);
+        }
         let spdx_lines = Self::extract_spdx_lines(&block.source);
 
         if spdx_lines.is_empty() {
@@ -80,13 +123,19 @@ impl Hook for SpdxLicenseHook {
     }
 
     fn post_tangle(&self, content: &str, block: &CodeBlock) -> Result<Option<PostTangleResult>> {
-        let spdx_lines = Self::extract_spdx_lines(&block.source);
-
-        if spdx_lines.is_empty() || !block.has_target() {
+        if !block.has_target() {
             return Ok(None);
         }
 
-        let spdx_header = spdx_lines.join("\n");
+        let spdx_lines = Self::extract_spdx_lines(&block.source);
+        let spdx_header = if !spdx_lines.is_empty() {
+            spdx_lines.join("\n")
+        } else if let Some(ref id) = self.license_id {
+            comment_for_block(&self.languages, block)
+                .wrap_line(&format!("SPDX-License-Identifier: {}", id))
+        } else {
+            return Ok(None);
+        };
 
         Ok(Some(PostTangleResult {
             prefix: Some(spdx_header),
@@ -94,6 +143,23 @@ impl Hook for SpdxLicenseHook {
             suffix: None,
         }))
     }
+
+    /// Restores the SPDX header taken out of the block by `pre_tangle`,
+    /// after the content's shebang if it has one.
+    fn pre_stitch(&self, content: &str, block: &CodeBlock) -> Result<Option<String>> {
+        if !block.has_target() || Self::extract_spdx_lines(&block.source).is_empty() {
+            return Ok(None);
+        }
+        let (shebang, rest) = Self::split_shebang(content);
+        if !Self::extract_spdx_lines(rest).is_empty() {
+            return Ok(None);
+        }
+        let header = Self::header_lines(&block.source).join("\n");
+        Ok(Some(match shebang {
+            Some(shebang) => format!("{}\n{}\n{}", shebang, header, rest),
+            None => format!("{}\n{}", header, rest),
+        }))
+    }
 }
 
 #[cfg(test)]
@@ -143,11 +209,38 @@ mod tests {
             "test",
             "// SPDX-License-Identifier: MIT\n\nfn main() {}",
             "rust",
-        );
+        )
+        .with_target(PathBuf::from("main.rs"));
 
         let result = hook.pre_tangle(&block).unwrap().unwrap();
         assert!(!result.source.contains("SPDX"));
         assert!(result.metadata[0].1.contains("MIT"));
+
+        // Stitch puts the header back
+        assert_eq!(
+            hook.pre_stitch(&result.source, &block).unwrap().as_deref(),
+            Some(block.source.as_str())
+        );
+        assert!(hook.pre_stitch(&block.source, &block).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pre_tangle_after_shebang() {
+        let hook = SpdxLicenseHook::new();
+        let block = test_utils::make_block_with_target(
+            "test",
+            "#!/bin/sh\n# SPDX-License-Identifier: MIT\necho hi",
+            "run.sh",
+        );
+
+        let result = hook.pre_tangle(&block).unwrap().unwrap();
+        assert_eq!(result.source, "#!/bin/sh\necho hi");
+        assert_eq!(
+            hook.pre_stitch("#!/bin/sh\necho bye", &block)
+                .unwrap()
+                .as_deref(),
+            Some("#!/bin/sh\n# SPDX-License-Identifier: MIT\necho bye")
+        );
     }
 
     #[test]
@@ -179,4 +272,43 @@ mod tests {
         let result = hook.post_tangle("fn main() {}", &block).unwrap();
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_post_tangle_configured_license_id() {
+        let hook = SpdxLicenseHook::new().with_license_id("Apache-2.0");
+        let block = test_utils::make_block_lang("test", "fn main() {}", "rust")
+            .with_target(PathBuf::from("lib.rs"));
+
+        let result = hook.post_tangle("fn main() {}", &block).unwrap().unwrap();
+        assert_eq!(
+            result.prefix.as_deref(),
+            Some("// SPDX-License-Identifier: Apache-2.0")
+        );
+    }
+
+    #[test]
+    fn test_post_tangle_configured_license_block_comment() {
+        let hook = SpdxLicenseHook::new().with_license_id("MIT");
+        let block = test_utils::make_block_lang("test", "body {}", "css")
+            .with_target(PathBuf::from("style.css"));
+
+        let result = hook.post_tangle("body {}", &block).unwrap().unwrap();
+        assert_eq!(
+            result.prefix.as_deref(),
+            Some("/* SPDX-License-Identifier: MIT */")
+        );
+    }
+
+    #[test]
+    fn test_post_tangle_configured_license_unknown_language() {
+        let hook = SpdxLicenseHook::new().with_license_id("MIT");
+        let block = test_utils::make_block_lang("test", "stuff", "nosuchlang")
+            .with_target(PathBuf::from("out.txt"));
+
+        let result = hook.post_tangle("stuff", &block).unwrap().unwrap();
+        assert_eq!(
+            result.prefix.as_deref(),
+            Some("# SPDX-License-Identifier: MIT")
+        );
+    }
 }
@@ -1,11 +1,31 @@
 //! Execution context for Entangled operations.
 
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 
-use crate::config::Config;
-use crate::hooks::{HookRegistry, ShebangHook, SpdxLicenseHook};
-use crate::io::{FileCache, FileDB, RealFileCache};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::{strip_fingerprints, AnnotationMethod, Config, FileDbFormat};
+use crate::errors::{EntangledError, Result};
+use crate::hooks::{FormatHook, GeneratedBannerHook, HookRegistry, ShebangHook, SpdxLicenseHook};
+use crate::io::{
+    escapes_directory, hexdigest_str, normalize_separators, BlockRecord, DocumentCache,
+    DocumentRecord, FileCache, FileDB, FileLock, Journal, RealFileCache, Stat, Transaction,
+};
+use crate::model::{CodeBlock, ReferenceId, ReferenceMap, ReferenceName};
+use crate::readers::{parse_document, ParsedDocument};
+
+use super::document::{collect_refs, tangle_name, tangle_target_content};
+
+/// Number of leading bytes searched for a NUL byte when deciding whether a
+/// file that is not valid UTF-8 is binary.
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// How long after a file's modification time its stat is trusted to identify
+/// its content. Filesystem timestamps are coarse (two seconds on FAT), so a
+/// file written again within this window may keep the same mtime.
+const RACY_MTIME_WINDOW_SECS: i64 = 2;
 
 /// Context for Entangled operations.
 ///
@@ -20,20 +40,167 @@ pub struct Context {
     pub file_cache: Arc<dyn FileCache>,
     /// File database for tracking tangled files.
     pub filedb: FileDB,
+    /// Parsed documents recorded by the last full tangle, saved next to the
+    /// file database.
+    pub document_cache: DocumentCache,
     /// Base directory for operations.
     pub base_dir: PathBuf,
     /// Path to the file database.
     pub filedb_path: PathBuf,
+    /// Resolved backup directory, if `backup_dir` is set.
+    backup_dir: Option<PathBuf>,
+    /// Lock on the file database, held for the lifetime of the context.
+    lock: Option<FileLock>,
+    /// Parsed documents, keyed by path, with the hash of the content and
+    /// configuration they were parsed with.
+    parse_cache: Mutex<HashMap<PathBuf, (String, ParsedDocument)>>,
+    /// Documents returned by [`Context::document_at`], keyed by resolved path.
+    documents: RwLock<HashMap<PathBuf, CachedDocument>>,
+    /// Files read during the current operation; see [`Context::with_read_cache`].
+    read_cache: Mutex<ReadCache>,
+    /// Documents skipped for failing to parse, with `keep_going` enabled.
+    failed_documents: Mutex<BTreeMap<PathBuf, EntangledError>>,
+    /// Documents skipped for not being valid UTF-8.
+    skipped_documents: Mutex<BTreeSet<PathBuf>>,
+}
+
+/// File contents cached for the duration of an operation.
+#[derive(Debug, Default)]
+struct ReadCache {
+    /// Number of nested [`Context::with_read_cache`] scopes currently open.
+    depth: usize,
+    /// Contents by resolved path.
+    files: HashMap<PathBuf, Arc<str>>,
+}
+
+/// A parsed document with the stat and configuration it was parsed with.
+#[derive(Debug)]
+struct CachedDocument {
+    stat: Stat,
+    config: String,
+    parsed_at: DateTime<Utc>,
+    parsed: Arc<ParsedDocument>,
+}
+
+impl CachedDocument {
+    /// Returns true if a file with `stat` can be assumed to still have the
+    /// content this entry was parsed from.
+    ///
+    /// Only files last modified well before they were parsed are trusted;
+    /// for a recent mtime a later write may not have changed it.
+    fn is_fresh(&self, stat: &Stat, config: &str) -> bool {
+        self.stat == *stat
+            && self.config == config
+            && stat.mtime + Duration::seconds(RACY_MTIME_WINDOW_SECS) < self.parsed_at
+    }
+}
+
+/// Converts a code block to its document cache record.
+fn block_record(block: &CodeBlock) -> BlockRecord {
+    BlockRecord {
+        id: block.id.to_string(),
+        language: block.language.clone(),
+        classes: block.classes.clone(),
+        target: block.target.clone(),
+        extra_targets: block.extra_targets.clone(),
+        source: block.source.clone(),
+        location: block.location.clone(),
+        attributes: block.attributes.clone(),
+        skip: block.skip,
+        namespace: block.namespace.clone(),
+    }
+}
+
+/// Rebuilds the document at `path` from its document cache record, or
+/// `None` if the record is not readable.
+fn document_from_record(record: &DocumentRecord, path: &Path) -> Option<ParsedDocument> {
+    let mut refs = ReferenceMap::new();
+    for block in &record.blocks {
+        let id = ReferenceId::parse(&block.id)?;
+        refs.insert_with_id(
+            id.clone(),
+            CodeBlock {
+                id,
+                language: block.language.clone(),
+                classes: block.classes.clone(),
+                target: block.target.clone(),
+                extra_targets: block.extra_targets.clone(),
+                source: block.source.clone(),
+                location: block.location.clone(),
+                attributes: block.attributes.clone(),
+                skip: block.skip,
+                namespace: block.namespace.clone(),
+            },
+        );
+    }
+    let mut parsed = ParsedDocument::new().with_source_path(path.to_path_buf());
+    parsed.refs = refs;
+    parsed.frontmatter = record.frontmatter.clone();
+    Some(parsed)
+}
+
+/// Closes a read cache scope when dropped, even on panic.
+struct ReadScope<'a>(&'a Context);
+
+impl Drop for ReadScope<'_> {
+    fn drop(&mut self) {
+        let mut cache = self.0.read_cache();
+        cache.depth -= 1;
+        if cache.depth == 0 {
+            cache.files.clear();
+        }
+    }
 }
 
 impl Context {
     /// Creates a new context with the given configuration.
+    ///
+    /// Takes the lock on the file database (`<filedb_path>.lock`) until the
+    /// context is dropped, and fails if another context holds it, so that
+    /// concurrent runs cannot overwrite each other's database.
     pub fn new(config: Config, base_dir: PathBuf) -> std::io::Result<Self> {
-        let filedb_path = base_dir.join(&config.filedb_path);
-        let filedb = match FileDB::load(&filedb_path) {
+        let mut ctx = Self::new_unlocked(config, base_dir)?;
+        ctx.lock = Some(FileLock::acquire(FileLock::path_for(&ctx.filedb_path))?);
+        Ok(ctx)
+    }
+
+    /// Creates a new context without locking the file database.
+    ///
+    /// Meant for read-only work that can run alongside another process;
+    /// saving the database from such a context may lose that process's
+    /// changes.
+    pub fn new_unlocked(config: Config, base_dir: PathBuf) -> std::io::Result<Self> {
+        let file_cache = Arc::new(RealFileCache::new(base_dir.clone()));
+        Self::with_file_cache(config, base_dir, file_cache)
+    }
+
+    /// Creates a context that reads and writes all files through `file_cache`.
+    ///
+    /// Passing a [`VirtualFS`](crate::io::VirtualFS) runs every operation,
+    /// including the file database, entirely in memory.
+    pub fn with_file_cache(
+        config: Config,
+        base_dir: PathBuf,
+        file_cache: Arc<dyn FileCache>,
+    ) -> std::io::Result<Self> {
+        let (filedb_path, backup_dir) = resolve_config_paths(&config, &base_dir)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+        let filedb = match load_filedb(
+            file_cache.as_ref(),
+            &filedb_path,
+            config.filedb_format,
+            &base_dir,
+        ) {
             Ok(db) => db,
+            // Resetting would lose the newer database on the next save
+            Err(e @ EntangledError::UnsupportedFileDb { .. }) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    e.to_string(),
+                ));
+            }
             Err(e) => {
-                if filedb_path.exists() {
+                if file_cache.exists(&filedb_path) {
                     // File exists but failed to parse -- warn about data loss
                     tracing::warn!(
                         "Failed to load file database at {}: {}. Starting with empty database.",
@@ -41,26 +208,28 @@ impl Context {
                         e
                     );
                 }
-                FileDB::default()
+                FileDB::new().with_base_dir(base_dir.clone())
             }
         };
-        let file_cache = Arc::new(RealFileCache::new(base_dir.clone()));
-
-        let mut hooks = HookRegistry::new();
-        if config.hooks.shebang {
-            hooks.add(ShebangHook::new());
-        }
-        if config.hooks.spdx_license {
-            hooks.add(SpdxLicenseHook::new());
-        }
+        let document_cache =
+            DocumentCache::load(file_cache.as_ref(), &DocumentCache::path_for(&filedb_path));
+        let hooks = hooks_from_config(&config, &base_dir);
 
         Ok(Self {
             config,
             hooks,
             file_cache,
             filedb,
+            document_cache,
             base_dir,
             filedb_path,
+            backup_dir,
+            lock: None,
+            parse_cache: Mutex::new(HashMap::new()),
+            documents: RwLock::new(HashMap::new()),
+            read_cache: Mutex::new(ReadCache::default()),
+            failed_documents: Mutex::new(BTreeMap::new()),
+            skipped_documents: Mutex::new(BTreeSet::new()),
         })
     }
 
@@ -76,21 +245,271 @@ impl Context {
         Self::new(config, base_dir)
     }
 
+    /// Replaces the configuration, rebuilding everything derived from it.
+    ///
+    /// The hook registry is recreated from the new config (hooks added with
+    /// [`add_hook`](Self::add_hook) are dropped) and the parse cache is
+    /// cleared. The file database is kept, but saved to the new
+    /// `filedb_path` from now on.
+    ///
+    /// If the context holds the file database lock and `filedb_path`
+    /// changes, the lock moves to the new database.
+    ///
+    /// If a configured path refers to an unset environment variable, or the
+    /// new database is locked, the error is returned and the context is left
+    /// unchanged.
+    pub fn set_config(&mut self, config: Config) -> Result<()> {
+        let (filedb_path, backup_dir) = resolve_config_paths(&config, &self.base_dir)?;
+        if self.lock.is_some() && filedb_path != self.filedb_path {
+            self.lock = Some(FileLock::acquire(FileLock::path_for(&filedb_path))?);
+        }
+        self.hooks = hooks_from_config(&config, &self.base_dir);
+        self.filedb_path = filedb_path;
+        self.backup_dir = backup_dir;
+        self.config = config;
+        self.forget_parsed_documents();
+        Ok(())
+    }
+
+    /// Drops every cached parse result, including the [document
+    /// cache](Self::document_cache), so that each source is parsed again.
+    pub fn forget_parsed_documents(&mut self) {
+        self.parse_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+        self.documents
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+        self.document_cache.clear();
+    }
+
     /// Adds a hook to the registry.
     pub fn add_hook<H: crate::hooks::Hook + 'static>(&mut self, hook: H) {
         self.hooks.add(hook);
     }
 
-    /// Saves the file database.
-    pub fn save_filedb(&self) -> crate::errors::Result<()> {
-        self.filedb.save(&self.filedb_path)
+    /// Saves the file database in the configured `filedb_format`, and the
+    /// document cache next to it.
+    ///
+    /// An empty document cache is only written to replace an existing one.
+    pub fn save_filedb(&self) -> Result<()> {
+        let content = self.filedb.encode(self.config.filedb_format)?;
+        self.file_cache
+            .write_with(&self.filedb_path, &mut |w| w.write_all(&content))?;
+        let cache_path = self.document_cache_path();
+        if !self.document_cache.is_empty() || self.file_cache.exists(&cache_path) {
+            self.document_cache
+                .save(self.file_cache.as_ref(), &cache_path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the path of the document cache, next to the file database.
+    pub fn document_cache_path(&self) -> PathBuf {
+        DocumentCache::path_for(&self.filedb_path)
+    }
+
+    /// Executes a transaction against this context's file cache and
+    /// records the result in the file database.
+    ///
+    /// Conflicts are checked first unless `force` is set. With marker
+    /// fingerprints, a tracked file that differs from its new content only
+    /// in fingerprints is not a conflict, as described in
+    /// [`accept_stale_fingerprints`](Self::accept_stale_fingerprints).
+    pub fn execute(&mut self, transaction: &Transaction, force: bool) -> Result<()> {
+        // Files written by the transaction must not be served stale
+        self.read_cache().files.clear();
+        if self.config.markers.fingerprints {
+            self.accept_stale_fingerprints(transaction)?;
+        }
+        transaction.execute_in(self.file_cache.as_ref(), &mut self.filedb, force)
+    }
+
+    /// Records as unmodified every tracked file that `transaction` writes
+    /// and that differs from the content written only in marker
+    /// fingerprints.
+    ///
+    /// Once its edits are stitched, a target still carries the fingerprints
+    /// of the old sources, so it never equals its fresh tangle; without this,
+    /// every stitched edit would make the next tangle a conflict. Edits that
+    /// were not stitched still conflict. Streamed writes are not checked.
+    fn accept_stale_fingerprints(&mut self, transaction: &Transaction) -> Result<()> {
+        for action in transaction.actions() {
+            let Some(proposed) = action.proposed_content() else {
+                continue;
+            };
+            let path = action.target();
+            if !self.file_cache.exists(path) || !self.filedb.is_tracked(path) {
+                continue;
+            }
+            let current = self.file_cache.file_data(path)?;
+            if !self.filedb.is_modified(path, &current) {
+                continue;
+            }
+            let content = self.file_cache.read(path)?;
+            if strip_fingerprints(&content) == strip_fingerprints(proposed) {
+                self.filedb.record(path.to_path_buf(), current);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `f` with file reads cached, so that [`read_file`](Self::read_file)
+    /// reads each file at most once until `f` returns.
+    ///
+    /// Scopes nest; the cache is dropped when the outermost one ends. Files
+    /// written inside the scope other than through [`execute`](Self::execute)
+    /// are not seen by later cached reads, so keep the scope to read-only work.
+    pub fn with_read_cache<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.read_cache().depth += 1;
+        let _scope = ReadScope(self);
+        f()
+    }
+
+    /// Reads a file through the file cache, reusing its content if it was
+    /// already read inside the current [`with_read_cache`](Self::with_read_cache)
+    /// scope.
+    ///
+    /// Content that is not valid UTF-8 is an
+    /// [`InvalidUtf8`](EntangledError::InvalidUtf8) error naming the file and
+    /// the offset of the first invalid byte.
+    pub fn read_file(&self, path: &Path) -> Result<Arc<str>> {
+        let key = self.resolve_path(path);
+        if let Some(content) = self.read_cache().files.get(&key) {
+            return Ok(Arc::clone(content));
+        }
+
+        let bytes = self.file_cache.read_bytes(path)?;
+        let content: Arc<str> = match String::from_utf8(bytes) {
+            Ok(text) => text.into(),
+            Err(e) => {
+                let bytes = e.as_bytes();
+                // A NUL byte near the start is the usual sign of a binary file
+                let binary = bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0);
+                return Err(EntangledError::InvalidUtf8 {
+                    path: path.to_path_buf(),
+                    offset: e.utf8_error().valid_up_to(),
+                    binary,
+                });
+            }
+        };
+        let mut cache = self.read_cache();
+        if cache.depth > 0 {
+            cache.files.insert(key, Arc::clone(&content));
+        }
+        Ok(content)
+    }
+
+    fn read_cache(&self) -> std::sync::MutexGuard<'_, ReadCache> {
+        self.read_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Records a document skipped for failing to parse.
+    pub(crate) fn record_failed_document(&self, path: &Path, error: EntangledError) {
+        self.failed_documents().insert(path.to_path_buf(), error);
+    }
+
+    /// Returns true if a document was skipped for failing to parse since the
+    /// last [`take_failed_documents`](Self::take_failed_documents).
+    pub fn has_failed_documents(&self) -> bool {
+        !self.failed_documents().is_empty()
+    }
+
+    /// Records a document skipped for not being valid UTF-8.
+    pub(crate) fn record_skipped_document(&self, path: &Path) {
+        self.skipped_documents().insert(path.to_path_buf());
+    }
+
+    /// Returns true if any document was left out since the last
+    /// [`take_failed_documents`](Self::take_failed_documents), whether for
+    /// failing to parse or for not being valid UTF-8.
+    ///
+    /// The targets of such a document are unknown, so a run that skipped one
+    /// must not prune targets or record the sources as tangled.
+    pub fn has_skipped_documents(&self) -> bool {
+        self.has_failed_documents() || !self.skipped_documents().is_empty()
+    }
+
+    /// Returns the documents skipped for failing to parse, with `keep_going`
+    /// enabled, sorted by path, and forgets them, along with the documents
+    /// skipped for not being valid UTF-8.
+    pub fn take_failed_documents(&self) -> Vec<(PathBuf, EntangledError)> {
+        self.skipped_documents().clear();
+        std::mem::take(&mut *self.failed_documents())
+            .into_iter()
+            .collect()
+    }
+
+    /// Fails with a summary of the documents skipped for failing to parse,
+    /// forgetting them, or does nothing if there are none.
+    ///
+    /// Call this at the end of a run with `keep_going` enabled, so that the
+    /// run still fails after processing the remaining documents.
+    pub fn check_failed_documents(&self) -> Result<()> {
+        let failed = self.take_failed_documents();
+        if failed.is_empty() {
+            return Ok(());
+        }
+        let mut summary = format!(
+            "{} document{} failed to parse:",
+            failed.len(),
+            if failed.len() == 1 { "" } else { "s" }
+        );
+        for (path, error) in &failed {
+            summary.push_str(&format!("\n  {}: {}", path.display(), error));
+        }
+        Err(EntangledError::Other(summary))
+    }
+
+    fn failed_documents(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, EntangledError>> {
+        self.failed_documents
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn skipped_documents(&self) -> std::sync::MutexGuard<'_, BTreeSet<PathBuf>> {
+        self.skipped_documents
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Creates an empty reference map that matches names and escapes
+    /// references as configured.
+    pub fn new_reference_map(&self) -> ReferenceMap {
+        let refs = ReferenceMap::new()
+            .with_case_insensitive_names(self.config.case_insensitive_names)
+            .with_reference_escape(self.config.reference_escape.as_str());
+        if self.config.file_includes {
+            refs.with_file_includes(Arc::clone(&self.file_cache), self.base_dir.clone())
+                .with_escaping_file_includes(self.config.allow_escaping_targets)
+        } else {
+            refs
+        }
     }
 
     /// Returns source file paths matching the configured patterns.
-    pub fn source_files(&self) -> crate::errors::Result<Vec<PathBuf>> {
+    ///
+    /// Files matching any of `exclude_patterns` are left out.
+    pub fn source_files(&self) -> Result<Vec<PathBuf>> {
+        let excludes = self
+            .config
+            .exclude_patterns
+            .iter()
+            .map(|p| glob::Pattern::new(p))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
         let mut files = Vec::new();
         for pattern in &self.config.source_patterns {
-            files.extend(self.file_cache.glob(pattern)?);
+            files.extend(
+                self.file_cache
+                    .glob(pattern)?
+                    .into_iter()
+                    .filter(|f| !excludes.iter().any(|ex| ex.matches_path(f))),
+            );
         }
         // Remove duplicates and sort
         files.sort();
@@ -103,7 +522,7 @@ impl Context {
     /// Each filter path is resolved relative to `base_dir` and compared
     /// against the full set of source files. Returns an error if any
     /// filter path does not match a known source file.
-    pub fn source_files_filtered(&self, filter: &[PathBuf]) -> crate::errors::Result<Vec<PathBuf>> {
+    pub fn source_files_filtered(&self, filter: &[PathBuf]) -> Result<Vec<PathBuf>> {
         let all_files = self.source_files()?;
         let resolved_filters: Vec<PathBuf> = filter
             .iter()
@@ -121,7 +540,7 @@ impl Context {
             if let Some(found) = all_files.iter().find(|f| *f == filter_path) {
                 result.push(found.clone());
             } else {
-                return Err(crate::errors::EntangledError::Config(format!(
+                return Err(EntangledError::Config(format!(
                     "File {} is not a source file (does not match source_patterns)",
                     filter_path.display()
                 )));
@@ -136,21 +555,52 @@ impl Context {
     /// Returns source files matching any of the given glob patterns.
     ///
     /// Only files that are both matched by a glob AND present in
-    /// `source_files()` are returned. Returns an error if a pattern
-    /// matches no source files.
-    pub fn source_files_glob(&self, patterns: &[String]) -> crate::errors::Result<Vec<PathBuf>> {
+    /// `source_files()` are returned. A pattern starting with `!` excludes
+    /// the files it matches instead; with only exclusions, they are taken
+    /// from all source files. Returns an error if a pattern matches no
+    /// source files.
+    pub fn source_files_glob(&self, patterns: &[String]) -> Result<Vec<PathBuf>> {
+        self.glob_source_files(patterns, true)
+    }
+
+    /// Like [`source_files_glob`](Self::source_files_glob), but a pattern
+    /// that matches no source files is not an error.
+    pub fn source_files_glob_opt(&self, patterns: &[String]) -> Result<Vec<PathBuf>> {
+        self.glob_source_files(patterns, false)
+    }
+
+    /// Expands include and `!` exclude patterns against the source files,
+    /// failing on a pattern that matches none if `strict` is set.
+    fn glob_source_files(&self, patterns: &[String], strict: bool) -> Result<Vec<PathBuf>> {
         let all_files = self.source_files()?;
-        let mut matched = Vec::new();
-        for pattern in patterns {
-            let expanded = self.file_cache.glob(pattern)?;
-            let before = matched.len();
-            matched.extend(expanded.into_iter().filter(|p| all_files.contains(p)));
-            if matched.len() == before {
-                return Err(crate::errors::EntangledError::Config(format!(
+        let expand = |pattern: &str| -> Result<Vec<PathBuf>> {
+            let expanded: Vec<PathBuf> = self
+                .file_cache
+                .glob(pattern.strip_prefix('!').unwrap_or(pattern))?
+                .into_iter()
+                .filter(|p| all_files.contains(p))
+                .collect();
+            if strict && expanded.is_empty() {
+                return Err(EntangledError::Config(format!(
                     "Glob pattern '{}' matched no source files",
                     pattern
                 )));
             }
+            Ok(expanded)
+        };
+
+        let (excludes, includes): (Vec<&String>, Vec<&String>) =
+            patterns.iter().partition(|p| p.starts_with('!'));
+        let mut matched = Vec::new();
+        if includes.is_empty() && !excludes.is_empty() {
+            matched.extend(all_files.iter().cloned());
+        }
+        for pattern in includes {
+            matched.extend(expand(pattern)?);
+        }
+        for pattern in excludes {
+            let excluded = expand(pattern)?;
+            matched.retain(|p| !excluded.contains(p));
         }
         matched.sort();
         matched.dedup();
@@ -165,11 +615,283 @@ impl Context {
             self.base_dir.join(path)
         }
     }
+
+    /// Parses a source document, reusing the previous result for `path` if
+    /// neither the content nor the configuration has changed since.
+    ///
+    /// Previous results come from this context or, across runs, from the
+    /// [document cache](Self::document_cache) written by the last full
+    /// tangle.
+    pub fn parse_cached(&self, path: &Path, content: &str) -> Result<ParsedDocument> {
+        let key = self.parse_key(content);
+        let mut cache = self
+            .parse_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((cached_key, parsed)) = cache.get(path) {
+            if *cached_key == key {
+                return Ok(parsed.clone());
+            }
+        }
+
+        let recorded = self
+            .document_cache
+            .get(path)
+            .filter(|record| record.key == key)
+            .and_then(|record| document_from_record(record, path));
+        let parsed = match recorded {
+            Some(parsed) => parsed,
+            None => parse_document(content, Some(path), &self.config)?,
+        };
+        cache.insert(path.to_path_buf(), (key, parsed.clone()));
+        Ok(parsed)
+    }
+
+    /// Parses the source document at `path` with `content` and returns it in
+    /// the form recorded in the document cache, or `None` if it fails to
+    /// parse.
+    pub(crate) fn document_record(&self, path: &Path, content: &str) -> Option<DocumentRecord> {
+        let parsed = self.parse_cached(path, content).ok()?;
+        Some(DocumentRecord {
+            key: self.parse_key(content),
+            frontmatter: parsed.frontmatter,
+            blocks: parsed.refs.blocks().map(block_record).collect(),
+        })
+    }
+
+    /// Returns the key parse results are cached under: a hash of the
+    /// configuration and the content.
+    fn parse_key(&self, content: &str) -> String {
+        hexdigest_str(&format!("{}\0{}", self.config.fingerprint(), content))
+    }
+
+    /// Returns the parsed source document at `path`, parsing it only if it
+    /// changed since the last call.
+    ///
+    /// Results are cached by path and checked against the file's mtime and
+    /// size, so an unchanged file is not read again. A file modified within
+    /// a couple of seconds of being parsed is re-read, and re-parsed only if
+    /// its content differs. The document is parsed as written: project-level
+    /// adjustments such as Quarto output directories are applied by
+    /// [`Document::load`](super::Document::load).
+    pub fn document_at(&self, path: &Path) -> Result<Arc<ParsedDocument>> {
+        let key = self.resolve_path(path);
+        let stat = self.file_cache.stat(path)?;
+        let config = self.config.fingerprint();
+        {
+            let documents = self
+                .documents
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some(cached) = documents.get(&key) {
+                if cached.is_fresh(&stat, &config) {
+                    return Ok(Arc::clone(&cached.parsed));
+                }
+            }
+        }
+
+        let content = self.read_file(path)?;
+        let parsed = Arc::new(self.parse_cached(path, &content)?);
+        self.documents
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(
+                key,
+                CachedDocument {
+                    stat,
+                    config,
+                    parsed_at: Utc::now(),
+                    parsed: Arc::clone(&parsed),
+                },
+            );
+        Ok(parsed)
+    }
+
+    /// Returns the journal of the last run, kept next to the file database.
+    pub fn journal_path(&self) -> PathBuf {
+        Journal::path_for(&self.filedb_path)
+    }
+
+    /// Creates an empty transaction, journaled to [`journal_path`](Self::journal_path),
+    /// with backups enabled if `backup_dir` is set. Executing and diffing it
+    /// go through the context's file cache.
+    pub fn new_transaction(&self) -> Transaction {
+        let transaction = Transaction::new()
+            .with_journal(self.journal_path())
+            .with_file_cache(Arc::clone(&self.file_cache));
+        match &self.backup_dir {
+            Some(dir) => transaction.with_backup_dir(dir, &self.base_dir),
+            None => transaction,
+        }
+    }
+
+    /// Creates a transaction restoring the files changed by the last run to
+    /// their content from before it.
+    ///
+    /// Returns `None` if no run has been journaled. Executing the result
+    /// journals it in turn, so undoing twice redoes the run.
+    pub fn undo_transaction(&self) -> Result<Option<Transaction>> {
+        let Some(journal) = Journal::load(self.file_cache.as_ref(), &self.journal_path())? else {
+            return Ok(None);
+        };
+        let mut transaction = self.new_transaction();
+        journal.restore_into(self.file_cache.as_ref(), &mut transaction);
+        Ok(Some(transaction))
+    }
+
+    /// Resolves a tangle target path, refusing paths outside `base_dir`.
+    ///
+    /// Absolute targets and targets whose `..` components escape the project
+    /// directory are rejected unless `allow_escaping_targets` is set. The
+    /// configured `output_dir` may be anywhere, so only the part of a target
+    /// below it is checked. The check is purely lexical and does not touch
+    /// the filesystem.
+    pub fn resolve_target(&self, path: &Path) -> Result<PathBuf> {
+        if !self.config.allow_escaping_targets {
+            let output_dir = self.config.expanded_output_dir()?;
+            let path = output_dir
+                .and_then(|dir| path.strip_prefix(normalize_separators(&dir)).ok())
+                .unwrap_or(path);
+            if escapes_directory(path) {
+                return Err(EntangledError::InvalidProperty(format!(
+                    "file={} points outside the project directory (set allow_escaping_targets to permit)",
+                    path.display()
+                )));
+            }
+        }
+        Ok(self.resolve_path(path))
+    }
+
+    /// Runs the configured `post_tangle` command, if any, in `base_dir`.
+    pub fn run_post_tangle_command(&self) -> Result<()> {
+        match &self.config.hooks.post_tangle {
+            Some(command) => crate::hooks::run_command(command, &self.base_dir),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the configured `post_sync` command, if any, in `base_dir`.
+    pub fn run_post_sync_command(&self) -> Result<()> {
+        match &self.config.hooks.post_sync {
+            Some(command) => crate::hooks::run_command(command, &self.base_dir),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the tangled content for a target file without touching disk.
+    ///
+    /// The path may be given as written in the `file=` attribute or as an
+    /// absolute path inside `base_dir`. Post-tangle hooks are applied.
+    pub fn tangle_target(&self, path: &Path) -> Result<String> {
+        self.with_read_cache(|| {
+            let refs = collect_refs(self, &self.source_files()?)?;
+            let relative = path.strip_prefix(&self.base_dir).unwrap_or(path);
+            let name = refs
+                .get_target_name(relative)
+                .or_else(|| refs.get_target_name(path))
+                .ok_or_else(|| {
+                    EntangledError::ReferenceNotFound(ReferenceName::from_file_path(
+                        &path.to_string_lossy(),
+                    ))
+                })?;
+            let target = if refs.get_target_name(relative).is_some() {
+                relative
+            } else {
+                path
+            };
+            tangle_target_content(self, &refs, target, name)
+        })
+    }
+
+    /// Returns the fully-expanded content of a named reference.
+    ///
+    /// A name without a namespace also finds a block namespaced by its file,
+    /// as described in [`ReferenceMap::find_name`]. Annotation and hooks
+    /// follow the same rules as a file target.
+    pub fn tangle_block(&self, name: &ReferenceName) -> Result<String> {
+        self.tangle_block_with(name, None)
+    }
+
+    /// Like [`tangle_block`](Self::tangle_block), annotating with `method`
+    /// instead of the configured annotation method when one is given.
+    pub fn tangle_block_with(
+        &self,
+        name: &ReferenceName,
+        method: Option<AnnotationMethod>,
+    ) -> Result<String> {
+        self.with_read_cache(|| {
+            let refs = collect_refs(self, &self.source_files()?)?;
+            let name = refs.find_name(name.as_str())?;
+            if !refs.is_defined(&name) {
+                return Err(EntangledError::ReferenceNotFound(name));
+            }
+            tangle_name(self, &refs, &name, method)
+        })
+    }
+}
+
+/// Reads the file database through `file_cache`, or starts an empty one,
+/// with its paths relative to `base_dir`.
+fn load_filedb(
+    file_cache: &dyn FileCache,
+    path: &Path,
+    format: FileDbFormat,
+    base_dir: &Path,
+) -> Result<FileDB> {
+    if !file_cache.exists(path) {
+        return Ok(FileDB::new().with_base_dir(base_dir.to_path_buf()));
+    }
+    let mut db = FileDB::decode(&file_cache.read_bytes(path)?, format)?
+        .with_base_dir(base_dir.to_path_buf());
+    if db.needs_restat() {
+        db.restat(file_cache);
+    }
+    Ok(db)
+}
+
+/// Expands the paths in `config` and resolves them against `base_dir`.
+///
+/// Returns the file database path and backup directory. `output_dir` is
+/// expanded too, so that an unset variable in it is reported up front.
+fn resolve_config_paths(config: &Config, base_dir: &Path) -> Result<(PathBuf, Option<PathBuf>)> {
+    config.expanded_output_dir()?;
+    let filedb_path = base_dir.join(config.expanded_filedb_path()?);
+    let backup_dir = config.expanded_backup_dir()?.map(|dir| base_dir.join(dir));
+    Ok((filedb_path, backup_dir))
+}
+
+/// Builds the hook registry for the hooks enabled in `config`.
+fn hooks_from_config(config: &Config, base_dir: &Path) -> HookRegistry {
+    let mut hooks = HookRegistry::new();
+    if config.hooks.shebang {
+        hooks.add(ShebangHook::new());
+    }
+    if config.hooks.spdx_license {
+        let mut hook = SpdxLicenseHook::new().with_languages(config.languages.clone());
+        if let Some(ref id) = config.hooks.spdx_license_id {
+            hook = hook.with_license_id(id.clone());
+        }
+        hooks.add(hook);
+    }
+    if config.hooks.generated_banner {
+        let mut hook = GeneratedBannerHook::new()
+            .with_languages(config.languages.clone())
+            .with_base_dir(base_dir.to_path_buf());
+        if let Some(ref template) = config.hooks.generated_banner_template {
+            hook = hook.with_template(template.clone());
+        }
+        hooks.add(hook);
+    }
+    if !config.hooks.format.is_empty() {
+        hooks.add(FormatHook::new(config, base_dir.to_path_buf()));
+    }
+    hooks
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::io::VirtualFS;
     use tempfile::tempdir;
 
     #[test]
@@ -181,6 +903,133 @@ mod tests {
         assert!(ctx.filedb.is_empty());
     }
 
+    #[test]
+    fn test_in_memory_sync() {
+        let mut vfs = VirtualFS::new().with_base_dir("/project");
+        vfs.add_file("doc.md", "```python #main file=out.py\nprint('hi')\n```\n");
+        let vfs = Arc::new(vfs);
+        let mut ctx =
+            Context::with_file_cache(Config::default(), PathBuf::from("/project"), vfs.clone())
+                .unwrap();
+
+        crate::interface::sync_documents(&mut ctx, false).unwrap();
+        assert!(vfs
+            .read(Path::new("out.py"))
+            .unwrap()
+            .contains("print('hi')"));
+        assert!(vfs.exists(&ctx.filedb_path));
+
+        // A fresh context picks up the file database from the virtual fs
+        let ctx =
+            Context::with_file_cache(Config::default(), PathBuf::from("/project"), vfs).unwrap();
+        assert!(ctx.filedb.is_tracked(Path::new("/project/out.py")));
+    }
+
+    #[test]
+    fn test_transaction_uses_context_file_cache() {
+        let mut vfs = VirtualFS::new().with_base_dir("/project");
+        vfs.add_file("doc.md", "```python #main file=out.py\nprint('hi')\n```\n");
+        let vfs = Arc::new(vfs);
+        let mut ctx =
+            Context::with_file_cache(Config::default(), PathBuf::from("/project"), vfs.clone())
+                .unwrap();
+
+        let tx = crate::interface::tangle_documents(&ctx).unwrap();
+        assert_eq!(tx.diffs().len(), 1);
+        tx.execute(&mut ctx.filedb).unwrap();
+        assert!(vfs.exists(Path::new("out.py")));
+        assert!(!Path::new("/project/out.py").exists());
+        assert!(crate::interface::tangle_documents(&ctx)
+            .unwrap()
+            .diffs()
+            .is_empty());
+    }
+
+    /// A file cache that counts reads per path.
+    #[derive(Debug, Default)]
+    struct CountingFS {
+        inner: VirtualFS,
+        reads: Mutex<HashMap<PathBuf, usize>>,
+    }
+
+    impl CountingFS {
+        fn reads(&self, path: &str) -> usize {
+            self.reads
+                .lock()
+                .unwrap()
+                .get(Path::new(path))
+                .copied()
+                .unwrap_or(0)
+        }
+    }
+
+    impl FileCache for CountingFS {
+        fn read(&self, path: &Path) -> std::io::Result<String> {
+            let key = path.strip_prefix("/project").unwrap_or(path).to_path_buf();
+            *self.reads.lock().unwrap().entry(key).or_default() += 1;
+            self.inner.read(path)
+        }
+        fn exists(&self, path: &Path) -> bool {
+            self.inner.exists(path)
+        }
+        fn stat(&self, path: &Path) -> std::io::Result<crate::io::Stat> {
+            self.inner.stat(path)
+        }
+        fn file_data(&self, path: &Path) -> std::io::Result<crate::io::FileData> {
+            self.inner.file_data(path)
+        }
+        fn glob(&self, pattern: &str) -> std::io::Result<Vec<PathBuf>> {
+            self.inner.glob(pattern)
+        }
+        fn write(&self, path: &Path, content: &str) -> std::io::Result<()> {
+            self.inner.write(path, content)
+        }
+        fn remove(&self, path: &Path) -> std::io::Result<()> {
+            self.inner.remove(path)
+        }
+    }
+
+    #[test]
+    fn test_read_cache_scope() {
+        let fs = Arc::new(CountingFS {
+            inner: VirtualFS::new().with_base_dir("/project"),
+            ..Default::default()
+        });
+        fs.write(
+            Path::new("doc.md"),
+            "```python #main file=out.py\nprint('hi')\n```\n",
+        )
+        .unwrap();
+        let mut ctx =
+            Context::with_file_cache(Config::default(), PathBuf::from("/project"), fs.clone())
+                .unwrap();
+        let tx = crate::interface::tangle_documents(&ctx).unwrap();
+        ctx.execute(&tx, false).unwrap();
+
+        // Edit the tangled block so that stitch rewrites the markdown
+        let out = fs.read(Path::new("out.py")).unwrap();
+        fs.write(Path::new("out.py"), &out.replace("'hi'", "'bye'"))
+            .unwrap();
+        let before = fs.reads("doc.md");
+        let tx = crate::interface::stitch_documents(&ctx).unwrap();
+        assert_eq!(tx.len(), 1);
+        assert_eq!(fs.reads("doc.md") - before, 1);
+
+        // Outside a scope every call reads again
+        let before = fs.reads("out.py");
+        ctx.read_file(Path::new("out.py")).unwrap();
+        ctx.read_file(Path::new("out.py")).unwrap();
+        assert_eq!(fs.reads("out.py") - before, 2);
+
+        // Inside one, relative and absolute paths share an entry
+        let before = fs.reads("out.py");
+        ctx.with_read_cache(|| {
+            ctx.read_file(Path::new("out.py")).unwrap();
+            ctx.read_file(Path::new("/project/out.py")).unwrap();
+        });
+        assert_eq!(fs.reads("out.py") - before, 1);
+    }
+
     #[test]
     fn test_resolve_path() {
         let dir = tempdir().unwrap();
@@ -196,6 +1045,250 @@ mod tests {
         assert_eq!(resolved, absolute);
     }
 
+    #[test]
+    fn test_resolve_target_rejects_escaping_paths() {
+        let dir = tempdir().unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+
+        assert_eq!(
+            ctx.resolve_target(Path::new("src/../lib/out.py")).unwrap(),
+            dir.path().join("src/../lib/out.py")
+        );
+        assert!(ctx.resolve_target(Path::new("../../etc/cron.d/x")).is_err());
+        assert!(ctx.resolve_target(Path::new("src/../../x")).is_err());
+        assert!(ctx.resolve_target(&dir.path().join("abs.py")).is_err());
+    }
+
+    #[test]
+    fn test_resolve_target_allow_escaping() {
+        let dir = tempdir().unwrap();
+        let config = Config {
+            allow_escaping_targets: true,
+            ..Default::default()
+        };
+        let ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+
+        assert_eq!(
+            ctx.resolve_target(Path::new("../x")).unwrap(),
+            dir.path().join("../x")
+        );
+    }
+
+    #[test]
+    fn test_config_paths_are_expanded() {
+        let dir = tempdir().unwrap();
+        std::env::set_var("ENTANGLED_TEST_STATE", "state");
+        let config = Config::builder()
+            .filedb_path("$ENTANGLED_TEST_STATE/filedb.json")
+            .build();
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        assert_eq!(ctx.filedb_path, dir.path().join("state/filedb.json"));
+        // The stored config keeps the original string
+        assert_eq!(
+            ctx.config.filedb_path,
+            PathBuf::from("$ENTANGLED_TEST_STATE/filedb.json")
+        );
+
+        let unset = Config::builder()
+            .output_dir("${ENTANGLED_TEST_SURELY_UNSET}/gen")
+            .build();
+        let err = Context::new(unset.clone(), dir.path().to_path_buf()).unwrap_err();
+        assert!(err.to_string().contains("ENTANGLED_TEST_SURELY_UNSET"));
+        assert!(ctx.set_config(unset).is_err());
+        assert_eq!(ctx.filedb_path, dir.path().join("state/filedb.json"));
+    }
+
+    #[test]
+    fn test_filedb_format() {
+        let dir = tempdir().unwrap();
+        let config = Config::builder()
+            .filedb_path("filedb.toml")
+            .filedb_format(FileDbFormat::Toml)
+            .build();
+        let mut ctx = Context::new(config.clone(), dir.path().to_path_buf()).unwrap();
+        ctx.filedb.record(
+            dir.path().join("a.py"),
+            crate::io::FileData::from_content("a", chrono::Utc::now()),
+        );
+        ctx.save_filedb().unwrap();
+        drop(ctx);
+
+        let saved = std::fs::read_to_string(dir.path().join("filedb.toml")).unwrap();
+        assert!(saved.contains("hexdigest = "));
+        // Paths are saved relative to the project directory
+        assert!(!saved.contains(dir.path().to_str().unwrap()));
+        let ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        assert!(ctx.filedb.is_tracked(&dir.path().join("a.py")));
+    }
+
+    #[test]
+    fn test_filedb_from_windows_loads_elsewhere() {
+        let dir = tempdir().unwrap();
+        let json = r#"{
+            "version": "1.0",
+            "files": {
+                "src\\out.rs": {
+                    "stat": {"mtime": "2024-01-01T00:00:00Z", "size": 1},
+                    "hexdigest": "abc"
+                }
+            }
+        }"#;
+        std::fs::create_dir_all(dir.path().join(".entangled")).unwrap();
+        std::fs::write(dir.path().join(".entangled/filedb.json"), json).unwrap();
+
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        assert!(ctx
+            .filedb
+            .is_tracked(&dir.path().join("src").join("out.rs")));
+    }
+
+    #[test]
+    fn test_binary_filedb_in_virtual_fs() {
+        let vfs: Arc<dyn FileCache> = Arc::new(VirtualFS::new().with_base_dir("/project"));
+        let config = Config::builder()
+            .filedb_format(FileDbFormat::Binary)
+            .build();
+        let mut ctx =
+            Context::with_file_cache(config.clone(), PathBuf::from("/project"), Arc::clone(&vfs))
+                .unwrap();
+        ctx.filedb.record(
+            PathBuf::from("a.py"),
+            crate::io::FileData::from_content("a", chrono::Utc::now()),
+        );
+        ctx.save_filedb().unwrap();
+
+        let ctx = Context::with_file_cache(config, PathBuf::from("/project"), vfs).unwrap();
+        assert!(ctx.filedb.is_tracked(Path::new("a.py")));
+    }
+
+    #[test]
+    fn test_parse_cached_invalidation() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let path = dir.path().join("test.md");
+        let content = "```python #main file=out.py\npass\n```\n";
+
+        let first = ctx.parse_cached(&path, content).unwrap();
+        let again = ctx.parse_cached(&path, content).unwrap();
+        assert_eq!(first.refs.len(), again.refs.len());
+
+        // A config change that affects parsing is not served from the cache
+        ctx.config.namespace_default = crate::config::NamespaceDefault::None;
+        let reparsed = ctx.parse_cached(&path, content).unwrap();
+        assert!(reparsed.refs.contains_name(&ReferenceName::new("main")));
+        assert!(!first.refs.contains_name(&ReferenceName::new("main")));
+    }
+
+    #[test]
+    fn test_document_at_caches_by_stat() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.md");
+        let mut fs = crate::io::VirtualFS::new();
+        let old = Utc::now() - Duration::hours(1);
+        fs.add_file_with_mtime(&path, "```python #main file=out.py\npass\n```\n", old);
+        let fs = Arc::new(fs);
+        let config = Config {
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let ctx = Context::with_file_cache(
+            config,
+            dir.path().to_path_buf(),
+            Arc::clone(&fs) as Arc<dyn FileCache>,
+        )
+        .unwrap();
+
+        let first = ctx.document_at(&path).unwrap();
+        let again = ctx.document_at(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+
+        // A write changes the stat, so the document is parsed again
+        fs.write(&path, "```python #other file=out.py\npass\n```\n")
+            .unwrap();
+        let changed = ctx.document_at(&path).unwrap();
+        assert!(!Arc::ptr_eq(&first, &changed));
+        assert!(changed.refs.contains_name(&ReferenceName::new("other")));
+
+        // A recently modified file is re-read, but not re-parsed if unchanged
+        let reread = ctx.document_at(&path).unwrap();
+        assert!(reread.refs.contains_name(&ReferenceName::new("other")));
+    }
+
+    #[test]
+    fn test_tangle_target_and_block() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=out.py\n<<body>>\n```\n\n```python #body\nprint('hi')\n```\n",
+        )
+        .unwrap();
+
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.annotation = AnnotationMethod::Naked;
+        ctx.config.namespace_default = crate::config::NamespaceDefault::None;
+
+        assert_eq!(
+            ctx.tangle_target(Path::new("out.py")).unwrap(),
+            "print('hi')"
+        );
+        assert_eq!(
+            ctx.tangle_target(&dir.path().join("out.py")).unwrap(),
+            "print('hi')"
+        );
+        assert_eq!(
+            ctx.tangle_block(&ReferenceName::new("main")).unwrap(),
+            "print('hi')"
+        );
+        let annotated = ctx
+            .tangle_block_with(
+                &ReferenceName::new("main"),
+                Some(AnnotationMethod::Standard),
+            )
+            .unwrap();
+        assert!(annotated.starts_with("# ~/~ begin <<main[0]>>"));
+        assert!(!dir.path().join("out.py").exists());
+
+        assert!(matches!(
+            ctx.tangle_target(Path::new("missing.py")),
+            Err(EntangledError::ReferenceNotFound(_))
+        ));
+        assert!(ctx.tangle_block(&ReferenceName::new("nope")).is_err());
+    }
+
+    #[test]
+    fn test_tangle_block_finds_namespaced_name() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("a.md"),
+            "```python #main file=out.py\nprint('a')\n```\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("b.md"), "```python #util\nb = 1\n```\n").unwrap();
+        std::fs::write(dir.path().join("c.md"), "```python #util\nc = 1\n```\n").unwrap();
+
+        // Default file namespacing names the block `a.md#main`
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        ctx.config.annotation = AnnotationMethod::Naked;
+        assert_eq!(
+            ctx.tangle_block(&ReferenceName::new("main")).unwrap(),
+            "print('a')"
+        );
+        assert_eq!(
+            ctx.tangle_block(&ReferenceName::new("a.md#main")).unwrap(),
+            "print('a')"
+        );
+
+        let error = ctx
+            .tangle_block(&ReferenceName::new("util"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("b.md#util, c.md#util"), "{error}");
+        assert_eq!(
+            ctx.tangle_block(&ReferenceName::new("c.md#util")).unwrap(),
+            "c = 1"
+        );
+    }
+
     #[test]
     fn test_source_files() {
         let dir = tempdir().unwrap();
@@ -214,4 +1307,62 @@ mod tests {
             .iter()
             .any(|p| p.to_string_lossy().contains("other.txt")));
     }
+
+    #[test]
+    fn test_source_files_exclude_patterns() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("node_modules/pkg")).unwrap();
+        std::fs::create_dir_all(dir.path().join("vendor")).unwrap();
+        std::fs::write(dir.path().join("doc.md"), "# Doc").unwrap();
+        std::fs::write(dir.path().join("node_modules/pkg/README.md"), "# Pkg").unwrap();
+        std::fs::write(dir.path().join("vendor/notes.md"), "# Vendor").unwrap();
+
+        // Defaults exclude common vendor directories
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let files = ctx.source_files().unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(!files.iter().any(|p| p.starts_with("node_modules")));
+
+        ctx.config.exclude_patterns.push("vendor/**".to_string());
+        let files = ctx.source_files().unwrap();
+        assert_eq!(files, vec![PathBuf::from("doc.md")]);
+
+        // Filtering by an excluded file is an error; globs skip excluded files
+        assert!(ctx
+            .source_files_filtered(&[PathBuf::from("vendor/notes.md")])
+            .is_err());
+        assert_eq!(
+            ctx.source_files_glob(&["**/*.md".to_string()]).unwrap(),
+            vec![PathBuf::from("doc.md")]
+        );
+    }
+
+    #[test]
+    fn test_source_files_glob_exclude_and_lenient() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("drafts")).unwrap();
+        std::fs::write(dir.path().join("a.md"), "# A").unwrap();
+        std::fs::write(dir.path().join("b.md"), "# B").unwrap();
+        std::fs::write(dir.path().join("drafts/c.md"), "# C").unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        let glob =
+            |patterns: &[&str]| -> Vec<String> { patterns.iter().map(|p| p.to_string()).collect() };
+
+        assert_eq!(
+            ctx.source_files_glob(&glob(&["**/*.md", "!b.md"])).unwrap(),
+            vec![PathBuf::from("a.md"), PathBuf::from("drafts/c.md")]
+        );
+        // Exclusions alone apply to every source file
+        assert_eq!(
+            ctx.source_files_glob(&glob(&["!drafts/*"])).unwrap(),
+            vec![PathBuf::from("a.md"), PathBuf::from("b.md")]
+        );
+
+        let optional = glob(&["a.md", "missing/*.md", "!nothing.md"]);
+        assert!(ctx.source_files_glob(&optional).is_err());
+        assert_eq!(
+            ctx.source_files_glob_opt(&optional).unwrap(),
+            vec![PathBuf::from("a.md")]
+        );
+    }
 }
@@ -1,16 +1,29 @@
 //! Document orchestrator for tangle and stitch operations.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::config::{AnnotationMethod, Comment, Markers, REF_PATTERN};
-use crate::errors::Result;
-use crate::io::Transaction;
-use crate::model::{tangle_ref, ReferenceId, ReferenceMap};
-use crate::readers::{parse_markdown, read_annotated_file, split_yaml_header, ParsedDocument};
+use crate::config::{
+    begin_source_file, escape_reference, fingerprint, AnnotationMethod, AnnotationPatterns,
+    Comment, Markers, REF_PATTERN,
+};
+use crate::errors::{EntangledError, Result};
+use crate::io::{hexdigest_str, normalize_separators, DocumentCache, SourceState, Transaction};
+use crate::model::{
+    tangle_ref, tangle_ref_to, tangle_ref_with_line_directives, CodeBlock, MergePolicy,
+    ReferenceId, ReferenceMap, ReferenceName,
+};
+use crate::readers::{
+    is_notebook, parse_markdown, parse_simple_yaml, read_annotated_content_with, ParsedDocument,
+    FINGERPRINT_ATTRIBUTE, LINE_DIRECTIVE_PATTERN, SOURCE_FILE_ATTRIBUTE,
+};
+use crate::style::Style;
+use crate::text_location::TextLocation;
 
 use super::context::Context;
+use super::quarto_project;
 
 /// A document being processed by Entangled.
 #[derive(Debug, Clone)]
@@ -22,10 +35,14 @@ pub struct Document {
 }
 
 impl Document {
-    /// Loads a document from a file.
+    /// Loads a document from a file, parsed through
+    /// [`Context::document_at`].
+    ///
+    /// Targets are placed in the output directory as described in
+    /// [`place_targets`].
     pub fn load(path: &Path, ctx: &Context) -> Result<Self> {
-        let content = ctx.file_cache.read(path)?;
-        let parsed = parse_markdown(&content, Some(path), &ctx.config)?;
+        let mut parsed = ctx.document_at(path)?.as_ref().clone();
+        place_targets(ctx, Some(path), &mut parsed.refs)?;
 
         Ok(Self {
             path: path.to_path_buf(),
@@ -42,76 +59,717 @@ impl Document {
     pub fn targets(&self) -> Vec<PathBuf> {
         self.parsed.refs.targets().cloned().collect()
     }
+
+    /// Returns the raw YAML frontmatter, if present.
+    pub fn frontmatter(&self) -> Option<&str> {
+        self.parsed.frontmatter.as_deref()
+    }
+
+    /// Returns the frontmatter parsed as simple `key: value` pairs.
+    pub fn metadata(&self) -> HashMap<String, String> {
+        self.frontmatter()
+            .map(parse_simple_yaml)
+            .unwrap_or_default()
+    }
+
+    /// Returns the document as written, with the body of each named code
+    /// block replaced by its expansion.
+    ///
+    /// References are expanded recursively and without annotations,
+    /// resolving against all source files. Prose, fence lines and anonymous
+    /// blocks are kept as they are; formatting is left to the caller.
+    pub fn weave(&self, ctx: &Context) -> Result<String> {
+        super::export::weave_document(ctx, &self.path)
+    }
+}
+
+/// Places the targets of the blocks in `refs`, read from the document at
+/// `path`, in their output directory.
+///
+/// As in Org itself, the `:tangle` targets of an Org document are relative
+/// to the document's directory. With `quarto_project_integration` enabled,
+/// the targets of a Quarto-style document go to its project's `output-dir`;
+/// otherwise to the configured `output_dir`, if any. Absolute targets are
+/// left as they are.
+fn place_targets(ctx: &Context, path: Option<&Path>, refs: &mut ReferenceMap) -> Result<()> {
+    if let Some(path) =
+        path.filter(|p| Style::for_document(Some(p), ctx.config.style) == Style::Org)
+    {
+        let full_path = ctx.resolve_path(path);
+        let dir = full_path.parent().unwrap_or(Path::new(""));
+        let dir = dir.strip_prefix(&ctx.base_dir).unwrap_or(dir).to_path_buf();
+        if !dir.as_os_str().is_empty() {
+            refs.map_targets(|target| normalize_separators(&dir.join(target)));
+        }
+    }
+    let quarto_dir = match path {
+        Some(path) => quarto_project::output_dir(ctx, path)?,
+        None => None,
+    };
+    if let Some(output_dir) = quarto_dir.or(ctx.config.expanded_output_dir()?) {
+        refs.map_targets(|target| normalize_separators(&output_dir.join(target)));
+    }
+    Ok(())
 }
 
 /// Tangles all documents and produces output files.
+///
+/// With `prune` enabled, tracked targets that are no longer produced are
+/// deleted, with the usual conflict check.
 pub fn tangle_documents(ctx: &Context) -> Result<Transaction> {
+    ctx.with_read_cache(|| {
+        let source_files = ctx.source_files()?;
+        tangle_all(ctx, &source_files)
+    })
+}
+
+/// Tangles `source_files`, the complete set of sources, pruning stale
+/// targets if configured.
+///
+/// Nothing is pruned if a document was skipped, for failing to parse or for
+/// not being valid UTF-8, since its targets would look stale.
+fn tangle_all(ctx: &Context, source_files: &[PathBuf]) -> Result<Transaction> {
+    let mut transaction = tangle_files(ctx, source_files)?;
+    if ctx.config.prune && !ctx.has_skipped_documents() {
+        for path in stale_targets(ctx, source_files, &transaction) {
+            tracing::info!("{} is no longer tangled, deleting it", path.display());
+            transaction.delete(path);
+        }
+    }
+    Ok(transaction)
+}
+
+/// Returns tracked files that exist on disk but are not written by
+/// `transaction`, a tangle of all sources.
+///
+/// Stitching tracks the markdown it writes, so source files are never
+/// stale: neither current ones, nor ones recorded at the last tangle, nor
+/// any file matching `source_patterns`.
+fn stale_targets(
+    ctx: &Context,
+    source_files: &[PathBuf],
+    transaction: &Transaction,
+) -> Vec<PathBuf> {
+    // Tracked paths use `/` separators, so compare in that form
+    let resolve = |path: &Path| normalize_separators(&ctx.resolve_path(path));
+    let mut keep: HashSet<PathBuf> = transaction.actions().map(|a| resolve(a.target())).collect();
+    keep.extend(source_files.iter().map(|p| resolve(p)));
+    if let Some(recorded) = &ctx.filedb.last_tangle {
+        keep.extend(recorded.sources.keys().map(|p| resolve(p)));
+    }
+    let source_patterns: Vec<glob::Pattern> = ctx
+        .config
+        .source_patterns
+        .iter()
+        .filter_map(|p| glob::Pattern::new(p).ok())
+        .collect();
+
+    let mut stale: Vec<PathBuf> = ctx
+        .filedb
+        .tracked_files()
+        .filter(|path| !keep.contains(&resolve(path)))
+        .filter(|path| {
+            let relative = path.strip_prefix(&ctx.base_dir).unwrap_or(path);
+            !source_patterns.iter().any(|p| p.matches_path(relative))
+        })
+        .filter(|path| ctx.file_cache.exists(path))
+        .collect();
+    stale.sort();
+    stale
+}
+
+/// Forgets tracked files that no longer exist, returning their paths.
+///
+/// Call this after a full tangle has been executed: every target it
+/// produces exists by then, so only files that are neither tangled nor
+/// present on disk are removed. Does nothing unless `prune` is enabled.
+pub fn prune_filedb(ctx: &mut Context) -> Vec<PathBuf> {
+    if !ctx.config.prune {
+        return Vec::new();
+    }
+    let mut missing: Vec<PathBuf> = ctx
+        .filedb
+        .tracked_files()
+        .filter(|path| !ctx.file_cache.exists(path))
+        .collect();
+    missing.sort();
+    for path in &missing {
+        tracing::debug!("Forgetting {}, which no longer exists", path.display());
+        ctx.filedb.remove(path);
+    }
+    missing
+}
+
+/// Tangles all documents, skipping the work if nothing changed since the
+/// last recorded tangle.
+///
+/// The run is skipped (an empty transaction is returned) when the source
+/// files, their contents and the configuration match the state saved by
+/// [`record_source_state`], and every tracked file is unmodified on disk.
+/// Otherwise all documents are tangled, since a block in an unchanged file
+/// may reference one in a changed file.
+pub fn tangle_documents_incremental(ctx: &Context) -> Result<Transaction> {
+    ctx.with_read_cache(|| {
+        let source_files = ctx.source_files()?;
+        if is_up_to_date(ctx, &source_files)? {
+            tracing::info!("Sources unchanged since last tangle");
+            return Ok(ctx.new_transaction());
+        }
+        tangle_all(ctx, &source_files)
+    })
+}
+
+/// Records the current source and configuration hashes in the file database,
+/// for use by [`tangle_documents_incremental`], along with the parsed
+/// documents, so that the next run only parses the files that changed.
+///
+/// Call this after a full tangle has been executed.
+pub fn record_source_state(ctx: &mut Context) -> Result<()> {
     let source_files = ctx.source_files()?;
-    tangle_files(ctx, &source_files)
+    let state = source_state(ctx, &source_files)?;
+    let mut documents = DocumentCache::default();
+    for path in &source_files {
+        let Ok(content) = ctx.read_file(path) else {
+            continue;
+        };
+        if let Some(record) = ctx.document_record(path, &content) {
+            documents.insert(path, record);
+        }
+    }
+    ctx.filedb.last_tangle = Some(state);
+    ctx.document_cache = documents;
+    Ok(())
+}
+
+/// Hashes the configuration and the given source files, along with the
+/// Quarto project files that place their targets.
+fn source_state(ctx: &Context, source_files: &[PathBuf]) -> Result<SourceState> {
+    let mut sources = BTreeMap::new();
+    for path in source_files {
+        let Some(content) = skip_invalid(ctx, ctx.read_file(path))? else {
+            continue;
+        };
+        sources.insert(normalize_separators(path), hexdigest_str(&content));
+        if ctx.config.quarto_project_integration {
+            if let Some(project) = quarto_project::project_file(ctx, path) {
+                let content = ctx.read_file(&project)?;
+                sources.insert(normalize_separators(&project), hexdigest_str(&content));
+            }
+        }
+    }
+    Ok(SourceState {
+        config: ctx.config.fingerprint(),
+        sources,
+    })
+}
+
+/// Returns true if a tangle of `source_files` would change nothing.
+///
+/// Included files are not tracked, so with file includes this is never the
+/// case.
+fn is_up_to_date(ctx: &Context, source_files: &[PathBuf]) -> Result<bool> {
+    if ctx.config.file_includes {
+        return Ok(false);
+    }
+    let Some(recorded) = &ctx.filedb.last_tangle else {
+        return Ok(false);
+    };
+    if *recorded != source_state(ctx, source_files)? {
+        return Ok(false);
+    }
+
+    for path in ctx.filedb.tracked_files() {
+        if !ctx.file_cache.exists(&path)
+            || ctx
+                .filedb
+                .is_modified(&path, &ctx.file_cache.file_data(&path)?)
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
 }
 
 /// Tangles specific source files and produces output files.
 pub fn tangle_files(ctx: &Context, source_files: &[PathBuf]) -> Result<Transaction> {
-    let mut transaction = Transaction::new();
+    ctx.with_read_cache(|| {
+        let all_refs = collect_refs(ctx, source_files)?;
+        tangle_refs(ctx, all_refs)
+    })
+}
+
+/// Tangles markdown content that has no backing source file (e.g. stdin).
+///
+/// Since there is no path, namespace defaulting is skipped and block names
+/// are used as written. Targets are placed under `output_dir`.
+pub fn tangle_content(ctx: &Context, content: &str) -> Result<Transaction> {
+    let mut parsed = parse_markdown(content, None, &ctx.config)?;
+    place_targets(ctx, None, &mut parsed.refs)?;
+    let mut refs = ctx.new_reference_map();
+    refs.merge(&parsed.refs, MergePolicy::Append)?;
+    tangle_refs(ctx, refs)
+}
+
+/// Passes on the result of reading a source file, or `None` with a warning
+/// if the file is skipped for not being valid UTF-8.
+///
+/// Binary files are always skipped; other invalid files only with
+/// `skip_invalid` enabled. Skipped files are recorded in the context.
+fn skip_invalid<T>(ctx: &Context, result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Err(EntangledError::InvalidUtf8 {
+            path,
+            offset,
+            binary,
+        }) if binary || ctx.config.skip_invalid => {
+            let kind = if binary {
+                "binary file"
+            } else {
+                "not valid UTF-8"
+            };
+            tracing::warn!(
+                "Skipping {}: {} (invalid byte at offset {})",
+                path.display(),
+                kind,
+                offset
+            );
+            ctx.record_skipped_document(&path);
+            Ok(None)
+        }
+        other => other.map(Some),
+    }
+}
+
+/// Loads a source document, or returns `None` if it is skipped: for not
+/// being valid UTF-8 as described in [`skip_invalid`], or for failing to
+/// parse with `keep_going` enabled, in which case the error is recorded in
+/// the context.
+pub fn load_document(ctx: &Context, path: &Path) -> Result<Option<Document>> {
+    match skip_invalid(ctx, Document::load(path, ctx)) {
+        Err(e) if ctx.config.keep_going => {
+            tracing::warn!("Skipping {}: {}", path.display(), e);
+            ctx.record_failed_document(path, e);
+            Ok(None)
+        }
+        other => other,
+    }
+}
 
-    // Collect all references from all source files
-    let mut all_refs = ReferenceMap::new();
+/// Collects all references from the given source files into one map.
+///
+/// Blocks sharing a name are ordered by source file path and then by line,
+/// whatever the order of `source_files`.
+///
+/// When `warn_duplicate_ids` is enabled, a warning is logged for every block
+/// name that is defined in more than one source file; with
+/// `deny_duplicate_ids`, the first such name is an error. Source files that are
+/// not valid UTF-8 or fail to parse are skipped or rejected as described in
+/// [`load_document`].
+pub(crate) fn collect_refs(ctx: &Context, source_files: &[PathBuf]) -> Result<ReferenceMap> {
+    let mut all_refs = ctx.new_reference_map();
+    // First location of each name, and the (name, file) pairs already reported
+    let mut first_seen: HashMap<ReferenceName, TextLocation> = HashMap::new();
+    let mut reported: HashSet<(ReferenceName, PathBuf)> = HashSet::new();
 
     for path in source_files {
-        let doc = Document::load(path, ctx)?;
-        for (id, block) in doc.refs().iter_arcs() {
-            all_refs.insert_arc_with_id(id.clone(), Arc::clone(block));
+        let Some(doc) = load_document(ctx, path)? else {
+            continue;
+        };
+        if ctx.config.warn_duplicate_ids || ctx.config.deny_duplicate_ids {
+            for (id, block) in doc.refs().iter() {
+                match first_seen.get(&id.name) {
+                    Some(first) if first.filename.as_deref() != Some(path.as_path()) => {
+                        if ctx.config.deny_duplicate_ids {
+                            return Err(EntangledError::DuplicateBlock {
+                                name: id.name.clone(),
+                                first: first.clone(),
+                                second: block.location.clone(),
+                            });
+                        }
+                        if reported.insert((id.name.clone(), path.clone())) {
+                            tracing::warn!(
+                                "Block '{}' is defined in multiple files: {} and {}",
+                                id.name,
+                                first,
+                                block.location
+                            );
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        first_seen.insert(id.name.clone(), block.location.clone());
+                    }
+                }
+            }
+        }
+        all_refs.merge(doc.refs(), MergePolicy::Append)?;
+    }
+
+    all_refs.sort_by_location();
+    Ok(all_refs)
+}
+
+/// Expands a single reference and runs post-tangle hooks on the result.
+///
+/// The comment style, and whether to annotate at all, is taken from the
+/// language of the first block with the given name; `method` overrides the
+/// configured annotation method.
+pub(crate) fn tangle_name(
+    ctx: &Context,
+    all_refs: &ReferenceMap,
+    name: &ReferenceName,
+    method: Option<AnnotationMethod>,
+) -> Result<String> {
+    let expanded = pre_tangled(ctx, all_refs)?;
+    let content = expand_name(ctx, &expanded, name, method)?;
+
+    // Apply hooks, which see the annotation actually used
+    if let Some(block) = all_refs.get_by_name(name).first() {
+        ctx.hooks.run_post_tangle_annotated(&content, block, method)
+    } else {
+        Ok(content)
+    }
+}
+
+/// How a reference is annotated: comment style, markers and whether to
+/// emit `#line` directives.
+type Annotation = (Option<Comment>, Option<Markers>, bool);
+
+/// Chooses the annotation for a reference from the language of its first
+/// block, with `method`, if given, in place of the configured one.
+fn annotation_for_name(
+    ctx: &Context,
+    all_refs: &ReferenceMap,
+    name: &ReferenceName,
+    method: Option<AnnotationMethod>,
+) -> Annotation {
+    // Get language for comment style
+    let blocks = all_refs.get_by_name(name);
+    let language = blocks.first().and_then(|b| b.language.as_ref());
+    let lang_config = language.and_then(|l| ctx.config.find_language(l));
+
+    let method = method.unwrap_or_else(|| ctx.config.annotation_for(language.map(String::as_str)));
+    let (comment, markers) = match method {
+        AnnotationMethod::Standard | AnnotationMethod::Supplemental => {
+            let comment = lang_config
+                .as_ref()
+                .map(|l| l.comment.clone())
+                .unwrap_or_else(|| Comment::line("#"));
+            (Some(comment), Some(ctx.config.markers.clone()))
+        }
+        AnnotationMethod::Bare => (None, Some(ctx.config.markers.clone())),
+        AnnotationMethod::Naked => (None, None),
+    };
+
+    let use_line_directives = comment.is_some()
+        && ctx.config.line_directives
+        && lang_config.as_ref().is_some_and(|l| l.line_directives);
+    (comment, markers, use_line_directives)
+}
+
+/// Returns `all_refs` with the source of every block passed through the
+/// pre-tangle hooks, which is what is tangled between its markers.
+///
+/// Post-tangle and pre-stitch hooks are given the blocks of `all_refs`,
+/// with their markdown source.
+fn pre_tangled<'a>(ctx: &Context, all_refs: &'a ReferenceMap) -> Result<Cow<'a, ReferenceMap>> {
+    if ctx.hooks.is_empty() {
+        return Ok(Cow::Borrowed(all_refs));
+    }
+    let mut refs = all_refs.clone();
+    refs.map_sources(|block| Ok(ctx.hooks.run_pre_tangle(block)?.pop().map(|r| r.source)))?;
+    Ok(Cow::Owned(refs))
+}
+
+/// Expands a single reference without running hooks.
+fn expand_name(
+    ctx: &Context,
+    all_refs: &ReferenceMap,
+    name: &ReferenceName,
+    method: Option<AnnotationMethod>,
+) -> Result<String> {
+    match annotation_for_name(ctx, all_refs, name, method) {
+        (Some(c), Some(m), true) => tangle_ref_with_line_directives(all_refs, name, &c, &m),
+        (comment, markers, _) => tangle_ref(all_refs, name, comment.as_ref(), markers.as_ref()),
+    }
+}
+
+/// How blocks with a `mode` attribute contribute to a shared file target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TargetMode {
+    /// Contributions are written in document order.
+    Append,
+    /// Each contribution is written before the previous ones.
+    Prepend,
+}
+
+impl TargetMode {
+    fn parse(block: &CodeBlock) -> Result<Option<Self>> {
+        match block.get_attribute("mode") {
+            None => Ok(None),
+            Some("append") => Ok(Some(Self::Append)),
+            Some("prepend") => Ok(Some(Self::Prepend)),
+            Some(other) => Err(EntangledError::InvalidProperty(format!(
+                "mode={} at {} (expected `append` or `prepend`)",
+                other, block.location
+            ))),
+        }
+    }
+}
+
+/// Returns the reference names contributing to a `mode=append`/`mode=prepend`
+/// target, in output order, or `None` if no block targeting `target` has a
+/// `mode` attribute.
+///
+/// Blocks are ordered by source location; blocks sharing a name contribute
+/// once, at the position of the first of them.
+fn target_contributors<'a>(
+    all_refs: &'a ReferenceMap,
+    target: &Path,
+) -> Result<Option<Vec<&'a ReferenceName>>> {
+    let mut blocks: Vec<(&ReferenceId, &CodeBlock)> = all_refs
+        .iter()
+        .filter(|(_, b)| !b.skip && b.targets().any(|t| t == target))
+        .collect();
+
+    let mut mode: Option<(TargetMode, &CodeBlock)> = None;
+    for (_, block) in &blocks {
+        let Some(block_mode) = TargetMode::parse(block)? else {
+            continue;
+        };
+        match mode {
+            None => mode = Some((block_mode, block)),
+            Some((first, first_block)) if first != block_mode => {
+                return Err(EntangledError::Config(format!(
+                    "Conflicting mode values for target {}: `{}` at {} and `{}` at {}",
+                    target.display(),
+                    first_block.get_attribute("mode").unwrap_or_default(),
+                    first_block.location,
+                    block.get_attribute("mode").unwrap_or_default(),
+                    block.location
+                )));
+            }
+            Some(_) => {}
+        }
+    }
+    let Some((mode, _)) = mode else {
+        return Ok(None);
+    };
+
+    blocks.sort_by(|(_, a), (_, b)| {
+        (&a.location.filename, a.location.line).cmp(&(&b.location.filename, b.location.line))
+    });
+
+    let mut seen = HashSet::new();
+    let mut names: Vec<&ReferenceName> = blocks
+        .into_iter()
+        .map(|(id, _)| &id.name)
+        .filter(|name| seen.insert(*name))
+        .collect();
+    if mode == TargetMode::Prepend {
+        names.reverse();
+    }
+    Ok(Some(names))
+}
+
+/// Produces the content of a file target.
+///
+/// Targets assembled from `mode=append`/`mode=prepend` blocks concatenate
+/// each contributing reference; post-tangle hooks run once on the result.
+/// With `normalize_output`, the final content is normalized after the hooks.
+pub(crate) fn tangle_target_content(
+    ctx: &Context,
+    all_refs: &ReferenceMap,
+    target: &Path,
+    name: &ReferenceName,
+) -> Result<String> {
+    let content = match target_contributors(all_refs, target)? {
+        None => tangle_name(ctx, all_refs, name, None)?,
+        Some(names) => {
+            let expanded = pre_tangled(ctx, all_refs)?;
+            let parts = names
+                .iter()
+                .map(|name| expand_name(ctx, &expanded, name, None))
+                .collect::<Result<Vec<_>>>()?;
+            let content = parts.join("\n");
+
+            match names
+                .first()
+                .and_then(|n| all_refs.get_by_name(n).first().copied())
+            {
+                Some(block) => ctx.hooks.run_post_tangle(&content, block)?,
+                None => content,
+            }
+        }
+    };
+
+    Ok(if ctx.config.normalize_output {
+        normalize_output(&content)
+    } else {
+        content
+    })
+}
+
+/// Removes trailing whitespace from every line and ends the content with
+/// exactly one newline; empty content stays empty.
+///
+/// Stitch compares sources with [`stitch_equal`], which reads them through
+/// the same [`significant_lines`], so normalized targets stitch back without
+/// changes.
+fn normalize_output(content: &str) -> String {
+    let mut normalized = significant_lines(content).join("\n");
+    if !normalized.is_empty() {
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// Tangles each target of `source_files` on its own, without writing it, so
+/// that one broken target does not hide the content of the others.
+///
+/// Returns the resolved path of every target with the content a tangle
+/// would write, or the error tangling it raised.
+pub fn tangle_targets(
+    ctx: &Context,
+    source_files: &[PathBuf],
+) -> Result<Vec<(PathBuf, Result<String>)>> {
+    ctx.with_read_cache(|| {
+        let all_refs = collect_refs(ctx, source_files)?;
+        let mut results = Vec::new();
+        for target in all_refs.targets() {
+            let Some(name) = all_refs.get_target_name(target) else {
+                continue;
+            };
+            if only_skipped(&all_refs, target, name) {
+                continue;
+            }
+            let full_path = ctx.resolve_path(target);
+            let content = ctx.resolve_target(target).and_then(|full_path| {
+                let content = tangle_target_content(ctx, &all_refs, target, name)?;
+                Ok(keep_trailing_newline(
+                    content,
+                    existing_ends_with_newline(ctx, &full_path),
+                ))
+            });
+            results.push((full_path, content));
         }
+        Ok(results)
+    })
+}
+
+/// Returns whether every block that writes `target`, named `name`, is
+/// skipped.
+fn only_skipped(all_refs: &ReferenceMap, target: &Path, name: &ReferenceName) -> bool {
+    all_refs
+        .iter()
+        .filter(|(id, b)| &id.name == name || b.targets().any(|t| t == target))
+        .all(|(_, b)| b.skip)
+}
+
+/// Tangles every target registered in `all_refs` into a transaction.
+fn tangle_refs(ctx: &Context, all_refs: ReferenceMap) -> Result<Transaction> {
+    let mut transaction = ctx.new_transaction();
+
+    for issue in all_refs.validate_languages(&ctx.config) {
+        tracing::warn!("{}: {}", issue.location(), issue);
     }
 
     // Tangle each target file
     let mut tangled: HashMap<PathBuf, String> = HashMap::new();
+    let mut streamed: Vec<(PathBuf, ReferenceName)> = Vec::new();
 
     for target in all_refs.targets() {
         let name = all_refs.get_target_name(target).ok_or_else(|| {
-            crate::errors::EntangledError::Other(format!(
+            EntangledError::Other(format!(
                 "Internal error: target {} has no associated reference name",
                 target.display()
             ))
         })?;
 
-        // Get language for comment style
-        let blocks = all_refs.get_by_name(name);
-        let language = blocks.first().and_then(|b| b.language.as_ref());
-
-        let (comment, markers) = match ctx.config.annotation {
-            AnnotationMethod::Standard | AnnotationMethod::Supplemental => {
-                let comment = language
-                    .and_then(|l| ctx.config.find_language(l))
-                    .map(|l| l.comment)
-                    .unwrap_or_else(|| Comment::line("#"));
-                (Some(comment), Some(Markers::default()))
-            }
-            AnnotationMethod::Bare => (None, Some(Markers::default())),
-            AnnotationMethod::Naked => (None, None),
-        };
-
-        let content = tangle_ref(&all_refs, name, comment.as_ref(), markers.as_ref())?;
+        // A target made up only of skipped blocks produces no file
+        if only_skipped(&all_refs, target, name) {
+            continue;
+        }
 
-        // Apply hooks
-        let final_content = if let Some(block) = blocks.first() {
-            ctx.hooks.run_post_tangle(&content, block)?
-        } else {
-            content
-        };
+        // Hooks, mode= contributions and normalization need the whole
+        // content in memory
+        if ctx.config.stream_writes
+            && ctx.hooks.is_empty()
+            && !ctx.config.normalize_output
+            && target_contributors(&all_refs, target)?.is_none()
+        {
+            // Report errors now rather than while writing, without
+            // expanding the content twice
+            all_refs.check_expansion(name)?;
+            streamed.push((target.clone(), name.clone()));
+            continue;
+        }
 
-        tangled.insert(target.clone(), final_content);
+        tangled.insert(
+            target.clone(),
+            tangle_target_content(ctx, &all_refs, target, name)?,
+        );
     }
 
     // Create transaction actions
     for (path, content) in tangled {
-        let full_path = ctx.resolve_path(&path);
+        let full_path = ctx.resolve_target(&path)?;
+        let content = keep_trailing_newline(content, existing_ends_with_newline(ctx, &full_path));
         transaction.write(full_path, content);
     }
 
+    if !streamed.is_empty() {
+        let refs = Arc::new(all_refs);
+        for (path, name) in streamed {
+            let full_path = ctx.resolve_target(&path)?;
+            let refs = Arc::clone(&refs);
+            let (comment, markers, line_directives) = annotation_for_name(ctx, &refs, &name, None);
+            let final_newline = existing_ends_with_newline(ctx, &full_path) == Some(true);
+            transaction.stream_write(full_path, move |out| {
+                tangle_ref_to(
+                    &refs,
+                    &name,
+                    comment.as_ref(),
+                    markers.as_ref(),
+                    line_directives,
+                    out,
+                )?;
+                if final_newline {
+                    out.write_all(b"\n")?;
+                }
+                Ok(())
+            });
+        }
+    }
+
     Ok(transaction)
 }
 
+/// Returns whether the file at `path` ends with a newline, or `None` if it
+/// does not exist yet or is empty.
+fn existing_ends_with_newline(ctx: &Context, path: &Path) -> Option<bool> {
+    let existing = ctx.file_cache.read(path).ok()?;
+    (!existing.is_empty()).then(|| existing.ends_with('\n'))
+}
+
+/// Gives tangled `content` the same trailing newline state as the file it
+/// replaces, so that tangling never adds or drops one on its own.
+fn keep_trailing_newline(mut content: String, ends_with_newline: Option<bool>) -> String {
+    match ends_with_newline {
+        Some(true) if !content.is_empty() && !content.ends_with('\n') => content.push('\n'),
+        Some(false) => {
+            while content.ends_with('\n') {
+                content.pop();
+                if content.ends_with('\r') {
+                    content.pop();
+                }
+            }
+        }
+        _ => {}
+    }
+    content
+}
+
 /// Stitches changes from tangled files back to source documents.
 ///
 /// Reads annotated tangled output files, compares each code block with the
@@ -129,137 +787,483 @@ struct BlockLocation {
     content_start: usize,
     /// Last line of content (before closing fence), 1-indexed in the original file.
     content_end: usize,
+    /// Indentation of the opening fence, e.g. inside a list item, with
+    /// the markers of an enclosing blockquote.
+    indent: String,
 }
 
-/// Stitches specific source files.
+/// Returns the indentation the reader stripped from a block's content.
 ///
-/// For each source file, parses code blocks and their locations, then compares
-/// with the annotated tangled output. Modified blocks produce write actions
-/// that update the markdown source.
-pub fn stitch_files(ctx: &Context, source_files: &[PathBuf]) -> Result<Transaction> {
-    let mut transaction = Transaction::new();
+/// This is the prefix of the first non-blank content line in the markdown
+/// that is missing from the same line of `source`: the fence's indentation
+/// and blockquote markers for fenced blocks, or the code indentation of an
+/// indented block. Falls back to the indentation of the opening line.
+fn content_indent(lines: &[&str], opening_line: usize, source: &str) -> String {
+    let stripped = source
+        .lines()
+        .enumerate()
+        .find(|(_, line)| !line.trim().is_empty())
+        .and_then(|(i, line)| {
+            let raw = lines.get(opening_line + i)?;
+            raw.strip_suffix(line).filter(|p| is_indent(p))
+        });
+    let indent = stripped.unwrap_or_else(|| {
+        let opening = lines.get(opening_line - 1).copied().unwrap_or("");
+        &opening[..opening.len() - opening.trim_start_matches(is_indent_char).len()]
+    });
+    indent.to_string()
+}
 
-    // Collect all references from source files, tracking block locations
-    let mut source_refs = ReferenceMap::new();
-    let mut block_locations: HashMap<ReferenceId, BlockLocation> = HashMap::new();
+/// Returns true for characters the reader strips before block content:
+/// whitespace and blockquote markers.
+fn is_indent_char(c: char) -> bool {
+    c.is_whitespace() || c == '>'
+}
 
-    for path in source_files {
-        let raw_content = ctx.file_cache.read(path)?;
+/// Returns true if `prefix` is made of indentation and blockquote markers.
+fn is_indent(prefix: &str) -> bool {
+    prefix.chars().all(is_indent_char)
+}
 
-        // Compute YAML header offset: line numbers from parse_markdown are
-        // relative to content after YAML header stripping
-        let (yaml_header, _) = split_yaml_header(&raw_content);
-        let yaml_offset = yaml_header.map(|h| h.lines_consumed).unwrap_or(0);
+/// Prefixes every non-blank line of `content` with `indent`.
+///
+/// Blank lines get the blockquote markers of `indent`, if any, so that a
+/// quoted block stays inside its quote.
+fn indent_lines(content: &str, indent: &str) -> String {
+    if indent.is_empty() {
+        return content.to_string();
+    }
+    content
+        .lines()
+        .map(|line| {
+            if line.trim().is_empty() {
+                indent.trim_end().to_string()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-        let doc = Document::load(path, ctx)?;
+/// Replacement content for one block, collected before splicing.
+struct StitchChange {
+    id: ReferenceId,
+    content_start: usize,
+    content_end: usize,
+    content: String,
+}
 
-        for (id, block) in doc.refs().iter_arcs() {
-            // Correct line number for the YAML header offset
-            let actual_fence_line = block.location.line + yaml_offset;
-            let line_count = block.source.lines().count();
-            let content_start = actual_fence_line + 1;
-            // If source is empty, content_end < content_start (no lines to replace)
-            let content_end = actual_fence_line + line_count;
-
-            block_locations.insert(
-                id.clone(),
-                BlockLocation {
-                    source_path: path.clone(),
-                    content_start,
-                    content_end,
-                },
-            );
-            source_refs.insert_arc_with_id(id.clone(), Arc::clone(block));
+/// Checks that no two changes to one file touch the same lines.
+///
+/// `changes` must be sorted by start line, descending. Splicing overlapping
+/// ranges bottom-up would silently corrupt the file, so the first overlap is
+/// reported instead. An empty block (end before start) overlaps a range that
+/// contains its insertion point.
+fn check_stitch_overlaps(path: &Path, changes: &[StitchChange]) -> Result<()> {
+    for pair in changes.windows(2) {
+        let (later, earlier) = (&pair[0], &pair[1]);
+        if earlier.content_end >= later.content_start {
+            return Err(EntangledError::OverlappingStitch {
+                path: path.to_path_buf(),
+                first: earlier.id.clone(),
+                first_lines: (earlier.content_start, earlier.content_end),
+                second: later.id.clone(),
+                second_lines: (later.content_start, later.content_end),
+            });
         }
     }
+    Ok(())
+}
 
-    // Read tangled files and find modified blocks
-    // Group changes by source file for batch application
-    let mut changes_by_file: HashMap<PathBuf, Vec<(usize, usize, String)>> = HashMap::new();
+/// Checks that `tangled_block`, a block with references read from a file
+/// with compact markers, still has the lines of `source_block` other than
+/// its references.
+///
+/// Content edits are not stitched back into such blocks, so a mismatch means
+/// that a compact block inside it has a wrong `lines:` count: lines were
+/// added to or removed from it without updating the count. Stitching would
+/// then lose lines or take some from the enclosing block.
+fn check_compact_parent(
+    ctx: &Context,
+    source_block: &CodeBlock,
+    tangled_block: &CodeBlock,
+) -> Result<()> {
+    let expected: Vec<&str> = source_block
+        .source
+        .lines()
+        .filter(|line| !REF_PATTERN.is_match(line))
+        .collect();
+    let actual = ctx
+        .hooks
+        .run_pre_stitch(&tangled_block.source, source_block)?;
+    if stitch_equal(&expected.join("\n"), &actual) {
+        return Ok(());
+    }
+    Err(EntangledError::Parse {
+        location: tangled_block.location.clone(),
+        message: format!(
+            "Block {} does not match its source outside the blocks it includes; \
+             update the `lines:` count of any compact block edited in it",
+            tangled_block.id
+        ),
+    })
+}
 
-    for target in source_refs.targets() {
-        let full_path = ctx.resolve_path(target);
-        if !full_path.exists() {
-            continue;
-        }
+/// Stitches specific source files.
+///
+/// For each source file, parses code blocks and their locations, then compares
+/// with the annotated tangled output. Modified blocks produce write actions
+/// that update the markdown source.
+pub fn stitch_files(ctx: &Context, source_files: &[PathBuf]) -> Result<Transaction> {
+    ctx.with_read_cache(|| {
+        let mut transaction = ctx.new_transaction();
 
-        // Only stitch from annotated files (naked/bare modes have no annotations)
-        if ctx.config.annotation.is_one_way() {
-            continue;
+        // Collect all references from source files, tracking block locations
+        let mut source_refs = ctx.new_reference_map();
+        // Keyed by fence position, since IDs are only final once merged
+        let mut block_locations: HashMap<TextLocation, BlockLocation> = HashMap::new();
+
+        for path in source_files {
+            let Some(doc) = load_document(ctx, path)? else {
+                continue;
+            };
+            // IDs are assigned by the same merge as when tangling
+            source_refs.merge(doc.refs(), MergePolicy::Append)?;
+
+            // Notebooks and Org documents are read-only sources: their blocks
+            // are merged so that IDs match tangling, but no changes are
+            // written back
+            let writable = !is_notebook(path)
+                && Style::for_document(Some(path), ctx.config.style) != Style::Org;
+            let content = if writable {
+                Some(ctx.read_file(path)?)
+            } else {
+                None
+            };
+            let lines: Vec<&str> = content
+                .as_deref()
+                .map_or(Vec::new(), |c| c.lines().collect());
+
+            if !writable {
+                continue;
+            }
+            for (_, block) in doc.refs().iter() {
+                // Block locations already include the YAML header lines
+                let actual_fence_line = block.location.line;
+                let line_count = block.source.lines().count();
+                let content_start = actual_fence_line + 1;
+                // If source is empty, content_end < content_start (no lines to replace)
+                let content_end = actual_fence_line + line_count;
+                // The reader strips indentation from the content, so it is
+                // put back when splicing
+                let indent = content_indent(&lines, actual_fence_line, &block.source);
+
+                block_locations.insert(
+                    block.location.clone(),
+                    BlockLocation {
+                        source_path: path.clone(),
+                        content_start,
+                        content_end,
+                        indent,
+                    },
+                );
+            }
         }
 
-        let tangled_refs = read_annotated_file(&full_path)?;
-
-        for (id, tangled_block) in tangled_refs.iter() {
-            if let Some(source_block) = source_refs.get(id) {
-                // Skip blocks containing <<reference>> patterns -- these are
-                // expanded during tangle so their tangled content will differ
-                // from source. Only leaf blocks can be meaningfully stitched.
-                // REF_PATTERN uses ^/$ anchors, so check each line
-                let has_refs = source_block
-                    .source
-                    .lines()
-                    .any(|line| REF_PATTERN.is_match(line));
-                if has_refs {
-                    continue;
+        // Read tangled files and find modified blocks
+        // Group changes by source file for batch application
+        let mut changes_by_file: HashMap<PathBuf, Vec<StitchChange>> = HashMap::new();
+        let patterns = AnnotationPatterns::new(&ctx.config.markers)?;
+
+        for target in source_refs.targets() {
+            let full_path = ctx.resolve_path(target);
+            if !ctx.file_cache.exists(&full_path) {
+                if ctx.filedb.is_tracked(&full_path) {
+                    tracing::warn!(
+                        "{} was deleted since the last tangle; run tangle to recreate it",
+                        target.display()
+                    );
                 }
+                continue;
+            }
+
+            // Only stitch from annotated files (naked/bare modes have no annotations)
+            let language = source_refs
+                .get_target_name(target)
+                .and_then(|name| source_refs.get_by_name(name).first().copied())
+                .and_then(|b| b.language.as_deref());
+            if ctx.config.annotation_for(language).is_one_way() {
+                continue;
+            }
+
+            let tangled = ctx.read_file(&full_path)?;
+            let tangled_refs = read_annotated_content_with(&tangled, &full_path, &patterns)?;
+            // Compact blocks are found by their line count, which only the
+            // content of the enclosing blocks can confirm
+            let has_compact = tangled.lines().any(|line| patterns.compact.is_match(line));
+
+            for (id, tangled_block) in tangled_refs.iter() {
+                let tangled_block = &escape_references(tangled_block, &ctx.config.reference_escape);
+                if let Some(source_block) = stitched_block(&source_refs, tangled_block, target) {
+                    // Skip blocks containing <<reference>> patterns -- these are
+                    // expanded during tangle so their tangled content will differ
+                    // from source. Only leaf blocks can be meaningfully stitched.
+                    // REF_PATTERN uses ^/$ anchors, so check each line
+                    let has_refs = source_block
+                        .source
+                        .lines()
+                        .any(|line| REF_PATTERN.is_match(line));
+                    if has_refs {
+                        if has_compact {
+                            check_compact_parent(ctx, source_block, tangled_block)?;
+                        }
+                        continue;
+                    }
 
-                if source_block.source != tangled_block.source {
-                    if let Some(loc) = block_locations.get(id) {
-                        tracing::info!(
-                            "Block {} modified in {}, updating {}",
-                            id,
-                            target.display(),
-                            loc.source_path.display(),
-                        );
-                        changes_by_file
-                            .entry(loc.source_path.clone())
-                            .or_default()
-                            .push((
-                                loc.content_start,
-                                loc.content_end,
-                                tangled_block.source.clone(),
-                            ));
+                    // Hooks strip what they added when tangling
+                    let tangled_block = &CodeBlock {
+                        source: ctx
+                            .hooks
+                            .run_pre_stitch(&tangled_block.source, source_block)?,
+                        ..tangled_block.clone()
+                    };
+
+                    if !stitch_equal(&source_block.source, &tangled_block.source) {
+                        if let Some(loc) = block_locations.get(&source_block.location) {
+                            tracing::info!(
+                                "Block {} modified in {}, updating {}",
+                                id,
+                                target.display(),
+                                loc.source_path.display(),
+                            );
+                            changes_by_file
+                                .entry(loc.source_path.clone())
+                                .or_default()
+                                .push(StitchChange {
+                                    id: source_block.id.clone(),
+                                    content_start: loc.content_start,
+                                    content_end: loc.content_end,
+                                    content: indent_lines(&tangled_block.source, &loc.indent),
+                                });
+                        }
                     }
                 }
             }
         }
-    }
 
-    // Apply changes to each markdown file
-    for (path, mut changes) in changes_by_file {
-        let content = ctx.file_cache.read(&path)?;
-        let lines: Vec<&str> = content.lines().collect();
+        // Apply changes to each markdown file
+        for (path, mut changes) in changes_by_file {
+            let content = ctx.read_file(&path)?;
+
+            // Sort by start line descending -- apply from bottom to top
+            // so earlier line numbers remain valid after splicing
+            changes.sort_by_key(|c| std::cmp::Reverse(c.content_start));
+            // The same edit seen through two targets is applied once
+            changes.dedup_by(|a, b| a.id == b.id && a.content == b.content);
+            check_stitch_overlaps(&path, &changes)?;
+
+            // Lines keep their terminators, so everything outside the
+            // replaced blocks is written back byte for byte
+            let mut new_lines: Vec<String> =
+                content.split_inclusive('\n').map(str::to_string).collect();
+
+            for change in &changes {
+                let start_idx = change.content_start - 1; // 1-indexed to 0-indexed
+                let end_idx = change.content_end; // 1-indexed inclusive -> 0-indexed exclusive
+                splice_block(&mut new_lines, start_idx, end_idx, &change.content);
+            }
 
-        // Sort by start line descending -- apply from bottom to top
-        // so earlier line numbers remain valid after splicing
-        changes.sort_by(|a, b| b.0.cmp(&a.0));
+            let new_file_content = new_lines.concat();
 
-        let mut new_lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+            let full_path = ctx.resolve_path(&path);
+            transaction.write(full_path, new_file_content);
+        }
 
-        for (content_start, content_end, new_content) in &changes {
-            let start_idx = content_start - 1; // 1-indexed to 0-indexed
-            let end_idx = *content_end; // 1-indexed inclusive -> 0-indexed exclusive
+        Ok(transaction)
+    })
+}
 
-            let replacement: Vec<String> = if new_content.is_empty() {
-                Vec::new()
-            } else {
-                new_content.lines().map(|l| l.to_string()).collect()
-            };
+/// Returns the line terminator of `line`, which is empty for a last line
+/// without one.
+pub(crate) fn line_ending(line: &str) -> &str {
+    if line.ends_with("\r\n") {
+        "\r\n"
+    } else if line.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+/// Replaces lines `start..end` of `lines`, which include their terminators,
+/// with the lines of `content`.
+///
+/// Replacement lines take the terminator of the lines they replace, and the
+/// last one that of the last replaced line, so a block ending the file
+/// without a newline still does and CRLF documents stay CRLF. An empty
+/// block (`end < start`) takes its terminator from the fence before it.
+fn splice_block(lines: &mut Vec<String>, start: usize, end: usize, content: &str) {
+    let end = end.max(start);
+    let replaced = &lines[start..end];
+    let eol = replaced
+        .first()
+        .or_else(|| start.checked_sub(1).and_then(|i| lines.get(i)))
+        .map(|l| line_ending(l))
+        .filter(|ending| !ending.is_empty())
+        .unwrap_or("\n")
+        .to_string();
+    let last_eol = replaced
+        .last()
+        .map_or_else(|| eol.clone(), |l| line_ending(l).to_string());
+
+    let content_lines: Vec<&str> = content.lines().collect();
+    let count = content_lines.len();
+    let replacement: Vec<String> = content_lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let ending = if i + 1 == count { &last_eol } else { &eol };
+            format!("{}{}", line, ending)
+        })
+        .collect();
+    lines.splice(start..end, replacement);
+}
+
+/// Checks whether stitch considers two block sources equal.
+///
+/// Trailing whitespace on each line and trailing blank lines are ignored,
+/// so sources that differ only in these are equal.
+fn stitch_equal(a: &str, b: &str) -> bool {
+    a == b || significant_lines(a) == significant_lines(b)
+}
+
+/// Returns the lines of `source` without trailing whitespace, dropping
+/// trailing blank lines.
+fn significant_lines(source: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = source.lines().map(str::trim_end).collect();
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(0, |i| i + 1);
+    lines.truncate(end);
+    lines
+}
 
-            new_lines.splice(start_idx..end_idx, replacement);
+/// Returns a block read from a tangled file with every line that would be
+/// read as a reference escaped again, as it was written in the markdown.
+fn escape_references(block: &CodeBlock, escape: &str) -> CodeBlock {
+    let source = block
+        .source
+        .split('\n')
+        .map(|line| escape_reference(line, escape).unwrap_or_else(|| line.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    CodeBlock {
+        source,
+        ..block.clone()
+    }
+}
+
+/// Finds the markdown block that an edited block read from `target` updates.
+///
+/// Without a fingerprint this is the block with the same ID. With one, that
+/// block is used only if its source still has the fingerprint; otherwise the
+/// one block of that name that does is used, so blocks renumbered since the
+/// last tangle are still matched. If the begin marker records a source file,
+/// only blocks from that file are considered, and without a fingerprint the
+/// one block of that name in the file is used when the ID points elsewhere.
+/// If no block is found, a warning is logged and the edit is not stitched.
+fn stitched_block<'a>(
+    source_refs: &'a ReferenceMap,
+    tangled_block: &CodeBlock,
+    target: &Path,
+) -> Option<&'a CodeBlock> {
+    let id = &tangled_block.id;
+    let source_file = tangled_block.get_attribute(SOURCE_FILE_ATTRIBUTE);
+    let hash = tangled_block.get_attribute(FINGERPRINT_ATTRIBUTE);
+    let in_file = |b: &CodeBlock| source_file.is_none_or(|f| is_from_file(b, Path::new(f)));
+
+    match (source_refs.get(id).filter(|b| in_file(b)), hash) {
+        (Some(block), None) => return Some(block),
+        // Nothing to stitch, whatever the fingerprint says
+        (Some(block), Some(_)) if stitch_equal(&block.source, &tangled_block.source) => {
+            return Some(block)
         }
+        (Some(block), Some(hash)) if fingerprint(&block.source) == hash => return Some(block),
+        (None, None) if source_file.is_none() => return None,
+        _ => {}
+    }
 
-        let mut new_file_content = new_lines.join("\n");
-        if content.ends_with('\n') {
-            new_file_content.push('\n');
+    let mut matches = source_refs
+        .get_by_name(&id.name)
+        .into_iter()
+        .filter(|b| in_file(b))
+        .filter(|b| hash.is_none_or(|h| fingerprint(&b.source) == h));
+    match (matches.next(), matches.next()) {
+        (Some(block), None) => {
+            tracing::info!(
+                "Block {} in {} was tangled from {}",
+                id,
+                target.display(),
+                block.id
+            );
+            Some(block)
+        }
+        _ => {
+            let recorded: Vec<String> = [
+                source_file.map(|f| format!("from:{}", f)),
+                hash.map(|h| format!("hash:{}", h)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            tracing::warn!(
+                "Block {} in {} no longer matches a block in the markdown ({}); \
+                 not stitching it",
+                id,
+                target.display(),
+                recorded.join(" ")
+            );
+            None
         }
+    }
+}
 
-        let full_path = ctx.resolve_path(&path);
-        transaction.write(full_path, new_file_content);
+/// Returns the block an annotated block with the given ID was tangled from.
+///
+/// Without a recorded `source_file`, this is the block with the ID. With
+/// one, it is that block if it comes from the file, otherwise the one block
+/// of that name in the file, if there is exactly one.
+fn block_in_file<'a>(
+    refs: &'a ReferenceMap,
+    id: &ReferenceId,
+    source_file: Option<&Path>,
+) -> Option<&'a CodeBlock> {
+    let Some(path) = source_file else {
+        return refs.get(id);
+    };
+    if let Some(block) = refs.get(id).filter(|b| is_from_file(b, path)) {
+        return Some(block);
     }
+    let mut matches = refs
+        .get_by_name(&id.name)
+        .into_iter()
+        .filter(|b| is_from_file(b, path));
+    match (matches.next(), matches.next()) {
+        (Some(block), None) => Some(block),
+        _ => None,
+    }
+}
 
-    Ok(transaction)
+/// Returns true if `block` was read from the markdown file `path`, as
+/// recorded in a begin marker.
+fn is_from_file(block: &CodeBlock, path: &Path) -> bool {
+    block
+        .location
+        .filename
+        .as_deref()
+        .is_some_and(|f| normalize_separators(f) == normalize_separators(path))
 }
 
 /// Result of locating a source position from a tangled file position.
@@ -279,122 +1283,372 @@ impl std::fmt::Display for SourceLocation {
     }
 }
 
-/// Maps a line in a tangled output file back to its markdown source location.
-///
-/// Given a tangled file path and a line number within it, reads the annotation
-/// markers to determine which code block the line belongs to, then looks up
-/// that block's position in the markdown source.
-///
-/// Returns `None` if the line is an annotation marker or the file has no annotations.
-pub fn locate_source(
-    ctx: &Context,
-    target_file: &Path,
-    target_line: usize,
-) -> Result<Option<SourceLocation>> {
-    use once_cell::sync::Lazy;
-    use regex::Regex;
+/// Markdown source lines of one block, located from a range of tangled lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceRange {
+    /// Path to the markdown source file.
+    pub source_file: PathBuf,
+    /// First line in the markdown source (1-indexed).
+    pub first_line: usize,
+    /// Last line in the markdown source (1-indexed, inclusive).
+    pub last_line: usize,
+    /// The reference ID of the block.
+    pub block_id: ReferenceId,
+}
 
-    static BEGIN_PAT: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r"^\s*\S+\s+~/~\s+begin\s+<<(?P<ref>[^>]+)>>").unwrap());
-    static END_PAT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*\S+\s+~/~\s+end\s*$").unwrap());
+impl std::fmt::Display for SourceRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.source_file.display(), self.first_line)?;
+        if self.last_line != self.first_line {
+            write!(f, "-{}", self.last_line)?;
+        }
+        Ok(())
+    }
+}
 
-    // Read the tangled file
-    let tangled_content = std::fs::read_to_string(target_file)?;
+/// Result of locating a tangled file position from a markdown position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetLocation {
+    /// Path to the tangled output file.
+    pub target_file: PathBuf,
+    /// Line number in the tangled file (1-indexed).
+    pub target_line: usize,
+    /// The reference ID of the containing block.
+    pub block_id: ReferenceId,
+}
 
-    // Walk the file tracking annotation context
-    // For each content line, track (block_id, offset_within_block)
-    let mut stack: Vec<(ReferenceId, usize)> = Vec::new(); // (id, content_line_count)
-    let mut result_id: Option<ReferenceId> = None;
-    let mut result_offset: usize = 0;
+impl std::fmt::Display for TargetLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.target_file.display(), self.target_line)
+    }
+}
 
-    for (line_idx, line) in tangled_content.lines().enumerate() {
-        let line_number = line_idx + 1;
+/// Role of a single line in an annotated tangled file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AnnotatedLine {
+    /// A begin marker. `parent` is the enclosing block and the offset of the
+    /// `<<ref>>` line in it that this expansion replaces.
+    Begin {
+        parent: Option<(ReferenceId, usize)>,
+    },
+    /// An end marker.
+    End,
+    /// A compact marker closing the block on the lines before it. `parent`
+    /// is as for [`AnnotatedLine::Begin`].
+    Compact {
+        parent: Option<(ReferenceId, usize)>,
+    },
+    /// A generated `#line` directive.
+    Directive,
+    /// A content line at `offset` lines into the source of block `id`,
+    /// tangled from `source_file` if the begin marker records it.
+    Content {
+        id: ReferenceId,
+        offset: usize,
+        source_file: Option<PathBuf>,
+    },
+    /// A line outside any annotated block.
+    Outside,
+}
 
-        if let Some(caps) = BEGIN_PAT.captures(line) {
-            if line_number == target_line {
-                return Ok(None); // Target is an annotation marker
-            }
-            let ref_str = &caps["ref"];
-            if let Some(id) = ReferenceId::parse(ref_str) {
-                stack.push((id, 0));
-            }
-        } else if END_PAT.is_match(line) {
-            if line_number == target_line {
-                return Ok(None); // Target is an annotation marker
+/// Classifies every line of an annotated tangled file.
+fn classify_annotated_lines(content: &str, patterns: &AnnotationPatterns) -> Vec<AnnotatedLine> {
+    // For each open block: (id, content_line_count, source_file)
+    let mut stack: Vec<(ReferenceId, usize, Option<PathBuf>)> = Vec::new();
+    let mut roles = Vec::new();
+    let mut after_marker = false;
+
+    for line in content.lines() {
+        let role = if let Some(caps) = patterns.begin.captures(line) {
+            let parent = stack.last().map(|(id, count, _)| (id.clone(), *count));
+            if let Some(id) = ReferenceId::parse(&caps["ref"]) {
+                stack.push((id, 0, begin_source_file(&caps).map(PathBuf::from)));
             }
+            AnnotatedLine::Begin { parent }
+        } else if patterns.end.is_match(line) {
             stack.pop();
-        } else if let Some((_id, ref mut count)) = stack.last_mut() {
-            if line_number == target_line {
-                result_id = Some(_id.clone());
-                result_offset = *count;
-                break;
+            // The nested block replaced a single `<<ref>>` line in its parent
+            if let Some((_, count, _)) = stack.last_mut() {
+                *count += 1;
+            }
+            AnnotatedLine::End
+        } else if let Some(caps) = patterns.compact.captures(line) {
+            let count: usize = caps["lines"].parse().unwrap_or(0);
+            let parent_id = stack.last().map(|(id, _, _)| id.clone());
+            // The block's lines were classified as content of the parent
+            let first = roles.len().saturating_sub(count);
+            let in_parent = |role: &AnnotatedLine| match role {
+                AnnotatedLine::Content { id, .. } => Some(id) == parent_id.as_ref(),
+                AnnotatedLine::Outside => parent_id.is_none(),
+                _ => false,
+            };
+            match ReferenceId::parse(&caps["ref"]) {
+                Some(id) if roles.len() >= count && roles[first..].iter().all(in_parent) => {
+                    let source_file = begin_source_file(&caps).map(PathBuf::from);
+                    for (offset, role) in roles[first..].iter_mut().enumerate() {
+                        *role = AnnotatedLine::Content {
+                            id: id.clone(),
+                            offset,
+                            source_file: source_file.clone(),
+                        };
+                    }
+                    let parent = stack.last_mut().map(|(id, parent_count, _)| {
+                        // The block replaced a single `<<ref>>` line
+                        *parent_count -= count;
+                        *parent_count += 1;
+                        (id.clone(), *parent_count - 1)
+                    });
+                    AnnotatedLine::Compact { parent }
+                }
+                _ => AnnotatedLine::Outside,
             }
+        } else if after_marker && LINE_DIRECTIVE_PATTERN.is_match(line) {
+            AnnotatedLine::Directive
+        } else if let Some((id, count, source_file)) = stack.last_mut() {
+            let role = AnnotatedLine::Content {
+                id: id.clone(),
+                offset: *count,
+                source_file: source_file.clone(),
+            };
             *count += 1;
-        } else if line_number == target_line {
-            // Line is outside any annotated block
-            return Ok(None);
-        }
+            role
+        } else {
+            AnnotatedLine::Outside
+        };
+
+        after_marker = matches!(
+            role,
+            AnnotatedLine::Begin { .. } | AnnotatedLine::End | AnnotatedLine::Compact { .. }
+        );
+        roles.push(role);
     }
 
-    let block_id = match result_id {
-        Some(id) => id,
-        None => return Ok(None),
-    };
+    roles
+}
 
-    // Now find the markdown source location for this block
-    let source_files = ctx.source_files()?;
-    for path in &source_files {
-        let raw_content = ctx.file_cache.read(path)?;
-        let (yaml_header, _) = split_yaml_header(&raw_content);
-        let yaml_offset = yaml_header.map(|h| h.lines_consumed).unwrap_or(0);
-
-        let doc = Document::load(path, ctx)?;
-        if let Some(block) = doc.refs().get(&block_id) {
-            // block.location.line is relative to post-YAML content
-            let fence_line = block.location.line + yaml_offset;
-            // Content starts on the line after the fence
-            let source_line = fence_line + 1 + result_offset;
+/// Maps a line in a tangled output file back to its markdown source location.
+///
+/// Given a tangled file path and a line number within it, reads the annotation
+/// markers to determine which code block the line belongs to, then looks up
+/// that block's position in the markdown source.
+///
+/// Returns `None` if the line is an annotation marker or the file has no annotations.
+pub fn locate_source(
+    ctx: &Context,
+    target_file: &Path,
+    target_line: usize,
+) -> Result<Option<SourceLocation>> {
+    let ranges = locate_source_range(ctx, target_file, target_line, target_line)?;
+    Ok(ranges.into_iter().next().map(|range| SourceLocation {
+        source_file: range.source_file,
+        source_line: range.first_line,
+        block_id: range.block_id,
+    }))
+}
+
+/// Maps a range of lines in a tangled output file back to markdown source.
+///
+/// Returns one [`SourceRange`] per code block with content in
+/// `first_line..=last_line`, in the order the blocks first appear in the
+/// range. Each range covers the block's lines that fall inside the selection.
+/// Annotation markers and directives in the range are skipped, so a range that
+/// crosses block boundaries yields every block it touches.
+///
+/// Returns an empty list if no line in the range has a source mapping.
+pub fn locate_source_range(
+    ctx: &Context,
+    target_file: &Path,
+    first_line: usize,
+    last_line: usize,
+) -> Result<Vec<SourceRange>> {
+    ctx.with_read_cache(|| {
+        // Read the tangled file
+        let tangled_content = ctx.read_file(target_file)?;
+
+        let patterns = AnnotationPatterns::new(&ctx.config.markers)?;
+        let roles = classify_annotated_lines(&tangled_content, &patterns);
+
+        // Smallest and largest content offset of each block within the range
+        let mut spans: Vec<(ReferenceId, Option<PathBuf>, usize, usize)> = Vec::new();
+        let selected = roles
+            .iter()
+            .take(last_line)
+            .skip(first_line.saturating_sub(1));
+        for role in selected {
+            // Annotation markers, directives and lines outside any block
+            let AnnotatedLine::Content {
+                id,
+                offset,
+                source_file,
+            } = role
+            else {
+                continue;
+            };
+            match spans
+                .iter_mut()
+                .find(|(span_id, span_file, _, _)| span_id == id && span_file == source_file)
+            {
+                Some((_, _, low, high)) => {
+                    *low = (*low).min(*offset);
+                    *high = (*high).max(*offset);
+                }
+                None => spans.push((id.clone(), source_file.clone(), *offset, *offset)),
+            }
+        }
+        if spans.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Now find the markdown source location for each block
+        // IDs are assigned over the merged map, exactly as when tangling
+        let refs = collect_refs(ctx, &ctx.source_files()?)?;
+        let mut ranges = Vec::new();
+        for (block_id, source_file, low, high) in spans {
+            // Block ID not found in any source file
+            let Some(block) = block_in_file(&refs, &block_id, source_file.as_deref()) else {
+                continue;
+            };
+            let Some(source_file) = block.location.filename.clone() else {
+                continue;
+            };
 
-            return Ok(Some(SourceLocation {
-                source_file: path.clone(),
-                source_line,
+            // Content starts on the line after the fence
+            let content_start = block.location.line + 1;
+            ranges.push(SourceRange {
+                source_file,
+                first_line: content_start + low,
+                last_line: content_start + high,
                 block_id,
-            }));
+            });
+        }
+        Ok(ranges)
+    })
+}
+
+/// Maps a line in a markdown source file to its positions in tangled output.
+///
+/// Finds the code block whose content spans `source_line`, then walks the
+/// annotation markers of every existing target file to find where that line
+/// was written. A block referenced several times yields several locations.
+/// For a `<<ref>>` line, the begin marker of its expansion is returned.
+///
+/// Returns an empty list if the line is not inside a code block, or the
+/// block does not appear in any annotated target.
+pub fn locate_target(
+    ctx: &Context,
+    source_file: &Path,
+    source_line: usize,
+) -> Result<Vec<TargetLocation>> {
+    ctx.with_read_cache(|| {
+        let refs = collect_refs(ctx, &ctx.source_files()?)?;
+        let wanted = ctx.resolve_path(source_file);
+
+        // Block content spans the lines after its opening fence
+        let found = refs.iter_arcs().find_map(|(id, block)| {
+            let path = block.location.filename.as_ref()?;
+            let fence_line = block.location.line;
+            let line_count = block.source.lines().count();
+            let in_block = source_line > fence_line && source_line <= fence_line + line_count;
+            (in_block && ctx.resolve_path(path) == wanted)
+                .then(|| (id.clone(), source_line - fence_line - 1))
+        });
+        let Some((block_id, offset)) = found else {
+            return Ok(Vec::new());
+        };
+
+        let mut targets: Vec<&PathBuf> = refs.targets().collect();
+        targets.sort();
+
+        let patterns = AnnotationPatterns::new(&ctx.config.markers)?;
+        let mut locations = Vec::new();
+        for target in targets {
+            let full_path = ctx.resolve_path(target);
+            if !ctx.file_cache.exists(&full_path) {
+                if ctx.filedb.is_tracked(&full_path) {
+                    tracing::warn!(
+                        "{} was deleted since the last tangle; run tangle to recreate it",
+                        target.display()
+                    );
+                }
+                continue;
+            }
+            let content = ctx.read_file(&full_path)?;
+
+            for (idx, role) in classify_annotated_lines(&content, &patterns)
+                .into_iter()
+                .enumerate()
+            {
+                let hit = match role {
+                    AnnotatedLine::Content { id, offset: o, .. } => id == block_id && o == offset,
+                    AnnotatedLine::Begin {
+                        parent: Some((id, o)),
+                    }
+                    | AnnotatedLine::Compact {
+                        parent: Some((id, o)),
+                    } => id == block_id && o == offset,
+                    _ => false,
+                };
+                if hit {
+                    locations.push(TargetLocation {
+                        target_file: full_path.clone(),
+                        target_line: idx + 1,
+                        block_id: block_id.clone(),
+                    });
+                }
+            }
         }
-    }
 
-    // Block ID not found in any source file
-    Ok(None)
+        Ok(locations)
+    })
 }
 
 /// Synchronizes documents (stitch then tangle).
 ///
-/// When `force` is true, file conflict checks are skipped.
+/// When `force` is true, file conflict checks are skipped. If anything was
+/// written, the configured `post_sync` command is run afterwards. With
+/// `keep_going` enabled, documents that fail to parse are skipped and the
+/// sync fails at the end, as described in [`Context::check_failed_documents`].
 pub fn sync_documents(ctx: &mut Context, force: bool) -> Result<()> {
+    sync_documents_with(ctx, force, |_, _| {})
+}
+
+/// Like [`sync_documents`], calling `inspect` with the stitch and then the
+/// tangle transaction before each is executed.
+pub fn sync_documents_with(
+    ctx: &mut Context,
+    force: bool,
+    mut inspect: impl FnMut(&Context, &Transaction),
+) -> Result<()> {
+    let started = chrono::Utc::now();
+
     // First stitch any changes from tangled files
     let stitch_tx = stitch_documents(ctx)?;
+    inspect(ctx, &stitch_tx);
     if !stitch_tx.is_empty() {
-        if force {
-            stitch_tx.execute_force(&mut ctx.filedb)?;
-        } else {
-            stitch_tx.execute(&mut ctx.filedb)?;
-        }
+        ctx.execute(&stitch_tx, force)?;
     }
 
-    // Then tangle all documents
-    let tangle_tx = tangle_documents(ctx)?;
+    // Then tangle all documents, journaled with the stitch so that undo
+    // restores both
+    let tangle_tx = tangle_documents(ctx)?.continuing_journal(started);
+    inspect(ctx, &tangle_tx);
     if !tangle_tx.is_empty() {
-        if force {
-            tangle_tx.execute_force(&mut ctx.filedb)?;
-        } else {
-            tangle_tx.execute(&mut ctx.filedb)?;
-        }
+        ctx.execute(&tangle_tx, force)?;
+    }
+    // Skipped documents were not tangled, so the next run must not skip them
+    if !ctx.has_skipped_documents() {
+        record_source_state(ctx)?;
+        prune_filedb(ctx);
     }
 
     // Save file database
     ctx.save_filedb()?;
 
-    Ok(())
+    if !stitch_tx.is_empty() || !tangle_tx.is_empty() {
+        ctx.run_post_sync_command()?;
+    }
+
+    ctx.check_failed_documents()
 }
 
 #[cfg(test)]
@@ -427,6 +1681,40 @@ print('hello')
         let doc = Document::load(&md_path, &ctx).unwrap();
         assert!(!doc.refs().is_empty());
         assert_eq!(doc.targets().len(), 1);
+        assert!(doc.frontmatter().is_none());
+        assert!(doc.metadata().is_empty());
+    }
+
+    #[test]
+    fn test_document_metadata() {
+        let (dir, ctx) = setup_test_dir();
+
+        let md_path = dir.path().join("test.md");
+        fs::write(
+            &md_path,
+            "---\ntitle: \"Guide\"\nauthor: Ada\n---\n\n# Guide\n",
+        )
+        .unwrap();
+
+        let doc = Document::load(&md_path, &ctx).unwrap();
+        assert_eq!(doc.frontmatter(), Some("title: \"Guide\"\nauthor: Ada"));
+        let metadata = doc.metadata();
+        assert_eq!(metadata.get("title").map(String::as_str), Some("Guide"));
+        assert_eq!(metadata.get("author").map(String::as_str), Some("Ada"));
+    }
+
+    #[test]
+    fn test_language_attribute_sets_comment_style() {
+        let (dir, ctx) = setup_test_dir();
+        fs::write(
+            dir.path().join("test.md"),
+            "```text #main file=main.rs language=rust\nfn main() {}\n```\n",
+        )
+        .unwrap();
+
+        let tangled = ctx.tangle_target(Path::new("main.rs")).unwrap();
+        assert!(tangled.starts_with("// ~/~ begin <<test.md#main[0]>>"));
+        assert!(tangled.ends_with("// ~/~ end"));
     }
 
     #[test]
@@ -479,6 +1767,276 @@ print('hello')
         assert!(!tx.is_empty());
     }
 
+    #[test]
+    fn test_stitch_preserves_fence_indentation() {
+        let dir = tempdir().unwrap();
+        let md = "- Step one:\n\n    ```python #main file=out.py\n    if x:\n        y = 1\n\n    z = 2\n    ```\n\n- Step two\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let mut ctx = Context::new(Default::default(), dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert!(tangled.contains("if x:\n    y = 1\n"));
+
+        fs::write(&out, tangled.replace("y = 1", "y = 3")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let doc = fs::read_to_string(dir.path().join("doc.md")).unwrap();
+        assert_eq!(doc, md.replace("y = 1", "y = 3"));
+    }
+
+    #[test]
+    fn test_quoted_block_round_trip() {
+        let dir = tempdir().unwrap();
+        let md = "> Example:\n>\n> ```python #main file=out.py\n> if x:\n>     y = 1\n>\n> z = 2\n> ```\n\nDone.\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let config = crate::config::Config::builder().quoted_blocks(true).build();
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert!(tangled.contains("\nif x:\n    y = 1\n\nz = 2\n"));
+
+        fs::write(&out, tangled.replace("y = 1", "y = 3")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let doc = fs::read_to_string(dir.path().join("doc.md")).unwrap();
+        assert_eq!(doc, md.replace("y = 1", "y = 3"));
+    }
+
+    #[test]
+    fn test_indented_block_round_trip() {
+        let dir = tempdir().unwrap();
+        let md =
+            "Setup:\n\n<!-- file: out.py -->\n    if x:\n        y = 1\n\n    z = 2\n\nDone.\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let config = crate::config::Config::builder()
+            .indented_blocks(true)
+            .build();
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert!(tangled.contains("if x:\n    y = 1\n\nz = 2\n"));
+
+        fs::write(&out, tangled.replace("y = 1", "y = 3")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let doc = fs::read_to_string(dir.path().join("doc.md")).unwrap();
+        assert_eq!(doc, md.replace("y = 1", "y = 3"));
+    }
+
+    #[test]
+    fn test_check_stitch_overlaps() {
+        let change = |name: &str, start: usize, end: usize| StitchChange {
+            id: ReferenceId::new(ReferenceName::new(name), 0),
+            content_start: start,
+            content_end: end,
+            content: String::new(),
+        };
+        let path = Path::new("doc.md");
+
+        // Adjacent ranges and an empty block between them are fine
+        let ok = [change("c", 8, 9), change("b", 7, 6), change("a", 2, 5)];
+        assert!(check_stitch_overlaps(path, &ok).is_ok());
+
+        let overlapping = [change("b", 5, 9), change("a", 2, 5)];
+        let err = check_stitch_overlaps(path, &overlapping).unwrap_err();
+        assert!(matches!(
+            &err,
+            EntangledError::OverlappingStitch { first, second, .. }
+                if first.name.as_str() == "a" && second.name.as_str() == "b"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Cannot stitch doc.md: blocks a[0] (lines 2-5) and b[0] (lines 5-9) overlap"
+        );
+
+        // An empty block whose insertion point falls inside another block
+        let inside = [change("b", 4, 3), change("a", 2, 5)];
+        assert!(check_stitch_overlaps(path, &inside).is_err());
+    }
+
+    #[test]
+    fn test_quoted_block_names_round_trip() {
+        let dir = tempdir().unwrap();
+        let md = "```python #main file=out.py\n<<my block name>>\n<<\"größe\">>\n```\n\n\
+                  ```python #\"my block name\"\nx = 1\n```\n\n\
+                  ```python #größe\ny = 2\n```\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let mut ctx = Context::new(Default::default(), dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert!(tangled.contains("# ~/~ begin <<doc.md#my block name[0]>>\nx = 1\n"));
+        assert!(tangled.contains("# ~/~ begin <<doc.md#größe[0]>>\ny = 2\n"));
+
+        fs::write(&out, tangled.replace("x = 1", "x = 3")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let doc = fs::read_to_string(dir.path().join("doc.md")).unwrap();
+        assert!(doc.contains("#\"my block name\"\nx = 3\n"));
+    }
+
+    #[test]
+    fn test_case_insensitive_names() {
+        let dir = tempdir().unwrap();
+        let md = "```python #Main file=out.py\n<<helper>>\n```\n\n\
+                  ```python #Helper\nx = 1\n```\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let exact = Context::new(Default::default(), dir.path().to_path_buf()).unwrap();
+        assert!(tangle_documents(&exact).is_err());
+        drop(exact);
+
+        let config = crate::config::Config {
+            case_insensitive_names: true,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert!(tangled.contains("<<doc.md#helper[0]>>"));
+
+        // Stitching looks blocks up with the same folding
+        fs::write(&out, tangled.replace("x = 1", "x = 2")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let doc = fs::read_to_string(dir.path().join("doc.md")).unwrap();
+        assert!(doc.contains("#Helper\nx = 2"));
+    }
+
+    #[test]
+    fn test_case_insensitive_names_warn_across_documents() {
+        #[derive(Clone, Default)]
+        struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.md"), "```python #Helper\nx = 1\n```\n").unwrap();
+        fs::write(
+            dir.path().join("b.md"),
+            "```python file=out.py\n<<helper>>\n```\n\n```python #helper\ny = 2\n```\n",
+        )
+        .unwrap();
+        let config = crate::config::Config {
+            case_insensitive_names: true,
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || tangle_documents(&ctx).unwrap());
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            logs.contains("Block names 'Helper' and 'helper' differ only by case"),
+            "{}",
+            logs
+        );
+    }
+
+    #[test]
+    fn test_tangle_stream_writes() {
+        let dir = tempdir().unwrap();
+        let md = "```python #main file=output.py\ndef main():\n    <<body>>\n```\n\n\
+                  ```python #body\nprint('hello')\n```\n";
+        fs::write(dir.path().join("test.md"), md).unwrap();
+
+        let buffered = Context::new(Default::default(), dir.path().to_path_buf()).unwrap();
+        let expected = buffered.tangle_target(Path::new("output.py")).unwrap();
+        drop(buffered);
+
+        let config = crate::config::Config {
+            stream_writes: true,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        let tx = tangle_documents(&ctx).unwrap();
+        assert_eq!(tx.actions().next().unwrap().proposed_content(), None);
+        tx.execute(&mut ctx.filedb).unwrap();
+
+        let out = dir.path().join("output.py");
+        assert_eq!(fs::read_to_string(&out).unwrap(), expected);
+        // The recorded digest matches, so a second run sees no conflict
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+
+        // A broken reference fails planning, before anything is written
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\n<<missing>>\n```\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            tangle_documents(&ctx),
+            Err(EntangledError::ReferenceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_tangle_content_without_path() {
+        let (dir, ctx) = setup_test_dir();
+
+        // No source path, so `#main` is not namespaced and <<body>> resolves
+        let tx = tangle_content(
+            &ctx,
+            "```python #main file=output.py\n<<body>>\n```\n\n```python #body\nprint('hi')\n```\n",
+        )
+        .unwrap();
+        assert_eq!(tx.len(), 1);
+        assert!(tx
+            .describe()
+            .iter()
+            .any(|d| d.contains(&dir.path().join("output.py").display().to_string())));
+    }
+
     #[test]
     fn test_empty_document() {
         let (dir, ctx) = setup_test_dir();
@@ -518,6 +2076,141 @@ print('hello')
         );
     }
 
+    #[test]
+    fn test_deleted_target_is_recreated() {
+        let (dir, mut ctx) = setup_test_dir();
+        let out = dir.path().join("output.py");
+
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\nprint('hello')\n```\n",
+        )
+        .unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        fs::remove_file(&out).unwrap();
+
+        // Stitch has nothing to read back
+        assert!(stitch_documents(&ctx).unwrap().is_empty());
+
+        // Tangle recreates the file without forcing
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert!(fs::read_to_string(&out).unwrap().contains("print('hello')"));
+    }
+
+    #[test]
+    fn test_files_list_writes_every_target() {
+        let (dir, mut ctx) = setup_test_dir();
+        let md = dir.path().join("test.md");
+        let (a, b) = (dir.path().join("a.py"), dir.path().join("b.py"));
+
+        fs::write(
+            &md,
+            "```python #main files=\"a.py b.py\"\nprint('hello')\n```\n",
+        )
+        .unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert!(fs::read_to_string(&a).unwrap().contains("print('hello')"));
+        assert_eq!(
+            fs::read_to_string(&a).unwrap(),
+            fs::read_to_string(&b).unwrap()
+        );
+
+        // An edit through either copy is stitched back into the one block
+        let edited = fs::read_to_string(&b).unwrap().replace("hello", "bye");
+        fs::write(&b, edited).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute_force(&mut ctx.filedb)
+            .unwrap();
+        assert!(fs::read_to_string(&md).unwrap().contains("print('bye')"));
+    }
+
+    #[test]
+    fn test_tangle_deletes_stale_targets() {
+        let (dir, mut ctx) = setup_test_dir();
+        let md = dir.path().join("test.md");
+        let (a, b) = (dir.path().join("a.py"), dir.path().join("b.py"));
+        let run = |ctx: &mut Context| {
+            let tx = tangle_documents(ctx).unwrap();
+            ctx.execute(&tx, false).unwrap();
+            record_source_state(ctx).unwrap();
+        };
+
+        fs::write(
+            &md,
+            "```python #a file=a.py\nx = 1\n```\n\n```python #b file=b.py\ny = 2\n```\n",
+        )
+        .unwrap();
+        run(&mut ctx);
+
+        // Stitching tracks the markdown it writes; it must never be deleted
+        ctx.filedb
+            .record(md.clone(), crate::io::FileData::from_path(&md).unwrap());
+
+        fs::write(&md, "```python #a file=a.py\nx = 1\n```\n").unwrap();
+        let tx = tangle_documents(&ctx).unwrap();
+        let deleted: Vec<&Path> = tx
+            .actions()
+            .filter(|a| a.kind() == "delete")
+            .map(|a| a.target())
+            .collect();
+        assert_eq!(deleted, vec![b.as_path()]);
+        ctx.execute(&tx, false).unwrap();
+        assert!(!b.exists());
+        assert!(!ctx.filedb.is_tracked(&b));
+        assert!(md.exists());
+
+        // Without pruning, nothing is deleted
+        fs::write(&md, "# nothing\n").unwrap();
+        ctx.config.prune = false;
+        assert!(tangle_documents(&ctx).unwrap().is_empty());
+
+        // A stale target edited since the last tangle is a conflict
+        ctx.config.prune = true;
+        fs::write(&a, "edited\n").unwrap();
+        let tx = tangle_documents(&ctx).unwrap();
+        assert!(ctx.execute(&tx, false).is_err());
+        assert!(a.exists());
+    }
+
+    #[test]
+    fn test_prune_filedb_forgets_missing_files() {
+        let (dir, mut ctx) = setup_test_dir();
+        let out = dir.path().join("output.py");
+        let gone = dir.path().join("gone.py");
+        ctx.filedb.record(
+            gone.clone(),
+            crate::io::FileData::from_content("x", chrono::Utc::now()),
+        );
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\nprint('hello')\n```\n",
+        )
+        .unwrap();
+        let tx = tangle_documents(&ctx).unwrap();
+        ctx.execute(&tx, false).unwrap();
+
+        assert_eq!(prune_filedb(&mut ctx), vec![gone.clone()]);
+        assert!(!ctx.filedb.is_tracked(&gone));
+        assert!(ctx.filedb.is_tracked(&out));
+
+        ctx.config.prune = false;
+        ctx.filedb.record(
+            gone.clone(),
+            crate::io::FileData::from_content("x", chrono::Utc::now()),
+        );
+        assert!(prune_filedb(&mut ctx).is_empty());
+    }
+
     #[test]
     fn test_stitch_detects_modification() {
         let (dir, mut ctx) = setup_test_dir();
@@ -569,28 +2262,451 @@ print('hello')
         );
     }
 
+    /// Substitutes the version for `VERSION` when tangling, and puts the
+    /// placeholder back when stitching.
+    struct VersionHook;
+
+    impl crate::hooks::Hook for VersionHook {
+        fn name(&self) -> &str {
+            "version"
+        }
+
+        fn pre_tangle(&self, _block: &CodeBlock) -> Result<Option<crate::hooks::PreTangleResult>> {
+            Ok(None)
+        }
+
+        fn post_tangle(
+            &self,
+            content: &str,
+            _block: &CodeBlock,
+        ) -> Result<Option<crate::hooks::PostTangleResult>> {
+            Ok(Some(crate::hooks::PostTangleResult {
+                prefix: None,
+                content: content.replace("VERSION", "1.0"),
+                suffix: None,
+            }))
+        }
+
+        fn pre_stitch(&self, content: &str, _block: &CodeBlock) -> Result<Option<String>> {
+            Ok(Some(content.replace("1.0", "VERSION")))
+        }
+    }
+
     #[test]
-    fn test_stitch_preserves_markdown_structure() {
+    fn test_stitch_runs_pre_stitch_hooks() {
         let (dir, mut ctx) = setup_test_dir();
-
+        ctx.add_hook(VersionHook);
         let md_path = dir.path().join("test.md");
         fs::write(
             &md_path,
-            r#"# My Document
-
-Some description.
+            "```python #main file=output.py\nversion = 'VERSION'\n```\n",
+        )
+        .unwrap();
 
-```python #main file=output.py
-print('hello')
-```
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output_path = dir.path().join("output.py");
+        let tangled = fs::read_to_string(&output_path).unwrap();
+        assert!(tangled.contains("# ~/~ begin <<test.md#main[0]>>\nversion = '1.0'\n"));
 
-More text after the code block.
-"#,
+        // The substitution inside the markers is undone; only the edit is
+        // stitched back
+        fs::write(
+            &output_path,
+            tangled.replace("'1.0'", "'1.0'\ndebug = False"),
         )
         .unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute_force(&mut ctx.filedb)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&md_path).unwrap(),
+            "```python #main file=output.py\nversion = 'VERSION'\ndebug = False\n```\n"
+        );
+    }
 
-        // Tangle
-        let tangle_tx = tangle_documents(&ctx).unwrap();
+    #[test]
+    fn test_tangle_moves_shebang_and_spdx_out_of_block() {
+        let dir = tempdir().unwrap();
+        let mut config = crate::config::Config::default();
+        config.hooks.shebang = true;
+        config.hooks.spdx_license = true;
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        let md_path = dir.path().join("test.md");
+        let source =
+            "```bash #run file=run.sh\n#!/bin/bash\n# SPDX-License-Identifier: MIT\necho hi\n```\n";
+        fs::write(&md_path, source).unwrap();
+
+        let tx = tangle_documents(&ctx).unwrap();
+        ctx.execute(&tx, false).unwrap();
+        let output_path = dir.path().join("run.sh");
+        let tangled = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(
+            tangled,
+            "#!/bin/bash\n# SPDX-License-Identifier: MIT\n\
+             # ~/~ begin <<test.md#run[0]>>\necho hi\n# ~/~ end"
+        );
+
+        // Unedited, nothing is stitched; an edit keeps both lines in the block
+        assert!(stitch_documents(&ctx).unwrap().is_empty());
+        fs::write(&output_path, tangled.replace("echo hi", "echo bye")).unwrap();
+        let tx = stitch_documents(&ctx).unwrap();
+        ctx.execute(&tx, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&md_path).unwrap(),
+            source.replace("echo hi", "echo bye")
+        );
+    }
+
+    #[test]
+    fn test_normalize_output() {
+        assert_eq!(normalize_output("a  \n\tb\t\n\n\n"), "a\n\tb\n");
+        assert_eq!(normalize_output("a"), "a\n");
+        assert_eq!(normalize_output("  \n"), "");
+        assert_eq!(normalize_output(""), "");
+    }
+
+    #[test]
+    fn test_tangle_keeps_trailing_newline() {
+        for stream_writes in [false, true] {
+            let (dir, mut ctx) = setup_test_dir();
+            ctx.config.stream_writes = stream_writes;
+            let md_path = dir.path().join("test.md");
+            let output_path = dir.path().join("output.py");
+            fs::write(&md_path, "```python #main file=output.py\nx = 1\n```\n").unwrap();
+
+            let tx = tangle_documents(&ctx).unwrap();
+            ctx.execute(&tx, false).unwrap();
+            let tangled = fs::read_to_string(&output_path).unwrap();
+            assert!(!tangled.ends_with('\n'));
+
+            // A newline added to the target by hand survives re-tangling
+            fs::write(&output_path, format!("{}\n", tangled)).unwrap();
+            ctx.filedb.record(
+                output_path.clone(),
+                crate::io::FileData::from_path(&output_path).unwrap(),
+            );
+            fs::write(&md_path, "```python #main file=output.py\nx = 2\n```\n").unwrap();
+            let tx = tangle_documents(&ctx).unwrap();
+            ctx.execute(&tx, false).unwrap();
+            let tangled = fs::read_to_string(&output_path).unwrap();
+            assert!(tangled.contains("x = 2"));
+            assert!(tangled.ends_with("end\n") && !tangled.ends_with("\n\n"));
+        }
+    }
+
+    #[test]
+    fn test_normalized_output_is_stable() {
+        let (dir, mut ctx) = setup_test_dir();
+        ctx.config.normalize_output = true;
+        let md_path = dir.path().join("test.md");
+        let md = "```python #main file=output.py\ndef f():  \n    <<body>>\n\n```\n\n\
+                  ```python #body\nx = 1\n\ny = 2 \n```\n";
+        fs::write(&md_path, md).unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output_path = dir.path().join("output.py");
+        let tangled = fs::read_to_string(&output_path).unwrap();
+        assert!(tangled.lines().all(|l| l == l.trim_end()));
+        assert!(tangled.ends_with("\n") && !tangled.ends_with("\n\n"));
+
+        // Neither a second tangle nor a stitch changes anything
+        assert!(tangle_documents(&ctx).unwrap().diffs().is_empty());
+        assert!(stitch_documents(&ctx).unwrap().is_empty());
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), tangled);
+        assert_eq!(fs::read_to_string(&md_path).unwrap(), md);
+    }
+
+    #[test]
+    fn test_stitch_ignores_trailing_whitespace() {
+        let (dir, mut ctx) = setup_test_dir();
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=output.py\nx = 1\n```\n",
+        )
+        .unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+
+        let output_path = dir.path().join("output.py");
+        let tangled = fs::read_to_string(&output_path).unwrap();
+        fs::write(&output_path, tangled.replace("x = 1", "x = 1  \n")).unwrap();
+        assert!(stitch_documents(&ctx).unwrap().is_empty());
+
+        fs::write(&output_path, tangled.replace("x = 1", "x = 2")).unwrap();
+        assert!(!stitch_documents(&ctx).unwrap().is_empty());
+
+        assert!(stitch_equal("a  \nb\n\n \n", "a\nb"));
+        assert!(!stitch_equal("a\n\nb", "a\nb"));
+    }
+
+    #[test]
+    fn test_invalid_utf8_sources() {
+        let (dir, mut ctx) = setup_test_dir();
+        fs::write(
+            dir.path().join("a.md"),
+            "```python #main file=out.py\nx = 1\n```\n",
+        )
+        .unwrap();
+        // A binary file matched by `**/*.md` is skipped with a warning
+        fs::write(
+            dir.path().join("image.md"),
+            b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR",
+        )
+        .unwrap();
+
+        let tx = tangle_documents(&ctx).unwrap();
+        tx.execute(&mut ctx.filedb).unwrap();
+        assert!(dir.path().join("out.py").exists());
+
+        // Text in another encoding is an error naming the file and offset
+        fs::write(dir.path().join("latin1.md"), b"caf\xe9\n").unwrap();
+        match collect_refs(&ctx, &ctx.source_files().unwrap()) {
+            Err(EntangledError::InvalidUtf8 {
+                path,
+                offset,
+                binary,
+            }) => {
+                assert_eq!(path, PathBuf::from("latin1.md"));
+                assert_eq!(offset, 3);
+                assert!(!binary);
+            }
+            other => panic!("expected InvalidUtf8, got {:?}", other.map(|_| ())),
+        }
+
+        // ... unless invalid files are skipped
+        ctx.config.skip_invalid = true;
+        let refs = collect_refs(&ctx, &ctx.source_files().unwrap()).unwrap();
+        assert_eq!(refs.len(), 1);
+    }
+
+    #[test]
+    fn test_skipped_invalid_source_keeps_its_targets() {
+        let (dir, mut ctx) = setup_test_dir();
+        fs::write(
+            dir.path().join("a.md"),
+            "```python #main file=out.py\nx = 1\n```\n",
+        )
+        .unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        record_source_state(&mut ctx).unwrap();
+
+        // The source is no longer valid UTF-8, so its targets are unknown
+        fs::write(
+            dir.path().join("a.md"),
+            b"```python #main file=out.py\ncaf\xe9\n```\n",
+        )
+        .unwrap();
+        ctx.config.skip_invalid = true;
+        let tx = tangle_documents(&ctx).unwrap();
+        assert!(tx.actions().all(|a| a.kind() != "delete"));
+        assert!(ctx.has_skipped_documents());
+
+        // Sync neither prunes nor records the skipped source as tangled
+        let recorded = ctx.filedb.last_tangle.clone();
+        sync_documents(&mut ctx, false).unwrap();
+        assert!(dir.path().join("out.py").exists());
+        assert_eq!(ctx.filedb.last_tangle, recorded);
+        assert!(!ctx.has_skipped_documents());
+    }
+
+    #[test]
+    fn test_keep_going_skips_documents_that_fail_to_parse() {
+        let (dir, mut ctx) = setup_test_dir();
+        fs::write(
+            dir.path().join("good.md"),
+            "```python file=good.py\ngood = 1\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("other.md"),
+            "```python file=other.py\nother = 1\n```\n",
+        )
+        .unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+
+        fs::write(
+            dir.path().join("good.md"),
+            "```python file=good.py\ngood = 2\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("other.md"),
+            "```{.python file=other.py}\n```\n",
+        )
+        .unwrap();
+        assert!(tangle_documents(&ctx).is_err());
+
+        ctx.config.keep_going = true;
+        let tx = tangle_documents(&ctx).unwrap();
+        // The broken document's target is not pruned
+        assert!(tx.actions().all(|a| a.kind() != "delete"));
+        tx.execute(&mut ctx.filedb).unwrap();
+        assert!(fs::read_to_string(dir.path().join("good.py"))
+            .unwrap()
+            .contains("good = 2"));
+        assert!(dir.path().join("other.py").exists());
+
+        let failed = ctx.take_failed_documents();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, PathBuf::from("other.md"));
+        assert!(matches!(failed[0].1, EntangledError::InvalidHeader { .. }));
+        assert!(!ctx.has_failed_documents());
+
+        // Sync fails at the end, naming the broken document, and does not
+        // record the sources as tangled
+        let error = sync_documents(&mut ctx, false).unwrap_err().to_string();
+        assert!(error.contains("1 document failed to parse"));
+        assert!(error.contains("other.md"));
+        assert!(ctx.filedb.last_tangle.is_none());
+    }
+
+    #[test]
+    fn test_stitch_keeps_bom() {
+        let (dir, mut ctx) = setup_test_dir();
+        let md_path = dir.path().join("test.md");
+        let md = "\u{feff}---\ntitle: Demo\n---\n\n```python #main file=output.py\nx = 1\n```\n";
+        fs::write(&md_path, md).unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output_path = dir.path().join("output.py");
+        let tangled = fs::read_to_string(&output_path).unwrap();
+        assert!(!tangled.contains('\u{feff}'));
+
+        fs::write(&output_path, tangled.replace("x = 1", "x = 2")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&md_path).unwrap(),
+            md.replace("x = 1", "x = 2")
+        );
+    }
+
+    #[test]
+    fn test_stitch_block_at_eof_without_newline() {
+        let (dir, mut ctx) = setup_test_dir();
+        let md_path = dir.path().join("test.md");
+        let md = "```python #main file=output.py\nx = 1\ny = 2\n```";
+        fs::write(&md_path, md).unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output_path = dir.path().join("output.py");
+        let tangled = fs::read_to_string(&output_path).unwrap();
+        fs::write(&output_path, tangled.replace("y = 2", "y = 3")).unwrap();
+
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute_force(&mut ctx.filedb)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&md_path).unwrap(),
+            md.replace("y = 2", "y = 3")
+        );
+    }
+
+    #[test]
+    fn test_stitch_keeps_line_endings() {
+        let (dir, mut ctx) = setup_test_dir();
+        let md_path = dir.path().join("test.md");
+        let md = "# Title\r\n\n```python #main file=output.py\r\nx = 1\r\n```\nText\r\n";
+        fs::write(&md_path, md).unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output_path = dir.path().join("output.py");
+        let tangled = fs::read_to_string(&output_path).unwrap();
+        fs::write(&output_path, tangled.replace("x = 1", "x = 2\ny = 3")).unwrap();
+
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute_force(&mut ctx.filedb)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&md_path).unwrap(),
+            "# Title\r\n\n```python #main file=output.py\r\nx = 2\r\ny = 3\r\n```\nText\r\n"
+        );
+    }
+
+    #[test]
+    fn test_stitch_keeps_reference_escape() {
+        let (dir, mut ctx) = setup_test_dir();
+
+        let md_path = dir.path().join("test.md");
+        let md = "```python #main file=output.py\nprint('hello')\n\\<<not_a_ref>>\n```\n";
+        fs::write(&md_path, md).unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output_path = dir.path().join("output.py");
+        let tangled = fs::read_to_string(&output_path).unwrap();
+        assert!(tangled.contains("\n<<not_a_ref>>\n"));
+
+        // An unchanged target has nothing to stitch
+        assert!(stitch_documents(&ctx).unwrap().is_empty());
+
+        fs::write(&output_path, tangled.replace("hello", "world")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute_force(&mut ctx.filedb)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&md_path).unwrap(),
+            md.replace("hello", "world")
+        );
+    }
+
+    #[test]
+    fn test_stitch_preserves_markdown_structure() {
+        let (dir, mut ctx) = setup_test_dir();
+
+        let md_path = dir.path().join("test.md");
+        fs::write(
+            &md_path,
+            r#"# My Document
+
+Some description.
+
+```python #main file=output.py
+print('hello')
+```
+
+More text after the code block.
+"#,
+        )
+        .unwrap();
+
+        // Tangle
+        let tangle_tx = tangle_documents(&ctx).unwrap();
         tangle_tx.execute(&mut ctx.filedb).unwrap();
 
         // Modify tangled file
@@ -733,4 +2849,843 @@ print('hello')
         let stitch_tx = stitch_documents(&ctx).unwrap();
         assert!(stitch_tx.is_empty(), "Stitch should skip naked-mode files");
     }
+
+    #[test]
+    fn test_no_annotation_languages_json_target() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            no_annotation_languages: vec!["json".to_string()],
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+
+        fs::write(
+            dir.path().join("test.md"),
+            r#"
+```json #settings file=settings.json
+{"debug": true}
+```
+
+```python #main file=main.py
+print('hello')
+```
+"#,
+        )
+        .unwrap();
+
+        let tangle_tx = tangle_documents(&ctx).unwrap();
+        tangle_tx.execute(&mut ctx.filedb).unwrap();
+
+        let json = fs::read_to_string(dir.path().join("settings.json")).unwrap();
+        assert_eq!(json.trim_end(), r#"{"debug": true}"#);
+        let python = fs::read_to_string(dir.path().join("main.py")).unwrap();
+        assert!(python.contains("~/~ begin"));
+
+        // Editing the naked JSON target must not be stitched back
+        fs::write(dir.path().join("settings.json"), "{\"debug\": false}\n").unwrap();
+        let stitch_tx = stitch_documents(&ctx).unwrap();
+        assert!(stitch_tx.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_ids_across_files_still_concatenate() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            namespace_default: crate::config::NamespaceDefault::None,
+            annotation: crate::config::AnnotationMethod::Naked,
+            ..Default::default()
+        };
+        let ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+
+        let a = dir.path().join("a.md");
+        let b = dir.path().join("b.md");
+        fs::write(&a, "```python #main file=out.py\nprint('a')\n```\n").unwrap();
+        fs::write(&b, "```python #main\nprint('b')\n```\n").unwrap();
+
+        // Duplicates only warn; both definitions are kept in path order,
+        // whatever the order the files are read in
+        for files in [[a.clone(), b.clone()], [b, a]] {
+            let refs = collect_refs(&ctx, &files).unwrap();
+            assert_eq!(
+                refs.concatenate_source(&ReferenceName::new("main"))
+                    .unwrap(),
+                "print('a')\nprint('b')"
+            );
+        }
+    }
+
+    #[test]
+    fn test_deny_duplicate_ids() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            namespace_default: crate::config::NamespaceDefault::None,
+            deny_duplicate_ids: true,
+            ..Default::default()
+        };
+        let ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+
+        let a = dir.path().join("a.md");
+        let b = dir.path().join("b.md");
+        fs::write(&a, "```python #main file=out.py\nprint('a')\n```\n").unwrap();
+        fs::write(&b, "```python #main\nprint('b')\n```\n").unwrap();
+
+        let err = collect_refs(&ctx, &[a, b]).unwrap_err();
+        let EntangledError::DuplicateBlock {
+            name,
+            first,
+            second,
+        } = &err
+        else {
+            panic!("expected DuplicateBlock, got {err}");
+        };
+        assert_eq!(name, &ReferenceName::new("main"));
+        assert!(first.filename.as_ref().unwrap().ends_with("a.md"));
+        assert!(second.filename.as_ref().unwrap().ends_with("b.md"));
+    }
+
+    fn naked_context(dir: &Path) -> Context {
+        let config = crate::config::Config {
+            namespace_default: crate::config::NamespaceDefault::None,
+            annotation: crate::config::AnnotationMethod::Naked,
+            ..Default::default()
+        };
+        Context::new(config, dir.to_path_buf()).unwrap()
+    }
+
+    #[test]
+    fn test_mode_append_concatenates_in_document_order() {
+        let dir = tempdir().unwrap();
+        let mut ctx = naked_context(dir.path());
+
+        fs::write(
+            dir.path().join("a.md"),
+            "```toml #server file=config.toml mode=append\n[server]\n```\n\n\
+             ```toml #client file=config.toml mode=append\n[client]\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("b.md"),
+            "```toml #logging file=config.toml mode=append\n[logging]\n```\n",
+        )
+        .unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output = fs::read_to_string(dir.path().join("config.toml")).unwrap();
+        assert_eq!(output, "[server]\n[client]\n[logging]");
+    }
+
+    #[test]
+    fn test_mode_prepend_reverses_order() {
+        let dir = tempdir().unwrap();
+        let ctx = naked_context(dir.path());
+
+        let md_path = dir.path().join("test.md");
+        fs::write(
+            &md_path,
+            "```sh #first file=out.sh mode=prepend\necho 1\n```\n\n\
+             ```sh #second file=out.sh mode=prepend\necho 2\n```\n",
+        )
+        .unwrap();
+
+        let output = ctx.tangle_target(Path::new("out.sh")).unwrap();
+        assert_eq!(output, "echo 2\necho 1");
+    }
+
+    #[test]
+    fn test_mode_conflict_is_an_error() {
+        let dir = tempdir().unwrap();
+        let ctx = naked_context(dir.path());
+
+        fs::write(
+            dir.path().join("test.md"),
+            "```sh #first file=out.sh mode=append\necho 1\n```\n\n\
+             ```sh #second file=out.sh mode=prepend\necho 2\n```\n",
+        )
+        .unwrap();
+
+        let err = tangle_documents(&ctx).unwrap_err();
+        assert!(matches!(err, EntangledError::Config(_)));
+        assert!(err.to_string().contains("Conflicting mode values"));
+    }
+
+    #[test]
+    fn test_locate_after_nested_reference() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("test.md"),
+            "```python #main file=out.py\ndef main():\n    <<body>>\n    return 0\n```\n\n```python #body\nprint('hi')\n```\n",
+        )
+        .unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let output = fs::read_to_string(&out).unwrap();
+        let return_line = output.lines().position(|l| l.contains("return 0")).unwrap() + 1;
+        let loc = locate_source(&ctx, &out, return_line).unwrap().unwrap();
+        assert_eq!(loc.source_line, 4);
+    }
+
+    #[test]
+    fn test_line_directives_round_trip() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            line_directives: true,
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+
+        let md_path = dir.path().join("test.md");
+        fs::write(
+            &md_path,
+            "---\ntitle: Test\n---\n\n```c #main file=main.c\nint main() {\n    <<body>>\n    return 0;\n}\n```\n\n```c #body\nputs(\"hi\");\n```\n",
+        )
+        .unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output = fs::read_to_string(dir.path().join("main.c")).unwrap();
+        // Source paths are relative to the project directory
+        let md = "test.md";
+        // Fence is on line 5 (after the 3-line YAML header and a blank line)
+        assert!(output.contains(&format!("#line 6 \"{}\"", md)));
+        assert!(output.contains(&format!("#line 13 \"{}\"", md)));
+        assert!(output.contains(&format!("#line 8 \"{}\"", md)));
+
+        // Directives are not stitched back into the markdown
+        let stitch_tx = stitch_documents(&ctx).unwrap();
+        assert!(stitch_tx.is_empty());
+
+        // Locate skips directives and accounts for the nested block
+        let main_c = dir.path().join("main.c");
+        let lines: Vec<&str> = output.lines().collect();
+        let return_line = lines.iter().position(|l| l.contains("return 0")).unwrap() + 1;
+        let loc = locate_source(&ctx, &main_c, return_line).unwrap().unwrap();
+        assert_eq!(loc.source_line, 8);
+    }
+
+    #[test]
+    fn test_custom_markers_round_trip() {
+        let dir = tempdir().unwrap();
+        let md = "```python #main file=out.py\nx = 1\n```\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let config = crate::config::Config {
+            markers: Markers::new("{{", "}}", "start", "stop").with_sigil("@@"),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert_eq!(tangled, "# @@ start {{doc.md#main[0]}}\nx = 1\n# @@ stop");
+
+        let loc = locate_source(&ctx, &out, 2).unwrap().unwrap();
+        assert_eq!(loc.source_line, 2);
+        let targets = locate_target(&ctx, Path::new("doc.md"), 2).unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].target_line, 2);
+
+        fs::write(&out, tangled.replace("x = 1", "x = 2")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let doc = fs::read_to_string(dir.path().join("doc.md")).unwrap();
+        assert_eq!(doc, md.replace("x = 1", "x = 2"));
+    }
+
+    #[test]
+    fn test_compact_markers_round_trip() {
+        let dir = tempdir().unwrap();
+        let md = "```python #main file=out.py\ndef f():\n    <<body>>\nprint(f())\n```\n\n\
+                  ```python #body\nx = 1\nreturn x\n```\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let config = crate::config::Config {
+            markers: Markers::default().with_compact(true),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert_eq!(
+            tangled,
+            "# ~/~ begin <<doc.md#main[0]>>\ndef f():\n    x = 1\n    return x\n    \
+             # ~/~ end <<doc.md#body[0]>> lines:2\nprint(f())\n# ~/~ end"
+        );
+
+        // Line 4 is `return x`, line 6 `print(f())`
+        let loc = locate_source(&ctx, &out, 4).unwrap().unwrap();
+        assert_eq!(
+            (loc.block_id.to_string(), loc.source_line),
+            ("doc.md#body[0]".into(), 9)
+        );
+        let loc = locate_source(&ctx, &out, 6).unwrap().unwrap();
+        assert_eq!(loc.source_line, 4);
+        let targets = locate_target(&ctx, Path::new("doc.md"), 3).unwrap();
+        assert_eq!(targets[0].target_line, 5);
+
+        fs::write(&out, tangled.replace("x = 1", "x = 2")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let doc = fs::read_to_string(dir.path().join("doc.md")).unwrap();
+        assert_eq!(doc, md.replace("x = 1", "x = 2"));
+    }
+
+    #[test]
+    fn test_compact_markers_refuse_wrong_line_count() {
+        let dir = tempdir().unwrap();
+        let md = "```python #main file=out.py\ndef f():\n    <<body>>\nprint(f())\n```\n\n\
+                  ```python #body\nx = 1\nreturn x\n```\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let config = crate::config::Config {
+            markers: Markers::default().with_compact(true),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let tangled = fs::read_to_string(&out).unwrap();
+
+        // A line added to the compact block without updating `lines:2`
+        // would leave `x = 1` in `main`, and stitch would drop it
+        fs::write(&out, tangled.replace("x = 1", "x = 1\n    y = 2")).unwrap();
+        let error = stitch_documents(&ctx).unwrap_err().to_string();
+        assert!(error.contains("lines:"), "{error}");
+
+        // A removed line would take `def f():` from `main` instead
+        fs::write(&out, tangled.replace("    x = 1\n", "")).unwrap();
+        assert!(stitch_documents(&ctx).is_err());
+
+        // With the count updated, the edit is stitched
+        fs::write(
+            &out,
+            tangled
+                .replace("x = 1", "x = 1\n    y = 2")
+                .replace("lines:2", "lines:3"),
+        )
+        .unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let doc = fs::read_to_string(dir.path().join("doc.md")).unwrap();
+        assert_eq!(doc, md.replace("x = 1", "x = 1\ny = 2"));
+    }
+
+    #[test]
+    fn test_locate_source_range_crosses_blocks() {
+        let dir = tempdir().unwrap();
+        let md = "```python #main file=out.py\na = 1\n<<helper>>\nb = 2\n```\n\n```python #helper\nx = 1\ny = 2\n```\n";
+        fs::write(dir.path().join("doc.md"), md).unwrap();
+
+        let mut ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        // 1: begin main, 2: a = 1, 3: begin helper, 4: x = 1, 5: y = 2,
+        // 6: end, 7: b = 2, 8: end
+        let out = dir.path().join("out.py");
+
+        let ranges = locate_source_range(&ctx, &out, 2, 4).unwrap();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].block_id.to_string(), "doc.md#main[0]");
+        assert_eq!((ranges[0].first_line, ranges[0].last_line), (2, 2));
+        assert_eq!(ranges[1].block_id.to_string(), "doc.md#helper[0]");
+        assert_eq!((ranges[1].first_line, ranges[1].last_line), (8, 8));
+
+        // Spanning the whole file merges the lines on both sides of the helper
+        let ranges = locate_source_range(&ctx, &out, 1, 8).unwrap();
+        assert_eq!((ranges[0].first_line, ranges[0].last_line), (2, 4));
+        assert_eq!((ranges[1].first_line, ranges[1].last_line), (8, 9));
+        assert_eq!(ranges[1].to_string(), "doc.md:8-9");
+
+        // Marker lines only
+        assert!(locate_source_range(&ctx, &out, 6, 6).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fingerprints_survive_renumbering() {
+        let dir = tempdir().unwrap();
+        let md = dir.path().join("doc.md");
+        let out = dir.path().join("out.py");
+        let fence = |id: &str, body: &str| format!("```python #{}\n{}\n```\n\n", id, body);
+        let main = "```python #main file=out.py\n<<part>>\n```\n\n";
+        fs::write(
+            &md,
+            format!(
+                "{}{}{}",
+                main,
+                fence("part", "a = 1"),
+                fence("part", "b = 2")
+            ),
+        )
+        .unwrap();
+
+        let config = crate::config::Config {
+            markers: Markers::default().with_fingerprints(true),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert!(tangled.contains(&format!("<<doc.md#part[1]>> hash:{}", fingerprint("b = 2"))));
+
+        // A block inserted before the others shifts their indices
+        fs::write(
+            &md,
+            format!(
+                "{}{}{}{}",
+                main,
+                fence("part", "z = 0"),
+                fence("part", "a = 1"),
+                fence("part", "b = 2")
+            ),
+        )
+        .unwrap();
+        fs::write(&out, tangled.replace("b = 2", "b = 3")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute_force(&mut ctx.filedb)
+            .unwrap();
+
+        let doc = fs::read_to_string(&md).unwrap();
+        assert!(doc.contains("z = 0") && doc.contains("a = 1") && doc.contains("b = 3"));
+        assert!(!doc.contains("b = 2"));
+    }
+
+    #[test]
+    fn test_fingerprints_edit_sync_tangle() {
+        let dir = tempdir().unwrap();
+        let md = dir.path().join("m.md");
+        let out = dir.path().join("m.py");
+        fs::write(&md, "```python #main file=m.py\nx = 1\n```\n").unwrap();
+        let config = crate::config::Config {
+            markers: Markers::default().with_fingerprints(true),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        sync_documents(&mut ctx, false).unwrap();
+
+        // The edit is stitched, and the target's fingerprint refreshed
+        let tangled = fs::read_to_string(&out).unwrap();
+        fs::write(&out, tangled.replace("x = 1", "x = 2")).unwrap();
+        sync_documents(&mut ctx, false).unwrap();
+        assert!(fs::read_to_string(&md).unwrap().contains("x = 2"));
+        assert!(fs::read_to_string(&out)
+            .unwrap()
+            .contains(&format!("hash:{}", fingerprint("x = 2"))));
+
+        // Later tangles need no force
+        fs::write(&md, "```python #main file=m.py\nx = 3\n```\n").unwrap();
+        let tx = tangle_documents(&ctx).unwrap();
+        ctx.execute(&tx, false).unwrap();
+        assert!(fs::read_to_string(&out).unwrap().contains("x = 3"));
+
+        // An edit that is not stitched still conflicts
+        let tangled = fs::read_to_string(&out).unwrap();
+        fs::write(&out, format!("# note\n{}", tangled)).unwrap();
+        fs::write(&md, "```python #main file=m.py\nx = 4\n```\n").unwrap();
+        let tx = tangle_documents(&ctx).unwrap();
+        assert!(matches!(
+            ctx.execute(&tx, false),
+            Err(EntangledError::FileConflict { .. })
+        ));
+    }
+
+    #[test]
+    fn test_source_paths_disambiguate_stitch() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.md");
+        let b = dir.path().join("b.md");
+        let out = dir.path().join("out.py");
+        fs::write(
+            &a,
+            "```python #main file=out.py\n<<helper>>\n```\n\n```python #helper\na = 1\n```\n",
+        )
+        .unwrap();
+        fs::write(&b, "```python #helper\nb = 2\n```\n").unwrap();
+
+        let config = crate::config::Config {
+            markers: Markers::default().with_source_paths(true),
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let tangled = fs::read_to_string(&out).unwrap();
+        assert!(tangled.contains("<<helper[1]>> from:b.md"));
+
+        // Dropping the block from a.md renumbers the one in b.md to helper[0]
+        fs::write(&a, "```python #main file=out.py\n<<helper>>\n```\n").unwrap();
+        fs::write(&out, tangled.replace("b = 2", "b = 3")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute_force(&mut ctx.filedb)
+            .unwrap();
+
+        let doc = fs::read_to_string(&b).unwrap();
+        assert!(doc.contains("b = 3"));
+        assert!(!doc.contains("a = 1"));
+    }
+
+    #[test]
+    fn test_quarto_project_output_dir() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("_quarto.yml"),
+            "project:\n  output-dir: _site\n",
+        )
+        .unwrap();
+        let qmd = dir.path().join("doc.qmd");
+        fs::write(&qmd, "```{python}\n#| file: out.py\nx = 1\n```\n").unwrap();
+        fs::write(
+            dir.path().join("doc.md"),
+            "```python file=plain.py\ny = 1\n```\n",
+        )
+        .unwrap();
+
+        let config = crate::config::Config::builder()
+            .quarto_project_integration(true)
+            .build();
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("_site/out.py");
+        assert!(fs::read_to_string(&out).unwrap().contains("x = 1"));
+        assert!(!dir.path().join("out.py").exists());
+        // Non-Quarto documents are unaffected
+        assert!(dir.path().join("plain.py").exists());
+
+        // Stitch reads the target back from the same place
+        let tangled = fs::read_to_string(&out).unwrap();
+        fs::write(&out, tangled.replace("x = 1", "x = 2")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert!(fs::read_to_string(&qmd).unwrap().contains("x = 2"));
+    }
+
+    #[test]
+    fn test_quarto_output_dir_keeps_same_named_targets_apart() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("_quarto.yml"),
+            "project:\n  output-dir: _site\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("a.qmd"),
+            "```{python}\n#| file: out.py\nx = 1\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("b.md"),
+            "```python file=out.py\ny = 2\n```\n",
+        )
+        .unwrap();
+
+        let config = crate::config::Config::builder()
+            .quarto_project_integration(true)
+            .build();
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let placed = fs::read_to_string(dir.path().join("_site/out.py")).unwrap();
+        assert!(placed.contains("x = 1") && !placed.contains("y = 2"));
+        let plain = fs::read_to_string(dir.path().join("out.py")).unwrap();
+        assert!(plain.contains("y = 2") && !plain.contains("x = 1"));
+    }
+
+    #[test]
+    fn test_output_dir_places_targets() {
+        let dir = tempdir().unwrap();
+        let md = dir.path().join("doc.md");
+        fs::write(&md, "```python file=out.py\nx = 1\n```\n").unwrap();
+
+        let config = crate::config::Config::builder().output_dir("gen").build();
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("gen/out.py");
+        assert!(fs::read_to_string(&out).unwrap().contains("x = 1"));
+        assert!(!dir.path().join("out.py").exists());
+
+        // Stitch reads the target back from the same place
+        let tangled = fs::read_to_string(&out).unwrap();
+        fs::write(&out, tangled.replace("x = 1", "x = 2")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert!(fs::read_to_string(&md).unwrap().contains("x = 2"));
+
+        // The output directory may be outside the project, but a target
+        // may still not escape it
+        drop(ctx);
+        let build = tempdir().unwrap();
+        let config = crate::config::Config::builder()
+            .output_dir(build.path())
+            .build();
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert!(build.path().join("out.py").exists());
+        fs::write(&md, "```python file=../out.py\nx = 1\n```\n").unwrap();
+        assert!(tangle_documents(&ctx).is_err());
+    }
+
+    #[test]
+    fn test_dir_attribute_under_output_dir() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("doc.md"),
+            "```python file=out.py dir=pkg\nx = 1\n```\n",
+        )
+        .unwrap();
+
+        let config = crate::config::Config::builder().output_dir("gen").build();
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert!(dir.path().join("gen/pkg/out.py").exists());
+        assert!(!dir.path().join("pkg/out.py").exists());
+    }
+
+    #[test]
+    fn test_tangle_notebook_source() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            source_patterns: vec!["**/*.md".to_string(), "**/*.ipynb".to_string()],
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("analysis.ipynb"),
+            r##"{"cells": [{"cell_type": "code", "metadata": {}, "outputs": [],
+                "source": ["#| label: helper\n", "x = 1\n"]}],
+               "metadata": {"kernelspec": {"language": "python"}}}"##,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("main.md"),
+            "```python #main file=out.py\n<<helper>>\nprint(x)\n```\n",
+        )
+        .unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let output = fs::read_to_string(&out).unwrap();
+        assert!(output.contains("x = 1"));
+
+        // Edits to notebook blocks are not stitched back into the notebook
+        fs::write(&out, output.replace("x = 1", "x = 2")).unwrap();
+        assert!(stitch_documents(&ctx).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tangle_org_source() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            source_patterns: vec!["**/*.org".to_string()],
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("notes.org"),
+            "#+name: helper\n#+begin_src python\nx = 1\n#+end_src\n\n\
+             #+begin_src python :tangle out.py :noweb yes\n<<helper>>\nprint(x)\n#+end_src\n",
+        )
+        .unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let output = fs::read_to_string(&out).unwrap();
+        assert!(output.contains("x = 1"));
+        assert!(!output.contains("<<helper>>"));
+
+        // Org documents are not stitched yet
+        fs::write(&out, output.replace("x = 1", "x = 2")).unwrap();
+        assert!(stitch_documents(&ctx).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_org_targets_are_relative_to_document() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            source_patterns: vec!["**/*.org".to_string()],
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        fs::create_dir(dir.path().join("notes")).unwrap();
+        fs::write(
+            dir.path().join("notes/tools.org"),
+            "#+begin_src sh :tangle run.sh\necho hi\n#+end_src\n\n\
+             #+begin_src sh :tangle ../top.sh\necho top\n#+end_src\n",
+        )
+        .unwrap();
+
+        let tx = tangle_documents(&ctx).unwrap();
+        ctx.execute(&tx, false).unwrap();
+        assert!(dir.path().join("notes/run.sh").exists());
+        assert!(dir.path().join("top.sh").exists());
+        assert!(!dir.path().join("run.sh").exists());
+    }
+
+    #[test]
+    fn test_org_target_apart_from_same_named_root_target() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            source_patterns: vec!["**/*.org".to_string(), "**/*.md".to_string()],
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(
+            dir.path().join("sub/a.org"),
+            "#+begin_src python :tangle out.py\nx = 1\n#+end_src\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("r.md"),
+            "```python file=out.py\ny = 2\n```\n",
+        )
+        .unwrap();
+
+        let tx = tangle_documents(&ctx).unwrap();
+        ctx.execute(&tx, false).unwrap();
+        let nested = fs::read_to_string(dir.path().join("sub/out.py")).unwrap();
+        assert!(nested.contains("x = 1") && !nested.contains("y = 2"));
+        let root = fs::read_to_string(dir.path().join("out.py")).unwrap();
+        assert!(root.contains("y = 2") && !root.contains("x = 1"));
+    }
+
+    #[test]
+    fn test_tangle_and_stitch_asciidoc_source() {
+        let dir = tempdir().unwrap();
+        let config = crate::config::Config {
+            source_patterns: vec!["**/*.adoc".to_string()],
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        let adoc = dir.path().join("guide.adoc");
+        fs::write(
+            &adoc,
+            "= Guide\n\n[[helper]]\n[source,python]\n----\nx = 1\n----\n\n\
+             [source,python,file=out.py]\n----\n<<helper>>\nprint(x)\n----\n",
+        )
+        .unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let out = dir.path().join("out.py");
+        let output = fs::read_to_string(&out).unwrap();
+        assert!(output.contains("x = 1"));
+        assert!(output.contains("print(x)"));
+
+        fs::write(&out, output.replace("x = 1", "x = 2")).unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        assert!(fs::read_to_string(&adoc)
+            .unwrap()
+            .contains("[source,python]\n----\nx = 2\n----"));
+    }
+
+    #[test]
+    fn test_tangle_namespace_qualified_references() {
+        let dir = tempdir().unwrap();
+        let mut ctx = Context::new(Default::default(), dir.path().to_path_buf()).unwrap();
+        fs::write(
+            dir.path().join("a.md"),
+            "```python #helper\ndef helper(): pass\n```\n\n```python #local\na_local = 1\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("b.md"),
+            "```python #main file=out.py\n<<a.md#helper>>\n<<local>>\n```\n\n\
+             ```python #local\nb_local = 1\n```\n",
+        )
+        .unwrap();
+
+        tangle_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let output = fs::read_to_string(dir.path().join("out.py")).unwrap();
+        assert!(output.contains("def helper(): pass"));
+        assert!(output.contains("b_local = 1"));
+        assert!(!output.contains("a_local"));
+
+        // Qualified annotations still stitch back to the right file
+        let out = dir.path().join("out.py");
+        fs::write(
+            &out,
+            output.replace("def helper(): pass", "def helper(): return 1"),
+        )
+        .unwrap();
+        stitch_documents(&ctx)
+            .unwrap()
+            .execute(&mut ctx.filedb)
+            .unwrap();
+        let a = fs::read_to_string(dir.path().join("a.md")).unwrap();
+        assert!(a.contains("def helper(): return 1"));
+    }
 }
@@ -0,0 +1,401 @@
+//! Exporting woven documents: prose with fully expanded code blocks.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::errors::{EntangledError, Result};
+use crate::model::{expand_block, CodeBlock, ReferenceMap};
+use crate::readers::{
+    extract_yaml_header, is_notebook, parse_simple_yaml, DelimitedTokenGetter, ExtractResult,
+};
+
+use crate::style::Style;
+
+use super::context::Context;
+use super::document::collect_refs;
+
+/// Output format for [`export_document`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum ExportFormat {
+    /// Markdown, with every named code block replaced by its expansion.
+    #[default]
+    #[cfg_attr(feature = "clap", value(name = "md", alias = "markdown"))]
+    Markdown,
+    /// A minimal standalone HTML page: prose paragraphs, escaped as written,
+    /// and code in `<pre><code class="language-...">` for client-side
+    /// highlighting. Markdown markup in the prose is not rendered.
+    Html,
+}
+
+/// A piece of a source document, in order.
+enum Part<'a> {
+    /// A line of prose.
+    Prose(&'a str),
+    /// A code block: its raw lines, fence info, indentation, and the
+    /// expanded content if the block is named.
+    Code {
+        raw: &'a [&'a str],
+        info: String,
+        indent: String,
+        expanded: Option<String>,
+    },
+}
+
+/// Renders a source document with every named code block expanded.
+///
+/// References are inlined recursively, without annotations, so that each
+/// block shows the code it contributes. Prose, frontmatter and anonymous
+/// blocks are kept as written. The document does not need to match
+/// `source_patterns`, but references are resolved against all source files.
+pub fn export_document(ctx: &Context, path: &Path, format: ExportFormat) -> Result<String> {
+    render_document(ctx, path, "export", |header, parts| match format {
+        ExportFormat::Markdown => render_markdown(header, parts),
+        ExportFormat::Html => {
+            let title = parse_simple_yaml(&header.join("\n"))
+                .remove("title")
+                .unwrap_or_else(|| {
+                    let relative = path.strip_prefix(&ctx.base_dir).unwrap_or(path);
+                    relative.display().to_string()
+                });
+            render_html(&title, parts)
+        }
+    })
+}
+
+/// Returns a source document as written, with the body of every named code
+/// block replaced by its expansion.
+///
+/// Unlike [`export_document`], fence lines are kept exactly as written, so
+/// every prose line and fence stays where it was.
+pub(crate) fn weave_document(ctx: &Context, path: &Path) -> Result<String> {
+    let mut woven = render_document(ctx, path, "weave", render_woven)?;
+    if ctx.read_file(path)?.ends_with('\n') {
+        woven.push('\n');
+    }
+    Ok(woven)
+}
+
+/// Splits a source document into parts, with references resolved against
+/// all source files, and renders them with `render`.
+///
+/// `render` is given the lines of the document's YAML header and the parts
+/// of its body. `verb` names the operation in errors.
+fn render_document(
+    ctx: &Context,
+    path: &Path,
+    verb: &str,
+    render: impl FnOnce(&[&str], &[Part]) -> String,
+) -> Result<String> {
+    ctx.with_read_cache(|| {
+        let style = Style::for_document(Some(path), ctx.config.style);
+        if is_notebook(path) || matches!(style, Style::Org | Style::AsciiDoc) {
+            return Err(EntangledError::Other(format!(
+                "cannot {} {}: only markdown documents are supported",
+                verb,
+                path.display()
+            )));
+        }
+
+        let mut source_files = ctx.source_files()?;
+        let resolved = ctx.resolve_path(path);
+        if !source_files.iter().any(|f| ctx.resolve_path(f) == resolved) {
+            source_files.push(path.to_path_buf());
+        }
+        let refs = collect_refs(ctx, &source_files)?;
+        // The document's own blocks, keyed by the line of their opening fence
+        let document = ctx.document_at(path)?;
+        let blocks: HashMap<usize, &CodeBlock> = document
+            .refs
+            .blocks()
+            .map(|b| (b.location.line, b))
+            .collect();
+
+        let content = ctx.read_file(path)?;
+        let header_lines = extract_yaml_header(&content).map_or(0, |h| h.lines_consumed);
+        let lines: Vec<&str> = content.lines().collect();
+        let header_lines = header_lines.min(lines.len());
+        let parts = split_parts(
+            &lines[header_lines..],
+            header_lines,
+            &blocks,
+            &refs,
+            ctx.config.quoted_blocks,
+        )?;
+
+        Ok(render(&lines[..header_lines], &parts))
+    })
+}
+
+/// Splits the document body into prose lines and code blocks.
+///
+/// `offset` is the number of lines before `body` (the YAML header), used to
+/// find the parsed block of each fence in `blocks`. With `quotes`, fences
+/// inside blockquotes are code blocks too.
+fn split_parts<'a>(
+    body: &'a [&'a str],
+    offset: usize,
+    blocks: &HashMap<usize, &CodeBlock>,
+    refs: &ReferenceMap,
+    quotes: bool,
+) -> Result<Vec<Part<'a>>> {
+    let mut parts = Vec::new();
+    let mut getter = DelimitedTokenGetter::new().with_quotes(quotes);
+    let mut iter = body.iter().copied();
+    let mut line = 1;
+    while let Some(result) = getter.extract(&mut iter) {
+        match result {
+            ExtractResult::NotDelimited(_) => {
+                parts.push(Part::Prose(body[line - 1]));
+                line += 1;
+            }
+            ExtractResult::Token(token) => {
+                let expanded = match blocks.get(&(offset + token.location.line)) {
+                    Some(block) => Some(expand_block(refs, block)?),
+                    None => None,
+                };
+                parts.push(Part::Code {
+                    raw: &body[line - 1..token.end_line],
+                    info: token.info,
+                    indent: token.indent,
+                    expanded,
+                });
+                line = token.end_line + 1;
+            }
+            ExtractResult::Unclosed { .. } => {
+                // An unclosed fence runs to the end of the document
+                parts.extend(body[line - 1..].iter().map(|l| Part::Prose(l)));
+                break;
+            }
+        }
+    }
+    Ok(parts)
+}
+
+/// Returns a backtick fence longer than any backtick run in `content`.
+fn fence_for(content: &str) -> String {
+    let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn render_markdown(header: &[&str], parts: &[Part]) -> String {
+    let mut out: Vec<String> = header.iter().map(|l| l.to_string()).collect();
+    for part in parts {
+        match part {
+            Part::Prose(line) => out.push(line.to_string()),
+            Part::Code {
+                info,
+                indent,
+                expanded: Some(expanded),
+                ..
+            } => {
+                let fence = fence_for(expanded);
+                out.push(format!("{}{}{}", indent, fence, info));
+                out.extend(expanded.lines().map(|l| format!("{}{}", indent, l)));
+                out.push(format!("{}{}", indent, fence));
+            }
+            Part::Code { raw, .. } => out.extend(raw.iter().map(|l| l.to_string())),
+        }
+    }
+    out.join("\n") + "\n"
+}
+
+/// Renders the parts as written, replacing the lines between the fences of
+/// each named block with its expansion.
+fn render_woven(header: &[&str], parts: &[Part]) -> String {
+    let mut out: Vec<String> = header.iter().map(|l| l.to_string()).collect();
+    for part in parts {
+        match part {
+            Part::Prose(line) => out.push(line.to_string()),
+            Part::Code {
+                raw,
+                indent,
+                expanded: Some(expanded),
+                ..
+            } => {
+                out.push(raw[0].to_string());
+                out.extend(expanded.lines().map(|l| format!("{}{}", indent, l)));
+                out.push(raw[raw.len() - 1].to_string());
+            }
+            Part::Code { raw, .. } => out.extend(raw.iter().map(|l| l.to_string())),
+        }
+    }
+    out.join("\n")
+}
+
+/// Renders the parts as a standalone HTML page titled `title`.
+///
+/// Runs of prose lines separated by blank lines become paragraphs. Named
+/// blocks show their expansion, other blocks their content as written.
+fn render_html(title: &str, parts: &[Part]) -> String {
+    let mut body = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let flush = |paragraph: &mut Vec<&str>, body: &mut Vec<String>| {
+        if !paragraph.is_empty() {
+            body.push(format!("<p>{}</p>", escape_html(&paragraph.join("\n"))));
+            paragraph.clear();
+        }
+    };
+
+    for part in parts {
+        match part {
+            Part::Prose(line) if line.trim().is_empty() => flush(&mut paragraph, &mut body),
+            Part::Prose(line) => paragraph.push(line.trim()),
+            Part::Code {
+                raw,
+                info,
+                expanded,
+                ..
+            } => {
+                flush(&mut paragraph, &mut body);
+                let content = match expanded {
+                    Some(expanded) => expanded.clone(),
+                    None => raw[1..raw.len().saturating_sub(1).max(1)].join("\n"),
+                };
+                let class = fence_language(info)
+                    .map(|l| format!(" class=\"language-{}\"", escape_html(l)))
+                    .unwrap_or_default();
+                body.push(format!(
+                    "<pre><code{}>{}</code></pre>",
+                    class,
+                    escape_html(&content)
+                ));
+            }
+        }
+    }
+    flush(&mut paragraph, &mut body);
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        escape_html(title),
+        body.join("\n")
+    )
+}
+
+/// Returns the language named first in a fence's info string, as in
+/// `python #main` or `{.python #main}`.
+fn fence_language(info: &str) -> Option<&str> {
+    info.trim()
+        .trim_matches(|c| c == '{' || c == '}')
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .next()
+        .map(|l| l.trim_start_matches('.'))
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.contains('='))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, NamespaceDefault};
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    fn context(dir: &Path) -> Context {
+        let config = Config {
+            namespace_default: NamespaceDefault::None,
+            ..Default::default()
+        };
+        Context::new(config, dir.to_path_buf()).unwrap()
+    }
+
+    const DOC: &str = "---\ntitle: Demo\n---\n\n# Intro\n\nSome *prose*.\n\n\
+        ```python #main file=out.py\ndef main():\n    <<body>>\n```\n\n\
+        ```python #body\nprint(\"a < b\")\n```\n\n```\nplain\n```\n";
+
+    #[test]
+    fn test_export_markdown() {
+        let dir = tempdir().unwrap();
+        let ctx = context(dir.path());
+        fs::write(dir.path().join("doc.md"), DOC).unwrap();
+
+        let out = export_document(&ctx, Path::new("doc.md"), ExportFormat::Markdown).unwrap();
+        assert!(out.starts_with("---\ntitle: Demo\n---\n\n# Intro\n"));
+        assert!(
+            out.contains("```python #main file=out.py\ndef main():\n    print(\"a < b\")\n```\n")
+        );
+        assert!(out.contains("```\nplain\n```\n"));
+    }
+
+    #[test]
+    fn test_export_html() {
+        let dir = tempdir().unwrap();
+        let ctx = context(dir.path());
+        fs::write(dir.path().join("doc.md"), DOC).unwrap();
+
+        let out = export_document(&ctx, Path::new("doc.md"), ExportFormat::Html).unwrap();
+        assert!(out.starts_with("<!DOCTYPE html>"));
+        assert!(out.contains("<title>Demo</title>"));
+        assert!(out.contains("<p># Intro</p>\n<p>Some *prose*.</p>"));
+        assert!(out.contains(
+            "<pre><code class=\"language-python\">def main():\n    print(&quot;a &lt; b&quot;)</code></pre>"
+        ));
+        assert!(out.contains("<pre><code>plain</code></pre>"));
+        assert!(!out.contains("---"));
+    }
+
+    #[test]
+    fn test_fence_language() {
+        assert_eq!(fence_language("python #main file=out.py"), Some("python"));
+        assert_eq!(fence_language("{.rust #main}"), Some("rust"));
+        assert_eq!(fence_language(""), None);
+        assert_eq!(fence_language("{#main}"), None);
+    }
+
+    #[test]
+    fn test_export_matches_blocks_by_location() {
+        let dir = tempdir().unwrap();
+        let ctx = context(dir.path());
+        fs::write(dir.path().join("doc.md"), DOC).unwrap();
+
+        // However the path is spelled, the document is read once and its
+        // blocks are found
+        for path in [
+            PathBuf::from("./doc.md"),
+            dir.path().join("doc.md"),
+            dir.path().join(".").join("doc.md"),
+        ] {
+            let out = export_document(&ctx, &path, ExportFormat::Markdown).unwrap();
+            assert!(
+                out.contains("def main():\n    print(\"a < b\")\n"),
+                "{:?}",
+                path
+            );
+            assert_eq!(out.matches("print(").count(), 2, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_weave_keeps_layout() {
+        let dir = tempdir().unwrap();
+        let ctx = context(dir.path());
+        let doc = "# Intro\n\n  ~~~~python #main file=out.py\n  <<body>>\n  ~~~~\n\n\
+                   ```python #body\nx = 1\ny = 2\n```";
+        fs::write(dir.path().join("doc.md"), doc).unwrap();
+
+        let document = crate::interface::Document::load(Path::new("doc.md"), &ctx).unwrap();
+        assert_eq!(
+            document.weave(&ctx).unwrap(),
+            "# Intro\n\n  ~~~~python #main file=out.py\n  x = 1\n  y = 2\n  ~~~~\n\n\
+             ```python #body\nx = 1\ny = 2\n```"
+        );
+
+        fs::write(dir.path().join("doc.md"), DOC).unwrap();
+        let woven = weave_document(&ctx, Path::new("doc.md")).unwrap();
+        assert_eq!(woven.lines().count(), DOC.lines().count());
+        assert!(woven.ends_with("```\nplain\n```\n"));
+    }
+
+    #[test]
+    fn test_fence_for_nested_backticks() {
+        assert_eq!(fence_for("plain"), "```");
+        assert_eq!(fence_for("a ```` b"), "`````");
+    }
+}
@@ -0,0 +1,396 @@
+//! Import of Python Entangled projects.
+//!
+//! Python Entangled keeps its settings in `entangled.toml` (or under
+//! `[tool.entangled]` in `pyproject.toml`) with its own key names, and its
+//! file database in `.entangled/filedb.json` as a list of file stats plus
+//! `source`/`target` path lists. This module translates both into a
+//! [`Config`] and a [`FileDB`] on a best-effort basis; anything that has no
+//! counterpart here is reported as a warning rather than an error.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use serde_json::Value as Json;
+use toml::Value as Toml;
+
+use crate::config::{AnnotationMethod, Comment, Config, Language, NamespaceDefault};
+use crate::errors::{EntangledError, Result};
+use crate::io::{FileDB, FileData, Stat};
+use crate::style::Style;
+
+/// Location of the Python Entangled file database, relative to the project.
+pub const PYTHON_FILEDB_PATH: &str = ".entangled/filedb.json";
+
+/// Configuration formats of older Python Entangled releases that are not read.
+const UNSUPPORTED_CONFIGS: &[&str] = &["entangled.dhall", "entangled.yaml", "entangled.yml"];
+
+/// Result of translating a Python Entangled project.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    /// Translated configuration.
+    pub config: Config,
+    /// Where the configuration was read from, if anywhere.
+    pub config_source: Option<PathBuf>,
+    /// Tracked targets, keyed by path resolved against the project directory.
+    pub filedb: FileDB,
+    /// Settings that could not be carried over.
+    pub warnings: Vec<String>,
+}
+
+/// Reads the Python Entangled configuration and file database in `base_dir`.
+///
+/// The configuration is taken from `entangled.toml`, falling back to
+/// `[tool.entangled]` in `pyproject.toml`; without either, defaults are used.
+/// A missing file database yields an empty one.
+pub fn migrate_python(base_dir: &Path) -> Result<Migration> {
+    let mut warnings = Vec::new();
+
+    let (table, config_source) = match read_python_config(base_dir)? {
+        Some((table, path)) => (table, Some(path)),
+        None => {
+            for name in UNSUPPORTED_CONFIGS {
+                if base_dir.join(name).exists() {
+                    warnings.push(format!(
+                        "{} is not supported; convert it to entangled.toml first",
+                        name
+                    ));
+                }
+            }
+            (toml::Table::new(), None)
+        }
+    };
+    let config = python_config(&table, &mut warnings);
+
+    let filedb_path = base_dir.join(PYTHON_FILEDB_PATH);
+    let filedb = if filedb_path.exists() {
+        let json: Json = serde_json::from_str(&fs::read_to_string(&filedb_path)?)?;
+        python_filedb(&json, base_dir, &mut warnings)?
+    } else {
+        if base_dir.join(".entangled/db.sqlite").exists() {
+            warnings.push(
+                ".entangled/db.sqlite is not supported; run `entangled tangle --force` after migrating"
+                    .to_string(),
+            );
+        }
+        FileDB::new().with_base_dir(base_dir.to_path_buf())
+    };
+
+    Ok(Migration {
+        config,
+        config_source,
+        filedb,
+        warnings,
+    })
+}
+
+/// Finds the Python configuration table and the file it came from.
+fn read_python_config(base_dir: &Path) -> Result<Option<(toml::Table, PathBuf)>> {
+    let path = base_dir.join("entangled.toml");
+    if path.exists() {
+        let table: toml::Table = toml::from_str(&fs::read_to_string(&path)?)?;
+        if table.contains_key("source_patterns") && !table.contains_key("watch_list") {
+            return Err(EntangledError::Config(format!(
+                "{} is already in entangled-rs format",
+                path.display()
+            )));
+        }
+        return Ok(Some((table, path)));
+    }
+
+    let path = base_dir.join("pyproject.toml");
+    if path.exists() {
+        let mut table: toml::Table = toml::from_str(&fs::read_to_string(&path)?)?;
+        let section = table
+            .remove("tool")
+            .and_then(|tool| match tool {
+                Toml::Table(mut tool) => tool.remove("entangled"),
+                _ => None,
+            })
+            .and_then(|section| match section {
+                Toml::Table(section) => Some(section),
+                _ => None,
+            });
+        return Ok(section.map(|section| (section, path)));
+    }
+
+    Ok(None)
+}
+
+/// Translates a Python Entangled configuration table.
+///
+/// Python Entangled reads pandoc-style attributes by default, so the style is
+/// set to [`Style::Pandoc`] unless the `quarto_attributes` hook is enabled.
+pub fn python_config(table: &toml::Table, warnings: &mut Vec<String>) -> Config {
+    let mut config = Config {
+        style: Style::Pandoc,
+        ..Config::default()
+    };
+
+    for (key, value) in table {
+        match key.as_str() {
+            "version" => {}
+            "watch_list" => match string_list(value) {
+                Some(patterns) => config.source_patterns = patterns,
+                None => warnings.push("watch_list is not a list of strings".to_string()),
+            },
+            "ignore_list" => match string_list(value) {
+                Some(patterns) => config.exclude_patterns = patterns,
+                None => warnings.push("ignore_list is not a list of strings".to_string()),
+            },
+            "annotation" => match value.as_str().map(str::to_lowercase).as_deref() {
+                Some("standard") => config.annotation = AnnotationMethod::Standard,
+                Some("naked") => config.annotation = AnnotationMethod::Naked,
+                Some("supplemented") | Some("supplemental") => {
+                    config.annotation = AnnotationMethod::Supplemental
+                }
+                _ => warnings.push(format!("annotation = {} is not supported", value)),
+            },
+            "use_line_directives" => match value.as_bool() {
+                Some(enabled) => config.line_directives = enabled,
+                None => warnings.push("use_line_directives is not a boolean".to_string()),
+            },
+            "namespace_default" => match value.clone().try_into::<NamespaceDefault>() {
+                Ok(namespace) => config.namespace_default = namespace,
+                Err(_) => warnings.push(format!("namespace_default = {} is not supported", value)),
+            },
+            "hooks" => {
+                for hook in value.as_array().into_iter().flatten() {
+                    match hook.as_str() {
+                        Some("shebang") => config.hooks.shebang = true,
+                        Some("spdx_license") => config.hooks.spdx_license = true,
+                        Some("quarto_attributes") => config.style = Style::Quarto,
+                        _ => warnings.push(format!("hook {} is not supported", hook)),
+                    }
+                }
+            }
+            "languages" => {
+                for language in value.as_array().into_iter().flatten() {
+                    match python_language(language) {
+                        Some(language) => config.languages.push(language),
+                        None => warnings.push(format!("language {} could not be read", language)),
+                    }
+                }
+            }
+            _ => warnings.push(format!("{} has no equivalent and was dropped", key)),
+        }
+    }
+
+    config
+}
+
+/// Translates a `[[languages]]` entry, whose comment is `{open}` or `{open, close}`.
+fn python_language(value: &Toml) -> Option<Language> {
+    let table = value.as_table()?;
+    let name = table.get("name")?.as_str()?;
+    let comment = match table.get("comment")? {
+        Toml::String(prefix) => Comment::line(prefix.as_str()),
+        Toml::Table(comment) => {
+            let open = comment.get("open")?.as_str()?;
+            match comment.get("close").and_then(Toml::as_str) {
+                Some(close) => Comment::block(open, close),
+                None => Comment::line(open),
+            }
+        }
+        _ => return None,
+    };
+    let identifiers = match table.get("identifiers") {
+        Some(value) => string_list(value)?,
+        None => Vec::new(),
+    };
+    Some(Language::new(name, comment).with_identifiers(identifiers))
+}
+
+fn string_list(value: &Toml) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| v.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Translates a Python Entangled file database.
+///
+/// Only paths listed under `target` are carried over (every file when the
+/// list is absent), so sources are not mistaken for tangled output. The
+/// recorded digest is kept, so files edited since the last Python tangle are
+/// still reported as conflicts.
+pub fn python_filedb(json: &Json, base_dir: &Path, warnings: &mut Vec<String>) -> Result<FileDB> {
+    let stats: Vec<&Json> = match json.get("files") {
+        Some(Json::Array(files)) => files.iter().collect(),
+        Some(Json::Object(files)) if files.values().any(|f| f.get("stat").is_some()) => {
+            return Err(EntangledError::Other(
+                "filedb.json is already in entangled-rs format".to_string(),
+            ))
+        }
+        Some(Json::Object(files)) => files.values().collect(),
+        _ => {
+            return Err(EntangledError::Other(
+                "filedb.json has no `files` entry; is it from Python Entangled?".to_string(),
+            ))
+        }
+    };
+    let targets: Option<Vec<&str>> = json
+        .get("target")
+        .and_then(Json::as_array)
+        .map(|paths| paths.iter().filter_map(Json::as_str).collect());
+
+    let mut filedb = FileDB::new().with_base_dir(base_dir.to_path_buf());
+    for stat in stats {
+        let (Some(path), Some(hexdigest)) = (
+            stat.get("path").and_then(Json::as_str),
+            stat.get("hexdigest").and_then(Json::as_str),
+        ) else {
+            warnings.push(format!("skipped unreadable file entry {}", stat));
+            continue;
+        };
+        if targets.as_ref().is_some_and(|t| !t.contains(&path)) {
+            continue;
+        }
+
+        let full_path = base_dir.join(path);
+        let mtime = stat
+            .get("modified")
+            .and_then(Json::as_str)
+            .and_then(parse_modified)
+            .unwrap_or_else(Utc::now);
+        let size = fs::metadata(&full_path).map(|m| m.len()).unwrap_or(0);
+        filedb.record(
+            full_path,
+            FileData::new(Stat::new(mtime, size), hexdigest.to_string()),
+        );
+    }
+    Ok(filedb)
+}
+
+/// Parses a Python `isoformat()` timestamp, which is local time unless it
+/// carries an offset.
+fn parse_modified(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+    Some(
+        naive
+            .and_local_timezone(Local)
+            .earliest()?
+            .with_timezone(&Utc),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_python_config() {
+        let table: toml::Table = toml::from_str(
+            r#"
+            version = "2.0"
+            watch_list = ["docs/**/*.md"]
+            ignore_list = ["docs/drafts/*.md"]
+            annotation = "supplemented"
+            use_line_directives = true
+            hooks = ["shebang", "build"]
+            markers = { open = "x", close = "y" }
+
+            [[languages]]
+            name = "Fortran"
+            identifiers = ["f90"]
+            comment = { open = "!" }
+            "#,
+        )
+        .unwrap();
+
+        let mut warnings = Vec::new();
+        let config = python_config(&table, &mut warnings);
+        assert_eq!(config.source_patterns, vec!["docs/**/*.md"]);
+        assert_eq!(config.exclude_patterns, vec!["docs/drafts/*.md"]);
+        assert_eq!(config.annotation, AnnotationMethod::Supplemental);
+        assert!(config.line_directives);
+        assert!(config.hooks.shebang);
+        assert_eq!(config.style, Style::Pandoc);
+        assert_eq!(config.languages[0].name, "Fortran");
+        assert_eq!(config.languages[0].comment, Comment::line("!"));
+        assert_eq!(config.languages[0].identifiers, vec!["f90"]);
+
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().any(|w| w.contains("build")));
+        assert!(warnings.iter().any(|w| w.starts_with("markers")));
+    }
+
+    #[test]
+    fn test_migrate_python_project() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("pyproject.toml"),
+            "[tool.entangled]\nwatch_list = [\"*.md\"]\nhooks = [\"quarto_attributes\"]\n",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join(".entangled")).unwrap();
+        fs::write(dir.path().join("hello.py"), "print('hi')\n").unwrap();
+        fs::write(
+            dir.path().join(PYTHON_FILEDB_PATH),
+            r#"{
+              "version": "2.0.0",
+              "files": [
+                {"path": "README.md", "deps": null, "modified": "2024-01-15T10:30:00.5", "hexdigest": "aaa"},
+                {"path": "hello.py", "deps": ["README.md"], "modified": "2024-01-15T10:30:01+00:00", "hexdigest": "bbb"}
+              ],
+              "source": ["README.md"],
+              "target": ["hello.py"]
+            }"#,
+        )
+        .unwrap();
+
+        let migration = migrate_python(dir.path()).unwrap();
+        assert_eq!(
+            migration.config_source,
+            Some(dir.path().join("pyproject.toml"))
+        );
+        assert_eq!(migration.config.source_patterns, vec!["*.md"]);
+        assert_eq!(migration.config.style, Style::Quarto);
+        assert!(migration.warnings.is_empty());
+
+        assert_eq!(migration.filedb.len(), 1);
+        let data = migration.filedb.get(&dir.path().join("hello.py")).unwrap();
+        assert_eq!(data.hexdigest, "bbb");
+        assert_eq!(data.stat.size, 12);
+        assert_eq!(data.stat.mtime.to_rfc3339(), "2024-01-15T10:30:01+00:00");
+    }
+
+    #[test]
+    fn test_migrate_without_python_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("entangled.dhall"), "{}").unwrap();
+
+        let migration = migrate_python(dir.path()).unwrap();
+        assert!(migration.config_source.is_none());
+        assert!(migration.filedb.is_empty());
+        assert_eq!(migration.warnings.len(), 1);
+        assert!(migration.warnings[0].contains("entangled.dhall"));
+    }
+
+    #[test]
+    fn test_python_filedb_rejects_other_formats() {
+        let json: Json = serde_json::from_str(r#"{"version": "1.0"}"#).unwrap();
+        assert!(python_filedb(&json, Path::new("."), &mut Vec::new()).is_err());
+
+        let mut native = FileDB::new();
+        native.record(
+            PathBuf::from("out.py"),
+            FileData::from_content("x", Utc::now()),
+        );
+        let json = serde_json::to_value(&native).unwrap();
+        assert!(python_filedb(&json, Path::new("."), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_migrate_refuses_native_config() {
+        let dir = tempdir().unwrap();
+        let config = toml::to_string(&Config::default()).unwrap();
+        fs::write(dir.path().join("entangled.toml"), config).unwrap();
+        assert!(migrate_python(dir.path()).is_err());
+    }
+}
@@ -2,9 +2,17 @@
 
 mod context;
 mod document;
+mod export;
+pub mod migrate;
+mod quarto_project;
+mod rename;
 
 pub use context::Context;
 pub use document::{
-    locate_source, stitch_documents, stitch_files, sync_documents, tangle_documents, tangle_files,
-    Document, SourceLocation,
+    load_document, locate_source, locate_source_range, locate_target, prune_filedb,
+    record_source_state, stitch_documents, stitch_files, sync_documents, sync_documents_with,
+    tangle_content, tangle_documents, tangle_documents_incremental, tangle_files, tangle_targets,
+    Document, SourceLocation, SourceRange, TargetLocation,
 };
+pub use export::{export_document, ExportFormat};
+pub use rename::rename_block;
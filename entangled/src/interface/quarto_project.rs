@@ -0,0 +1,111 @@
+//! Quarto project integration.
+//!
+//! With `quarto_project_integration` enabled, the targets of Quarto-style
+//! documents are placed in the `output-dir` of the Quarto project the
+//! document belongs to, so that entangled and Quarto agree on the layout.
+
+use std::path::{Path, PathBuf};
+
+use crate::errors::{EntangledError, Result};
+use crate::style::Style;
+
+use super::context::Context;
+
+/// File names of a Quarto project configuration.
+const PROJECT_FILES: [&str; 2] = ["_quarto.yml", "_quarto.yaml"];
+
+/// Returns the Quarto project file governing the document at `path`.
+///
+/// This is the first `_quarto.yml` (or `_quarto.yaml`) found in the
+/// document's directory or one of its parents, stopping at the base
+/// directory. The returned path is absolute.
+pub(crate) fn project_file(ctx: &Context, path: &Path) -> Option<PathBuf> {
+    let full_path = ctx.resolve_path(path);
+    full_path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(&ctx.base_dir))
+        .flat_map(|dir| PROJECT_FILES.iter().map(move |name| dir.join(name)))
+        .find(|file| ctx.file_cache.exists(file))
+}
+
+/// Returns the directory, relative to the base directory, that targets of
+/// the document at `path` are placed in.
+///
+/// `None` unless `quarto_project_integration` is enabled, the document is
+/// Quarto-style and its project file sets `project.output-dir`. The output
+/// directory is relative to the project file's directory.
+pub(crate) fn output_dir(ctx: &Context, path: &Path) -> Result<Option<PathBuf>> {
+    if !ctx.config.quarto_project_integration
+        || Style::for_document(Some(path), ctx.config.style) != Style::Quarto
+    {
+        return Ok(None);
+    }
+    let Some(file) = project_file(ctx, path) else {
+        return Ok(None);
+    };
+
+    let project: serde_yaml::Value = serde_yaml::from_str(&ctx.read_file(&file)?)
+        .map_err(|e| EntangledError::Config(format!("{}: {}", file.display(), e)))?;
+    let Some(output_dir) = project
+        .get("project")
+        .and_then(|p| p.get("output-dir"))
+        .and_then(|d| d.as_str())
+    else {
+        return Ok(None);
+    };
+
+    let project_dir = file.parent().unwrap_or(&ctx.base_dir);
+    let project_dir = project_dir
+        .strip_prefix(&ctx.base_dir)
+        .unwrap_or(project_dir);
+    Ok(Some(project_dir.join(output_dir)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_output_dir() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("book/chapters")).unwrap();
+        fs::write(
+            dir.path().join("book/_quarto.yml"),
+            "project:\n  type: book\n  output-dir: _build\n",
+        )
+        .unwrap();
+        let config = Config::builder().quarto_project_integration(true).build();
+        let ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+
+        let qmd = Path::new("book/chapters/intro.qmd");
+        assert_eq!(
+            project_file(&ctx, qmd),
+            Some(dir.path().join("book/_quarto.yml"))
+        );
+        assert_eq!(
+            output_dir(&ctx, qmd).unwrap(),
+            Some(PathBuf::from("book/_build"))
+        );
+
+        // Only Quarto-style documents are affected
+        assert_eq!(output_dir(&ctx, Path::new("book/notes.md")).unwrap(), None);
+        // Documents outside the project are not
+        assert_eq!(output_dir(&ctx, Path::new("other.qmd")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_output_dir_disabled() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("_quarto.yml"),
+            "project:\n  output-dir: _site\n",
+        )
+        .unwrap();
+        let ctx = Context::default_for_dir(dir.path().to_path_buf()).unwrap();
+        assert_eq!(output_dir(&ctx, Path::new("doc.qmd")).unwrap(), None);
+    }
+}
@@ -0,0 +1,468 @@
+//! Renaming code blocks across source documents.
+
+use regex::Regex;
+
+use crate::config::{ref_count, ref_name, REF_PATTERN};
+use crate::errors::{EntangledError, Result};
+use crate::io::Transaction;
+use crate::model::{resolve_reference, CodeBlock, ReferenceMap, ReferenceName};
+use crate::readers::{is_notebook, parse_markdown};
+use crate::style::Style;
+
+use super::context::Context;
+use super::document::{collect_refs, line_ending, Document};
+
+/// Renames the code block `old` to `new` in every source document.
+///
+/// `old` is resolved like a reference: a qualified name (`a.md#helper`) as
+/// written, and an unqualified one as a global name or else as the only
+/// namespaced block with that ID. `new` is the ID to write in place of the
+/// old one, in the same namespace.
+///
+/// The returned transaction rewrites the ID in the header of every block of
+/// the name and every `<<...>>` line that resolves to it, keeping
+/// qualification, counts and indentation; prose is left untouched. Fails if
+/// a block named `new` already exists, if a rewritten reference would
+/// resolve to another block, or if a notebook or Org document, which cannot
+/// be written, defines or references the block.
+pub fn rename_block(ctx: &Context, old: &str, new: &str) -> Result<Transaction> {
+    ctx.with_read_cache(|| {
+        let source_files = ctx.source_files()?;
+        let refs = collect_refs(ctx, &source_files)?;
+
+        if new.is_empty() || new.contains(['#', '"']) || new.contains(char::is_whitespace) {
+            return Err(EntangledError::Config(format!(
+                "Invalid block name '{}': expected an ID without '#', quotes or whitespace",
+                new
+            )));
+        }
+        let old_name = resolve_old_name(&refs, old)?;
+        let namespace = refs
+            .get_by_name(&old_name)
+            .first()
+            .and_then(|b| b.namespace.clone());
+        let old_id = match &namespace {
+            Some(ns) => old_name.as_str()[ns.len() + 1..].to_string(),
+            None => old_name.as_str().to_string(),
+        };
+        let new_name = refs
+            .canonical_name(&ReferenceName::new(match &namespace {
+                Some(ns) => format!("{}#{}", ns, new),
+                None => new.to_string(),
+            }))
+            .into_owned();
+        if refs.contains_name(&new_name) {
+            return Err(EntangledError::DuplicateReference(new_name));
+        }
+
+        let occurrence = occurrence_pattern(&old_id, ctx.config.case_insensitive_names)?;
+        let mut transaction = ctx.new_transaction();
+        for path in &source_files {
+            let doc = Document::load(path, ctx)?;
+            let style = Style::for_document(Some(path), ctx.config.style);
+            let touched = doc
+                .refs()
+                .blocks()
+                .any(|b| defines(&refs, b, &old_name) || references(&refs, b, &old_name));
+            if !touched {
+                continue;
+            }
+            if is_notebook(path) || style == Style::Org {
+                return Err(EntangledError::Other(format!(
+                    "Cannot rename '{}': {} uses it and cannot be rewritten",
+                    old_name,
+                    path.display()
+                )));
+            }
+
+            let content = ctx.read_file(path)?;
+            // Each line's terminator is kept, so CRLF documents stay CRLF
+            let (mut lines, endings): (Vec<String>, Vec<&str>) = content
+                .split_inclusive('\n')
+                .map(|line| {
+                    let ending = line_ending(line);
+                    (line[..line.len() - ending.len()].to_string(), ending)
+                })
+                .unzip();
+            for block in doc.refs().blocks() {
+                let fence = block.location.line - 1;
+                let close = closing_line(&lines, fence);
+                if defines(&refs, block, &old_name) {
+                    let header = header_range(&lines, fence, close, style);
+                    rename_header(ctx, path, &mut lines, header, close, &occurrence, new)
+                        .ok_or_else(|| {
+                            EntangledError::Other(format!(
+                                "Cannot rename '{}': the header of the block at {} does not \
+                                 set its ID in a form that can be rewritten",
+                                old_name, block.location
+                            ))
+                        })?;
+                }
+                for line in &mut lines[fence + 1..close] {
+                    if let Some(renamed) =
+                        rename_reference(&refs, block, line, &old_name, new, &new_name)?
+                    {
+                        *line = renamed;
+                    }
+                }
+            }
+
+            let new_content: String = lines
+                .iter()
+                .zip(&endings)
+                .map(|(line, ending)| format!("{}{}", line, ending))
+                .collect();
+            if new_content != *content {
+                transaction.write(ctx.resolve_path(path), new_content);
+            }
+        }
+        Ok(transaction)
+    })
+}
+
+/// Resolves the name of the block to rename, in the map's canonical form.
+fn resolve_old_name(refs: &ReferenceMap, old: &str) -> Result<ReferenceName> {
+    let name = refs.canonical_name(&ReferenceName::new(old)).into_owned();
+    if name.is_file_target() {
+        return Err(EntangledError::Config(format!(
+            "'{}' is named after its target file and has no ID to rename",
+            old
+        )));
+    }
+    refs.find_name(old)
+}
+
+/// Returns true if `block` is one of the blocks named `name`.
+fn defines(refs: &ReferenceMap, block: &CodeBlock, name: &ReferenceName) -> bool {
+    *refs.canonical_name(block.name()) == *name
+}
+
+/// Returns true if a reference line in `block` resolves to `name`.
+fn references(refs: &ReferenceMap, block: &CodeBlock, name: &ReferenceName) -> bool {
+    block.source.lines().any(|line| {
+        REF_PATTERN.captures(line).is_some_and(|caps| {
+            resolve_reference(refs, &ref_name(&caps), block.namespace.as_deref()) == *name
+        })
+    })
+}
+
+/// Rewrites `line` of `block` if it is a reference resolving to `old_name`.
+///
+/// A qualified reference keeps its namespace. Fails if the rewritten
+/// reference would resolve to a block other than `new_name`.
+fn rename_reference(
+    refs: &ReferenceMap,
+    block: &CodeBlock,
+    line: &str,
+    old_name: &ReferenceName,
+    new: &str,
+    new_name: &ReferenceName,
+) -> Result<Option<String>> {
+    let Some(caps) = REF_PATTERN.captures(line) else {
+        return Ok(None);
+    };
+    let refname = ref_name(&caps);
+    let namespace = block.namespace.as_deref();
+    if resolve_reference(refs, &refname, namespace) != *old_name {
+        return Ok(None);
+    }
+
+    let renamed = match refname.rsplit_once('#') {
+        Some((prefix, _)) => format!("{}#{}", prefix, new),
+        None => new.to_string(),
+    };
+    let resolved = resolve_reference(refs, &renamed, namespace);
+    if resolved != *new_name && refs.contains_name(&resolved) {
+        return Err(EntangledError::Config(format!(
+            "Renaming to '{}' would make <<{}>> at {} refer to {}",
+            new, renamed, block.location, resolved
+        )));
+    }
+    let count = ref_count(&caps).map_or(String::new(), |n| format!("[{}]", n));
+    Ok(Some(format!("{}<<{}{}>>", &caps["indent"], renamed, count)))
+}
+
+/// Matches `id` wherever it appears in a line.
+fn occurrence_pattern(id: &str, case_insensitive: bool) -> Result<Regex> {
+    let flags = if case_insensitive { "(?i)" } else { "" };
+    Ok(Regex::new(&format!("{}{}", flags, regex::escape(id)))?)
+}
+
+/// Rewrites the ID set in the `header` lines of the block closed at `close`
+/// to `new`.
+///
+/// The ID is located by the style's own parser rather than by pattern: each
+/// occurrence of the old ID in the header is replaced in turn, and the first
+/// replacement after which the block parses with the new ID and is otherwise
+/// unchanged is kept. The same text in a quoted attribute value or a file
+/// name is left alone. Returns `None` if no occurrence sets the ID.
+fn rename_header(
+    ctx: &Context,
+    path: &std::path::Path,
+    lines: &mut [String],
+    header: std::ops::Range<usize>,
+    close: usize,
+    occurrence: &Regex,
+    new: &str,
+) -> Option<()> {
+    let end = close.min(lines.len() - 1);
+    let parse = |lines: &[String]| {
+        let snippet = lines[header.start..=end].join("\n");
+        parse_markdown(&snippet, Some(path), &ctx.config)
+            .ok()
+            .and_then(|doc| doc.refs.blocks().next().cloned())
+    };
+    let before = parse(lines)?;
+    let expected = match &before.namespace {
+        Some(ns) => format!("{}#{}", ns, new),
+        None => new.to_string(),
+    };
+
+    for index in header.clone() {
+        let original = lines[index].clone();
+        for found in occurrence.find_iter(&original) {
+            lines[index] = format!(
+                "{}{}{}",
+                &original[..found.start()],
+                new,
+                &original[found.end()..]
+            );
+            if let Some(mut after) = parse(lines) {
+                if after.name().as_str() == expected {
+                    after.id = before.id.clone();
+                    if after == before {
+                        return Some(());
+                    }
+                }
+            }
+        }
+        lines[index] = original;
+    }
+    None
+}
+
+/// Returns the indices of the lines that may set the ID of the block whose
+/// opening line is `fence`: the opening line, Quarto `#|` option lines
+/// below it, and the attribute and anchor lines above an AsciiDoc block.
+fn header_range(
+    lines: &[String],
+    fence: usize,
+    close: usize,
+    style: Style,
+) -> std::ops::Range<usize> {
+    let mut start = fence;
+    if style == Style::AsciiDoc {
+        while start > 0 && lines[start - 1].trim_start().starts_with(['[', '.']) {
+            start -= 1;
+        }
+    }
+    let mut end = fence + 1;
+    while end < close && lines[end].trim_start().starts_with("#|") {
+        end += 1;
+    }
+    start..end
+}
+
+/// Returns the index of the line closing the block opened at `fence`, or the
+/// number of lines if it is not closed.
+fn closing_line(lines: &[String], fence: usize) -> usize {
+    let opening = lines[fence].trim_start();
+    let delimiter: String = match opening.chars().next() {
+        Some(c @ ('`' | '~' | '-' | '=')) => opening.chars().take_while(|&d| d == c).collect(),
+        // An indented code block runs up to the first line at a lower indent
+        _ => {
+            let indent = lines[fence].len() - opening.len();
+            return lines[fence + 1..]
+                .iter()
+                .position(|l| !l.trim().is_empty() && l.len() - l.trim_start().len() < indent)
+                .map_or(lines.len(), |i| fence + 1 + i);
+        }
+    };
+    lines[fence + 1..]
+        .iter()
+        .position(|l| {
+            let l = l.trim();
+            l.starts_with(&delimiter) && l.chars().all(|c| delimiter.starts_with(c))
+        })
+        .map_or(lines.len(), |i| fence + 1 + i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, NamespaceDefault};
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn context(namespace_default: NamespaceDefault) -> (tempfile::TempDir, Context) {
+        let dir = tempdir().unwrap();
+        let config = Config {
+            namespace_default,
+            ..Config::default()
+        };
+        let ctx = Context::new(config, dir.path().to_path_buf()).unwrap();
+        (dir, ctx)
+    }
+
+    fn rename(ctx: &mut Context, old: &str, new: &str) -> Result<()> {
+        let tx = rename_block(ctx, old, new)?;
+        ctx.execute(&tx, false)
+    }
+
+    #[test]
+    fn test_rename_block_rewrites_ids_and_references() {
+        let (dir, mut ctx) = context(NamespaceDefault::None);
+        fs::write(
+            dir.path().join("a.md"),
+            "Call `greet` below.\n\n```python #greet\nprint('hi')\n```\n\n\
+             ```python file=main.py\n<<greet>>\n  <<greet[1]>>\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("b.md"),
+            "```python file=other.py\n<<greeting>>\n<<greet>>\n```\n\n```python #greeting\npass\n```\n",
+        )
+        .unwrap();
+
+        rename(&mut ctx, "greet", "hello").unwrap();
+
+        let a = fs::read_to_string(dir.path().join("a.md")).unwrap();
+        assert_eq!(
+            a,
+            "Call `greet` below.\n\n```python #hello\nprint('hi')\n```\n\n\
+             ```python file=main.py\n<<hello>>\n  <<hello[1]>>\n```\n"
+        );
+        // A name sharing a prefix is left alone
+        let b = fs::read_to_string(dir.path().join("b.md")).unwrap();
+        assert!(b.contains("<<greeting>>\n<<hello>>"));
+        assert!(b.contains("#greeting"));
+    }
+
+    #[test]
+    fn test_rename_block_keeps_line_endings() {
+        let (dir, mut ctx) = context(NamespaceDefault::None);
+        fs::write(
+            dir.path().join("a.md"),
+            "Intro\r\n\r\n```python #greet\r\nprint('hi')\r\n```\r\n\r\n\
+             ```python file=main.py\r\n<<greet>>\r\n```",
+        )
+        .unwrap();
+
+        rename(&mut ctx, "greet", "hello").unwrap();
+
+        let a = fs::read_to_string(dir.path().join("a.md")).unwrap();
+        assert_eq!(
+            a,
+            "Intro\r\n\r\n```python #hello\r\nprint('hi')\r\n```\r\n\r\n\
+             ```python file=main.py\r\n<<hello>>\r\n```"
+        );
+    }
+
+    #[test]
+    fn test_rename_block_respects_namespaces() {
+        let (dir, mut ctx) = context(NamespaceDefault::File);
+        fs::write(
+            dir.path().join("a.md"),
+            "```python #helper\npass\n```\n\n```python file=a.py\n<<helper>>\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("b.md"),
+            "```python #helper\npass\n```\n\n```python file=b.py\n<<helper>>\n<<a.md#helper>>\n```\n",
+        )
+        .unwrap();
+
+        // Two blocks have the ID
+        assert!(rename_block(&ctx, "helper", "util").is_err());
+
+        rename(&mut ctx, "a.md#helper", "util").unwrap();
+        let a = fs::read_to_string(dir.path().join("a.md")).unwrap();
+        assert!(a.contains("```python #util\n") && a.contains("<<util>>"));
+        let b = fs::read_to_string(dir.path().join("b.md")).unwrap();
+        assert!(b.contains("```python #helper\n"));
+        assert!(b.contains("<<helper>>\n<<a.md#util>>"));
+    }
+
+    #[test]
+    fn test_rename_block_detects_collisions() {
+        let (dir, ctx) = context(NamespaceDefault::None);
+        fs::write(
+            dir.path().join("a.md"),
+            "```python #one\npass\n```\n\n```python #two\npass\n```\n",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            rename_block(&ctx, "one", "two"),
+            Err(EntangledError::DuplicateReference(_))
+        ));
+        assert!(matches!(
+            rename_block(&ctx, "three", "four"),
+            Err(EntangledError::ReferenceNotFound(_))
+        ));
+        assert!(rename_block(&ctx, "one", "with space").is_err());
+    }
+
+    #[test]
+    fn test_rename_block_quarto_and_asciidoc_headers() {
+        let (dir, mut ctx) = context(NamespaceDefault::None);
+        fs::write(
+            dir.path().join("q.qmd"),
+            "```{python}\n#| label: main\n#| file: q.py\nx = 1\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("d.adoc"),
+            "[[main2]]\n[source,python]\n----\n<<main>>\n----\n",
+        )
+        .unwrap();
+        ctx.config.source_patterns = vec!["**/*.qmd".to_string(), "**/*.adoc".to_string()];
+
+        rename(&mut ctx, "main", "entry").unwrap();
+        let q = fs::read_to_string(dir.path().join("q.qmd")).unwrap();
+        assert_eq!(
+            q,
+            "```{python}\n#| label: entry\n#| file: q.py\nx = 1\n```\n"
+        );
+        let d = fs::read_to_string(dir.path().join("d.adoc")).unwrap();
+        assert_eq!(d, "[[main2]]\n[source,python]\n----\n<<entry>>\n----\n");
+
+        rename(&mut ctx, "main2", "caller").unwrap();
+        let d = fs::read_to_string(dir.path().join("d.adoc")).unwrap();
+        assert!(d.starts_with("[[caller]]\n"));
+    }
+
+    #[test]
+    fn test_rename_block_leaves_quoted_values_alone() {
+        let (dir, mut ctx) = context(NamespaceDefault::None);
+        fs::write(
+            dir.path().join("a.md"),
+            "```python title=\"see #old\" #old\npass\n```\n\n\
+             ```python file=a.py\n<<old>>\n```\n",
+        )
+        .unwrap();
+
+        rename(&mut ctx, "old", "new").unwrap();
+        let a = fs::read_to_string(dir.path().join("a.md")).unwrap();
+        assert!(a.starts_with("```python title=\"see #old\" #new\n"));
+        assert!(a.contains("<<new>>"));
+    }
+
+    #[test]
+    fn test_rename_block_knitr_positional_label() {
+        let (dir, mut ctx) = context(NamespaceDefault::None);
+        fs::write(
+            dir.path().join("a.Rmd"),
+            "```{r old, echo=FALSE}\nx <- 1\n```\n\n```{r, file=a.R}\n<<old>>\n```\n",
+        )
+        .unwrap();
+        ctx.config.source_patterns = vec!["**/*.Rmd".to_string()];
+
+        rename(&mut ctx, "old", "setup").unwrap();
+        let a = fs::read_to_string(dir.path().join("a.Rmd")).unwrap();
+        assert_eq!(
+            a,
+            "```{r setup, echo=FALSE}\nx <- 1\n```\n\n```{r, file=a.R}\n<<setup>>\n```\n"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Cache of parsed source documents, kept next to the file database.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::file_cache::FileCache;
+use super::filedb::normalize_separators;
+use crate::errors::Result;
+use crate::text_location::TextLocation;
+
+/// Parsed source documents from the last full tangle, so that unchanged
+/// files need not be parsed again.
+///
+/// Kept in a file of its own (`<filedb_path>.documents`) rather than in the
+/// [`FileDB`](super::FileDB), which only records hashes: the cache holds the
+/// full source of every block, and losing it only costs a re-parse.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentCache {
+    /// Documents by source path, saved sorted by path.
+    #[serde(default)]
+    pub documents: BTreeMap<PathBuf, DocumentRecord>,
+}
+
+/// The code blocks of a parsed source document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentRecord {
+    /// Hash of the configuration and the content the document was parsed from.
+    pub key: String,
+    /// YAML frontmatter, if present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontmatter: Option<String>,
+    /// Code blocks in document order.
+    #[serde(default)]
+    pub blocks: Vec<BlockRecord>,
+}
+
+/// A code block of a [`DocumentRecord`], with the fields of a
+/// [`CodeBlock`](crate::model::CodeBlock).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRecord {
+    /// Reference ID, as `name[count]`.
+    pub id: String,
+    /// Language identifier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Additional classes from the code fence.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<String>,
+    /// Primary target file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<PathBuf>,
+    /// Further target files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_targets: Vec<PathBuf>,
+    /// Block content.
+    pub source: String,
+    /// Location in the source document.
+    pub location: TextLocation,
+    /// Attributes from the code fence, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<(String, String)>,
+    /// Whether the block is left out of tangled output.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip: bool,
+    /// Namespace of the document the block was read from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl DocumentCache {
+    /// Returns the cache file kept next to the file database at
+    /// `filedb_path`.
+    pub fn path_for(filedb_path: &Path) -> PathBuf {
+        let mut name = filedb_path.as_os_str().to_owned();
+        name.push(".documents");
+        PathBuf::from(name)
+    }
+
+    /// Loads the cache at `path` from `fs`.
+    ///
+    /// A missing or unreadable cache is empty, since every document can be
+    /// parsed again.
+    pub fn load(fs: &dyn FileCache, path: &Path) -> Self {
+        if !fs.exists(path) {
+            return Self::default();
+        }
+        let loaded = match fs.read(path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        loaded.unwrap_or_else(|e| {
+            tracing::debug!("Ignoring document cache at {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    /// Writes the cache to `path` in `fs`.
+    pub fn save(&self, fs: &dyn FileCache, path: &Path) -> Result<()> {
+        fs.write(path, &serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Returns the record of the document at `path`.
+    pub fn get(&self, path: &Path) -> Option<&DocumentRecord> {
+        self.documents.get(&normalize_separators(path))
+    }
+
+    /// Records the document at `path`.
+    pub fn insert(&mut self, path: &Path, record: DocumentRecord) {
+        self.documents.insert(normalize_separators(path), record);
+    }
+
+    /// Returns the number of cached documents.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Returns true if no document is cached.
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Drops every cached document.
+    pub fn clear(&mut self) {
+        self.documents.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::VirtualFS;
+
+    fn record(key: &str) -> DocumentRecord {
+        DocumentRecord {
+            key: key.to_string(),
+            frontmatter: None,
+            blocks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(
+            DocumentCache::path_for(Path::new(".entangled/filedb.json")),
+            PathBuf::from(".entangled/filedb.json.documents")
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let fs = VirtualFS::new();
+        let path = Path::new(".entangled/filedb.json.documents");
+        let mut cache = DocumentCache::default();
+        cache.insert(Path::new("b.md"), record("b"));
+        cache.insert(Path::new("a.md"), record("a"));
+        cache.save(&fs, path).unwrap();
+
+        let loaded = DocumentCache::load(&fs, path);
+        assert_eq!(loaded, cache);
+        assert_eq!(loaded.get(Path::new("a.md")), Some(&record("a")));
+    }
+
+    #[test]
+    fn test_load_missing_or_unreadable() {
+        let fs = VirtualFS::new();
+        let path = Path::new("filedb.json.documents");
+        assert!(DocumentCache::load(&fs, path).is_empty());
+        fs.write(path, "not json").unwrap();
+        assert!(DocumentCache::load(&fs, path).is_empty());
+    }
+}
@@ -1,19 +1,31 @@
 //! File system abstraction for testability.
 
 use std::collections::HashMap;
-use std::fs;
-use std::io;
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use chrono::{DateTime, Utc};
 
-use super::stat::{FileData, Stat};
+use super::stat::{FileData, HashingWriter, Stat};
+
+/// Callback that renders a file's content into a writer.
+pub type Render<'a> = dyn FnMut(&mut dyn Write) -> io::Result<()> + 'a;
 
 /// Trait for file system operations, allowing both real and virtual implementations.
 pub trait FileCache: Send + Sync + std::fmt::Debug {
     /// Reads the contents of a file.
     fn read(&self, path: &Path) -> io::Result<String>;
 
+    /// Reads the contents of a file that need not be text.
+    ///
+    /// The default implementation reads the file as text.
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.read(path).map(String::into_bytes)
+    }
+
     /// Checks if a file exists.
     fn exists(&self, path: &Path) -> bool;
 
@@ -25,6 +37,27 @@ pub trait FileCache: Send + Sync + std::fmt::Debug {
 
     /// Lists files matching a glob pattern.
     fn glob(&self, pattern: &str) -> io::Result<Vec<PathBuf>>;
+
+    /// Writes a file, creating parent directories as needed.
+    fn write(&self, path: &Path, content: &str) -> io::Result<()>;
+
+    /// Removes a file. Removing a file that does not exist is not an error.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Writes a file whose content is produced by `render`, returning the
+    /// data of the written file.
+    ///
+    /// The default implementation renders into memory and calls
+    /// [`write`](FileCache::write). Implementations backed by disk stream the
+    /// content into the file instead.
+    fn write_with(&self, path: &Path, render: &mut Render) -> io::Result<FileData> {
+        let mut buf = Vec::new();
+        render(&mut buf)?;
+        let content =
+            String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.write(path, &content)?;
+        self.file_data(path)
+    }
 }
 
 /// Real file system implementation.
@@ -60,6 +93,10 @@ impl FileCache for RealFileCache {
         fs::read_to_string(self.resolve(path))
     }
 
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(self.resolve(path))
+    }
+
     fn exists(&self, path: &Path) -> bool {
         self.resolve(path).exists()
     }
@@ -88,37 +125,155 @@ impl FileCache for RealFileCache {
 
         Ok(paths)
     }
+
+    fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+        let path = self.resolve(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        atomic_write(&path, content)
+    }
+
+    fn write_with(&self, path: &Path, render: &mut Render) -> io::Result<FileData> {
+        let path = self.resolve(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        atomic_write_with(&path, render)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        match fs::remove_file(self.resolve(path)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
 }
 
-/// Virtual file system for testing.
-#[derive(Debug, Clone, Default)]
+/// Counter for unique temp file names.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// File name prefix of the temporary files used for atomic writes.
+pub const TEMP_FILE_PREFIX: &str = ".entangled-tmp-";
+
+/// Writes content to a file atomically using a temp file.
+fn atomic_write(path: &Path, content: &str) -> io::Result<()> {
+    atomic_write_with(path, &mut |w| w.write_all(content.as_bytes()))?;
+    Ok(())
+}
+
+/// Streams rendered content into a temp file next to `path`, then renames
+/// it into place. The content is hashed as it is written.
+///
+/// The temp file is removed if rendering fails.
+fn atomic_write_with(path: &Path, render: &mut Render) -> io::Result<FileData> {
+    // Create temp file in the same directory with unique name
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let counter = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_path = parent.join(format!(
+        "{}{}-{}",
+        TEMP_FILE_PREFIX,
+        std::process::id(),
+        counter
+    ));
+
+    // Write to temp file
+    let written = File::create(&temp_path).and_then(|file| {
+        let mut writer = HashingWriter::new(io::BufWriter::new(file));
+        render(&mut writer)?;
+        let (buffered, hexdigest) = writer.finish();
+        let file = buffered.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(hexdigest)
+    });
+    let hexdigest = match written {
+        Ok(hexdigest) => hexdigest,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+    };
+
+    // Rename to target
+    fs::rename(&temp_path, path)?;
+
+    Ok(FileData::new(Stat::from_path(path)?, hexdigest))
+}
+
+/// In-memory file system, for tests and for running without disk access.
+///
+/// Files can be added up front and are written back by transactions
+/// executed against it. With a base directory set, absolute paths below it
+/// address the same files as the corresponding relative paths, mirroring
+/// how [`RealFileCache`] resolves paths.
+#[derive(Debug, Default)]
 pub struct VirtualFS {
     /// Files stored in memory.
-    files: HashMap<PathBuf, VirtualFile>,
+    files: RwLock<HashMap<PathBuf, VirtualFile>>,
+    /// Base directory that absolute paths are made relative to.
+    base_dir: Option<PathBuf>,
 }
 
 /// A file in the virtual file system.
 #[derive(Debug, Clone)]
 struct VirtualFile {
-    content: String,
+    /// Raw content; files written by [`FileCache::write_with`] need not be
+    /// text.
+    content: Vec<u8>,
     mtime: DateTime<Utc>,
 }
 
+impl Clone for VirtualFS {
+    fn clone(&self) -> Self {
+        Self {
+            files: RwLock::new(self.files().clone()),
+            base_dir: self.base_dir.clone(),
+        }
+    }
+}
+
 impl VirtualFS {
     /// Creates a new empty virtual file system.
     pub fn new() -> Self {
-        Self {
-            files: HashMap::new(),
-        }
+        Self::default()
+    }
+
+    /// Sets the base directory, so that absolute paths below it resolve to
+    /// the same files as relative ones.
+    #[must_use]
+    pub fn with_base_dir(mut self, base_dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = Some(base_dir.into());
+        self
+    }
+
+    /// Returns the key a path is stored under.
+    fn key(&self, path: &Path) -> PathBuf {
+        self.base_dir
+            .as_deref()
+            .and_then(|base| path.strip_prefix(base).ok())
+            .unwrap_or(path)
+            .to_path_buf()
+    }
+
+    fn files(&self) -> RwLockReadGuard<'_, HashMap<PathBuf, VirtualFile>> {
+        self.files
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn files_mut(&self) -> RwLockWriteGuard<'_, HashMap<PathBuf, VirtualFile>> {
+        self.files
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
     }
 
     /// Adds a file to the virtual file system.
     pub fn add_file(&mut self, path: impl Into<PathBuf>, content: impl Into<String>) {
-        let path = path.into();
-        self.files.insert(
+        let path = self.key(&path.into());
+        self.files_mut().insert(
             path,
             VirtualFile {
-                content: content.into(),
+                content: content.into().into_bytes(),
                 mtime: Utc::now(),
             },
         );
@@ -131,11 +286,11 @@ impl VirtualFS {
         content: impl Into<String>,
         mtime: DateTime<Utc>,
     ) {
-        let path = path.into();
-        self.files.insert(
+        let path = self.key(&path.into());
+        self.files_mut().insert(
             path,
             VirtualFile {
-                content: content.into(),
+                content: content.into().into_bytes(),
                 mtime,
             },
         );
@@ -143,38 +298,54 @@ impl VirtualFS {
 
     /// Removes a file from the virtual file system.
     pub fn remove_file(&mut self, path: &Path) {
-        self.files.remove(path);
+        let path = self.key(path);
+        self.files_mut().remove(&path);
+    }
+
+    /// Lists all files in the virtual file system, sorted.
+    ///
+    /// Files are kept behind a lock, so the paths are owned.
+    pub fn file_paths(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.files().keys().cloned().collect();
+        files.sort();
+        files
     }
 
-    /// Lists all files in the virtual file system.
-    pub fn list_files(&self) -> Vec<&PathBuf> {
-        self.files.keys().collect()
+    /// Lists all files in the virtual file system, sorted.
+    #[deprecated(note = "use `file_paths`; the paths can no longer be borrowed")]
+    pub fn list_files(&self) -> Vec<PathBuf> {
+        self.file_paths()
     }
 }
 
 impl FileCache for VirtualFS {
     fn read(&self, path: &Path) -> io::Result<String> {
-        self.files
-            .get(path)
+        String::from_utf8(self.read_bytes(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files()
+            .get(&self.key(path))
             .map(|f| f.content.clone())
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))
     }
 
     fn exists(&self, path: &Path) -> bool {
-        self.files.contains_key(path)
+        self.files().contains_key(&self.key(path))
     }
 
     fn stat(&self, path: &Path) -> io::Result<Stat> {
-        self.files
-            .get(path)
+        self.files()
+            .get(&self.key(path))
             .map(|f| Stat::new(f.mtime, f.content.len() as u64))
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))
     }
 
     fn file_data(&self, path: &Path) -> io::Result<FileData> {
-        self.files
-            .get(path)
-            .map(|f| FileData::from_content(&f.content, f.mtime))
+        self.files()
+            .get(&self.key(path))
+            .map(|f| FileData::from_bytes(&f.content, f.mtime))
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))
     }
 
@@ -182,21 +353,47 @@ impl FileCache for VirtualFS {
         let glob_pattern = glob::Pattern::new(pattern)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
 
-        let matches = self
-            .files
+        let mut matches: Vec<PathBuf> = self
+            .files()
             .keys()
             .filter(|p| glob_pattern.matches_path(p))
             .cloned()
             .collect();
+        matches.sort();
 
         Ok(matches)
     }
+
+    fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+        self.files_mut().insert(
+            self.key(path),
+            VirtualFile {
+                content: content.as_bytes().to_vec(),
+                mtime: Utc::now(),
+            },
+        );
+        Ok(())
+    }
+
+    fn write_with(&self, path: &Path, render: &mut Render) -> io::Result<FileData> {
+        let mut content = Vec::new();
+        render(&mut content)?;
+        let mtime = Utc::now();
+        let data = FileData::from_bytes(&content, mtime);
+        self.files_mut()
+            .insert(self.key(path), VirtualFile { content, mtime });
+        Ok(data)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.files_mut().remove(&self.key(path));
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
     use tempfile::tempdir;
 
     #[test]
@@ -244,6 +441,73 @@ mod tests {
         assert_eq!(md_files.len(), 1);
     }
 
+    #[test]
+    fn test_virtual_fs_write_and_base_dir() {
+        let vfs = VirtualFS::new().with_base_dir("/project");
+        vfs.write(Path::new("/project/src/main.rs"), "fn main() {}")
+            .unwrap();
+
+        assert_eq!(vfs.read(Path::new("src/main.rs")).unwrap(), "fn main() {}");
+        assert_eq!(vfs.file_paths(), vec![PathBuf::from("src/main.rs")]);
+
+        vfs.remove(Path::new("src/main.rs")).unwrap();
+        assert!(!vfs.exists(Path::new("/project/src/main.rs")));
+        vfs.remove(Path::new("src/main.rs")).unwrap();
+    }
+
+    #[test]
+    fn test_real_file_cache_write_and_remove() {
+        let dir = tempdir().unwrap();
+        let cache = RealFileCache::new(dir.path().to_path_buf());
+
+        cache.write(Path::new("nested/out.txt"), "data").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("nested/out.txt")).unwrap(),
+            "data"
+        );
+
+        cache.remove(Path::new("nested/out.txt")).unwrap();
+        assert!(!dir.path().join("nested/out.txt").exists());
+        cache.remove(Path::new("nested/out.txt")).unwrap();
+    }
+
+    #[test]
+    fn test_write_with_streams_and_hashes() {
+        let dir = tempdir().unwrap();
+        let cache = RealFileCache::new(dir.path().to_path_buf());
+
+        let data = cache
+            .write_with(Path::new("out.txt"), &mut |w| {
+                w.write_all(b"hello ")?;
+                w.write_all(b"world")
+            })
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("out.txt")).unwrap(),
+            "hello world"
+        );
+        assert_eq!(data.hexdigest, crate::io::hexdigest_str("hello world"));
+        assert_eq!(data.stat.size, 11);
+
+        // A failed render leaves neither the target nor a temp file behind
+        let result = cache.write_with(Path::new("bad.txt"), &mut |w| {
+            w.write_all(b"partial")?;
+            Err(io::Error::other("render failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_virtual_fs_write_with() {
+        let fs = VirtualFS::new();
+        let data = fs
+            .write_with(Path::new("out.txt"), &mut |w| w.write_all(b"content"))
+            .unwrap();
+        assert_eq!(fs.read(Path::new("out.txt")).unwrap(), "content");
+        assert_eq!(data.hexdigest, crate::io::hexdigest_str("content"));
+    }
+
     #[test]
     fn test_real_file_cache() {
         let dir = tempdir().unwrap();
@@ -1,28 +1,162 @@
 //! File database for tracking tangled file states.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 
-use super::stat::FileData;
-use crate::errors::Result;
+use super::file_cache::{FileCache, RealFileCache};
+use super::stat::{FileData, Stat};
+use crate::config::FileDbFormat;
+use crate::errors::{EntangledError, Result};
+
+/// Version of the file database format written by this crate.
+///
+/// Bump it when the serialized form changes, and teach
+/// [`FileDB::from_json`] to read the previous version.
+pub const FILEDB_VERSION: &str = "1.0";
 
 /// Database of file states for conflict detection.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+///
+/// Files are keyed by their path relative to the [base
+/// directory](Self::with_base_dir), with `/` separators, so that a database
+/// stays valid when the project is moved or checked out on another platform.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileDB {
-    /// Map from file path to its recorded state.
-    #[serde(default)]
+    /// Map from file path, relative to the base directory, to its recorded
+    /// state, saved sorted by path.
+    #[serde(default, serialize_with = "serialize_sorted")]
     pub files: HashMap<PathBuf, FileData>,
 
     /// Version of the database format.
     #[serde(default = "default_version")]
     pub version: String,
+
+    /// Source and configuration hashes recorded after the last full tangle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_tangle: Option<SourceState>,
+
+    /// Set when the database was migrated and the file stats may be out of
+    /// date; see [`FileDB::restat`].
+    #[serde(skip)]
+    needs_restat: bool,
+
+    /// Directory that `files` keys are relative to.
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+/// Hashes of the inputs to a tangle, used to skip unchanged runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceState {
+    /// Fingerprint of the configuration.
+    pub config: String,
+    /// Content hash of each source file.
+    pub sources: BTreeMap<PathBuf, String>,
 }
 
 fn default_version() -> String {
-    "1.0".to_string()
+    FILEDB_VERSION.to_string()
+}
+
+impl Default for FileDB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// On Windows, returns `path` with every `\` separator replaced by `/`.
+///
+/// Target and file database paths are kept in this form, so that a database
+/// written on Windows matches the same files on other platforms. Windows
+/// accepts `/` as a separator too, so normalized paths can be opened as they
+/// are. Elsewhere `\` is an ordinary file name character, and `path` is
+/// returned unchanged.
+pub fn normalize_separators(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(s) if cfg!(windows) && s.contains('\\') => PathBuf::from(s.replace('\\', "/")),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Returns `path` with every `\` replaced by `/`, on every platform.
+///
+/// Used for paths read from a saved database, which are always written with
+/// `/`; a `\` there comes from a database written on Windows by an older
+/// version.
+fn portable_separators(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(s) if s.contains('\\') => PathBuf::from(s.replace('\\', "/")),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Returns true if `path` is absolute or its `..` components climb out of
+/// the directory it is relative to.
+///
+/// The check is purely lexical and does not touch the filesystem.
+pub fn escapes_directory(path: &Path) -> bool {
+    path.is_absolute()
+        || path.has_root()
+        || !normalize_path(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Lexically normalizes a path, resolving `.` and `..` components.
+///
+/// Leading `..` components that cannot be resolved are kept.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(result.components().next_back(), Some(Component::Normal(_))) {
+                    result.pop();
+                } else {
+                    result.push(component);
+                }
+            }
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+/// Serializes a map with its entries sorted by path, so that a saved
+/// database only changes where its entries did.
+fn serialize_sorted<S, V>(
+    map: &HashMap<PathBuf, V>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    V: Serialize,
+{
+    serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>())
+}
+
+/// Parses `content` in `format` into a generic value.
+fn decode_value(content: &[u8], format: FileDbFormat) -> Result<serde_json::Value> {
+    match format {
+        FileDbFormat::Json => Ok(serde_json::from_slice(content)?),
+        FileDbFormat::Toml => {
+            let text = std::str::from_utf8(content).map_err(|e| {
+                EntangledError::Other(format!("File database is not valid UTF-8: {}", e))
+            })?;
+            Ok(serde_json::to_value(toml::from_str::<toml::Value>(text)?)?)
+        }
+        FileDbFormat::Binary => ciborium::from_reader(content)
+            .map_err(|e| EntangledError::Other(format!("Invalid binary file database: {}", e))),
+    }
+}
+
+/// Parses a `major.minor` version string.
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let (major, minor) = version.split_once('.').unwrap_or((version, "0"));
+    Some((major.parse().ok()?, minor.parse().ok()?))
 }
 
 impl FileDB {
@@ -32,20 +166,191 @@ impl FileDB {
         Self {
             files: HashMap::new(),
             version: default_version(),
+            last_tangle: None,
+            needs_restat: false,
+            base_dir: PathBuf::new(),
         }
     }
 
+    /// Sets the directory that recorded paths are relative to.
+    ///
+    /// Paths passed to [`record`](Self::record) and the other lookups may be
+    /// absolute; those inside `base_dir` are stored relative to it. Entries
+    /// already recorded, such as absolute paths from an older database, are
+    /// rewritten the same way.
+    #[must_use]
+    pub fn with_base_dir(mut self, base_dir: PathBuf) -> Self {
+        self.base_dir = base_dir;
+        self.files = std::mem::take(&mut self.files)
+            .into_iter()
+            .map(|(path, data)| (self.key(&path), data))
+            .collect();
+        self
+    }
+
+    /// Returns the directory that recorded paths are relative to.
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    /// Returns the key `path` is stored under: relative to the base
+    /// directory when inside it, with `/` separators.
+    fn key(&self, path: &Path) -> PathBuf {
+        normalize_separators(path.strip_prefix(&self.base_dir).unwrap_or(path))
+    }
+
     /// Loads the file database from a JSON file.
+    ///
+    /// Older formats are migrated as described in [`from_json`](Self::from_json),
+    /// and the stats of migrated entries are refreshed from disk.
     pub fn load(path: &Path) -> Result<Self> {
         if !path.exists() {
             return Ok(Self::new());
         }
 
         let content = fs::read_to_string(path)?;
-        let db: FileDB = serde_json::from_str(&content)?;
+        let mut db = Self::from_json(&content)?;
+        if db.needs_restat() {
+            db.restat(&RealFileCache::default());
+        }
+        Ok(db)
+    }
+
+    /// Parses a serialized file database, migrating older formats.
+    ///
+    /// A database from an older version, or one whose entries no longer
+    /// match the current format, keeps the recorded hash of every entry
+    /// that has one, so that external modifications are still detected;
+    /// entries without a hash are dropped with a warning. Such a database
+    /// [needs a re-stat](Self::needs_restat). A database written by a newer
+    /// version is an error rather than being discarded.
+    pub fn from_json(content: &str) -> Result<Self> {
+        Self::from_value(serde_json::from_str(content)?)
+    }
+
+    /// Serializes the database in `format`.
+    ///
+    /// JSON and TOML list files sorted by path, so a database kept under
+    /// version control only changes where tracked files did.
+    pub fn encode(&self, format: FileDbFormat) -> Result<Vec<u8>> {
+        let serialize_error = |e: &dyn std::fmt::Display| {
+            EntangledError::Other(format!("Failed to serialize file database: {}", e))
+        };
+        match format {
+            FileDbFormat::Json => Ok(serde_json::to_string_pretty(self)?.into_bytes()),
+            FileDbFormat::Toml => toml::to_string_pretty(self)
+                .map(String::into_bytes)
+                .map_err(|e| serialize_error(&e)),
+            FileDbFormat::Binary => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(self, &mut buf).map_err(|e| serialize_error(&e))?;
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Parses a database serialized in `format`, migrating older versions
+    /// like [`from_json`](Self::from_json).
+    ///
+    /// Content that is not valid `format` is tried in the other formats, so
+    /// that changing `filedb_format` converts the database on the next save.
+    pub fn decode(content: &[u8], format: FileDbFormat) -> Result<Self> {
+        let value = match decode_value(content, format) {
+            Ok(value) => value,
+            Err(e) => FileDbFormat::all()
+                .into_iter()
+                .filter(|other| *other != format)
+                .find_map(|other| decode_value(content, other).ok())
+                .ok_or(e)?,
+        };
+        Self::from_value(value)
+    }
+
+    /// Reads a database in the generic form shared by all formats.
+    fn from_value(json: serde_json::Value) -> Result<Self> {
+        let version = json
+            .get("version")
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or(FILEDB_VERSION)
+            .to_string();
+        let parsed = parse_version(&version);
+        let current = parse_version(FILEDB_VERSION);
+        if parsed > current {
+            return Err(EntangledError::UnsupportedFileDb {
+                found: version,
+                supported: FILEDB_VERSION.to_string(),
+            });
+        }
+
+        let mut db = match serde_json::from_value::<FileDB>(json.clone()) {
+            Ok(db) if parsed == current => return Ok(db.with_normalized_paths()),
+            Ok(db) => db.with_normalized_paths(),
+            Err(e) => Self::salvage(&json).ok_or(e)?,
+        };
+        tracing::info!(
+            "Migrating file database from version {} to {}",
+            version,
+            FILEDB_VERSION
+        );
+        db.version = default_version();
+        db.needs_restat = true;
         Ok(db)
     }
 
+    /// Rewrites every recorded path with `/` separators, whatever the
+    /// platform.
+    fn with_normalized_paths(mut self) -> Self {
+        self.files = std::mem::take(&mut self.files)
+            .into_iter()
+            .map(|(path, data)| (portable_separators(&path), data))
+            .collect();
+        if let Some(state) = &mut self.last_tangle {
+            state.sources = std::mem::take(&mut state.sources)
+                .into_iter()
+                .map(|(path, hash)| (portable_separators(&path), hash))
+                .collect();
+        }
+        self
+    }
+
+    /// Recovers the path and hash of every readable entry under `files`.
+    ///
+    /// Stats are placeholders until the next [`restat`](Self::restat).
+    fn salvage(json: &serde_json::Value) -> Option<Self> {
+        let files = json.get("files")?.as_object()?;
+        let mut db = Self::new();
+        for (path, entry) in files {
+            match entry.get("hexdigest").and_then(serde_json::Value::as_str) {
+                Some(hexdigest) => db.record(
+                    portable_separators(Path::new(path)),
+                    FileData::new(
+                        Stat::new(chrono::DateTime::UNIX_EPOCH, 0),
+                        hexdigest.to_string(),
+                    ),
+                ),
+                None => tracing::warn!("Dropping unreadable file database entry for {}", path),
+            }
+        }
+        Some(db)
+    }
+
+    /// Returns true if the database was migrated and the stats of tracked
+    /// files should be refreshed with [`restat`](Self::restat).
+    pub fn needs_restat(&self) -> bool {
+        self.needs_restat
+    }
+
+    /// Refreshes the recorded stat of every tracked file that exists in
+    /// `fs`, keeping the recorded hash.
+    pub fn restat(&mut self, fs: &dyn FileCache) {
+        for (path, data) in &mut self.files {
+            if let Ok(stat) = fs.stat(&self.base_dir.join(path)) {
+                data.stat = stat;
+            }
+        }
+        self.needs_restat = false;
+    }
+
     /// Saves the file database to a JSON file.
     pub fn save(&self, path: &Path) -> Result<()> {
         // Ensure parent directory exists
@@ -59,28 +364,31 @@ impl FileDB {
     }
 
     /// Records a file's state.
+    ///
+    /// Paths are stored relative to the base directory with `/` separators;
+    /// see [`with_base_dir`](Self::with_base_dir) and [`normalize_separators`].
     pub fn record(&mut self, path: PathBuf, data: FileData) {
-        self.files.insert(path, data);
+        self.files.insert(self.key(&path), data);
     }
 
     /// Removes a file from the database.
     pub fn remove(&mut self, path: &Path) {
-        self.files.remove(path);
+        self.files.remove(&self.key(path));
     }
 
     /// Gets the recorded state for a file.
     pub fn get(&self, path: &Path) -> Option<&FileData> {
-        self.files.get(path)
+        self.files.get(&self.key(path))
     }
 
     /// Checks if a file is tracked.
     pub fn is_tracked(&self, path: &Path) -> bool {
-        self.files.contains_key(path)
+        self.files.contains_key(&self.key(path))
     }
 
-    /// Returns all tracked file paths.
-    pub fn tracked_files(&self) -> impl Iterator<Item = &PathBuf> {
-        self.files.keys()
+    /// Returns all tracked file paths, joined to the base directory.
+    pub fn tracked_files(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.files.keys().map(|path| self.base_dir.join(path))
     }
 
     /// Returns the number of tracked files.
@@ -93,9 +401,10 @@ impl FileDB {
         self.files.is_empty()
     }
 
-    /// Clears all tracked files.
+    /// Clears all tracked files and the recorded source state.
     pub fn clear(&mut self) {
         self.files.clear();
+        self.last_tangle = None;
     }
 
     /// Checks if a file has been modified externally.
@@ -123,7 +432,7 @@ mod tests {
     fn test_new_db() {
         let db = FileDB::new();
         assert!(db.is_empty());
-        assert_eq!(db.version, "1.0");
+        assert_eq!(db.version, FILEDB_VERSION);
     }
 
     #[test]
@@ -175,6 +484,108 @@ mod tests {
         assert!(db.is_empty());
     }
 
+    #[test]
+    fn test_from_json_current_version() {
+        let mut db = FileDB::new();
+        db.record(PathBuf::from("a.py"), make_file_data("a"));
+        let json = serde_json::to_string(&db).unwrap();
+
+        let loaded = FileDB::from_json(&json).unwrap();
+        assert!(loaded.is_tracked(Path::new("a.py")));
+        assert!(!loaded.needs_restat());
+    }
+
+    #[test]
+    fn test_from_json_migrates_older_version() {
+        let mut db = FileDB::new();
+        db.record(PathBuf::from("a.py"), make_file_data("a"));
+        let mut json = serde_json::to_value(&db).unwrap();
+        json["version"] = "0.9".into();
+
+        let loaded = FileDB::from_json(&json.to_string()).unwrap();
+        assert_eq!(loaded.version, FILEDB_VERSION);
+        assert_eq!(
+            loaded.get(Path::new("a.py")).unwrap().hexdigest,
+            make_file_data("a").hexdigest
+        );
+        assert!(loaded.needs_restat());
+    }
+
+    #[test]
+    fn test_from_json_salvages_hashes() {
+        let json = r#"{
+            "version": "0.1",
+            "files": {
+                "a.py": {"hexdigest": "abc", "stat": "unknown format"},
+                "b.py": {"checksum": "def"}
+            }
+        }"#;
+
+        let loaded = FileDB::from_json(json).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.get(Path::new("a.py")).unwrap().hexdigest, "abc");
+        assert!(loaded.needs_restat());
+    }
+
+    #[test]
+    fn test_from_json_rejects_newer_version() {
+        let json = r#"{"version": "99.0", "files": {}}"#;
+        assert!(matches!(
+            FileDB::from_json(json),
+            Err(EntangledError::UnsupportedFileDb { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_restats_migrated_files() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.py");
+        fs::write(&file, "hello").unwrap();
+        let db_path = dir.path().join("filedb.json");
+        let json = serde_json::json!({
+            "version": "0.9",
+            "files": {file.to_str().unwrap(): {"hexdigest": "abc"}},
+        });
+        fs::write(&db_path, json.to_string()).unwrap();
+
+        let loaded = FileDB::load(&db_path).unwrap();
+        let data = loaded.get(&file).unwrap();
+        assert_eq!(data.hexdigest, "abc");
+        assert_eq!(data.stat.size, 5);
+        assert!(!loaded.needs_restat());
+    }
+
+    #[test]
+    fn test_encode_decode_formats() {
+        let mut db = FileDB::new();
+        db.record(PathBuf::from("b.py"), make_file_data("b"));
+        db.record(PathBuf::from("a.py"), make_file_data("a"));
+
+        for format in FileDbFormat::all() {
+            let encoded = db.encode(format).unwrap();
+            let decoded = FileDB::decode(&encoded, format).unwrap();
+            assert_eq!(decoded.files, db.files, "{:?}", format);
+            assert!(!decoded.needs_restat());
+        }
+
+        // Text formats list files sorted by path
+        for format in [FileDbFormat::Json, FileDbFormat::Toml] {
+            let text = String::from_utf8(db.encode(format).unwrap()).unwrap();
+            assert!(text.find("a.py").unwrap() < text.find("b.py").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_decode_other_format() {
+        let mut db = FileDB::new();
+        db.record(PathBuf::from("a.py"), make_file_data("a"));
+        let json = db.encode(FileDbFormat::Json).unwrap();
+
+        let decoded = FileDB::decode(&json, FileDbFormat::Toml).unwrap();
+        assert!(decoded.is_tracked(Path::new("a.py")));
+        assert!(FileDB::decode(b"not a database", FileDbFormat::Json).is_err());
+    }
+
     #[test]
     fn test_is_modified() {
         let mut db = FileDB::new();
@@ -189,6 +600,73 @@ mod tests {
         assert!(db.is_modified(&path, &different));
     }
 
+    #[test]
+    fn test_paths_use_forward_slashes() {
+        // `\` is a separator only on Windows
+        let mut db = FileDB::new();
+        db.record(PathBuf::from("src\\lib\\out.rs"), make_file_data("a"));
+        assert_eq!(db.is_tracked(Path::new("src/lib/out.rs")), cfg!(windows));
+        assert!(db.is_tracked(Path::new("src\\lib\\out.rs")));
+
+        // A saved database is read with forward slashes on every platform
+        let json = serde_json::to_string(&db).unwrap();
+        let loaded = FileDB::from_json(&json).unwrap();
+        assert!(loaded.files.contains_key(Path::new("src/lib/out.rs")));
+
+        db.remove(Path::new("src\\lib\\out.rs"));
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn test_load_converts_backslash_keys() {
+        let base = Path::new("/project");
+        let json = r#"{
+            "version": "1.0",
+            "files": {
+                "src\\lib\\out.rs": {
+                    "stat": {"mtime": "2024-01-01T00:00:00Z", "size": 1},
+                    "hexdigest": "abc"
+                }
+            }
+        }"#;
+
+        let loaded = FileDB::from_json(json)
+            .unwrap()
+            .with_base_dir(base.to_path_buf());
+        assert_eq!(
+            loaded.get(&base.join("src/lib/out.rs")).unwrap().hexdigest,
+            "abc"
+        );
+        assert_eq!(
+            loaded.tracked_files().collect::<Vec<_>>(),
+            vec![base.join("src/lib/out.rs")]
+        );
+    }
+
+    #[test]
+    fn test_paths_relative_to_base_dir() {
+        let base = Path::new("/project");
+        let mut db = FileDB::new().with_base_dir(base.to_path_buf());
+        db.record(base.join("src/out.rs"), make_file_data("a"));
+        assert!(db.files.contains_key(Path::new("src/out.rs")));
+        assert!(db.is_tracked(&base.join("src/out.rs")));
+
+        // The saved database matches wherever the project is checked out
+        let json = serde_json::to_string(&db).unwrap();
+        assert!(!json.contains("/project"));
+        let moved = Path::new("/elsewhere");
+        let loaded = FileDB::from_json(&json)
+            .unwrap()
+            .with_base_dir(moved.to_path_buf());
+        assert!(loaded.is_tracked(&moved.join("src/out.rs")));
+
+        // Absolute keys from an older database are made relative
+        let mut old = FileDB::new();
+        old.record(base.join("src/out.rs"), make_file_data("a"));
+        let old = old.with_base_dir(base.to_path_buf());
+        assert!(old.files.contains_key(Path::new("src/out.rs")));
+    }
+
     #[test]
     fn test_tracked_files() {
         let mut db = FileDB::new();
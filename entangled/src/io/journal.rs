@@ -0,0 +1,198 @@
+//! Journal of the files changed by the last executed transaction.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::file_cache::FileCache;
+use super::transaction::Transaction;
+use crate::errors::{EntangledError, Result};
+
+/// The content a file had before a transaction changed it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Path of the changed file.
+    pub path: PathBuf,
+    /// Previous content, or `None` if the transaction created the file.
+    pub previous: Option<String>,
+}
+
+/// Record of the files changed by the most recent transaction, holding
+/// their content from before it ran so that the run can be undone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journal {
+    /// When the transaction was executed.
+    pub timestamp: DateTime<Utc>,
+    /// One entry per changed file, in the order they were changed.
+    pub entries: Vec<JournalEntry>,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Journal {
+    /// Creates an empty journal stamped with the current time.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            timestamp: Utc::now(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the journal file kept next to the file database at `filedb_path`.
+    pub fn path_for(filedb_path: &Path) -> PathBuf {
+        let mut name = filedb_path.as_os_str().to_owned();
+        name.push(".journal");
+        PathBuf::from(name)
+    }
+
+    /// Loads the journal at `path` from `fs`, or `None` if there is none.
+    pub fn load(fs: &dyn FileCache, path: &Path) -> Result<Option<Self>> {
+        if !fs.exists(path) {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs.read(path)?)?))
+    }
+
+    /// Writes the journal to `path` in `fs`.
+    pub fn save(&self, fs: &dyn FileCache, path: &Path) -> Result<()> {
+        fs.write(path, &serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Returns true if no file was changed.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Records the current content of `path` in `fs`, unless it is already
+    /// recorded: the first content seen is the one from before the run.
+    ///
+    /// A file that does not exist is recorded as created. Fails if the file
+    /// exists but cannot be read as text, as it could not be restored.
+    pub fn record(&mut self, fs: &dyn FileCache, path: &Path) -> Result<()> {
+        if self.entries.iter().any(|e| e.path == path) {
+            return Ok(());
+        }
+        let previous = match fs.read(path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(EntangledError::Transaction(format!(
+                    "cannot journal {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        self.entries.push(JournalEntry {
+            path: path.to_path_buf(),
+            previous,
+        });
+        Ok(())
+    }
+
+    /// Adds the entries of a journal written later in the same run, keeping
+    /// the earlier entry of a file recorded by both.
+    pub fn extend(&mut self, later: Journal) {
+        for entry in later.entries {
+            if !self.entries.iter().any(|e| e.path == entry.path) {
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// Adds the actions restoring every recorded file in `fs` to its
+    /// previous content to `transaction`.
+    ///
+    /// Files that were created are deleted again. Files already at their
+    /// previous content are left alone.
+    pub fn restore_into(&self, fs: &dyn FileCache, transaction: &mut Transaction) {
+        for entry in &self.entries {
+            let current = fs.read(&entry.path).ok();
+            if current == entry.previous {
+                continue;
+            }
+            match (&entry.previous, current) {
+                (Some(previous), Some(_)) => transaction.write(&entry.path, previous),
+                (Some(previous), None) => transaction.create(&entry.path, previous),
+                (None, _) => transaction.delete(&entry.path),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{FileDB, VirtualFS};
+
+    #[test]
+    fn test_journal_undoes_transaction() {
+        let fs = VirtualFS::new();
+        fs.write(Path::new("keep.py"), "old\n").unwrap();
+        fs.write(Path::new("gone.py"), "bye\n").unwrap();
+        let journal_path = PathBuf::from(".entangled/filedb.json.journal");
+        let mut db = FileDB::new();
+
+        let mut tx = Transaction::new().with_journal(&journal_path);
+        tx.write("keep.py", "new\n");
+        tx.create("made.py", "hi\n");
+        tx.delete("gone.py");
+        tx.execute_in(&fs, &mut db, true).unwrap();
+
+        let journal = Journal::load(&fs, &journal_path).unwrap().unwrap();
+        assert_eq!(journal.entries.len(), 3);
+        assert_eq!(journal.entries[1].previous, None);
+
+        let mut undo = Transaction::new();
+        journal.restore_into(&fs, &mut undo);
+        assert_eq!(undo.len(), 3);
+        undo.execute_in(&fs, &mut db, true).unwrap();
+
+        assert_eq!(fs.read(Path::new("keep.py")).unwrap(), "old\n");
+        assert_eq!(fs.read(Path::new("gone.py")).unwrap(), "bye\n");
+        assert!(!fs.exists(Path::new("made.py")));
+    }
+
+    #[test]
+    fn test_unchanged_run_keeps_journal() {
+        let fs = VirtualFS::new();
+        let journal_path = PathBuf::from("filedb.json.journal");
+        let mut db = FileDB::new();
+
+        let mut tx = Transaction::new().with_journal(&journal_path);
+        tx.create("a.py", "a\n");
+        tx.execute_in(&fs, &mut db, true).unwrap();
+
+        let mut tx = Transaction::new().with_journal(&journal_path);
+        tx.write("a.py", "a\n");
+        tx.execute_in(&fs, &mut db, true).unwrap();
+
+        let journal = Journal::load(&fs, &journal_path).unwrap().unwrap();
+        assert_eq!(journal.entries[0].path, PathBuf::from("a.py"));
+        assert_eq!(journal.entries[0].previous, None);
+    }
+
+    #[test]
+    fn test_unreadable_file_is_not_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let fs = crate::io::RealFileCache::new(dir.path().to_path_buf());
+        std::fs::write(dir.path().join("data.bin"), [0xff, 0xfe, 0x00]).unwrap();
+        let journal_path = PathBuf::from("filedb.json.journal");
+        let mut db = FileDB::new();
+
+        let mut tx = Transaction::new().with_journal(&journal_path);
+        tx.write("data.bin", "text\n");
+        assert!(tx.execute_in(&fs, &mut db, true).is_err());
+        assert_eq!(
+            std::fs::read(dir.path().join("data.bin")).unwrap(),
+            [0xff, 0xfe, 0x00]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Advisory lock that keeps concurrent runs from clobbering the file database.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// An exclusive advisory lock on a lock file, released when dropped.
+///
+/// The lock file holds the id of the process that owns the lock, so that a
+/// second process can report who holds it. The file itself is left behind
+/// on release: only the lock on it matters, and removing it would let two
+/// processes lock different files under the same name.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Returns the lock file guarding the file database at `filedb_path`.
+    pub fn path_for(filedb_path: &Path) -> PathBuf {
+        let mut name = filedb_path.as_os_str().to_owned();
+        name.push(".lock");
+        PathBuf::from(name)
+    }
+
+    /// Acquires the lock on `path`, creating the file and its directory if
+    /// needed.
+    ///
+    /// Fails with [`std::io::ErrorKind::WouldBlock`] if another process, or
+    /// another lock in this process, already holds it.
+    pub fn acquire(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                let holder = match holder.trim() {
+                    "" => "another entangled process".to_string(),
+                    pid => format!("another entangled process (pid {})", pid),
+                };
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::WouldBlock,
+                    format!(
+                        "{} is locked by {}; wait for it to finish",
+                        path.display(),
+                        holder
+                    ),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(Self { file, path })
+    }
+
+    /// Returns the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(
+            FileLock::path_for(Path::new(".entangled/filedb.json")),
+            PathBuf::from(".entangled/filedb.json.lock")
+        );
+    }
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(".entangled/filedb.json.lock");
+
+        let lock = FileLock::acquire(&path).unwrap();
+        let err = FileLock::acquire(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+        assert!(err
+            .to_string()
+            .contains(&format!("pid {}", std::process::id())));
+
+        drop(lock);
+        FileLock::acquire(&path).unwrap();
+    }
+}
@@ -1,11 +1,20 @@
 //! I/O operations for file handling and persistence.
 
+mod document_cache;
 mod file_cache;
 mod filedb;
+mod journal;
+mod lock;
 mod stat;
 mod transaction;
 
-pub use file_cache::{FileCache, RealFileCache, VirtualFS};
-pub use filedb::FileDB;
-pub use stat::{hexdigest_file, hexdigest_str, FileData, Stat};
-pub use transaction::{Action, Create, Delete, Transaction, WriteAction};
+pub use document_cache::{BlockRecord, DocumentCache, DocumentRecord};
+pub use file_cache::{FileCache, RealFileCache, Render, VirtualFS, TEMP_FILE_PREFIX};
+pub use filedb::{escapes_directory, normalize_separators, FileDB, SourceState, FILEDB_VERSION};
+pub use journal::{Journal, JournalEntry};
+pub use lock::FileLock;
+pub use stat::{hexdigest_file, hexdigest_str, FileData, HashingWriter, Stat};
+pub use transaction::{
+    unified_diff, Action, ActionData, Create, Delete, DiffStat, RenderFn, StreamWrite, Transaction,
+    TransactionSummary, WriteAction, DEFAULT_DIFF_CONTEXT,
+};
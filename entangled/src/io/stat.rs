@@ -1,7 +1,7 @@
 //! File statistics and hashing.
 
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::path::Path;
 
 use chrono::{DateTime, Utc};
@@ -56,11 +56,16 @@ impl FileData {
 
     /// Creates FileData from content string.
     pub fn from_content(content: &str, mtime: DateTime<Utc>) -> Self {
-        let size = content.len() as u64;
-        let hexdigest = hexdigest_str(content);
+        Self::from_bytes(content.as_bytes(), mtime)
+    }
+
+    /// Creates file data for content that need not be text.
+    pub fn from_bytes(content: &[u8], mtime: DateTime<Utc>) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(content);
         Self {
-            stat: Stat::new(mtime, size),
-            hexdigest,
+            stat: Stat::new(mtime, content.len() as u64),
+            hexdigest: hex::encode(hasher.finalize()),
         }
     }
 }
@@ -72,6 +77,52 @@ pub fn hexdigest_str(content: &str) -> String {
     hex::encode(hasher.finalize())
 }
 
+/// Writer adapter that hashes everything written through it.
+///
+/// Used to record a file's digest while streaming it to disk, without
+/// holding the content in memory.
+#[derive(Debug)]
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// Wraps a writer.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the wrapped writer and the hex-encoded digest of the bytes
+    /// written.
+    pub fn finish(self) -> (W, String) {
+        (self.inner, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 /// Computes SHA256 hash of a file, returning hex-encoded digest.
 pub fn hexdigest_file(path: &Path) -> io::Result<String> {
     let mut file = fs::File::open(path)?;
@@ -104,6 +155,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hashing_writer_matches_hexdigest() {
+        let mut writer = HashingWriter::new(Vec::new());
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+        assert_eq!(writer.size(), 11);
+
+        let (buf, digest) = writer.finish();
+        assert_eq!(buf, b"hello world");
+        assert_eq!(digest, hexdigest_str("hello world"));
+    }
+
     #[test]
     fn test_hexdigest_empty() {
         let hash = hexdigest_str("");
@@ -1,14 +1,16 @@
 //! Transaction system for atomic file operations.
 
-use std::fs::{self, File};
-use std::io::{self, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
+use super::file_cache::{FileCache, RealFileCache};
 use super::filedb::FileDB;
-use super::stat::FileData;
+use super::journal::Journal;
+use super::stat::{hexdigest_str, FileData, HashingWriter};
 use crate::errors::{EntangledError, Result};
 
 /// An action that can be executed as part of a transaction.
@@ -16,11 +18,11 @@ pub trait Action: std::fmt::Debug + Send + Sync {
     /// Returns the target file path.
     fn target(&self) -> &Path;
 
-    /// Checks if this action conflicts with the current file state.
-    fn check_conflict(&self, db: &FileDB) -> Result<()>;
+    /// Checks if this action conflicts with the current file state in `fs`.
+    fn check_conflict(&self, fs: &dyn FileCache, db: &FileDB) -> Result<()>;
 
-    /// Executes the action.
-    fn execute(&self) -> Result<()>;
+    /// Executes the action against `fs`.
+    fn execute(&self, fs: &dyn FileCache) -> Result<()>;
 
     /// Updates the file database after execution.
     fn update_db(&self, db: &mut FileDB) -> Result<()>;
@@ -32,6 +34,150 @@ pub trait Action: std::fmt::Debug + Send + Sync {
     fn proposed_content(&self) -> Option<&str> {
         None
     }
+
+    /// Returns a short name for the kind of action (`create`, `write`, `delete`).
+    fn kind(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Returns a unified diff of the change this action makes on disk.
+    ///
+    /// Returns `None` when the action would leave the file unchanged.
+    fn diff(&self) -> Option<String> {
+        self.diff_with_context(DEFAULT_DIFF_CONTEXT)
+    }
+
+    /// Like [`diff`](Action::diff), with `context` unchanged lines around
+    /// each change.
+    fn diff_with_context(&self, context: usize) -> Option<String> {
+        self.diff_in(&RealFileCache::default(), context)
+    }
+
+    /// Like [`diff_with_context`](Action::diff_with_context), comparing
+    /// against the current file in `fs`.
+    fn diff_in(&self, fs: &dyn FileCache, context: usize) -> Option<String> {
+        content_diff(fs, self.target(), self.proposed_content(), context)
+    }
+
+    /// Returns line addition and deletion counts for this action.
+    ///
+    /// Returns `None` when the action would leave the file unchanged.
+    fn diff_stat(&self) -> Option<DiffStat> {
+        self.diff_stat_in(&RealFileCache::default())
+    }
+
+    /// Like [`diff_stat`](Action::diff_stat), comparing against the current
+    /// file in `fs`.
+    fn diff_stat_in(&self, fs: &dyn FileCache) -> Option<DiffStat> {
+        content_diff_stat(fs, self.target(), self.proposed_content())
+    }
+}
+
+/// Diffs the current content of `path` in `fs` against `new_content`, where
+/// `None` means the file is deleted.
+fn content_diff(
+    fs: &dyn FileCache,
+    path: &Path,
+    new_content: Option<&str>,
+    context: usize,
+) -> Option<String> {
+    let path_str = path.display().to_string();
+
+    if let Some(new_content) = new_content {
+        let old_content = if fs.exists(path) {
+            fs.read(path).unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        if old_content == new_content {
+            return None;
+        }
+
+        let old_label = format!("a/{}", path_str);
+        let new_label = format!("b/{}", path_str);
+        let diff = unified_diff(&old_content, new_content, &old_label, &new_label, context);
+        if diff.is_empty() {
+            None
+        } else {
+            Some(diff)
+        }
+    } else {
+        // Delete action
+        if fs.exists(path) {
+            if let Ok(content) = fs.read(path) {
+                let old_label = format!("a/{}", path_str);
+                let diff = unified_diff(&content, "", &old_label, "/dev/null", context);
+                Some(diff)
+            } else {
+                Some(format!("delete {}", path_str))
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// Counts the lines changed between `path` in `fs` and `new_content`.
+fn content_diff_stat(
+    fs: &dyn FileCache,
+    path: &Path,
+    new_content: Option<&str>,
+) -> Option<DiffStat> {
+    let old_content = if fs.exists(path) {
+        fs.read(path).unwrap_or_default()
+    } else {
+        String::new()
+    };
+    // A delete proposes no content
+    let new_content = new_content.unwrap_or_default();
+    if old_content == new_content {
+        return None;
+    }
+
+    let (additions, deletions) = line_changes(&old_content, new_content);
+    Some(DiffStat {
+        path: path.to_path_buf(),
+        additions,
+        deletions,
+    })
+}
+
+/// Number of unchanged context lines shown around each change in diffs.
+pub const DEFAULT_DIFF_CONTEXT: usize = 3;
+
+/// Per-file line counts of a pending change, as shown by `--stat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffStat {
+    /// Target file path.
+    pub path: PathBuf,
+    /// Number of added lines.
+    pub additions: usize,
+    /// Number of removed lines.
+    pub deletions: usize,
+}
+
+/// Target paths of a transaction, grouped by what executing it does to them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TransactionSummary {
+    /// Files that do not exist yet.
+    pub created: Vec<PathBuf>,
+    /// Existing files whose content changes.
+    pub written: Vec<PathBuf>,
+    /// Existing files that are rewritten with the same content.
+    pub unchanged: Vec<PathBuf>,
+    /// Files that are removed.
+    pub deleted: Vec<PathBuf>,
+}
+
+impl TransactionSummary {
+    /// Appends the paths of `other`, e.g. for the two halves of a sync.
+    pub fn extend(&mut self, other: TransactionSummary) {
+        self.created.extend(other.created);
+        self.written.extend(other.written);
+        self.unchanged.extend(other.unchanged);
+        self.deleted.extend(other.deleted);
+    }
 }
 
 /// Create a new file (fails if file exists).
@@ -58,8 +204,8 @@ impl Action for Create {
         &self.path
     }
 
-    fn check_conflict(&self, _db: &FileDB) -> Result<()> {
-        if self.path.exists() {
+    fn check_conflict(&self, fs: &dyn FileCache, _db: &FileDB) -> Result<()> {
+        if fs.exists(&self.path) {
             return Err(EntangledError::FileConflict {
                 path: self.path.clone(),
             });
@@ -67,14 +213,8 @@ impl Action for Create {
         Ok(())
     }
 
-    fn execute(&self) -> Result<()> {
-        // Ensure parent directory exists
-        if let Some(parent) = self.path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        // Write atomically via temp file
-        atomic_write(&self.path, &self.content)?;
+    fn execute(&self, fs: &dyn FileCache) -> Result<()> {
+        fs.write(&self.path, &self.content)?;
         Ok(())
     }
 
@@ -88,6 +228,10 @@ impl Action for Create {
         format!("create {}", self.path.display())
     }
 
+    fn kind(&self) -> &'static str {
+        "create"
+    }
+
     fn proposed_content(&self) -> Option<&str> {
         Some(&self.content)
     }
@@ -117,27 +261,12 @@ impl Action for WriteAction {
         &self.path
     }
 
-    fn check_conflict(&self, db: &FileDB) -> Result<()> {
-        // If file exists and is tracked, check for external modifications
-        if self.path.exists() && db.is_tracked(&self.path) {
-            let current = FileData::from_path(&self.path)?;
-            if db.is_modified(&self.path, &current) {
-                return Err(EntangledError::FileConflict {
-                    path: self.path.clone(),
-                });
-            }
-        }
-        Ok(())
+    fn check_conflict(&self, fs: &dyn FileCache, db: &FileDB) -> Result<()> {
+        check_overwrite(fs, db, &self.path, || Some(hexdigest_str(&self.content)))
     }
 
-    fn execute(&self) -> Result<()> {
-        // Ensure parent directory exists
-        if let Some(parent) = self.path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        // Write atomically via temp file
-        atomic_write(&self.path, &self.content)?;
+    fn execute(&self, fs: &dyn FileCache) -> Result<()> {
+        fs.write(&self.path, &self.content)?;
         Ok(())
     }
 
@@ -151,11 +280,147 @@ impl Action for WriteAction {
         format!("write {}", self.path.display())
     }
 
+    fn kind(&self) -> &'static str {
+        "write"
+    }
+
     fn proposed_content(&self) -> Option<&str> {
         Some(&self.content)
     }
 }
 
+/// Fails if `path` is tracked and was modified since it was last written.
+///
+/// A tracked file that was deleted externally is not a conflict: writing it
+/// recreates it, just like a [`Create`]. Neither is a modified file that
+/// already has the proposed content, whose digest `proposed_digest` returns,
+/// such as a target whose edits were just stitched back into its source.
+fn check_overwrite(
+    fs: &dyn FileCache,
+    db: &FileDB,
+    path: &Path,
+    proposed_digest: impl FnOnce() -> Option<String>,
+) -> Result<()> {
+    // If file exists and is tracked, check for external modifications
+    if fs.exists(path) && db.is_tracked(path) {
+        let current = fs.file_data(path)?;
+        if db.is_modified(path, &current) && proposed_digest() != Some(current.hexdigest) {
+            return Err(EntangledError::FileConflict {
+                path: path.to_path_buf(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Renders file content into a writer, for [`StreamWrite`].
+pub type RenderFn = dyn Fn(&mut dyn Write) -> Result<()> + Send + Sync;
+
+/// Write to an existing file, streaming content that is rendered on demand.
+///
+/// Unlike [`WriteAction`], the content is never held in memory while
+/// executing: it is written straight into the atomic temp file and hashed on
+/// the way. Diffs render the content in full, since they need both sides.
+pub struct StreamWrite {
+    /// Target file path.
+    pub path: PathBuf,
+    render: Box<RenderFn>,
+    /// Data of the written file, recorded by `execute`.
+    written: Mutex<Option<FileData>>,
+}
+
+impl StreamWrite {
+    /// Creates a new streaming write action.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        render: impl Fn(&mut dyn Write) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            render: Box::new(render),
+            written: Mutex::new(None),
+        }
+    }
+
+    /// Returns the digest of the content, rendered without keeping it.
+    fn render_digest(&self) -> Option<String> {
+        let mut writer = HashingWriter::new(std::io::sink());
+        (self.render)(&mut writer).ok()?;
+        Some(writer.finish().1)
+    }
+
+    /// Renders the full content into memory.
+    fn render_to_string(&self) -> Option<String> {
+        let mut buf = Vec::new();
+        (self.render)(&mut buf).ok()?;
+        String::from_utf8(buf).ok()
+    }
+}
+
+impl std::fmt::Debug for StreamWrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamWrite")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Action for StreamWrite {
+    fn target(&self) -> &Path {
+        &self.path
+    }
+
+    fn check_conflict(&self, fs: &dyn FileCache, db: &FileDB) -> Result<()> {
+        check_overwrite(fs, db, &self.path, || self.render_digest())
+    }
+
+    fn execute(&self, fs: &dyn FileCache) -> Result<()> {
+        let data = fs.write_with(&self.path, &mut |w| {
+            (self.render)(w).map_err(|e| match e {
+                EntangledError::Io(e) => e,
+                e => std::io::Error::other(e.to_string()),
+            })
+        })?;
+        *self
+            .written
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(data);
+        Ok(())
+    }
+
+    fn update_db(&self, db: &mut FileDB) -> Result<()> {
+        let data = self
+            .written
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take()
+            .ok_or_else(|| {
+                EntangledError::Transaction(format!(
+                    "{} was not written before updating the file database",
+                    self.path.display()
+                ))
+            })?;
+        db.record(self.path.clone(), data);
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!("write {}", self.path.display())
+    }
+
+    fn kind(&self) -> &'static str {
+        "write"
+    }
+
+    fn diff_in(&self, fs: &dyn FileCache, context: usize) -> Option<String> {
+        content_diff(fs, &self.path, Some(&self.render_to_string()?), context)
+    }
+
+    fn diff_stat_in(&self, fs: &dyn FileCache) -> Option<DiffStat> {
+        content_diff_stat(fs, &self.path, Some(&self.render_to_string()?))
+    }
+}
+
 /// Delete a file.
 #[derive(Debug)]
 pub struct Delete {
@@ -175,10 +440,10 @@ impl Action for Delete {
         &self.path
     }
 
-    fn check_conflict(&self, db: &FileDB) -> Result<()> {
+    fn check_conflict(&self, fs: &dyn FileCache, db: &FileDB) -> Result<()> {
         // If file exists and is tracked, check for external modifications
-        if self.path.exists() && db.is_tracked(&self.path) {
-            let current = FileData::from_path(&self.path)?;
+        if fs.exists(&self.path) && db.is_tracked(&self.path) {
+            let current = fs.file_data(&self.path)?;
             if db.is_modified(&self.path, &current) {
                 return Err(EntangledError::FileConflict {
                     path: self.path.clone(),
@@ -188,10 +453,8 @@ impl Action for Delete {
         Ok(())
     }
 
-    fn execute(&self) -> Result<()> {
-        if self.path.exists() {
-            fs::remove_file(&self.path)?;
-        }
+    fn execute(&self, fs: &dyn FileCache) -> Result<()> {
+        fs.remove(&self.path)?;
         Ok(())
     }
 
@@ -203,6 +466,68 @@ impl Action for Delete {
     fn describe(&self) -> String {
         format!("delete {}", self.path.display())
     }
+
+    fn kind(&self) -> &'static str {
+        "delete"
+    }
+}
+
+/// Serializable form of a built-in action, used by [`Transaction::to_json`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ActionData {
+    /// A [`Create`] action.
+    Create { path: PathBuf, content: String },
+    /// A [`WriteAction`].
+    Write { path: PathBuf, content: String },
+    /// A [`Delete`] action.
+    Delete { path: PathBuf },
+}
+
+impl ActionData {
+    /// Captures an action, or returns `None` for custom action types.
+    fn from_action(action: &dyn Action) -> Option<Self> {
+        let path = action.target().to_path_buf();
+        let content = action.proposed_content().map(String::from);
+        match (action.kind(), content) {
+            ("create", Some(content)) => Some(Self::Create { path, content }),
+            ("write", Some(content)) => Some(Self::Write { path, content }),
+            ("delete", None) => Some(Self::Delete { path }),
+            _ => None,
+        }
+    }
+}
+
+/// Where to copy files before they are overwritten or deleted.
+#[derive(Debug, Clone)]
+struct Backup {
+    /// Backup directory.
+    dir: PathBuf,
+    /// Directory that target paths are made relative to inside `dir`.
+    base_dir: PathBuf,
+}
+
+impl Backup {
+    /// Copies `path` to `<dir>/<relative path>.<timestamp>` if it exists.
+    fn save(&self, fs: &dyn FileCache, path: &Path) -> Result<()> {
+        let Ok(content) = fs.read(path) else {
+            return Ok(());
+        };
+        let relative = path.strip_prefix(&self.base_dir).unwrap_or(path);
+        // Keep absolute paths outside base_dir inside the backup directory
+        let relative: PathBuf = relative
+            .components()
+            .filter(|c| matches!(c, std::path::Component::Normal(_)))
+            .collect();
+
+        let mut name = relative.into_os_string();
+        name.push(format!(".{}", Utc::now().format("%Y%m%dT%H%M%S%.3fZ")));
+        let backup_path = self.dir.join(name);
+
+        fs.write(&backup_path, &content)?;
+        tracing::debug!("Backed up {} to {}", path.display(), backup_path.display());
+        Ok(())
+    }
 }
 
 /// A collection of actions to execute atomically.
@@ -210,6 +535,15 @@ impl Action for Delete {
 pub struct Transaction {
     /// Actions to execute.
     actions: Vec<Box<dyn Action>>,
+    /// Backup settings, if enabled.
+    backup: Option<Backup>,
+    /// Journal file, if enabled.
+    journal: Option<PathBuf>,
+    /// Start of the run, if the journal continues one written since then.
+    journal_since: Option<DateTime<Utc>>,
+    /// Files that the methods without an explicit `fs` use, if not the real
+    /// file system.
+    file_cache: Option<Arc<dyn FileCache>>,
 }
 
 impl Transaction {
@@ -218,9 +552,68 @@ impl Transaction {
     pub fn new() -> Self {
         Self {
             actions: Vec::new(),
+            backup: None,
+            journal: None,
+            journal_since: None,
+            file_cache: None,
+        }
+    }
+
+    /// Makes [`execute`](Self::execute), [`diffs`](Self::diffs) and the other
+    /// methods without an explicit `fs` work on `file_cache` instead of the
+    /// real file system.
+    #[must_use]
+    pub fn with_file_cache(mut self, file_cache: Arc<dyn FileCache>) -> Self {
+        self.file_cache = Some(file_cache);
+        self
+    }
+
+    /// Runs `f` with the transaction's file cache, or the real file system.
+    fn with_fs<T>(&self, f: impl FnOnce(&dyn FileCache) -> T) -> T {
+        match &self.file_cache {
+            Some(fs) => f(fs.as_ref()),
+            None => f(&RealFileCache::default()),
         }
     }
 
+    /// Enables backups: existing files are copied into `dir` before a write or
+    /// delete replaces them.
+    ///
+    /// Backups are stored as `<dir>/<path>.<timestamp>`, with `path` taken
+    /// relative to `base_dir`.
+    #[must_use]
+    pub fn with_backup_dir(
+        mut self,
+        dir: impl Into<PathBuf>,
+        base_dir: impl Into<PathBuf>,
+    ) -> Self {
+        self.backup = Some(Backup {
+            dir: dir.into(),
+            base_dir: base_dir.into(),
+        });
+        self
+    }
+
+    /// Enables the journal: the previous content of every file changed is
+    /// written to `path` once the transaction has executed, replacing the
+    /// journal of the previous run, so that the run can be undone.
+    ///
+    /// A run that changes no file leaves the journal as it was.
+    #[must_use]
+    pub fn with_journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.journal = Some(path.into());
+        self
+    }
+
+    /// Makes the journal continue one written at or after `since` instead of
+    /// replacing it, so that several transactions of one run, such as the
+    /// stitch and tangle halves of a sync, are undone together.
+    #[must_use]
+    pub fn continuing_journal(mut self, since: DateTime<Utc>) -> Self {
+        self.journal_since = Some(since);
+        self
+    }
+
     /// Adds an action to the transaction.
     pub fn add(&mut self, action: impl Action + 'static) {
         self.actions.push(Box::new(action));
@@ -236,6 +629,15 @@ impl Transaction {
         self.add(WriteAction::new(path, content));
     }
 
+    /// Adds a write action whose content is streamed from `render`.
+    pub fn stream_write(
+        &mut self,
+        path: impl Into<PathBuf>,
+        render: impl Fn(&mut dyn Write) -> Result<()> + Send + Sync + 'static,
+    ) {
+        self.add(StreamWrite::new(path, render));
+    }
+
     /// Adds a delete action.
     pub fn delete(&mut self, path: impl Into<PathBuf>) {
         self.add(Delete::new(path));
@@ -251,6 +653,11 @@ impl Transaction {
         self.actions.is_empty()
     }
 
+    /// Returns an iterator over the actions in order.
+    pub fn actions(&self) -> impl Iterator<Item = &dyn Action> {
+        self.actions.iter().map(|a| a.as_ref())
+    }
+
     /// Returns descriptions of all actions.
     pub fn describe(&self) -> Vec<String> {
         self.actions.iter().map(|a| a.describe()).collect()
@@ -262,97 +669,238 @@ impl Transaction {
     /// produces a unified diff against the proposed content. Delete actions
     /// show the full file as removed.
     pub fn diffs(&self) -> Vec<String> {
+        self.diffs_with_context(DEFAULT_DIFF_CONTEXT)
+    }
+
+    /// Returns unified diffs with `context` unchanged lines around each change.
+    pub fn diffs_with_context(&self, context: usize) -> Vec<String> {
+        self.with_fs(|fs| self.diffs_in(fs, context))
+    }
+
+    /// Returns unified diffs against the current files in `fs`.
+    pub fn diffs_in(&self, fs: &dyn FileCache, context: usize) -> Vec<String> {
         self.actions
             .iter()
-            .filter_map(|action| {
-                let path = action.target();
-                let path_str = path.display().to_string();
-
-                if let Some(new_content) = action.proposed_content() {
-                    let old_content = if path.exists() {
-                        fs::read_to_string(path).unwrap_or_default()
-                    } else {
-                        String::new()
-                    };
-
-                    if old_content == new_content {
-                        return None;
-                    }
+            .filter_map(|action| action.diff_in(fs, context))
+            .collect()
+    }
 
-                    let old_label = format!("a/{}", path_str);
-                    let new_label = format!("b/{}", path_str);
-                    let diff = unified_diff(&old_content, new_content, &old_label, &new_label);
-                    if diff.is_empty() {
-                        None
-                    } else {
-                        Some(diff)
-                    }
-                } else {
-                    // Delete action
-                    if path.exists() {
-                        if let Ok(content) = fs::read_to_string(path) {
-                            let old_label = format!("a/{}", path_str);
-                            let diff = unified_diff(&content, "", &old_label, "/dev/null");
-                            Some(diff)
-                        } else {
-                            Some(format!("delete {}", path_str))
-                        }
-                    } else {
-                        None
-                    }
-                }
-            })
+    /// Returns added/removed line counts for every action that changes a file.
+    pub fn diff_stats(&self) -> Vec<DiffStat> {
+        self.with_fs(|fs| self.diff_stats_in(fs))
+    }
+
+    /// Returns added/removed line counts against the current files in `fs`.
+    pub fn diff_stats_in(&self, fs: &dyn FileCache) -> Vec<DiffStat> {
+        self.actions
+            .iter()
+            .filter_map(|action| action.diff_stat_in(fs))
             .collect()
     }
 
+    /// Classifies every action by its effect on the current files in `fs`.
+    ///
+    /// Call this before executing: afterwards every write looks unchanged.
+    pub fn summary_in(&self, fs: &dyn FileCache) -> TransactionSummary {
+        let mut summary = TransactionSummary::default();
+        for action in &self.actions {
+            let path = action.target().to_path_buf();
+            if action.kind() == "delete" {
+                summary.deleted.push(path);
+            } else if !fs.exists(&path) {
+                summary.created.push(path);
+            } else if action.diff_stat_in(fs).is_none() {
+                summary.unchanged.push(path);
+            } else {
+                summary.written.push(path);
+            }
+        }
+        summary
+    }
+
+    /// Serializes the planned actions to JSON.
+    ///
+    /// Only the built-in action types can be serialized; a custom action
+    /// yields [`EntangledError::Transaction`].
+    pub fn to_json(&self) -> Result<String> {
+        let data = self
+            .actions
+            .iter()
+            .map(|action| {
+                ActionData::from_action(action.as_ref()).ok_or_else(|| {
+                    EntangledError::Transaction(format!(
+                        "cannot serialize action: {}",
+                        action.describe()
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(serde_json::to_string_pretty(&data)?)
+    }
+
+    /// Rebuilds a transaction from [`to_json`](Self::to_json) output.
+    ///
+    /// Conflicts are checked against the file database when the restored
+    /// transaction is executed, not when it is loaded.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let data: Vec<ActionData> = serde_json::from_str(json)?;
+        let mut transaction = Self::new();
+        for action in data {
+            match action {
+                ActionData::Create { path, content } => transaction.create(path, content),
+                ActionData::Write { path, content } => transaction.write(path, content),
+                ActionData::Delete { path } => transaction.delete(path),
+            }
+        }
+        Ok(transaction)
+    }
+
     /// Checks all actions for conflicts.
     pub fn check_conflicts(&self, db: &FileDB) -> Result<()> {
+        self.with_fs(|fs| self.check_conflicts_in(fs, db))
+    }
+
+    /// Checks all actions for conflicts with the files in `fs`.
+    pub fn check_conflicts_in(&self, fs: &dyn FileCache, db: &FileDB) -> Result<()> {
         for action in &self.actions {
-            action.check_conflict(db)?;
+            action.check_conflict(fs, db)?;
         }
         Ok(())
     }
 
     /// Executes all actions and updates the database.
     pub fn execute(&self, db: &mut FileDB) -> Result<()> {
-        // First check all conflicts
-        self.check_conflicts(db)?;
+        self.with_fs(|fs| self.execute_in(fs, db, false))
+    }
 
-        // Execute all actions
-        for action in &self.actions {
-            action.execute()?;
-            action.update_db(db)?;
+    /// Executes all actions, ignoring conflicts, and updates the database.
+    pub fn execute_force(&self, db: &mut FileDB) -> Result<()> {
+        self.with_fs(|fs| self.execute_in(fs, db, true))
+    }
+
+    /// Executes all actions against `fs` instead of the real file system.
+    ///
+    /// Conflicts are checked first unless `force` is set. This is how a
+    /// transaction is applied to a [`VirtualFS`](super::VirtualFS).
+    pub fn execute_in(&self, fs: &dyn FileCache, db: &mut FileDB, force: bool) -> Result<()> {
+        if !force {
+            self.check_conflicts_in(fs, db)?;
         }
+        let mut journal = Journal::new();
+        let result = self
+            .actions
+            .iter()
+            .try_for_each(|action| self.apply(fs, action.as_ref(), db, &mut journal));
+        self.save_journal(fs, &journal)?;
+        result
+    }
 
-        Ok(())
+    /// Executes only the actions accepted by `filter`.
+    ///
+    /// Conflicts are checked for the selected actions unless `force` is set;
+    /// the remaining actions are left untouched.
+    pub fn execute_filtered(
+        &self,
+        db: &mut FileDB,
+        force: bool,
+        filter: impl FnMut(&dyn Action) -> bool,
+    ) -> Result<()> {
+        self.with_fs(|fs| self.execute_filtered_in(fs, db, force, filter))
     }
 
-    /// Executes all actions, ignoring conflicts, and updates the database.
-    pub fn execute_force(&self, db: &mut FileDB) -> Result<()> {
-        for action in &self.actions {
-            action.execute()?;
-            action.update_db(db)?;
+    /// Like [`execute_filtered`](Self::execute_filtered), against `fs`.
+    pub fn execute_filtered_in(
+        &self,
+        fs: &dyn FileCache,
+        db: &mut FileDB,
+        force: bool,
+        mut filter: impl FnMut(&dyn Action) -> bool,
+    ) -> Result<()> {
+        let selected: Vec<&dyn Action> = self
+            .actions
+            .iter()
+            .map(|a| a.as_ref())
+            .filter(|a| filter(*a))
+            .collect();
+
+        if !force {
+            for action in &selected {
+                action.check_conflict(fs, db)?;
+            }
         }
-        Ok(())
+        let mut journal = Journal::new();
+        let result = selected
+            .into_iter()
+            .try_for_each(|action| self.apply(fs, action, db, &mut journal));
+        self.save_journal(fs, &journal)?;
+        result
+    }
+
+    /// Backs up and journals the target if enabled, then executes a single
+    /// action.
+    fn apply(
+        &self,
+        fs: &dyn FileCache,
+        action: &dyn Action,
+        db: &mut FileDB,
+        journal: &mut Journal,
+    ) -> Result<()> {
+        if self.backup.is_some() || self.journal.is_some() {
+            let target = action.target();
+            let unchanged = action
+                .proposed_content()
+                .is_some_and(|new| fs.read(target).is_ok_and(|old| old == new));
+            if !unchanged {
+                if let Some(backup) = &self.backup {
+                    backup.save(fs, target)?;
+                }
+                if self.journal.is_some() {
+                    journal.record(fs, target)?;
+                }
+            }
+        }
+        action.execute(fs)?;
+        action.update_db(db)
+    }
+
+    /// Writes `journal` if enabled and any file was changed.
+    ///
+    /// Called after a failed action too, so that the files changed before
+    /// the failure can still be restored.
+    fn save_journal(&self, fs: &dyn FileCache, journal: &Journal) -> Result<()> {
+        let Some(path) = &self.journal else {
+            return Ok(());
+        };
+        if journal.is_empty() {
+            return Ok(());
+        }
+        if let Some(since) = self.journal_since {
+            if let Some(mut earlier) = Journal::load(fs, path)? {
+                if earlier.timestamp >= since {
+                    earlier.extend(journal.clone());
+                    return earlier.save(fs, path);
+                }
+            }
+        }
+        journal.save(fs, path)
     }
 }
 
-/// Produces a unified diff between two strings.
-fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
-    let old_lines: Vec<&str> = if old.is_empty() {
-        Vec::new()
-    } else {
-        old.lines().collect()
-    };
-    let new_lines: Vec<&str> = if new.is_empty() {
-        Vec::new()
-    } else {
-        new.lines().collect()
-    };
+/// Produces a unified diff between two strings, with `context` unchanged
+/// lines around each change. Returns an empty string if they are equal.
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_label: &str,
+    new_label: &str,
+    context: usize,
+) -> String {
+    let old_lines = diff_lines(old);
+    let new_lines = diff_lines(new);
 
     // Simple line-by-line diff using longest common subsequence
     let lcs = lcs_table(&old_lines, &new_lines);
-    let mut hunks = collect_hunks(&old_lines, &new_lines, &lcs, 3);
+    let mut hunks = collect_hunks(&old_lines, &new_lines, &lcs, context);
 
     if hunks.is_empty() {
         return String::new();
@@ -376,6 +924,51 @@ fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> Strin
     output.join("\n")
 }
 
+/// Counts added and removed lines between two strings.
+fn line_changes(old: &str, new: &str) -> (usize, usize) {
+    let old_lines = diff_lines(old);
+    let new_lines = diff_lines(new);
+    let common = lcs_table(&old_lines, &new_lines)[old_lines.len()][new_lines.len()];
+    (new_lines.len() - common, old_lines.len() - common)
+}
+
+/// A line being diffed.
+///
+/// The final line of a file without a trailing newline is distinct from the
+/// same text followed by a newline, so adding or removing the newline shows
+/// up as a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DiffLine<'a> {
+    text: &'a str,
+    no_newline: bool,
+}
+
+/// Splits content into diff lines, marking a missing final newline.
+fn diff_lines(content: &str) -> Vec<DiffLine<'_>> {
+    let mut lines: Vec<DiffLine> = content
+        .lines()
+        .map(|text| DiffLine {
+            text,
+            no_newline: false,
+        })
+        .collect();
+    if !content.ends_with('\n') {
+        if let Some(last) = lines.last_mut() {
+            last.no_newline = true;
+        }
+    }
+    lines
+}
+
+/// Appends a `-`/`+`/` ` prefixed line to a hunk, with the standard marker
+/// if it lacks a trailing newline.
+fn push_line(lines: &mut Vec<String>, prefix: char, line: &DiffLine) {
+    lines.push(format!("{}{}", prefix, line.text));
+    if line.no_newline {
+        lines.push("\\ No newline at end of file".to_string());
+    }
+}
+
 struct DiffHunk {
     old_start: usize,
     old_count: usize,
@@ -384,7 +977,7 @@ struct DiffHunk {
     lines: Vec<String>,
 }
 
-fn lcs_table(old: &[&str], new: &[&str]) -> Vec<Vec<usize>> {
+fn lcs_table(old: &[DiffLine], new: &[DiffLine]) -> Vec<Vec<usize>> {
     let m = old.len();
     let n = new.len();
     let mut table = vec![vec![0usize; n + 1]; m + 1];
@@ -402,7 +995,12 @@ fn lcs_table(old: &[&str], new: &[&str]) -> Vec<Vec<usize>> {
     table
 }
 
-fn collect_hunks(old: &[&str], new: &[&str], lcs: &[Vec<usize>], context: usize) -> Vec<DiffHunk> {
+fn collect_hunks(
+    old: &[DiffLine],
+    new: &[DiffLine],
+    lcs: &[Vec<usize>],
+    context: usize,
+) -> Vec<DiffHunk> {
     // Build edit script from LCS table
     let mut edits: Vec<(char, usize, usize)> = Vec::new(); // (type, old_idx, new_idx)
     let mut i = old.len();
@@ -436,13 +1034,14 @@ fn collect_hunks(old: &[&str], new: &[&str], lcs: &[Vec<usize>], context: usize)
         return Vec::new();
     }
 
-    // Group changes into hunks (merge if within 2*context lines of each other)
+    // Group changes into hunks (merge if within 2*context lines of each other;
+    // with zero context, only directly adjacent changes)
     let mut groups: Vec<(usize, usize)> = Vec::new(); // (first_change_idx, last_change_idx)
     let mut group_start = change_indices[0];
     let mut group_end = change_indices[0];
 
     for &ci in &change_indices[1..] {
-        if ci - group_end <= 2 * context {
+        if ci - group_end <= (2 * context).max(1) {
             group_end = ci;
         } else {
             groups.push((group_start, group_end));
@@ -471,7 +1070,7 @@ fn collect_hunks(old: &[&str], new: &[&str], lcs: &[Vec<usize>], context: usize)
                         old_start = edit.1;
                         new_start = edit.2;
                     }
-                    lines.push(format!(" {}", old[edit.1]));
+                    push_line(&mut lines, ' ', &old[edit.1]);
                     old_count += 1;
                     new_count += 1;
                 }
@@ -480,7 +1079,7 @@ fn collect_hunks(old: &[&str], new: &[&str], lcs: &[Vec<usize>], context: usize)
                         old_start = edit.1;
                         new_start = edit.2;
                     }
-                    lines.push(format!("-{}", old[edit.1]));
+                    push_line(&mut lines, '-', &old[edit.1]);
                     old_count += 1;
                 }
                 '+' => {
@@ -488,7 +1087,7 @@ fn collect_hunks(old: &[&str], new: &[&str], lcs: &[Vec<usize>], context: usize)
                         old_start = edit.1;
                         new_start = edit.2;
                     }
-                    lines.push(format!("+{}", new[edit.2]));
+                    push_line(&mut lines, '+', &new[edit.2]);
                     new_count += 1;
                 }
                 _ => {}
@@ -507,32 +1106,11 @@ fn collect_hunks(old: &[&str], new: &[&str], lcs: &[Vec<usize>], context: usize)
     hunks
 }
 
-/// Counter for unique temp file names.
-static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
-
-/// Writes content to a file atomically using a temp file.
-fn atomic_write(path: &Path, content: &str) -> io::Result<()> {
-    // Create temp file in the same directory with unique name
-    let parent = path.parent().unwrap_or(Path::new("."));
-    let counter = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
-    let temp_path = parent.join(format!(".entangled-tmp-{}-{}", std::process::id(), counter,));
-
-    // Write to temp file
-    {
-        let mut file = File::create(&temp_path)?;
-        file.write_all(content.as_bytes())?;
-        file.sync_all()?;
-    }
-
-    // Rename to target
-    fs::rename(&temp_path, path)?;
-
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::io::VirtualFS;
+    use std::fs;
     use tempfile::tempdir;
 
     #[test]
@@ -543,8 +1121,10 @@ mod tests {
         let action = Create::new(&path, "content");
         let mut db = FileDB::new();
 
-        action.check_conflict(&db).unwrap();
-        action.execute().unwrap();
+        action
+            .check_conflict(&RealFileCache::default(), &db)
+            .unwrap();
+        action.execute(&RealFileCache::default()).unwrap();
         action.update_db(&mut db).unwrap();
 
         assert!(path.exists());
@@ -561,7 +1141,9 @@ mod tests {
         let action = Create::new(&path, "new");
         let db = FileDB::new();
 
-        assert!(action.check_conflict(&db).is_err());
+        assert!(action
+            .check_conflict(&RealFileCache::default(), &db)
+            .is_err());
     }
 
     #[test]
@@ -575,8 +1157,10 @@ mod tests {
         db.record(path.clone(), original_data);
 
         let action = WriteAction::new(&path, "updated");
-        action.check_conflict(&db).unwrap();
-        action.execute().unwrap();
+        action
+            .check_conflict(&RealFileCache::default(), &db)
+            .unwrap();
+        action.execute(&RealFileCache::default()).unwrap();
         action.update_db(&mut db).unwrap();
 
         assert_eq!(fs::read_to_string(&path).unwrap(), "updated");
@@ -594,7 +1178,27 @@ mod tests {
 
         // File has different content than recorded
         let action = WriteAction::new(&path, "updated");
-        assert!(action.check_conflict(&db).is_err());
+        assert!(action
+            .check_conflict(&RealFileCache::default(), &db)
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_recreates_deleted_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+
+        // Tracked, but deleted outside entangled
+        let mut db = FileDB::new();
+        db.record(path.clone(), FileData::from_content("recorded", Utc::now()));
+
+        let mut transaction = Transaction::new();
+        transaction.write(&path, "recreated");
+        transaction.execute(&mut db).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "recreated");
+        let current = FileData::from_path(&path).unwrap();
+        assert!(!db.is_modified(&path, &current));
     }
 
     #[test]
@@ -608,14 +1212,111 @@ mod tests {
         db.record(path.clone(), data);
 
         let action = Delete::new(&path);
-        action.check_conflict(&db).unwrap();
-        action.execute().unwrap();
+        action
+            .check_conflict(&RealFileCache::default(), &db)
+            .unwrap();
+        action.execute(&RealFileCache::default()).unwrap();
         action.update_db(&mut db).unwrap();
 
         assert!(!path.exists());
         assert!(!db.is_tracked(&path));
     }
 
+    #[test]
+    fn test_execute_in_virtual_fs() {
+        let vfs = VirtualFS::new().with_base_dir("/project");
+        vfs.write(Path::new("stale.txt"), "old").unwrap();
+        let mut db = FileDB::new();
+
+        let mut tx = Transaction::new();
+        tx.create("/project/out/a.txt", "a");
+        tx.delete("/project/stale.txt");
+        tx.execute_in(&vfs, &mut db, false).unwrap();
+
+        assert_eq!(vfs.file_paths(), vec![PathBuf::from("out/a.txt")]);
+        assert!(db.is_tracked(Path::new("/project/out/a.txt")));
+
+        // Create conflicts with a file that now exists in the virtual fs
+        let mut tx = Transaction::new();
+        tx.create("out/a.txt", "again");
+        assert!(tx.execute_in(&vfs, &mut db, false).is_err());
+    }
+
+    #[test]
+    fn test_stream_write() {
+        let vfs = VirtualFS::new();
+        let mut db = FileDB::new();
+
+        let mut tx = Transaction::new();
+        tx.stream_write("out.txt", |w| {
+            for i in 0..3 {
+                writeln!(w, "line {}", i)?;
+            }
+            Ok(())
+        });
+        assert_eq!(
+            tx.diff_stats_in(&vfs)[0].additions,
+            3,
+            "diffs render the streamed content"
+        );
+        tx.execute_in(&vfs, &mut db, false).unwrap();
+
+        let content = vfs.read(Path::new("out.txt")).unwrap();
+        assert_eq!(content, "line 0\nline 1\nline 2\n");
+        assert!(!db.is_modified(
+            Path::new("out.txt"),
+            &vfs.file_data(Path::new("out.txt")).unwrap()
+        ));
+
+        // Streamed writes are checked for external edits like regular writes
+        vfs.write(Path::new("out.txt"), "edited").unwrap();
+        let mut tx = Transaction::new();
+        tx.stream_write("out.txt", |w| Ok(w.write_all(b"new")?));
+        assert!(tx.execute_in(&vfs, &mut db, false).is_err());
+
+        // ...unless the edit already matches the streamed content
+        vfs.write(Path::new("out.txt"), "new").unwrap();
+        tx.execute_in(&vfs, &mut db, false).unwrap();
+    }
+
+    #[test]
+    fn test_diffs_in_virtual_fs() {
+        let vfs = VirtualFS::new();
+        vfs.write(Path::new("a.txt"), "old\n").unwrap();
+
+        let mut tx = Transaction::new();
+        tx.write("a.txt", "new\n");
+        tx.create("b.txt", "b\n");
+
+        let diffs = tx.diffs_in(&vfs, DEFAULT_DIFF_CONTEXT);
+        assert_eq!(diffs.len(), 2);
+        assert!(diffs[0].contains("-old\n+new"));
+
+        let stats = tx.diff_stats_in(&vfs);
+        assert_eq!((stats[0].additions, stats[0].deletions), (1, 1));
+        assert_eq!((stats[1].additions, stats[1].deletions), (1, 0));
+    }
+
+    #[test]
+    fn test_summary_in() {
+        let vfs = VirtualFS::new();
+        vfs.write(Path::new("a.txt"), "old\n").unwrap();
+        vfs.write(Path::new("same.txt"), "same\n").unwrap();
+        vfs.write(Path::new("gone.txt"), "x\n").unwrap();
+
+        let mut tx = Transaction::new();
+        tx.write("a.txt", "new\n");
+        tx.write("same.txt", "same\n");
+        tx.create("b.txt", "b\n");
+        tx.delete("gone.txt");
+
+        let summary = tx.summary_in(&vfs);
+        assert_eq!(summary.created, vec![PathBuf::from("b.txt")]);
+        assert_eq!(summary.written, vec![PathBuf::from("a.txt")]);
+        assert_eq!(summary.unchanged, vec![PathBuf::from("same.txt")]);
+        assert_eq!(summary.deleted, vec![PathBuf::from("gone.txt")]);
+    }
+
     #[test]
     fn test_transaction() {
         let dir = tempdir().unwrap();
@@ -675,4 +1376,171 @@ mod tests {
         tx.execute_force(&mut db).unwrap();
         assert_eq!(fs::read_to_string(&path).unwrap(), "forced");
     }
+
+    #[test]
+    fn test_action_kind_and_diff() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old\n").unwrap();
+
+        let mut tx = Transaction::new();
+        tx.write(&path, "new\n");
+        tx.delete(dir.path().join("absent.txt"));
+
+        let actions: Vec<&dyn Action> = tx.actions().collect();
+        assert_eq!(actions[0].kind(), "write");
+        assert!(actions[0].diff().unwrap().contains("+new"));
+        assert_eq!(actions[1].kind(), "delete");
+        assert!(actions[1].diff().is_none());
+    }
+
+    #[test]
+    fn test_backup_before_forced_overwrite() {
+        let dir = tempdir().unwrap();
+        let backups = dir.path().join(".entangled/backups");
+        let path = dir.path().join("src/file.txt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "hand edited").unwrap();
+
+        let mut db = FileDB::new();
+        db.record(path.clone(), FileData::from_content("tangled", Utc::now()));
+
+        let mut tx = Transaction::new().with_backup_dir(&backups, dir.path());
+        tx.write(&path, "forced");
+        tx.execute_force(&mut db).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "forced");
+        let saved: Vec<_> = fs::read_dir(backups.join("src"))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(saved.len(), 1);
+        let name = saved[0].file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("file.txt."));
+        assert_eq!(fs::read_to_string(&saved[0]).unwrap(), "hand edited");
+    }
+
+    #[test]
+    fn test_no_backup_by_default() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old").unwrap();
+
+        let mut tx = Transaction::new();
+        tx.write(&path, "new");
+        tx.execute_force(&mut FileDB::new()).unwrap();
+
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_default_diff_hunks_unchanged() {
+        let old: String = (1..=12).map(|i| format!("line {:02}\n", i)).collect();
+
+        // Changes six unchanged lines apart stay in separate hunks
+        let new = old
+            .replace("line 02\n", "two\n")
+            .replace("line 09\n", "nine\n");
+        let diff = unified_diff(&old, &new, "a/f", "b/f", DEFAULT_DIFF_CONTEXT);
+        assert_eq!(
+            diff.lines()
+                .filter(|l| l.starts_with("@@"))
+                .collect::<Vec<_>>(),
+            vec!["@@ -1,5 +1,5 @@", "@@ -6,7 +6,7 @@"]
+        );
+
+        // Five lines apart, they share one
+        let new = old
+            .replace("line 02\n", "two\n")
+            .replace("line 08\n", "eight\n");
+        let diff = unified_diff(&old, &new, "a/f", "b/f", DEFAULT_DIFF_CONTEXT);
+        assert_eq!(
+            diff.lines()
+                .filter(|l| l.starts_with("@@"))
+                .collect::<Vec<_>>(),
+            vec!["@@ -1,11 +1,11 @@"]
+        );
+    }
+
+    #[test]
+    fn test_diff_context_and_stat() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        let old: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        fs::write(&path, &old).unwrap();
+
+        let mut tx = Transaction::new();
+        tx.write(&path, old.replace("line 5\n", "five\nfive again\n"));
+
+        let default = &tx.diffs()[0];
+        assert!(default.contains("@@ -2,7 +2,8 @@"));
+        let narrow = &tx.diffs_with_context(0)[0];
+        assert!(narrow.contains("@@ -5,1 +5,2 @@"));
+        assert!(!narrow.contains(" line 4"));
+
+        let stats = tx.diff_stats();
+        assert_eq!(
+            stats,
+            vec![DiffStat {
+                path: path.clone(),
+                additions: 2,
+                deletions: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_trailing_newline_change() {
+        let diff = unified_diff("a\nb\n", "a\nb", "a/f", "b/f", 3);
+        assert_eq!(
+            diff,
+            "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file"
+        );
+
+        let diff = unified_diff("x\nend", "y\nend", "a/f", "b/f", 3);
+        assert!(diff.ends_with(" end\n\\ No newline at end of file"));
+        assert_eq!(line_changes("a\nb", "a\nb\n"), (1, 1));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+
+        let mut tx = Transaction::new();
+        tx.create(dir.path().join("new.txt"), "new");
+        tx.write(&path, "planned");
+        tx.delete(dir.path().join("old.txt"));
+
+        let json = tx.to_json().unwrap();
+        assert!(json.contains("\"kind\": \"write\""));
+
+        let restored = Transaction::from_json(&json).unwrap();
+        assert_eq!(restored.describe(), tx.describe());
+
+        // Conflicts are still detected when the plan is applied
+        fs::write(&path, "changed elsewhere").unwrap();
+        let mut db = FileDB::new();
+        db.record(path.clone(), FileData::from_content("tangled", Utc::now()));
+        assert!(restored.execute(&mut db).is_err());
+    }
+
+    #[test]
+    fn test_execute_filtered() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+
+        let mut db = FileDB::new();
+        let mut tx = Transaction::new();
+        tx.write(&a, "a");
+        tx.write(&b, "b");
+
+        tx.execute_filtered(&mut db, false, |action| action.target() == a)
+            .unwrap();
+        assert!(a.exists());
+        assert!(!b.exists());
+        assert!(db.is_tracked(&a));
+        assert!(!db.is_tracked(&b));
+    }
 }
@@ -2,6 +2,8 @@
 
 use std::path::PathBuf;
 
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
 use super::reference_id::ReferenceId;
 use super::reference_name::ReferenceName;
 use crate::text_location::TextLocation;
@@ -21,6 +23,10 @@ pub struct CodeBlock {
     /// Target output file, if specified.
     pub target: Option<PathBuf>,
 
+    /// Further files written with the same content as `target`, from a
+    /// `files=` list or repeated `file=` attributes.
+    pub extra_targets: Vec<PathBuf>,
+
     /// The source code content.
     pub source: String,
 
@@ -29,6 +35,15 @@ pub struct CodeBlock {
 
     /// Additional attributes from the code fence.
     pub attributes: Vec<(String, String)>,
+
+    /// Whether this block is excluded from tangled output (e.g. Quarto `eval: false`).
+    pub skip: bool,
+
+    /// Namespace of the document the block was read from (e.g. `a.md`).
+    ///
+    /// Unqualified references inside the block resolve within this namespace
+    /// first; see [`resolve_reference`](super::resolve_reference).
+    pub namespace: Option<String>,
 }
 
 impl CodeBlock {
@@ -44,9 +59,12 @@ impl CodeBlock {
             language,
             classes: Vec::new(),
             target: None,
+            extra_targets: Vec::new(),
             source,
             location,
             attributes: Vec::new(),
+            skip: false,
+            namespace: None,
         }
     }
 
@@ -60,6 +78,11 @@ impl CodeBlock {
         self.target.is_some()
     }
 
+    /// Returns every target file of this block, the primary target first.
+    pub fn targets(&self) -> impl Iterator<Item = &PathBuf> {
+        self.target.iter().chain(&self.extra_targets)
+    }
+
     /// Returns the line count of the source.
     pub fn line_count(&self) -> usize {
         self.source.lines().count()
@@ -88,6 +111,18 @@ impl CodeBlock {
         self
     }
 
+    /// Marks the block as skipped for tangling.
+    pub fn with_skip(mut self, skip: bool) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    /// Sets the namespace that unqualified references resolve in.
+    pub fn with_namespace(mut self, namespace: Option<String>) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
     /// Gets an attribute value by key.
     pub fn get_attribute(&self, key: &str) -> Option<&str> {
         self.attributes
@@ -97,6 +132,27 @@ impl CodeBlock {
     }
 }
 
+/// Serializes the fields of the JSON document dump: `id` (`name[count]`),
+/// `name`, `language`, `classes`, `source`, `target`, `targets` (every
+/// target, the primary one first), `attributes` (`[key, value]` pairs in
+/// order, since keys may repeat), `location` and `skip`.
+impl Serialize for CodeBlock {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CodeBlock", 10)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("name", self.name())?;
+        state.serialize_field("language", &self.language)?;
+        state.serialize_field("classes", &self.classes)?;
+        state.serialize_field("source", &self.source)?;
+        state.serialize_field("target", &self.target)?;
+        state.serialize_field("targets", &self.targets().collect::<Vec<_>>())?;
+        state.serialize_field("attributes", &self.attributes)?;
+        state.serialize_field("location", &self.location)?;
+        state.serialize_field("skip", &self.skip)?;
+        state.end()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -6,12 +6,19 @@ mod reference_id;
 mod reference_map;
 mod reference_name;
 mod tangle;
+mod validate;
 
 pub use code_block::CodeBlock;
+pub(crate) use properties::{asciidoc_anchor, org_noweb_expands};
 pub use properties::{
-    extract_quarto_options, parse_properties, Properties, Property, QuartoOptions,
+    extract_quarto_options, extract_quarto_options_keeping, parse_properties, parse_properties_at,
+    Properties, Property, PropertySyntaxError, QuartoOptions,
 };
 pub use reference_id::ReferenceId;
-pub use reference_map::ReferenceMap;
+pub use reference_map::{MergePolicy, ReferenceMap};
 pub use reference_name::ReferenceName;
-pub use tangle::{tangle_annotated, tangle_naked, tangle_ref, CycleDetector};
+pub use tangle::{
+    expand_block, resolve_reference, tangle_annotated, tangle_naked, tangle_ref, tangle_ref_to,
+    tangle_ref_with_line_directives, CycleDetector,
+};
+pub use validate::ValidationIssue;
@@ -0,0 +1,155 @@
+//! AsciiDoc property parsing.
+
+use super::{escape_quoted, unquote_value, Properties, Property};
+
+/// Parses an AsciiDoc block attribute list: `[source#main,python,file=out.py]`.
+///
+/// The first positional attribute is the block style, `source` for code,
+/// which may carry `#id` and `.role` shorthands; the second is the language.
+/// Named `id` and `role` attributes become an ID and classes, and other
+/// named attributes are kept as they are. Returns `None` if the list does not
+/// describe a source block.
+pub(crate) fn parse_asciidoc(input: &str) -> Option<Properties> {
+    let inner = strip_brackets(input.trim());
+    let mut parts = split_attributes(inner).into_iter();
+
+    let (style, shorthands) = split_shorthands(parts.next()?.trim());
+    if style != "source" && style != "listing" {
+        return None;
+    }
+
+    let mut language = None;
+    let mut ids = Vec::new();
+    let mut roles = Vec::new();
+    let mut attributes = Vec::new();
+    for (marker, value) in shorthands {
+        match marker {
+            '#' => ids.push(value.to_string()),
+            '.' => roles.push(value.to_string()),
+            _ => {}
+        }
+    }
+
+    for (i, part) in parts.enumerate() {
+        let part = part.trim();
+        match part.split_once('=') {
+            Some((key, value)) => {
+                let value = unquote_value(value);
+                match key.trim() {
+                    "id" => ids.push(value),
+                    "role" => roles.extend(value.split_whitespace().map(String::from)),
+                    "language" if language.is_none() => language = Some(value),
+                    key => attributes.push((key.to_string(), value)),
+                }
+            }
+            None if i == 0 && !part.is_empty() => language = Some(part.to_string()),
+            None => {}
+        }
+    }
+
+    let mut items = Vec::new();
+    items.extend(language.map(Property::Class));
+    items.extend(roles.into_iter().map(Property::Class));
+    items.extend(ids.into_iter().map(Property::Id));
+    items.extend(
+        attributes
+            .into_iter()
+            .map(|(k, v)| Property::Attribute(k, v)),
+    );
+    Some(Properties::new(items))
+}
+
+/// Reads the ID of a `[[id]]` or `[[id,reftext]]` anchor line.
+pub(crate) fn asciidoc_anchor(line: &str) -> Option<&str> {
+    let inner = line.trim().strip_prefix("[[")?.strip_suffix("]]")?;
+    let id = inner.split(',').next()?.trim();
+    (!id.is_empty()).then_some(id)
+}
+
+/// Renders properties as an AsciiDoc source block attribute list:
+/// `[source,python,id=main,file=out.py]`.
+///
+/// Classes after the first are written as `role`. Values containing commas,
+/// spaces or quotes are written as escaped strings.
+pub(crate) fn render_asciidoc(props: &Properties) -> String {
+    let mut parts = vec!["source".to_string()];
+    let classes = props.classes();
+    if let Some(lang) = classes.first() {
+        parts.push(lang.to_string());
+    }
+    if classes.len() > 1 {
+        parts.push(format!(
+            "role={}",
+            quote_asciidoc_value(&classes[1..].join(" "))
+        ));
+    }
+    for prop in &props.items {
+        match prop {
+            Property::Class(_) => {}
+            Property::Id(id) => parts.push(format!("id={}", quote_asciidoc_value(id))),
+            Property::Attribute(k, v) => parts.push(format!("{}={}", k, quote_asciidoc_value(v))),
+        }
+    }
+    format!("[{}]", parts.join(","))
+}
+
+/// Strips the outer `[` `]` of an attribute list line.
+fn strip_brackets(s: &str) -> &str {
+    s.strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(s)
+}
+
+/// Splits the first positional attribute into its style and its `#id`,
+/// `.role` and `%option` shorthands.
+fn split_shorthands(first: &str) -> (&str, Vec<(char, &str)>) {
+    let style_end = first.find(['#', '.', '%']).unwrap_or(first.len());
+    let mut shorthands = Vec::new();
+    let mut rest = &first[style_end..];
+    while let Some(marker) = rest.chars().next() {
+        let value = &rest[1..];
+        let end = value.find(['#', '.', '%']).unwrap_or(value.len());
+        if end > 0 {
+            shorthands.push((marker, &value[..end]));
+        }
+        rest = &value[end..];
+    }
+    (&first[..style_end], shorthands)
+}
+
+/// Splits an attribute list on commas, respecting double-quoted values and
+/// the backslash escapes inside them.
+fn split_attributes(input: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if in_quotes => {
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                parts.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&input[start..]);
+    parts
+}
+
+/// Quotes an attribute value if splitting, trimming or unquoting would
+/// change it.
+fn quote_asciidoc_value(value: &str) -> String {
+    if value.is_empty()
+        || value.contains([',', ']', '"', '\''])
+        || value.contains(char::is_whitespace)
+    {
+        escape_quoted(value)
+    } else {
+        value.to_string()
+    }
+}
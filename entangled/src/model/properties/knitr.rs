@@ -1,9 +1,11 @@
 //! Knitr/RMarkdown property parsing.
 
-use super::{strip_braces, strip_quotes, Properties, Property};
+use super::{escape_quoted, strip_braces, unquote_value, Properties, Property};
 
 /// Parse knitr-style comma-separated properties.
-/// Format: `python, label=main, file=out.py, echo=FALSE`
+/// Format: `python, label=main, file=out.py, echo=FALSE`; the label may
+/// also be given without `label=`, after the language (`python main`) or as
+/// the first option (`python, main`).
 pub(crate) fn parse_knitr_properties(input: &str) -> crate::errors::Result<Properties> {
     let mut items = Vec::new();
     let trimmed = input.trim();
@@ -22,18 +24,25 @@ pub(crate) fn parse_knitr_properties(input: &str) -> crate::errors::Result<Prope
         }
 
         if i == 0 && !part.contains('=') {
-            // First item without `=` is the language
-            items.push(Property::Class(part.to_string()));
+            // First item without `=` is the language, optionally followed
+            // by the label: `r setup`
+            let (language, label) = part.split_once(char::is_whitespace).unwrap_or((part, ""));
+            items.push(Property::Class(language.to_string()));
+            if !label.trim().is_empty() {
+                items.push(Property::Id(unquote_value(label)));
+            }
+        } else if i == 1 && !part.contains('=') && !items.iter().any(|p| p.as_id().is_some()) {
+            // The first unnamed option is the label: `r, setup`
+            items.push(Property::Id(unquote_value(part)));
         } else if let Some((key, value)) = part.split_once('=') {
             let key = key.trim();
-            let value = value.trim();
             // Strip quotes if present
-            let value = strip_quotes(value);
+            let value = unquote_value(value);
 
             // Convert knitr-specific keys
             match key {
-                "label" => items.push(Property::Id(value.to_string())),
-                _ => items.push(Property::Attribute(key.to_string(), value.to_string())),
+                "label" => items.push(Property::Id(value)),
+                _ => items.push(Property::Attribute(key.to_string(), value)),
             }
         } else {
             // Boolean flag without value (e.g., `eval` is treated as `eval=TRUE`)
@@ -52,15 +61,20 @@ pub(crate) fn parse_knitr(input: &str) -> crate::errors::Result<Properties> {
     parse_knitr_properties(inner)
 }
 
-/// Split knitr options by comma, respecting quoted values.
+/// Split knitr options by comma, respecting quoted values and the
+/// backslash escapes inside them.
 fn split_knitr_options(input: &str) -> Vec<String> {
     let mut parts = Vec::new();
     let mut current = String::new();
     let mut in_quotes = false;
-    let chars = input.chars();
+    let mut chars = input.chars();
 
-    for c in chars {
+    while let Some(c) = chars.next() {
         match c {
+            '\\' if in_quotes => {
+                current.push(c);
+                current.extend(chars.next());
+            }
             '"' if !in_quotes => {
                 in_quotes = true;
                 current.push(c);
@@ -83,3 +97,36 @@ fn split_knitr_options(input: &str) -> Vec<String> {
 
     parts
 }
+
+/// Renders properties as a knitr info string: `{python, label=main, file=out.py}`.
+///
+/// Knitr has a single language slot, so classes after the first are dropped.
+/// Values containing commas, spaces or quotes are written as escaped R strings.
+pub(crate) fn render_knitr(props: &Properties) -> String {
+    let mut parts = Vec::new();
+    if let Some(lang) = props.first_class() {
+        parts.push(lang.to_string());
+    }
+    for prop in &props.items {
+        match prop {
+            Property::Class(_) => {}
+            Property::Id(id) => parts.push(format!("label={}", quote_knitr_value(id))),
+            Property::Attribute(k, v) => parts.push(format!("{}={}", k, quote_knitr_value(v))),
+        }
+    }
+    format!("{{{}}}", parts.join(", "))
+}
+
+/// Quotes a knitr option value if splitting, trimming or unquoting would
+/// change it.
+fn quote_knitr_value(value: &str) -> String {
+    if value.is_empty()
+        || value.contains([',', '"'])
+        || value.contains(char::is_whitespace)
+        || value.starts_with('\'')
+    {
+        escape_quoted(value)
+    } else {
+        value.to_string()
+    }
+}
@@ -3,10 +3,15 @@
 //! Parses property strings like `.python #main file=output.py mode=0755`
 //! into structured Property values.
 
+mod asciidoc;
 mod knitr;
+mod org;
 mod quarto;
 
-pub use quarto::{extract_quarto_options, QuartoOptions};
+pub(crate) use asciidoc::asciidoc_anchor;
+pub(crate) use org::org_noweb_expands;
+
+pub use quarto::{extract_quarto_options, extract_quarto_options_keeping, QuartoOptions};
 
 use nom::{
     branch::alt,
@@ -15,9 +20,11 @@ use nom::{
     combinator::{map, opt, value},
     multi::many0,
     sequence::{delimited, preceded},
-    IResult, Parser,
+    IResult, Offset, Parser,
 };
 
+use crate::style::Style;
+
 /// A single property from a code block header.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Property {
@@ -70,6 +77,27 @@ impl Property {
     }
 }
 
+/// Splits a `files=` value on whitespace, honouring single-quoted entries.
+fn split_file_list(value: &str) -> Vec<&str> {
+    let mut entries = Vec::new();
+    let mut rest = value.trim_start();
+    while !rest.is_empty() {
+        let (entry, tail) = match rest.strip_prefix('\'') {
+            Some(quoted) => match quoted.find('\'') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        entries.push(entry);
+        rest = tail.trim_start();
+    }
+    entries
+}
+
 /// Check if a character is valid in an identifier.
 fn is_ident_char(c: char) -> bool {
     c.is_alphanumeric() || c == '_' || c == '-' || c == ':' || c == '/' || c == '.'
@@ -88,11 +116,15 @@ fn parse_class(input: &str) -> IResult<&str, Property> {
     .parse(input)
 }
 
-/// Parse an ID property: `#idname`
+/// Parse an ID property: `#idname` or `#"id with spaces"`
 fn parse_id(input: &str) -> IResult<&str, Property> {
-    map(preceded(char('#'), parse_ident), |s: &str| {
-        Property::Id(s.to_string())
-    })
+    map(
+        preceded(
+            char('#'),
+            alt((parse_quoted_string, map(parse_ident, str::to_string))),
+        ),
+        Property::Id,
+    )
     .parse(input)
 }
 
@@ -100,16 +132,19 @@ fn parse_id(input: &str) -> IResult<&str, Property> {
 fn parse_quoted_string(input: &str) -> IResult<&str, String> {
     delimited(
         char('"'),
-        escaped_transform(
-            none_of("\\\""),
-            '\\',
-            alt((
-                value("\\", tag("\\")),
-                value("\"", tag("\"")),
-                value("\n", tag("n")),
-                value("\t", tag("t")),
-                value("\r", tag("r")),
+        map(
+            opt(escaped_transform(
+                none_of("\\\""),
+                '\\',
+                alt((
+                    value("\\", tag("\\")),
+                    value("\"", tag("\"")),
+                    value("\n", tag("n")),
+                    value("\t", tag("t")),
+                    value("\r", tag("r")),
+                )),
             )),
+            Option::unwrap_or_default,
         ),
         char('"'),
     )
@@ -169,21 +204,42 @@ fn parse_properties_inner(input: &str) -> IResult<&str, Vec<Property>> {
     }
 }
 
-/// Parse a property string into a list of properties.
-pub fn parse_properties(input: &str) -> Result<Vec<Property>, crate::errors::EntangledError> {
+/// A property string that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertySyntaxError {
+    /// Byte offset into the input where parsing stopped.
+    pub offset: usize,
+    /// Description of the failure.
+    pub message: String,
+}
+
+/// Parse a property string, reporting the position of a failure.
+pub fn parse_properties_at(input: &str) -> Result<Vec<Property>, PropertySyntaxError> {
     match parse_properties_inner(input) {
         Ok(("", props)) => Ok(props),
-        Ok((remaining, _)) => Err(crate::errors::EntangledError::InvalidProperty(format!(
-            "Unexpected input: '{}'",
-            remaining
-        ))),
-        Err(e) => Err(crate::errors::EntangledError::InvalidProperty(format!(
-            "Parse error: {}",
-            e
-        ))),
+        Ok((remaining, _)) => Err(PropertySyntaxError {
+            offset: input.offset(remaining),
+            message: format!("Unexpected input: '{}'", remaining),
+        }),
+        Err(e) => {
+            let offset = match &e {
+                nom::Err::Error(inner) | nom::Err::Failure(inner) => input.offset(inner.input),
+                nom::Err::Incomplete(_) => input.len(),
+            };
+            Err(PropertySyntaxError {
+                offset,
+                message: format!("Parse error: {}", e),
+            })
+        }
     }
 }
 
+/// Parse a property string into a list of properties.
+pub fn parse_properties(input: &str) -> Result<Vec<Property>, crate::errors::EntangledError> {
+    parse_properties_at(input)
+        .map_err(|e| crate::errors::EntangledError::InvalidProperty(e.message))
+}
+
 /// Parsed properties with convenient accessors.
 #[derive(Debug, Clone, Default)]
 pub struct Properties {
@@ -237,7 +293,30 @@ impl Properties {
 
     /// Returns the file attribute if present.
     pub fn file(&self) -> Option<&str> {
-        self.get_attribute("file")
+        self.files().into_iter().next()
+    }
+
+    /// Returns every target file, in order of appearance.
+    ///
+    /// Collects repeated `file=` attributes and the entries of `files=`
+    /// lists, which are separated by whitespace; an entry containing spaces
+    /// can be wrapped in single quotes (`files="a.py 'my b.py'"`). Duplicates
+    /// are dropped.
+    pub fn files(&self) -> Vec<&str> {
+        let mut files: Vec<&str> = Vec::new();
+        for (key, value) in self.attributes() {
+            let entries = match key {
+                "file" => vec![value],
+                "files" => split_file_list(value),
+                _ => continue,
+            };
+            for entry in entries {
+                if !entry.is_empty() && !files.contains(&entry) {
+                    files.push(entry);
+                }
+            }
+        }
+        files
     }
 
     /// Parses a Pandoc-style info string: `{.python #main file=out.py}`.
@@ -248,12 +327,65 @@ impl Properties {
         Self::parse(inner)
     }
 
+    /// Parses a property string, reporting where parsing failed.
+    pub fn try_parse(input: &str) -> Result<Self, PropertySyntaxError> {
+        Ok(Self::new(parse_properties_at(input)?))
+    }
+
+    /// Parses a Pandoc-style info string, reporting where parsing failed.
+    ///
+    /// The offset is relative to `input`, outer braces included.
+    pub fn try_parse_pandoc(input: &str) -> Result<Self, PropertySyntaxError> {
+        let inner = strip_braces(input);
+        Self::try_parse(inner).map_err(|e| PropertySyntaxError {
+            offset: input.offset(inner) + e.offset,
+            ..e
+        })
+    }
+
     /// Parses a knitr-style info string: `{python, label=main, file=out.py}`.
     /// Handles comma-separated options and converts `label=x` to an ID.
     pub fn parse_knitr(input: &str) -> crate::errors::Result<Self> {
         knitr::parse_knitr(input)
     }
 
+    /// Parses a knitr-style info string, reporting an unclosed brace or
+    /// quote.
+    ///
+    /// The offset is relative to `input`.
+    pub fn try_parse_knitr(input: &str) -> Result<Self, PropertySyntaxError> {
+        check_delimiters(input)?;
+        Ok(knitr::parse_knitr(input).unwrap_or_default())
+    }
+
+    /// Parses an AsciiDoc block attribute list: `[source#main,python,file=out.py]`.
+    /// Converts `#id`/`id=` to an ID and `.role`/`role=` to classes; a list
+    /// whose style is not `source` gives no properties.
+    pub fn parse_asciidoc(input: &str) -> crate::errors::Result<Self> {
+        Ok(asciidoc::parse_asciidoc(input).unwrap_or_default())
+    }
+
+    /// Parses the arguments of an Org source block:
+    /// `python :tangle out.py :noweb-ref main`.
+    /// Converts `:tangle` to the `file` attribute and `:noweb-ref` to an ID.
+    pub fn parse_org(input: &str) -> crate::errors::Result<Self> {
+        Ok(org::parse_org(input))
+    }
+
+    /// Parses the arguments of an Org source block, reporting an unclosed
+    /// quote.
+    ///
+    /// The offset is relative to `input`.
+    pub fn try_parse_org(input: &str) -> Result<Self, PropertySyntaxError> {
+        if let Some(offset) = unclosed_quote(input) {
+            return Err(PropertySyntaxError {
+                offset,
+                message: "Unterminated string".to_string(),
+            });
+        }
+        Ok(org::parse_org(input))
+    }
+
     /// Parses a Quarto-style info string: `{python}`.
     /// Only extracts the language; options come from content.
     pub fn parse_quarto_info(input: &str) -> crate::errors::Result<Self> {
@@ -266,6 +398,78 @@ impl Properties {
             Ok(Self::new(vec![Property::Class(lang.to_string())]))
         }
     }
+
+    /// Parses a Quarto-style info string, reporting an unclosed brace.
+    ///
+    /// The offset is relative to `input`.
+    pub fn try_parse_quarto_info(input: &str) -> Result<Self, PropertySyntaxError> {
+        check_delimiters(input)?;
+        Ok(Self::parse_quarto_info(input).unwrap_or_default())
+    }
+
+    /// Renders the properties as the info string of a fence in `style`.
+    ///
+    /// The result parses back to the same properties with the matching
+    /// parser: `python #main file=x` for entangled-rs, `{.python #main file=x}`
+    /// for Pandoc, `{python, label=main, file=x}` for knitr and
+    /// `python :noweb-ref main :tangle x` for Org and
+    /// `[source,python,id=main,file=x]` for AsciiDoc. For Quarto it is
+    /// `{python}` followed by one `#| key: value` line per ID and attribute.
+    /// IDs and values that are not plain identifiers are quoted.
+    pub fn to_info_string(&self, style: Style) -> String {
+        match style {
+            Style::EntangledRs => render_properties(&self.items, true),
+            Style::Pandoc => format!("{{{}}}", render_properties(&self.items, false)),
+            Style::Knitr => knitr::render_knitr(self),
+            Style::Quarto => quarto::render_quarto(self),
+            Style::Org => org::render_org(self),
+            Style::AsciiDoc => asciidoc::render_asciidoc(self),
+        }
+    }
+}
+
+/// Renders properties separated by spaces. With `plain_language`, a leading
+/// class is written without its `.` prefix.
+fn render_properties(items: &[Property], plain_language: bool) -> String {
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, prop)| match prop {
+            Property::Class(c) if i == 0 && plain_language => c.clone(),
+            Property::Class(c) => format!(".{}", c),
+            Property::Id(id) => format!("#{}", quote_value(id)),
+            Property::Attribute(k, v) => format!("{}={}", k, quote_value(v)),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes a value unless it is a plain identifier, escaping as
+/// [`parse_quoted_string`] expects.
+fn quote_value(value: &str) -> String {
+    if !value.is_empty() && value.chars().all(is_ident_char) {
+        value.to_string()
+    } else {
+        escape_quoted(value)
+    }
+}
+
+/// Wraps a value in double quotes, escaping as [`parse_quoted_string`] expects.
+pub(crate) fn escape_quoted(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
 }
 
 /// Strip outer braces from a string like `{content}`.
@@ -278,6 +482,52 @@ pub(crate) fn strip_braces(s: &str) -> &str {
     }
 }
 
+/// Returns the offset of a double quote in `s` that is never closed,
+/// reading backslash escapes inside quotes.
+fn unclosed_quote(s: &str) -> Option<usize> {
+    let mut open = None;
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if open.is_some() => {
+                chars.next();
+            }
+            '"' => open = if open.is_some() { None } else { Some(i) },
+            _ => {}
+        }
+    }
+    open
+}
+
+/// Checks that a braced info string (`{python, label=main}`) closes its
+/// brace and its quotes.
+fn check_delimiters(input: &str) -> Result<(), PropertySyntaxError> {
+    if let Some(offset) = unclosed_quote(input) {
+        return Err(PropertySyntaxError {
+            offset,
+            message: "Unterminated string".to_string(),
+        });
+    }
+    let trimmed = input.trim_end();
+    if trimmed.trim_start().starts_with('{') && !trimmed.ends_with('}') {
+        return Err(PropertySyntaxError {
+            offset: trimmed.len(),
+            message: "Expected '}'".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Reads a value written by [`escape_quoted`]. Other values only have
+/// surrounding quotes stripped, as by [`strip_quotes`].
+pub(crate) fn unquote_value(s: &str) -> String {
+    let s = s.trim();
+    match parse_quoted_string(s) {
+        Ok(("", value)) => value,
+        _ => strip_quotes(s).to_string(),
+    }
+}
+
 /// Strip surrounding quotes from a value.
 pub(crate) fn strip_quotes(s: &str) -> &str {
     let s = s.trim();
@@ -390,6 +640,19 @@ mod tests {
         assert_eq!(props, vec![Property::Id("module::function".to_string())]);
     }
 
+    #[test]
+    fn test_quoted_and_unicode_ids() {
+        let props = parse_properties(".python #\"my block name\" #größe").unwrap();
+        assert_eq!(
+            props,
+            vec![
+                Property::Class("python".to_string()),
+                Property::Id("my block name".to_string()),
+                Property::Id("größe".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn test_file_path_with_slashes() {
         let props = parse_properties("file=src/lib/output.rs").unwrap();
@@ -402,6 +665,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_error_offset() {
+        let err = parse_properties_at(".python #main ==bad").unwrap_err();
+        assert_eq!(err.offset, 14);
+        assert_eq!(err.message, "Unexpected input: '==bad'");
+
+        let err = Properties::try_parse_pandoc("{.python #main ==bad}").unwrap_err();
+        assert_eq!(err.offset, 15);
+    }
+
     // Pandoc style tests
     #[test]
     fn test_pandoc_simple() {
@@ -462,6 +735,16 @@ mod tests {
         assert_eq!(props.file(), Some("path/to/file.R"));
     }
 
+    #[test]
+    fn test_knitr_positional_label() {
+        let props = Properties::parse_knitr("{r setup, echo=FALSE}").unwrap();
+        assert_eq!(props.first_class(), Some("r"));
+        assert_eq!(props.first_id(), Some("setup"));
+        let props = Properties::parse_knitr("{r, setup, file=out.R}").unwrap();
+        assert_eq!(props.first_id(), Some("setup"));
+        assert_eq!(props.file(), Some("out.R"));
+    }
+
     #[test]
     fn test_knitr_boolean_flags() {
         let props = Properties::parse_knitr("{r, echo=FALSE, eval=TRUE}").unwrap();
@@ -547,4 +830,185 @@ mod tests {
         assert_eq!(opts.label, Some("my label".to_string()));
         assert_eq!(opts.file, Some("out.py".to_string()));
     }
+
+    #[test]
+    fn test_quarto_options_quoted_colons() {
+        let content = "#| label: main\n#| title: \"a: b\"\n#| fig-cap='x: y = 1'\n#| url=\"http://example.com\"\ncode";
+        let (opts, remaining) = extract_quarto_options(content);
+
+        assert_eq!(opts.label, Some("main".to_string()));
+        assert_eq!(
+            opts.other,
+            vec![
+                ("title".to_string(), "a: b".to_string()),
+                ("fig-cap".to_string(), "x: y = 1".to_string()),
+                ("url".to_string(), "http://example.com".to_string()),
+            ]
+        );
+        assert_eq!(remaining, "code");
+    }
+
+    #[test]
+    fn test_quarto_options_multiline_values() {
+        let content = "#| label: main\n#| fig-cap: |\n#|   First line: one\n#|   second line\n#| layout:\n#|   - [a, b]\n#|   - c\n#| summary: a long\n#|   caption\n#| file: out.py\ncode";
+        let (opts, remaining) = extract_quarto_options(content);
+
+        assert_eq!(opts.label, Some("main".to_string()));
+        assert_eq!(opts.file, Some("out.py".to_string()));
+        assert_eq!(
+            opts.other,
+            vec![
+                (
+                    "fig-cap".to_string(),
+                    "First line: one\nsecond line".to_string()
+                ),
+                ("layout".to_string(), "- [a, b]\n- c".to_string()),
+                ("summary".to_string(), "a long caption".to_string()),
+            ]
+        );
+        assert_eq!(remaining, "code");
+    }
+
+    #[test]
+    fn test_quarto_options_keeping() {
+        let content = "#| label: main\n#| fig-cap: |\n#|   a caption\n#| echo: false\ncode";
+        let keep = vec!["label".to_string(), "fig-cap".to_string()];
+        let (opts, remaining) = extract_quarto_options_keeping(content, &keep);
+
+        // Kept options are still read
+        assert_eq!(opts.label, Some("main".to_string()));
+        assert_eq!(opts.other.len(), 2);
+        assert_eq!(
+            remaining,
+            "#| label: main\n#| fig-cap: |\n#|   a caption\ncode"
+        );
+    }
+
+    #[test]
+    fn test_files_list_and_repeated_file() {
+        let props = Properties::parse(".python files=\"a.py 'my b.py'  c.py\" file=d.py file=a.py")
+            .unwrap();
+        assert_eq!(props.files(), vec!["a.py", "my b.py", "c.py", "d.py"]);
+        assert_eq!(props.file(), Some("a.py"));
+
+        let props = Properties::parse(".python #main").unwrap();
+        assert!(props.files().is_empty());
+        assert_eq!(props.file(), None);
+    }
+
+    #[test]
+    fn test_to_info_string() {
+        let props = Properties::parse("python #main file=\"my out.py\" mode=0755").unwrap();
+        assert_eq!(
+            props.to_info_string(Style::EntangledRs),
+            "python #main file=\"my out.py\" mode=0755"
+        );
+        assert_eq!(
+            props.to_info_string(Style::Pandoc),
+            "{.python #main file=\"my out.py\" mode=0755}"
+        );
+        assert_eq!(
+            props.to_info_string(Style::Knitr),
+            "{python, label=main, file=\"my out.py\", mode=0755}"
+        );
+        assert_eq!(
+            props.to_info_string(Style::Quarto),
+            "{python}\n#| label: main\n#| file: my out.py\n#| mode: 0755"
+        );
+    }
+
+    #[test]
+    fn test_to_info_string_round_trip() {
+        let props = Properties::new(vec![
+            Property::Class("python".to_string()),
+            Property::Id("load the data".to_string()),
+            Property::Attribute("file".to_string(), "out, v2.py".to_string()),
+            Property::Attribute("title".to_string(), "\"hi\" it's\\n".to_string()),
+            Property::Attribute("note".to_string(), "it's: a 'b' #tag".to_string()),
+            Property::Attribute("quoted".to_string(), "'single'".to_string()),
+            Property::Attribute("doc".to_string(), "two\nlines".to_string()),
+            Property::Attribute("empty".to_string(), String::new()),
+        ]);
+
+        for style in [
+            Style::EntangledRs,
+            Style::Pandoc,
+            Style::Knitr,
+            Style::Org,
+            Style::AsciiDoc,
+        ] {
+            let info = props.to_info_string(style);
+            let parsed = match style {
+                Style::EntangledRs => Properties::parse(&info).unwrap(),
+                Style::Pandoc => Properties::parse_pandoc(&info).unwrap(),
+                Style::Knitr => Properties::parse_knitr(&info).unwrap(),
+                Style::Org => Properties::parse_org(&info).unwrap(),
+                _ => Properties::parse_asciidoc(&info).unwrap(),
+            };
+            assert_eq!(parsed.items, props.items, "{:?}: {}", style, info);
+        }
+
+        let rendered = props.to_info_string(Style::Quarto);
+        let (info, options) = rendered.split_once('\n').unwrap();
+        let language = Properties::parse_quarto_info(info).unwrap();
+        let (opts, _) = extract_quarto_options(options);
+        assert_eq!(
+            opts.to_properties(language.first_class()).items,
+            props.items,
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("#| note: \"it's: a 'b' #tag\""));
+        assert!(rendered.contains("#| title: '\"hi\" it''s\\n'"));
+    }
+
+    #[test]
+    fn test_parse_org() {
+        let props =
+            Properties::parse_org("python -n :tangle \"my out.py\" :noweb yes :noweb-ref main")
+                .unwrap();
+        assert_eq!(props.first_class(), Some("python"));
+        assert_eq!(props.file(), Some("my out.py"));
+        assert_eq!(props.get_attribute("noweb"), Some("yes"));
+        assert_eq!(props.first_id(), Some("main"));
+        assert_eq!(
+            Properties::parse_org(&props.to_info_string(Style::Org))
+                .unwrap()
+                .items,
+            props.items
+        );
+
+        // `:tangle yes` and `:tangle no` name no file
+        assert_eq!(Properties::parse_org("sh :tangle no").unwrap().file(), None);
+        assert_eq!(
+            Properties::parse_org("sh :tangle yes").unwrap().file(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_asciidoc() {
+        let props =
+            Properties::parse_asciidoc("[source#main.skip,python,file=\"my out.py\",linenums]")
+                .unwrap();
+        assert_eq!(props.classes(), vec!["python", "skip"]);
+        assert_eq!(props.first_id(), Some("main"));
+        assert_eq!(props.file(), Some("my out.py"));
+        assert_eq!(
+            Properties::parse_asciidoc(&props.to_info_string(Style::AsciiDoc))
+                .unwrap()
+                .items,
+            props.items
+        );
+
+        let props = Properties::parse_asciidoc("[source,rust,id=lib,role=\"a b\"]").unwrap();
+        assert_eq!(props.classes(), vec!["rust", "a", "b"]);
+        assert_eq!(props.first_id(), Some("lib"));
+
+        // Not a source block
+        assert!(Properties::parse_asciidoc("[quote,Author]")
+            .unwrap()
+            .items
+            .is_empty());
+    }
 }
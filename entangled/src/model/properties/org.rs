@@ -0,0 +1,116 @@
+//! Org-mode property parsing.
+
+use super::{escape_quoted, unquote_value, Properties, Property};
+
+/// Parses the arguments of an Org source block header:
+/// `python :tangle out.py :noweb yes`.
+///
+/// The first word is the language. Header arguments are `:key value` pairs
+/// whose value runs up to the next `:key`; `:tangle` becomes the `file`
+/// attribute (unless it is `yes` or `no`) and `:noweb-ref` an ID. Switches
+/// such as `-n` between the language and the first header argument are
+/// ignored.
+pub(crate) fn parse_org(input: &str) -> Properties {
+    let mut items = Vec::new();
+    let words = split_org_words(input.trim());
+    let mut words = words.iter().map(String::as_str).peekable();
+
+    if let Some(lang) = words.next_if(|w| !w.starts_with(':')) {
+        items.push(Property::Class(lang.to_string()));
+    }
+    // Switches
+    while words.next_if(|w| !w.starts_with(':')).is_some() {}
+
+    while let Some(key) = words.next() {
+        let key = &key[1..];
+        let mut values = Vec::new();
+        while let Some(value) = words.next_if(|w| !w.starts_with(':')) {
+            values.push(unquote_value(value));
+        }
+        let value = values.join(" ");
+        match key {
+            "tangle" if value == "yes" || value == "no" || value.is_empty() => {}
+            "tangle" => items.push(Property::Attribute("file".to_string(), value)),
+            "noweb-ref" => items.push(Property::Id(value)),
+            _ => items.push(Property::Attribute(key.to_string(), value)),
+        }
+    }
+
+    Properties::new(items)
+}
+
+/// Renders properties as Org source block arguments:
+/// `python :tangle out.py :noweb-ref main`.
+///
+/// Classes after the first are dropped. Values containing spaces or quotes
+/// are written as escaped strings.
+pub(crate) fn render_org(props: &Properties) -> String {
+    let mut parts = Vec::new();
+    if let Some(lang) = props.first_class() {
+        parts.push(lang.to_string());
+    }
+    for prop in &props.items {
+        match prop {
+            Property::Class(_) => {}
+            Property::Id(id) => parts.push(format!(":noweb-ref {}", quote_org_value(id))),
+            Property::Attribute(k, v) if k == "file" => {
+                parts.push(format!(":tangle {}", quote_org_value(v)))
+            }
+            Property::Attribute(k, v) => parts.push(format!(":{} {}", k, quote_org_value(v))),
+        }
+    }
+    parts.join(" ")
+}
+
+/// Returns true if the value of a `:noweb` header argument expands
+/// references when tangling.
+pub(crate) fn org_noweb_expands(value: Option<&str>) -> bool {
+    matches!(value, Some("yes" | "tangle" | "no-export" | "strip-export"))
+}
+
+/// Splits on whitespace, keeping double-quoted words, and the backslash
+/// escapes inside them, together.
+fn split_org_words(input: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_quotes => {
+                current.push(c);
+                current.extend(chars.next());
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+
+    words
+}
+
+/// Quotes an Org header value if it is empty, contains whitespace or a
+/// quote, or would be read as a header argument.
+fn quote_org_value(value: &str) -> String {
+    if value.is_empty()
+        || value.contains(char::is_whitespace)
+        || value.contains('"')
+        || value.starts_with([':', '\''])
+    {
+        escape_quoted(value)
+    } else {
+        value.to_string()
+    }
+}
@@ -1,6 +1,6 @@
 //! Quarto property parsing.
 
-use super::{strip_quotes, Properties, Property};
+use super::{escape_quoted, unquote_value, Properties, Property};
 
 /// Quarto options extracted from `#|` comment lines.
 #[derive(Debug, Clone, Default)]
@@ -19,6 +19,16 @@ impl QuartoOptions {
         Self::default()
     }
 
+    /// Returns true if the chunk should be left out of tangled output.
+    ///
+    /// This is controlled by the `eval` option: `#| eval: false` marks the
+    /// chunk as illustrative only.
+    pub fn skip(&self) -> bool {
+        self.other
+            .iter()
+            .any(|(k, v)| k == "eval" && v.eq_ignore_ascii_case("false"))
+    }
+
     /// Checks if any options are set.
     pub fn is_empty(&self) -> bool {
         self.label.is_none() && self.file.is_none() && self.other.is_empty()
@@ -57,46 +67,200 @@ impl QuartoOptions {
     }
 }
 
+/// Renders properties as a Quarto info string, `{python}`, followed by a
+/// `#| key: value` line for the label and each attribute.
+///
+/// Multi-line values are written as `|` block scalars; values that would not
+/// read back as written are quoted.
+pub(crate) fn render_quarto(props: &Properties) -> String {
+    let mut lines = vec![format!("{{{}}}", props.first_class().unwrap_or_default())];
+    let options = props.items.iter().filter_map(|prop| match prop {
+        Property::Id(id) => Some(("label", id.as_str())),
+        Property::Attribute(k, v) => Some((k.as_str(), v.as_str())),
+        Property::Class(_) => None,
+    });
+    for (key, value) in options {
+        if value.contains('\n') {
+            lines.push(format!("#| {}: |", key));
+            lines.extend(value.lines().map(|l| format!("#|   {}", l)));
+        } else {
+            lines.push(format!("#| {}: {}", key, quote_yaml_value(value)));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Quotes a single-line option value if YAML would not read it back as
+/// written: if it is empty, has surrounding whitespace, starts with an
+/// indicator character, or contains `: ` or ` #`.
+///
+/// Values with `"` or `\` are single-quoted, doubling any `'`; others are
+/// double-quoted.
+fn quote_yaml_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value.trim() != value
+        || value.starts_with([
+            '"', '\'', '|', '>', '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '%',
+            '@', '`',
+        ])
+        || value.ends_with(':')
+        || value.contains(": ")
+        || value.contains(" #");
+    if !needs_quotes {
+        value.to_string()
+    } else if value.contains(['"', '\\']) {
+        format!("'{}'", value.replace('\'', "''"))
+    } else {
+        escape_quoted(value)
+    }
+}
+
+/// Reads a single-line option value: a single-quoted value has `''`
+/// read as `'`, a double-quoted one has its backslash escapes read.
+fn unquote_yaml_value(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        Some(inner) => inner.replace("''", "'"),
+        None => unquote_value(value),
+    }
+}
+
 /// Extract `#|` options from Quarto-style code block content.
 ///
+/// An option may continue on following `#|` lines that are indented more
+/// deeply than its key, as in YAML: a block scalar (`key: |` or `key: >`)
+/// takes the text of those lines, and a nested list or mapping is kept as
+/// its YAML source. Continuation lines are never read as options of their own.
+///
 /// Returns the extracted options and the remaining content (with #| lines removed).
 pub fn extract_quarto_options(content: &str) -> (QuartoOptions, String) {
+    extract_quarto_options_keeping(content, &[])
+}
+
+/// Like [`extract_quarto_options`], but the `#|` lines of options whose key
+/// is in `keep`, continuation lines included, stay in the remaining content.
+///
+/// Kept options are still extracted.
+pub fn extract_quarto_options_keeping(content: &str, keep: &[String]) -> (QuartoOptions, String) {
     let mut options = QuartoOptions::new();
     let mut remaining_lines = Vec::new();
+    let mut pending: Option<PendingOption> = None;
 
     for line in content.lines() {
         let trimmed = line.trim_start();
         if let Some(rest) = trimmed.strip_prefix("#|") {
+            let rest = rest.strip_prefix(' ').unwrap_or(rest);
+            let indent = rest.len() - rest.trim_start().len();
+            if let Some(option) = pending.as_mut() {
+                if indent > option.indent || rest.trim().is_empty() {
+                    option.lines.push(rest);
+                    if option.keep {
+                        remaining_lines.push(line);
+                    }
+                    continue;
+                }
+            }
+            if let Some(option) = pending.take() {
+                option.finish(&mut options);
+            }
             // Parse the option: "key: value" or "key=value"
-            let rest = rest.trim();
-            if let Some((key, value)) = parse_quarto_option_line(rest) {
-                options.set(&key, value);
+            if let Some((key, value)) = parse_quarto_option_line(rest.trim()) {
+                let keep = keep.contains(&key);
+                if keep {
+                    remaining_lines.push(line);
+                }
+                pending = Some(PendingOption {
+                    key,
+                    value,
+                    indent,
+                    lines: Vec::new(),
+                    keep,
+                });
             }
         } else {
             remaining_lines.push(line);
         }
     }
+    if let Some(option) = pending {
+        option.finish(&mut options);
+    }
 
     (options, remaining_lines.join("\n"))
 }
 
+/// An option whose continuation lines are still being read.
+struct PendingOption<'a> {
+    key: String,
+    value: String,
+    /// Indentation of the key, after the `#|` prefix.
+    indent: usize,
+    /// Continuation lines, as written after the `#|` prefix.
+    lines: Vec<&'a str>,
+    /// Whether the option's lines stay in the content.
+    keep: bool,
+}
+
+impl PendingOption<'_> {
+    /// Combines the value with its continuation lines and records the option.
+    fn finish(self, options: &mut QuartoOptions) {
+        let lines = dedent(&self.lines);
+        let value = if lines.is_empty() {
+            self.value
+        } else if self.value.starts_with('|') {
+            lines.join("\n")
+        } else if self.value.starts_with('>') {
+            lines.join(" ")
+        } else if self.value.is_empty() {
+            lines.join("\n")
+        } else {
+            // A plain scalar folded over several lines
+            std::iter::once(self.value.as_str())
+                .chain(lines.iter().map(|l| l.trim()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        options.set(&self.key, value);
+    }
+}
+
+/// Removes the indentation shared by all non-blank lines, and trailing blank lines.
+fn dedent<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    let mut lines = lines.to_vec();
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .into_iter()
+        .map(|l| l.get(indent..).unwrap_or("").trim_end())
+        .collect()
+}
+
 /// Parse a single Quarto option line (after the `#|` prefix).
 /// Supports both `key: value` (YAML) and `key=value` formats.
+///
+/// The key ends at the first `:` or `=` outside quotes, so quoted values may
+/// contain either (`title: "a: b"`, `fig-cap="x = 1"`).
 fn parse_quarto_option_line(line: &str) -> Option<(String, String)> {
-    // Try YAML-style "key: value" first
-    if let Some((key, value)) = line.split_once(':') {
-        let key = key.trim();
-        let value = value.trim();
-        if !key.is_empty() {
-            return Some((key.to_string(), strip_quotes(value).to_string()));
-        }
-    }
-    // Try "key=value" format
-    if let Some((key, value)) = line.split_once('=') {
-        let key = key.trim();
-        let value = value.trim();
-        if !key.is_empty() {
-            return Some((key.to_string(), strip_quotes(value).to_string()));
+    let mut quote: Option<char> = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, ':' | '=') => {
+                let key = line[..i].trim();
+                let value = line[i + 1..].trim();
+                if key.is_empty() || key.contains(['"', '\'']) {
+                    return None;
+                }
+                return Some((key.to_string(), unquote_yaml_value(value)));
+            }
+            _ => {}
         }
     }
     None
@@ -2,6 +2,8 @@
 
 use std::fmt;
 
+use serde::{Serialize, Serializer};
+
 use super::ReferenceName;
 
 /// A reference ID uniquely identifies a code block instance.
@@ -48,6 +50,13 @@ impl fmt::Display for ReferenceId {
     }
 }
 
+/// Serializes as the `name[count]` string form.
+impl Serialize for ReferenceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,6 +1,7 @@
 //! Reference map with dual-index for code block lookup.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
@@ -10,6 +11,22 @@ use super::code_block::CodeBlock;
 use super::reference_id::ReferenceId;
 use super::reference_name::ReferenceName;
 use crate::errors::{EntangledError, Result};
+use crate::io::{escapes_directory, normalize_separators, FileCache};
+
+/// How [`ReferenceMap::merge`] handles blocks whose name or target is
+/// already present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+    /// Add blocks of an existing name after the present ones. A target
+    /// already written by another name is taken over, with a warning.
+    #[default]
+    Append,
+    /// Fail, adding nothing, if any name or target is already present.
+    Error,
+    /// Keep what is present: skip blocks of an existing name, and blocks
+    /// whose target another name already writes.
+    PreferExisting,
+}
 
 /// A map of code blocks with dual-index lookup.
 ///
@@ -19,6 +36,10 @@ use crate::errors::{EntangledError, Result};
 ///
 /// Blocks are stored behind `Arc` to allow cheap cloning when combining
 /// reference maps from multiple documents during tangle.
+///
+/// With [case-insensitive names](Self::with_case_insensitive_names), names
+/// are lowercased when blocks are inserted and in every lookup, so `Main`
+/// and `main` are the same reference throughout.
 #[derive(Debug, Clone, Default)]
 pub struct ReferenceMap {
     /// Primary storage: ID -> CodeBlock (insertion order preserved).
@@ -32,6 +53,28 @@ pub struct ReferenceMap {
 
     /// Counter for generating unique IDs per name.
     counters: HashMap<ReferenceName, usize>,
+
+    /// Whether names are matched case-insensitively.
+    fold_case: bool,
+
+    /// Escape that marks a reference-like line as literal; empty for none.
+    reference_escape: String,
+
+    /// First spelling seen for each case-folded name, to report names that
+    /// differ only by case.
+    spellings: HashMap<ReferenceName, ReferenceName>,
+
+    /// Where `<<file:path>>` references read included files from, if enabled.
+    file_includes: Option<FileIncludes>,
+}
+
+/// File cache and directory that included files are read from.
+#[derive(Debug, Clone)]
+struct FileIncludes {
+    fs: Arc<dyn FileCache>,
+    base_dir: PathBuf,
+    /// Whether included paths may be absolute or climb out of `base_dir`.
+    allow_escaping: bool,
 }
 
 impl ReferenceMap {
@@ -41,20 +84,145 @@ impl ReferenceMap {
         Self::default()
     }
 
+    /// Sets whether names are matched case-insensitively.
+    ///
+    /// Set this before inserting blocks; names already stored are not folded.
+    #[must_use]
+    pub fn with_case_insensitive_names(mut self, fold_case: bool) -> Self {
+        self.fold_case = fold_case;
+        self
+    }
+
+    /// Returns true if names are matched case-insensitively.
+    pub fn case_insensitive_names(&self) -> bool {
+        self.fold_case
+    }
+
+    /// Sets the escape that, in front of `<<`, makes a line that would be a
+    /// reference literal when tangling, such as `\<<name>>`.
+    ///
+    /// Empty, the default, disables escaping.
+    #[must_use]
+    pub fn with_reference_escape(mut self, escape: impl Into<String>) -> Self {
+        self.reference_escape = escape.into();
+        self
+    }
+
+    /// Returns the escape for reference-like lines; empty if disabled.
+    pub fn reference_escape(&self) -> &str {
+        &self.reference_escape
+    }
+
+    /// Enables file includes: a `<<file:path>>` reference that names no
+    /// block is expanded to the contents of `path`, read through `fs`
+    /// relative to `base_dir`.
+    ///
+    /// Paths that are absolute or climb out of `base_dir` are refused unless
+    /// allowed with [`with_escaping_file_includes`](Self::with_escaping_file_includes).
+    #[must_use]
+    pub fn with_file_includes(mut self, fs: Arc<dyn FileCache>, base_dir: PathBuf) -> Self {
+        self.file_includes = Some(FileIncludes {
+            fs,
+            base_dir,
+            allow_escaping: false,
+        });
+        self
+    }
+
+    /// Sets whether file includes may read files outside their base
+    /// directory. Has no effect unless file includes are enabled.
+    #[must_use]
+    pub fn with_escaping_file_includes(mut self, allow: bool) -> Self {
+        if let Some(includes) = &mut self.file_includes {
+            includes.allow_escaping = allow;
+        }
+        self
+    }
+
+    /// Returns true if `refname`, as written in a reference, includes a file
+    /// rather than naming blocks.
+    pub fn is_file_include(&self, refname: &str) -> bool {
+        self.file_includes.is_some()
+            && refname.starts_with("file:")
+            && !self.contains_name(&ReferenceName::new(refname))
+    }
+
+    /// Reads the file that `refname` includes, or returns `None` if it does
+    /// not include a file.
+    pub fn read_file_include(&self, refname: &str) -> Option<Result<String>> {
+        if !self.is_file_include(refname) {
+            return None;
+        }
+        let includes = self.file_includes.as_ref()?;
+        let path = refname.strip_prefix("file:")?;
+        if !includes.allow_escaping && escapes_directory(Path::new(path)) {
+            return Some(Err(EntangledError::InvalidProperty(format!(
+                "<<file:{}>> points outside the project directory (set allow_escaping_targets to permit)",
+                path
+            ))));
+        }
+        Some(
+            includes
+                .fs
+                .read(&includes.base_dir.join(path))
+                .map_err(|e| EntangledError::Other(format!("Cannot include {}: {}", path, e))),
+        )
+    }
+
+    /// Returns the form a name is stored under: lowercased if names are
+    /// case-insensitive, otherwise unchanged.
+    pub fn canonical_name<'a>(&self, name: &'a ReferenceName) -> Cow<'a, ReferenceName> {
+        if self.fold_case && name.as_str().chars().any(char::is_uppercase) {
+            Cow::Owned(ReferenceName::new(name.as_str().to_lowercase()))
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+
+    /// Returns the form an ID is stored under.
+    fn canonical_id<'a>(&self, id: &'a ReferenceId) -> Cow<'a, ReferenceId> {
+        match self.canonical_name(&id.name) {
+            Cow::Borrowed(_) => Cow::Borrowed(id),
+            Cow::Owned(name) => Cow::Owned(ReferenceId::new(name, id.count)),
+        }
+    }
+
+    /// Folds a name for insertion, warning if it collides with another
+    /// spelling of the same name.
+    fn fold_for_insert(&mut self, name: &ReferenceName) -> ReferenceName {
+        let folded = self.canonical_name(name).into_owned();
+        if self.fold_case {
+            let first = self
+                .spellings
+                .entry(folded.clone())
+                .or_insert_with(|| name.clone());
+            if first != name {
+                tracing::warn!(
+                    "Block names '{}' and '{}' differ only by case and are treated as the same name",
+                    first,
+                    name
+                );
+            }
+        }
+        folded
+    }
+
     /// Inserts a code block, generating a unique ID.
     ///
     /// Returns the assigned ReferenceId.
     pub fn insert(&mut self, mut block: CodeBlock) -> ReferenceId {
+        let name = self.fold_for_insert(block.name());
+
         // Get or create counter for this name
-        let count = self.counters.entry(block.name().clone()).or_insert(0);
-        let id = ReferenceId::new(block.name().clone(), *count);
+        let count = self.counters.entry(name.clone()).or_insert(0);
+        let id = ReferenceId::new(name, *count);
         *count += 1;
 
         // Update block's ID
         block.id = id.clone();
 
-        // Register target if present
-        if let Some(ref target) = block.target {
+        // Register targets if present
+        for target in block.targets() {
             self.targets.insert(target.clone(), block.name().clone());
         }
 
@@ -79,14 +247,25 @@ impl ReferenceMap {
     ///
     /// This avoids deep-cloning when transferring blocks between maps.
     pub fn insert_arc_with_id(&mut self, id: ReferenceId, block: Arc<CodeBlock>) {
+        let id = ReferenceId::new(self.fold_for_insert(&id.name), id.count);
+        self.insert_folded(id, block);
+    }
+
+    /// Inserts a block under an ID whose name is already folded by
+    /// [`fold_for_insert`](Self::fold_for_insert).
+    fn insert_folded(&mut self, id: ReferenceId, mut block: Arc<CodeBlock>) {
+        if self.fold_case && block.id != id {
+            Arc::make_mut(&mut block).id = id.clone();
+        }
+
         // Update counter if necessary
         let count = self.counters.entry(id.name.clone()).or_insert(0);
         if id.count >= *count {
             *count = id.count + 1;
         }
 
-        // Register target if present
-        if let Some(ref target) = block.target {
+        // Register targets if present
+        for target in block.targets() {
             self.targets.insert(target.clone(), id.name.clone());
         }
 
@@ -100,15 +279,311 @@ impl ReferenceMap {
         self.blocks.insert(id, block);
     }
 
+    /// Inserts a block from another map, keeping its ID unless already taken.
+    ///
+    /// When the ID collides (e.g. the same name defined in two documents), the
+    /// block is assigned the next free count for its name. Returns the ID used.
+    pub fn merge_arc(&mut self, id: &ReferenceId, block: &Arc<CodeBlock>) -> ReferenceId {
+        // Folded here, from the name as `other` spells it, so that another
+        // spelling of a name already merged is reported
+        let id = ReferenceId::new(self.fold_for_insert(&id.name), id.count);
+        if !self.blocks.contains_key(&id) {
+            self.insert_folded(id.clone(), Arc::clone(block));
+            return id;
+        }
+
+        let count = self.counters.get(&id.name).copied().unwrap_or(0);
+        let new_id = ReferenceId::new(id.name.clone(), count);
+        let mut renumbered = CodeBlock::clone(block);
+        renumbered.id = new_id.clone();
+        self.insert_folded(new_id.clone(), Arc::new(renumbered));
+        new_id
+    }
+
+    /// Adds every block of `other`, handling names and targets that are
+    /// already present as `policy` says.
+    ///
+    /// A name collides when `self` already has blocks of that name; a target
+    /// collides when `self` already has it registered to a different name,
+    /// unless both contribute to it with a `mode` attribute.
+    /// Blocks are added with [`merge_arc`](Self::merge_arc), so IDs are kept
+    /// unless taken. With [`MergePolicy::Error`], nothing is added if any
+    /// block collides.
+    pub fn merge(&mut self, other: &ReferenceMap, policy: MergePolicy) -> Result<()> {
+        // Names are checked against this map as it was before merging, so
+        // several blocks of one name in `other` do not collide with each other
+        let existing: HashSet<ReferenceName> = match policy {
+            MergePolicy::Append => HashSet::new(),
+            _ => other
+                .names()
+                .map(|name| self.canonical_name(name).into_owned())
+                .filter(|name| self.contains_name(name))
+                .collect(),
+        };
+
+        if policy == MergePolicy::Error {
+            if let Some((id, _)) = other
+                .iter()
+                .find(|(id, _)| existing.contains(self.canonical_name(&id.name).as_ref()))
+            {
+                return Err(EntangledError::DuplicateReference(id.name.clone()));
+            }
+            if let Some((path, first, second)) = other
+                .iter()
+                .find_map(|(_, block)| self.target_collision(block))
+            {
+                return Err(EntangledError::DuplicateTarget {
+                    path,
+                    first,
+                    second,
+                });
+            }
+        }
+
+        for (id, block) in other.iter_arcs() {
+            if existing.contains(self.canonical_name(&id.name).as_ref()) {
+                continue;
+            }
+            if let Some((path, first, second)) = self.target_collision(block) {
+                if policy == MergePolicy::PreferExisting {
+                    continue;
+                }
+                tracing::warn!(
+                    "{} is written by both {} and {}; using {}",
+                    path.display(),
+                    first,
+                    second,
+                    second
+                );
+            }
+            self.merge_arc(id, block);
+        }
+        Ok(())
+    }
+
+    /// Returns the first target of `block` that this map has registered to
+    /// another name, with that name and the block's.
+    ///
+    /// Blocks with a `mode` attribute (`mode=append`/`mode=prepend`) are
+    /// meant to share their target, so a target both blocks contribute to
+    /// that way does not collide.
+    fn target_collision(
+        &self,
+        block: &CodeBlock,
+    ) -> Option<(PathBuf, ReferenceName, ReferenceName)> {
+        let name = self.canonical_name(block.name());
+        let contributes = |b: &CodeBlock| b.get_attribute("mode").is_some();
+        block
+            .targets()
+            .find_map(|target| match self.targets.get(target) {
+                Some(first) if first != name.as_ref() => {
+                    let shared = contributes(block)
+                        && self
+                            .get_by_name(first)
+                            .iter()
+                            .any(|b| contributes(b) && b.targets().any(|t| t == target));
+                    (!shared).then(|| (target.clone(), first.clone(), name.clone().into_owned()))
+                }
+                _ => None,
+            })
+    }
+
+    /// Removes a block by ID, returning it.
+    ///
+    /// The block is dropped from the name index; a name left without blocks
+    /// is forgotten entirely, counter included. If the block had a target
+    /// that no remaining block writes to, the target is unregistered;
+    /// otherwise the target is reassigned to the last remaining block with it.
+    /// Each target of a block with several is handled this way.
+    pub fn remove(&mut self, id: &ReferenceId) -> Option<CodeBlock> {
+        let id = self.canonical_id(id).into_owned();
+        let id = &id;
+        let block = self.blocks.shift_remove(id)?;
+
+        if let Some(ids) = self.name_index.get_mut(&id.name) {
+            ids.retain(|i| i != id);
+            if ids.is_empty() {
+                self.name_index.remove(&id.name);
+                self.counters.remove(&id.name);
+                self.spellings.remove(&id.name);
+            }
+        }
+
+        for target in block.targets() {
+            match self
+                .blocks
+                .values()
+                .rev()
+                .find(|b| b.targets().any(|t| t == target))
+            {
+                Some(other) => {
+                    self.targets.insert(target.clone(), other.id.name.clone());
+                }
+                None => {
+                    self.targets.remove(target);
+                }
+            }
+        }
+
+        Some(Arc::unwrap_or_clone(block))
+    }
+
+    /// Renames every block called `old` to `new`, keeping their counts and
+    /// their position in insertion order.
+    ///
+    /// Fails if `old` does not exist or `new` is already in use. References
+    /// to `old` inside block sources are not rewritten.
+    pub fn rename(&mut self, old: &ReferenceName, new: ReferenceName) -> Result<()> {
+        if !self.contains_name(old) {
+            return Err(EntangledError::ReferenceNotFound(old.clone()));
+        }
+        if self.contains_name(&new) {
+            return Err(EntangledError::DuplicateReference(new));
+        }
+        let old = &self.canonical_name(old).into_owned();
+        self.spellings.remove(old);
+        let new = self.fold_for_insert(&new);
+
+        let renamed = |id: &ReferenceId| ReferenceId::new(new.clone(), id.count);
+        self.blocks = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(|(id, block)| {
+                if &id.name != old {
+                    return (id, block);
+                }
+                let new_id = renamed(&id);
+                let mut block = Arc::unwrap_or_clone(block);
+                block.id = new_id.clone();
+                (new_id, Arc::new(block))
+            })
+            .collect();
+
+        if let Some(ids) = self.name_index.remove(old) {
+            self.name_index
+                .insert(new.clone(), ids.iter().map(renamed).collect());
+        }
+        if let Some(count) = self.counters.remove(old) {
+            self.counters.insert(new.clone(), count);
+        }
+        for name in self.targets.values_mut() {
+            if name == old {
+                *name = new.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces every target path `t`, of blocks and in the target registry,
+    /// with `f(t)`.
+    ///
+    /// Blocks named after their target, `file:t`, are renamed `file:f(t)`,
+    /// so that targets placed in different directories keep distinct names.
+    pub fn map_targets(&mut self, f: impl Fn(&Path) -> PathBuf) {
+        let file_name = |path: &Path| {
+            self.canonical_name(&ReferenceName::from_file_path(&path.to_string_lossy()))
+                .into_owned()
+        };
+        let renames: HashMap<ReferenceName, ReferenceName> = self
+            .targets
+            .iter()
+            .filter(|(target, name)| **name == file_name(target))
+            .map(|(target, name)| (name.clone(), file_name(&f(target))))
+            .filter(|(old, new)| old != new)
+            .collect();
+
+        for block in self.blocks.values_mut() {
+            if block.has_target() {
+                let block = Arc::make_mut(block);
+                block.target = block.target.as_deref().map(&f);
+                block.extra_targets = block.extra_targets.iter().map(|t| f(t)).collect();
+            }
+        }
+        self.targets = std::mem::take(&mut self.targets)
+            .into_iter()
+            .map(|(target, name)| (f(&target), name))
+            .collect();
+        if !renames.is_empty() {
+            self.rename_all(&renames);
+        }
+    }
+
+    /// Replaces the source of every block for which `f` returns a new one.
+    pub fn map_sources(
+        &mut self,
+        mut f: impl FnMut(&CodeBlock) -> Result<Option<String>>,
+    ) -> Result<()> {
+        for block in self.blocks.values_mut() {
+            if let Some(source) = f(block)? {
+                Arc::make_mut(block).source = source;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames the blocks of every name in `renames`, from old to new name,
+    /// at once. The new names must not be in use.
+    fn rename_all(&mut self, renames: &HashMap<ReferenceName, ReferenceName>) {
+        let rename = |name: &ReferenceName| renames.get(name).unwrap_or(name).clone();
+        let rename_id = |id: &ReferenceId| ReferenceId::new(rename(&id.name), id.count);
+
+        self.blocks = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(|(id, mut block)| {
+                let new_id = rename_id(&id);
+                if new_id != id {
+                    Arc::make_mut(&mut block).id = new_id.clone();
+                }
+                (new_id, block)
+            })
+            .collect();
+        self.name_index = std::mem::take(&mut self.name_index)
+            .into_iter()
+            .map(|(name, ids)| (rename(&name), ids.iter().map(rename_id).collect()))
+            .collect();
+        self.counters = std::mem::take(&mut self.counters)
+            .into_iter()
+            .map(|(name, count)| (rename(&name), count))
+            .collect();
+        self.spellings = std::mem::take(&mut self.spellings)
+            .into_iter()
+            .map(|(name, spelling)| match renames.get(&name) {
+                Some(new) => (new.clone(), new.clone()),
+                None => (name, spelling),
+            })
+            .collect();
+        for name in self.targets.values_mut() {
+            *name = rename(name);
+        }
+    }
+
+    /// Orders the blocks of every name by source location: by file path,
+    /// then line and column.
+    ///
+    /// This is the order in which [`get_by_name`](Self::get_by_name) returns
+    /// them, and so the order in which they are concatenated when tangled.
+    /// Blocks at the same location keep their relative order. IDs are not
+    /// renumbered.
+    pub fn sort_by_location(&mut self) {
+        let blocks = &self.blocks;
+        for ids in self.name_index.values_mut() {
+            ids.sort_by(|a, b| {
+                let (a, b) = (&blocks[a].location, &blocks[b].location);
+                (&a.filename, a.line, a.column).cmp(&(&b.filename, b.line, b.column))
+            });
+        }
+    }
+
     /// Gets a code block by its ID.
     pub fn get(&self, id: &ReferenceId) -> Option<&CodeBlock> {
-        self.blocks.get(id).map(|arc| arc.as_ref())
+        self.blocks
+            .get(self.canonical_id(id).as_ref())
+            .map(|arc| arc.as_ref())
     }
 
     /// Gets all code blocks with the given name.
     pub fn get_by_name(&self, name: &ReferenceName) -> Vec<&CodeBlock> {
         self.name_index
-            .get(name)
+            .get(self.canonical_name(name).as_ref())
             .map(|ids| {
                 ids.iter()
                     .filter_map(|id| self.blocks.get(id))
@@ -121,24 +596,71 @@ impl ReferenceMap {
     /// Gets all IDs for blocks with the given name.
     pub fn get_ids_by_name(&self, name: &ReferenceName) -> Vec<&ReferenceId> {
         self.name_index
-            .get(name)
+            .get(self.canonical_name(name).as_ref())
             .map(|ids| ids.iter().collect())
             .unwrap_or_default()
     }
 
     /// Gets the reference name for a target file.
+    ///
+    /// `\` and `/` separators in `path` are treated alike.
     pub fn get_target_name(&self, path: &Path) -> Option<&ReferenceName> {
-        self.targets.get(path)
+        self.targets.get(&normalize_separators(path))
     }
 
     /// Checks if a name exists in the map.
     pub fn contains_name(&self, name: &ReferenceName) -> bool {
-        self.name_index.contains_key(name)
+        self.name_index
+            .contains_key(self.canonical_name(name).as_ref())
+    }
+
+    /// Finds the name a user means by `name`, in the map's canonical form.
+    ///
+    /// A name without a namespace that has no blocks of its own matches the
+    /// one namespaced name ending in `#name`, so that `main` finds
+    /// `docs/a.md#main` under file namespacing. Several such names are an
+    /// error listing them.
+    pub fn find_name(&self, name: &str) -> Result<ReferenceName> {
+        let name = self.canonical_name(&ReferenceName::new(name)).into_owned();
+        if self.contains_name(&name) {
+            return Ok(name);
+        }
+        if name.as_str().contains('#') {
+            return Err(EntangledError::ReferenceNotFound(name));
+        }
+
+        let suffix = format!("#{}", name);
+        let mut candidates: Vec<&ReferenceName> = self
+            .names()
+            .filter(|n| n.as_str().ends_with(&suffix))
+            .collect();
+        candidates.sort_by_key(|n| n.as_str());
+        match candidates.as_slice() {
+            [] => Err(EntangledError::ReferenceNotFound(name)),
+            [only] => Ok((*only).clone()),
+            several => Err(EntangledError::Config(format!(
+                "Block name '{}' is ambiguous; qualify it as one of: {}",
+                name,
+                several
+                    .iter()
+                    .map(|n| n.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+
+    /// Checks if a name has at least one block that is not skipped.
+    ///
+    /// A name whose blocks are all skipped is undefined as far as references
+    /// are concerned.
+    pub fn is_defined(&self, name: &ReferenceName) -> bool {
+        self.get_by_name(name).iter().any(|b| !b.skip)
     }
 
     /// Checks if an ID exists in the map.
     pub fn contains_id(&self, id: &ReferenceId) -> bool {
-        self.blocks.contains_key(id)
+        self.blocks.contains_key(self.canonical_id(id).as_ref())
     }
 
     /// Returns all registered target files.
@@ -179,14 +701,18 @@ impl ReferenceMap {
     }
 
     /// Concatenates all source code for blocks with the given name.
+    ///
+    /// Blocks marked as skipped are left out; a name with only skipped
+    /// blocks is not found.
     pub fn concatenate_source(&self, name: &ReferenceName) -> Result<String> {
         let blocks = self.get_by_name(name);
-        if blocks.is_empty() {
+        if !blocks.iter().any(|b| !b.skip) {
             return Err(EntangledError::ReferenceNotFound(name.clone()));
         }
 
         Ok(blocks
             .iter()
+            .filter(|b| !b.skip)
             .map(|b| b.source.as_str())
             .collect::<Vec<_>>()
             .join("\n"))
@@ -194,7 +720,10 @@ impl ReferenceMap {
 
     /// Returns the number of blocks with the given name.
     pub fn count_by_name(&self, name: &ReferenceName) -> usize {
-        self.name_index.get(name).map(|v| v.len()).unwrap_or(0)
+        self.name_index
+            .get(self.canonical_name(name).as_ref())
+            .map(|v| v.len())
+            .unwrap_or(0)
     }
 }
 
@@ -203,6 +732,267 @@ mod tests {
     use super::*;
     use crate::test_utils::{make_block, make_block_with_target};
 
+    #[test]
+    fn test_merge_arc_renumbers_collisions() {
+        let mut first = ReferenceMap::new();
+        first.insert(make_block("main", "a"));
+        let mut second = ReferenceMap::new();
+        second.insert(make_block("main", "b"));
+        second.insert(make_block("main", "c"));
+
+        let mut merged = ReferenceMap::new();
+        for (id, block) in first.iter_arcs().chain(second.iter_arcs()) {
+            merged.merge_arc(id, block);
+        }
+
+        assert_eq!(merged.len(), 3);
+        assert_eq!(
+            merged
+                .concatenate_source(&ReferenceName::new("main"))
+                .unwrap(),
+            "a\nb\nc"
+        );
+        let ids: Vec<usize> = merged
+            .get_by_name(&ReferenceName::new("main"))
+            .iter()
+            .map(|b| b.id.count)
+            .collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+    }
+
+    /// A map with `main` and `helper` blocks, and one with another `main`
+    /// block, a new `extra` block and an `other` block writing `main`'s target.
+    fn merge_fixture() -> (ReferenceMap, ReferenceMap) {
+        let mut base = ReferenceMap::new();
+        base.insert(make_block_with_target("main", "a", "out.py"));
+        base.insert(make_block("helper", "h"));
+        let mut other = ReferenceMap::new();
+        other.insert(make_block("main", "b"));
+        other.insert(make_block("extra", "e"));
+        other.insert(make_block_with_target("other", "o", "out.py"));
+        (base, other)
+    }
+
+    #[test]
+    fn test_merge_append() {
+        let (mut map, other) = merge_fixture();
+        map.merge(&other, MergePolicy::Append).unwrap();
+
+        assert_coherent(&map);
+        assert_eq!(map.len(), 5);
+        assert_eq!(
+            map.concatenate_source(&ReferenceName::new("main")).unwrap(),
+            "a\nb"
+        );
+        assert_eq!(
+            map.get_target_name(Path::new("out.py")),
+            Some(&ReferenceName::new("other"))
+        );
+    }
+
+    #[test]
+    fn test_merge_error() {
+        let (mut map, other) = merge_fixture();
+        let err = map.merge(&other, MergePolicy::Error).unwrap_err();
+        assert!(matches!(err, EntangledError::DuplicateReference(name) if name.as_str() == "main"));
+        assert_eq!(map.len(), 2);
+
+        // Only the target collides
+        let mut other = ReferenceMap::new();
+        other.insert(make_block_with_target("other", "o", "out.py"));
+        let err = map.merge(&other, MergePolicy::Error).unwrap_err();
+        assert!(
+            matches!(err, EntangledError::DuplicateTarget { ref path, .. } if path == Path::new("out.py"))
+        );
+        assert_eq!(map.len(), 2);
+
+        // Several blocks of a new name are fine
+        let mut other = ReferenceMap::new();
+        other.insert(make_block("extra", "e1"));
+        other.insert(make_block("extra", "e2"));
+        map.merge(&other, MergePolicy::Error).unwrap();
+        assert_coherent(&map);
+        assert_eq!(map.count_by_name(&ReferenceName::new("extra")), 2);
+    }
+
+    #[test]
+    fn test_merge_prefer_existing() {
+        let (mut map, other) = merge_fixture();
+        map.merge(&other, MergePolicy::PreferExisting).unwrap();
+
+        assert_coherent(&map);
+        assert_eq!(map.len(), 3);
+        assert_eq!(
+            map.concatenate_source(&ReferenceName::new("main")).unwrap(),
+            "a"
+        );
+        assert!(map.contains_name(&ReferenceName::new("extra")));
+        assert!(!map.contains_name(&ReferenceName::new("other")));
+        assert_eq!(
+            map.get_target_name(Path::new("out.py")),
+            Some(&ReferenceName::new("main"))
+        );
+    }
+
+    #[test]
+    fn test_merge_shared_mode_targets() {
+        let contributor = |name: &str| {
+            make_block_with_target(name, name, "config.toml")
+                .with_attribute("mode".to_string(), "append".to_string())
+        };
+        let mut map = ReferenceMap::new();
+        map.insert(contributor("server"));
+        let mut other = ReferenceMap::new();
+        other.insert(contributor("logging"));
+
+        // Contributions to one target are neither an error nor dropped
+        map.merge(&other, MergePolicy::Error).unwrap();
+        assert!(map.contains_name(&ReferenceName::new("logging")));
+        let mut other = ReferenceMap::new();
+        other.insert(contributor("client"));
+        map.merge(&other, MergePolicy::PreferExisting).unwrap();
+        assert!(map.contains_name(&ReferenceName::new("client")));
+        assert_coherent(&map);
+
+        // A block without `mode` still collides with them
+        let mut other = ReferenceMap::new();
+        other.insert(make_block_with_target("plain", "p", "config.toml"));
+        assert!(map.merge(&other, MergePolicy::Error).is_err());
+    }
+
+    /// Checks that every index agrees with primary storage.
+    fn assert_coherent(map: &ReferenceMap) {
+        let mut indexed = 0;
+        for (name, ids) in &map.name_index {
+            assert!(!ids.is_empty(), "empty name index entry for {}", name);
+            for id in ids {
+                assert_eq!(&id.name, name);
+                assert_eq!(map.get(id).map(|b| &b.id), Some(id));
+                assert!(id.count < map.counters[name]);
+            }
+            indexed += ids.len();
+        }
+        assert_eq!(indexed, map.len());
+        assert_eq!(map.counters.len(), map.name_index.len());
+        for (target, name) in &map.targets {
+            assert!(map
+                .blocks()
+                .any(|b| b.targets().any(|t| t == target) && b.name() == name));
+        }
+    }
+
+    #[test]
+    fn test_sort_by_location() {
+        use crate::text_location::TextLocation;
+
+        let mut map = ReferenceMap::new();
+        for (file, line, source) in [("b.md", 1, "b1"), ("a.md", 9, "a9"), ("a.md", 2, "a2")] {
+            let mut block = make_block("main", source);
+            block.location = TextLocation::file_line(PathBuf::from(file), line);
+            map.insert(block);
+        }
+        map.sort_by_location();
+
+        let name = ReferenceName::new("main");
+        assert_eq!(map.concatenate_source(&name).unwrap(), "a2\na9\nb1");
+        // IDs keep the numbers they were inserted with
+        let ids: Vec<String> = map
+            .get_ids_by_name(&name)
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        assert_eq!(ids, ["main[2]", "main[1]", "main[0]"]);
+        assert_coherent(&map);
+    }
+
+    #[test]
+    fn test_remove_and_rename_keep_indices_coherent() {
+        let mut map = ReferenceMap::new();
+        let main = map.insert(make_block_with_target("main", "<<helper>>", "out.py"));
+        let h0 = map.insert(make_block("helper", "a"));
+        let h1 = map.insert(make_block("helper", "b"));
+        map.insert(make_block_with_target("other", "x", "other.py"));
+
+        let removed = map.remove(&h0).unwrap();
+        assert_eq!(removed.source, "a");
+        assert!(map.remove(&h0).is_none());
+        assert_coherent(&map);
+        // Remaining blocks keep their IDs; new ones do not reuse removed counts
+        assert_eq!(map.get(&h1).unwrap().source, "b");
+        assert_eq!(map.insert(make_block("helper", "c")).count, 2);
+
+        map.rename(&ReferenceName::new("main"), ReferenceName::new("entry"))
+            .unwrap();
+        assert_coherent(&map);
+        assert!(!map.contains_id(&main));
+        assert_eq!(
+            map.get_target_name(Path::new("out.py")),
+            Some(&ReferenceName::new("entry"))
+        );
+        assert_eq!(map.blocks().next().unwrap().name().as_str(), "entry");
+
+        map.rename(&ReferenceName::new("helper"), ReferenceName::new("util"))
+            .unwrap();
+        assert_coherent(&map);
+        assert_eq!(
+            map.concatenate_source(&ReferenceName::new("util")).unwrap(),
+            "b\nc"
+        );
+
+        // Removing the only block with a target unregisters it
+        let other = map.get_ids_by_name(&ReferenceName::new("other"))[0].clone();
+        map.remove(&other);
+        assert_coherent(&map);
+        assert!(map.get_target_name(Path::new("other.py")).is_none());
+        assert!(!map.contains_name(&ReferenceName::new("other")));
+    }
+
+    #[test]
+    fn test_case_insensitive_names() {
+        let mut map = ReferenceMap::new().with_case_insensitive_names(true);
+        let id = map.insert(make_block_with_target("Main", "a", "out.py"));
+        assert_eq!(id.name.as_str(), "main");
+        assert_eq!(map.get(&id).unwrap().id, id);
+
+        // Another spelling is the same name
+        let mut other = ReferenceMap::new();
+        let other_id = other.insert(make_block("MAIN", "b"));
+        let merged = map.merge_arc(&other_id, &other.iter_arcs().next().unwrap().1.clone());
+        assert_eq!(merged, ReferenceId::new(ReferenceName::new("main"), 1));
+
+        assert_eq!(
+            map.concatenate_source(&ReferenceName::new("mAiN")).unwrap(),
+            "a\nb"
+        );
+        assert!(map.contains_id(&ReferenceId::new(ReferenceName::new("Main"), 1)));
+        assert_eq!(
+            map.get_target_name(Path::new("out.py")),
+            Some(&ReferenceName::new("main"))
+        );
+        assert_coherent(&map);
+
+        // Exact matching is the default
+        let mut exact = ReferenceMap::new();
+        exact.insert(make_block("Main", "a"));
+        assert!(!exact.contains_name(&ReferenceName::new("main")));
+    }
+
+    #[test]
+    fn test_rename_errors() {
+        let mut map = ReferenceMap::new();
+        map.insert(make_block("a", ""));
+        map.insert(make_block("b", ""));
+
+        assert!(matches!(
+            map.rename(&ReferenceName::new("missing"), ReferenceName::new("c")),
+            Err(EntangledError::ReferenceNotFound(_))
+        ));
+        assert!(matches!(
+            map.rename(&ReferenceName::new("a"), ReferenceName::new("b")),
+            Err(EntangledError::DuplicateReference(_))
+        ));
+    }
+
     #[test]
     fn test_insert_and_get() {
         let mut map = ReferenceMap::new();
@@ -2,11 +2,14 @@
 
 use std::fmt;
 
+use serde::Serialize;
+
 /// A reference name identifies a named code block.
 ///
 /// Names can include namespaces separated by `::`, e.g., `module::submodule::name`.
 /// They can also be file targets like `file:path/to/output.py`.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
 pub struct ReferenceName(String);
 
 impl ReferenceName {
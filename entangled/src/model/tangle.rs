@@ -1,10 +1,17 @@
 //! Tangle algorithm for expanding code block references.
 
+use std::borrow::Cow;
 use std::collections::HashSet;
+use std::io::Write;
 
-use crate::config::{annotation_begin, annotation_end, Comment, Markers, REF_PATTERN};
+use crate::config::{
+    annotation_end, block_annotation_begin, block_annotation_compact, ref_count, ref_name,
+    unescape_reference, Comment, Markers, REF_PATTERN,
+};
 use crate::errors::{EntangledError, Result};
 
+use super::code_block::CodeBlock;
+use super::reference_id::ReferenceId;
 use super::reference_map::ReferenceMap;
 use super::reference_name::ReferenceName;
 
@@ -51,105 +58,375 @@ impl CycleDetector {
     }
 }
 
+/// Resolves a `<<refname>>` written inside a block of the given namespace.
+///
+/// Resolution order:
+/// 1. A qualified name (`a.md#helper`) is looked up exactly as written.
+/// 2. An unqualified name is looked up in the referencing block's namespace
+///    (`<namespace>#helper`), if it has one and that name exists.
+/// 3. Otherwise the name is looked up as written, in the global scope.
+///
+/// The result is in the map's canonical form (lowercased if the map uses
+/// case-insensitive names).
+pub fn resolve_reference(
+    refs: &ReferenceMap,
+    refname: &str,
+    namespace: Option<&str>,
+) -> ReferenceName {
+    if !refname.contains('#') {
+        if let Some(ns) = namespace {
+            let qualified = ReferenceName::new(format!("{}#{}", ns, refname));
+            if refs.contains_name(&qualified) {
+                return refs.canonical_name(&qualified).into_owned();
+            }
+        }
+    }
+    refs.canonical_name(&ReferenceName::new(refname))
+        .into_owned()
+}
+
+/// Returns the name a reference is tracked by in the cycle detector and in
+/// errors: `name`, or `name[n]` for a reference to a single block.
+pub(super) fn reference_key(name: &ReferenceName, count: Option<usize>) -> Cow<'_, ReferenceName> {
+    match count {
+        Some(n) => Cow::Owned(ReferenceName::new(format!("{}[{}]", name, n))),
+        None => Cow::Borrowed(name),
+    }
+}
+
+/// Returns the non-skipped blocks a reference expands to, with their IDs:
+/// every block with the given name, or for `<<name[n]>>` (`count` is
+/// `Some(n)`) only the block with that count. Errors if there are none.
+fn blocks_for<'a>(
+    refs: &'a ReferenceMap,
+    name: &ReferenceName,
+    count: Option<usize>,
+) -> Result<Vec<(ReferenceId, &'a CodeBlock)>> {
+    let mut blocks = Vec::new();
+    match count {
+        Some(n) => {
+            let id = ReferenceId::new(name.clone(), n);
+            if let Some(block) = refs.get(&id).filter(|b| !b.skip) {
+                blocks.push((id, block));
+            }
+        }
+        None => {
+            for id in refs.get_ids_by_name(name) {
+                let block = refs.get(id).ok_or_else(|| {
+                    EntangledError::Other(format!(
+                        "Internal error: ReferenceMap has ID {} in name index but not in block storage",
+                        id
+                    ))
+                })?;
+                if !block.skip {
+                    blocks.push((id.clone(), block));
+                }
+            }
+        }
+    }
+    if blocks.is_empty() {
+        return Err(EntangledError::ReferenceNotFound(
+            reference_key(name, count).into_owned(),
+        ));
+    }
+    Ok(blocks)
+}
+
+/// Returns a source line that is not a reference as it is tangled: with the
+/// map's escape removed if it is an escaped reference, otherwise unchanged.
+fn literal<'a>(refs: &ReferenceMap, line: &'a str) -> Cow<'a, str> {
+    match unescape_reference(line, refs.reference_escape()) {
+        Some(unescaped) => Cow::Owned(unescaped),
+        None => Cow::Borrowed(line),
+    }
+}
+
+/// Returns the contents of the file a reference includes, or `None` if it
+/// names blocks.
+///
+/// Included files are leaves: references in them are not expanded, and they
+/// are not tracked for cycles. A reference to a single block (`<<name[n]>>`)
+/// never includes a file.
+fn file_include(
+    refs: &ReferenceMap,
+    refname: &str,
+    count: Option<usize>,
+) -> Result<Option<String>> {
+    if count.is_some() {
+        return Ok(None);
+    }
+    refs.read_file_include(refname).transpose()
+}
+
+/// Writes tangled output line by line, separating lines with `\n`.
+struct LineWriter<'w> {
+    out: &'w mut dyn Write,
+    /// Number of lines written so far.
+    lines: usize,
+}
+
+impl<'w> LineWriter<'w> {
+    fn new(out: &'w mut dyn Write) -> Self {
+        Self { out, lines: 0 }
+    }
+
+    /// Writes one line made of `indent` followed by `text`.
+    fn line(&mut self, indent: &str, text: &str) -> Result<()> {
+        if self.lines > 0 {
+            self.out.write_all(b"\n")?;
+        }
+        self.out.write_all(indent.as_bytes())?;
+        self.out.write_all(text.as_bytes())?;
+        self.lines += 1;
+        Ok(())
+    }
+
+    /// Writes every line of an included file, indented.
+    fn include(&mut self, indent: &str, content: &str) -> Result<()> {
+        for line in content.lines() {
+            self.line(indent, line)?;
+        }
+        Ok(())
+    }
+
+    /// Runs a nested expansion, writing an empty line if it produced none.
+    fn nested(&mut self, expand: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let before = self.lines;
+        expand(self)?;
+        if self.lines == before {
+            self.line("", "")?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the output of a line-writing expansion into a `String`.
+fn collect_lines(expand: impl FnOnce(&mut LineWriter) -> Result<()>) -> Result<String> {
+    let mut buf = Vec::new();
+    expand(&mut LineWriter::new(&mut buf))?;
+    String::from_utf8(buf).map_err(|e| EntangledError::Other(e.to_string()))
+}
+
 /// Tangles a reference without annotations (naked output).
 ///
-/// Expands all `<<refname>>` patterns recursively.
+/// Expands all `<<refname>>` patterns recursively, resolving each one with
+/// [`resolve_reference`] from the namespace of the block it appears in.
 pub fn tangle_naked(
     refs: &ReferenceMap,
     name: &ReferenceName,
     base_indent: &str,
     detector: &mut CycleDetector,
 ) -> Result<String> {
-    detector.enter(name)?;
-
-    let source = refs.concatenate_source(name)?;
-    let mut output = Vec::new();
+    collect_lines(|w| write_naked(refs, name, None, base_indent, detector, w))
+}
 
-    for line in source.lines() {
-        if let Some(caps) = REF_PATTERN.captures(line) {
-            let indent = &caps["indent"];
-            let refname = &caps["refname"];
-            let combined_indent = format!("{}{}", base_indent, indent);
+fn write_naked(
+    refs: &ReferenceMap,
+    name: &ReferenceName,
+    count: Option<usize>,
+    base_indent: &str,
+    detector: &mut CycleDetector,
+    w: &mut LineWriter,
+) -> Result<()> {
+    detector.enter(&reference_key(name, count))?;
 
-            let ref_name = ReferenceName::new(refname);
-            let expanded = tangle_naked(refs, &ref_name, &combined_indent, detector)?;
-            output.push(expanded);
-        } else {
-            output.push(format!("{}{}", base_indent, line));
+    for (_, block) in blocks_for(refs, name, count)? {
+        for line in block.source.lines() {
+            if let Some(caps) = REF_PATTERN.captures(line) {
+                let combined_indent = format!("{}{}", base_indent, &caps["indent"]);
+                let count = ref_count(&caps);
+                if let Some(content) = file_include(refs, &ref_name(&caps), count)? {
+                    w.nested(|w| w.include(&combined_indent, &content))?;
+                    continue;
+                }
+                let resolved =
+                    resolve_reference(refs, &ref_name(&caps), block.namespace.as_deref());
+                w.nested(|w| write_naked(refs, &resolved, count, &combined_indent, detector, w))?;
+            } else {
+                w.line(base_indent, &literal(refs, line))?;
+            }
         }
     }
 
     detector.exit();
-    Ok(output.join("\n"))
+    Ok(())
+}
+
+/// Expands the references in a single block, without annotations.
+///
+/// Unlike [`tangle_naked`], which concatenates every block with a given
+/// name, only `block`'s own source is used at the top level.
+pub fn expand_block(refs: &ReferenceMap, block: &CodeBlock) -> Result<String> {
+    let mut detector = CycleDetector::new();
+    detector.enter(&block.id.name)?;
+
+    collect_lines(|w| {
+        for line in block.source.lines() {
+            if let Some(caps) = REF_PATTERN.captures(line) {
+                let count = ref_count(&caps);
+                if let Some(content) = file_include(refs, &ref_name(&caps), count)? {
+                    w.nested(|w| w.include(&caps["indent"], &content))?;
+                    continue;
+                }
+                let resolved =
+                    resolve_reference(refs, &ref_name(&caps), block.namespace.as_deref());
+                w.nested(|w| {
+                    write_naked(refs, &resolved, count, &caps["indent"], &mut detector, w)
+                })?;
+            } else {
+                w.line("", &literal(refs, line))?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Formats a `#line` directive pointing at a line of the block's markdown source.
+///
+/// `offset` is the 0-based index of the line within the block content.
+/// Returns `None` when the block has no known source file.
+fn line_directive(block: &CodeBlock, offset: usize) -> Option<String> {
+    let path = block.location.filename.as_ref()?;
+    let escaped = path
+        .display()
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    Some(format!(
+        "#line {} \"{}\"",
+        block.location.line + 1 + offset,
+        escaped
+    ))
 }
 
 /// Tangles a reference with annotation comments.
 ///
-/// Adds begin/end markers around each expanded reference.
+/// Adds begin/end markers around each expanded reference. With
+/// `line_directives`, a `#line` directive follows each begin marker and each
+/// nested expansion, so compiler diagnostics point into the markdown.
+/// With compact markers, and without `line_directives`, a block without
+/// references is followed by a single compact marker instead.
 pub fn tangle_annotated(
     refs: &ReferenceMap,
     name: &ReferenceName,
     base_indent: &str,
     comment: &Comment,
     markers: &Markers,
+    line_directives: bool,
     detector: &mut CycleDetector,
 ) -> Result<String> {
-    detector.enter(name)?;
+    collect_lines(|w| {
+        write_annotated(
+            refs,
+            name,
+            None,
+            base_indent,
+            comment,
+            markers,
+            line_directives,
+            detector,
+            w,
+        )
+    })
+}
 
-    let ids = refs.get_ids_by_name(name);
-    if ids.is_empty() {
-        detector.exit();
-        return Err(EntangledError::ReferenceNotFound(name.clone()));
-    }
+#[allow(clippy::too_many_arguments)]
+fn write_annotated(
+    refs: &ReferenceMap,
+    name: &ReferenceName,
+    count: Option<usize>,
+    base_indent: &str,
+    comment: &Comment,
+    markers: &Markers,
+    line_directives: bool,
+    detector: &mut CycleDetector,
+    w: &mut LineWriter,
+) -> Result<()> {
+    detector.enter(&reference_key(name, count))?;
 
-    let mut output = Vec::new();
     let prefix = comment.prefix();
 
-    for id in ids {
-        let block = refs.get(id).ok_or_else(|| {
-            EntangledError::Other(format!(
-                "Internal error: ReferenceMap has ID {} in name index but not in block storage",
-                id
-            ))
-        })?;
-
-        // Add begin marker
-        let begin_marker = format!(
-            "{}{}",
+    for (id, block) in blocks_for(refs, name, count)? {
+        if markers.compact
+            && !line_directives
+            && !block.source.lines().any(|line| REF_PATTERN.is_match(line))
+        {
+            for line in block.source.lines() {
+                w.line(base_indent, &literal(refs, line))?;
+            }
+            w.line(
+                base_indent,
+                &block_annotation_compact(
+                    prefix,
+                    markers,
+                    &id.to_string(),
+                    &block.source,
+                    block.location.filename.as_deref(),
+                ),
+            )?;
+            continue;
+        }
+
+        w.line(
             base_indent,
-            annotation_begin(prefix, markers, &id.to_string())
-        );
-        output.push(begin_marker);
+            &block_annotation_begin(
+                prefix,
+                markers,
+                &id.to_string(),
+                &block.source,
+                block.location.filename.as_deref(),
+            ),
+        )?;
+
+        let directive = |w: &mut LineWriter, offset: usize| match line_directives
+            .then(|| line_directive(block, offset))
+            .flatten()
+        {
+            Some(d) => w.line(base_indent, &d),
+            None => Ok(()),
+        };
+        directive(w, 0)?;
 
         // Process source lines
-        for line in block.source.lines() {
+        let line_count = block.source.lines().count();
+        for (i, line) in block.source.lines().enumerate() {
             if let Some(caps) = REF_PATTERN.captures(line) {
-                let indent = &caps["indent"];
-                let refname = &caps["refname"];
-                let combined_indent = format!("{}{}", base_indent, indent);
-
-                let ref_name = ReferenceName::new(refname);
-                let expanded = tangle_annotated(
-                    refs,
-                    &ref_name,
-                    &combined_indent,
-                    comment,
-                    markers,
-                    detector,
-                )?;
-                output.push(expanded);
+                let combined_indent = format!("{}{}", base_indent, &caps["indent"]);
+                let count = ref_count(&caps);
+                if let Some(content) = file_include(refs, &ref_name(&caps), count)? {
+                    w.nested(|w| w.include(&combined_indent, &content))?;
+                } else {
+                    let resolved =
+                        resolve_reference(refs, &ref_name(&caps), block.namespace.as_deref());
+                    w.nested(|w| {
+                        write_annotated(
+                            refs,
+                            &resolved,
+                            count,
+                            &combined_indent,
+                            comment,
+                            markers,
+                            line_directives,
+                            detector,
+                            w,
+                        )
+                    })?;
+                }
+
+                // Resynchronize with this block's source after the expansion
+                if i + 1 < line_count {
+                    directive(w, i + 1)?;
+                }
             } else {
-                output.push(format!("{}{}", base_indent, line));
+                w.line(base_indent, &literal(refs, line))?;
             }
         }
 
-        // Add end marker
-        let end_marker = format!("{}{}", base_indent, annotation_end(prefix, markers));
-        output.push(end_marker);
+        w.line(base_indent, &annotation_end(prefix, markers))?;
     }
 
     detector.exit();
-    Ok(output.join("\n"))
+    Ok(())
 }
 
 /// Tangles a reference with blank-line separators between blocks (bare output).
@@ -162,24 +439,21 @@ pub fn tangle_bare(
     base_indent: &str,
     detector: &mut CycleDetector,
 ) -> Result<String> {
-    detector.enter(name)?;
+    write_bare(refs, name, None, base_indent, detector)
+}
 
-    let ids = refs.get_ids_by_name(name);
-    if ids.is_empty() {
-        detector.exit();
-        return Err(EntangledError::ReferenceNotFound(name.clone()));
-    }
+fn write_bare(
+    refs: &ReferenceMap,
+    name: &ReferenceName,
+    count: Option<usize>,
+    base_indent: &str,
+    detector: &mut CycleDetector,
+) -> Result<String> {
+    detector.enter(&reference_key(name, count))?;
 
     let mut output = Vec::new();
 
-    for id in ids {
-        let block = refs.get(id).ok_or_else(|| {
-            EntangledError::Other(format!(
-                "Internal error: ReferenceMap has ID {} in name index but not in block storage",
-                id
-            ))
-        })?;
-
+    for (_, block) in blocks_for(refs, name, count)? {
         // Blank line as block separator
         output.push(String::new());
 
@@ -187,14 +461,26 @@ pub fn tangle_bare(
         for line in block.source.lines() {
             if let Some(caps) = REF_PATTERN.captures(line) {
                 let indent = &caps["indent"];
-                let refname = &caps["refname"];
                 let combined_indent = format!("{}{}", base_indent, indent);
 
-                let ref_name = ReferenceName::new(refname);
-                let expanded = tangle_bare(refs, &ref_name, &combined_indent, detector)?;
+                if let Some(content) = file_include(refs, &ref_name(&caps), ref_count(&caps))? {
+                    for line in content.lines() {
+                        output.push(format!("{}{}", combined_indent, line));
+                    }
+                    continue;
+                }
+                let resolved =
+                    resolve_reference(refs, &ref_name(&caps), block.namespace.as_deref());
+                let expanded = write_bare(
+                    refs,
+                    &resolved,
+                    ref_count(&caps),
+                    &combined_indent,
+                    detector,
+                )?;
                 output.push(expanded);
             } else {
-                output.push(format!("{}{}", base_indent, line));
+                output.push(format!("{}{}", base_indent, literal(refs, line)));
             }
         }
 
@@ -255,17 +541,139 @@ pub fn tangle_ref(
     let mut detector = CycleDetector::new();
 
     match (comment, markers) {
-        (Some(c), Some(m)) => tangle_annotated(refs, name, "", c, m, &mut detector),
+        (Some(c), Some(m)) => tangle_annotated(refs, name, "", c, m, false, &mut detector),
         (None, Some(_)) => tangle_bare(refs, name, "", &mut detector),
         _ => tangle_naked(refs, name, "", &mut detector),
     }
 }
 
+/// Tangles a reference with annotations and `#line` directives.
+///
+/// For languages whose compilers understand `#line` (C, C++, CUDA), so that
+/// errors in the tangled file are reported against the markdown source.
+pub fn tangle_ref_with_line_directives(
+    refs: &ReferenceMap,
+    name: &ReferenceName,
+    comment: &Comment,
+    markers: &Markers,
+) -> Result<String> {
+    let mut detector = CycleDetector::new();
+    tangle_annotated(refs, name, "", comment, markers, true, &mut detector)
+}
+
+/// Tangles a reference directly into `out`, without holding the output in
+/// memory.
+///
+/// Produces the same bytes as [`tangle_ref`] (or
+/// [`tangle_ref_with_line_directives`] when `line_directives` is set with
+/// annotations). Bare output trims blank lines around every expansion, which
+/// needs the whole expansion, so it is built in memory before being written.
+/// On error, `out` may hold partial output.
+pub fn tangle_ref_to(
+    refs: &ReferenceMap,
+    name: &ReferenceName,
+    comment: Option<&Comment>,
+    markers: Option<&Markers>,
+    line_directives: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut detector = CycleDetector::new();
+    let mut w = LineWriter::new(out);
+
+    match (comment, markers) {
+        (Some(c), Some(m)) => write_annotated(
+            refs,
+            name,
+            None,
+            "",
+            c,
+            m,
+            line_directives,
+            &mut detector,
+            &mut w,
+        ),
+        (None, Some(_)) => {
+            let content = tangle_bare(refs, name, "", &mut detector)?;
+            Ok(out.write_all(content.as_bytes())?)
+        }
+        _ => write_naked(refs, name, None, "", &mut detector, &mut w),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test_utils::make_block;
 
+    fn namespaced(namespace: &str, id: &str, source: &str) -> CodeBlock {
+        make_block(&format!("{}#{}", namespace, id), source)
+            .with_namespace(Some(namespace.to_string()))
+    }
+
+    #[test]
+    fn test_resolve_reference_order() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(namespaced("a.md", "helper", "a"));
+        refs.insert(namespaced("b.md", "helper", "b"));
+        refs.insert(make_block("shared", "global"));
+
+        let resolve = |name, ns| resolve_reference(&refs, name, ns).as_str().to_string();
+        assert_eq!(resolve("helper", Some("b.md")), "b.md#helper");
+        assert_eq!(resolve("a.md#helper", Some("b.md")), "a.md#helper");
+        assert_eq!(resolve("shared", Some("b.md")), "shared");
+        assert_eq!(resolve("helper", None), "helper");
+    }
+
+    #[test]
+    fn test_tangle_cross_namespace() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(namespaced("a.md", "helper", "from_a()"));
+        refs.insert(namespaced("b.md", "helper", "from_b()"));
+        refs.insert(namespaced("b.md", "main", "<<helper>>\n<<a.md#helper>>"));
+
+        let result = tangle_ref(&refs, &ReferenceName::new("b.md#main"), None, None).unwrap();
+        assert_eq!(result, "from_b()\nfrom_a()");
+    }
+
+    #[test]
+    fn test_tangle_ref_to_matches_tangle_ref() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block(
+            "main",
+            "def f():\n    <<body>>\n    <<empty>>\nf()",
+        ));
+        refs.insert(make_block("body", "a = 1\nreturn a"));
+        refs.insert(make_block("empty", ""));
+        let name = ReferenceName::new("main");
+        let comment = Comment::line("#");
+        let markers = Markers::default();
+
+        for (c, m) in [
+            (None, None),
+            (Some(&comment), Some(&markers)),
+            (None, Some(&markers)),
+        ] {
+            let mut out = Vec::new();
+            tangle_ref_to(&refs, &name, c, m, false, &mut out).unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                tangle_ref(&refs, &name, c, m).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_expand_block_uses_only_that_block() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block("main", "def f():\n    <<body>>"));
+        refs.insert(make_block("main", "f()"));
+        refs.insert(make_block("body", "return 1"));
+
+        let first = refs.get_by_name(&ReferenceName::new("main"))[0].clone();
+        let expanded = expand_block(&refs, &first).unwrap();
+        assert_eq!(expanded, "def f():\n    return 1");
+    }
+
     #[test]
     fn test_tangle_naked_simple() {
         let mut refs = ReferenceMap::new();
@@ -285,6 +693,29 @@ mod tests {
         assert_eq!(result, "def main():\n    print('hello')");
     }
 
+    #[test]
+    fn test_tangle_escaped_reference() {
+        let source = "cat <<EOF\n    \\<<body>>\nEOF";
+        let mut refs = ReferenceMap::new().with_reference_escape("\\");
+        refs.insert(make_block("main", source));
+        let name = ReferenceName::new("main");
+
+        let expected = "cat <<EOF\n    <<body>>\nEOF";
+        assert_eq!(tangle_ref(&refs, &name, None, None).unwrap(), expected);
+        let annotated = tangle_ref(&refs, &name, Some(&Comment::line("#")), None).unwrap();
+        assert!(annotated.contains("\n    <<body>>\n"));
+        let mut detector = CycleDetector::new();
+        assert_eq!(
+            tangle_bare(&refs, &name, "", &mut detector).unwrap(),
+            expected
+        );
+
+        // Without an escape the line is kept as written
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block("main", source));
+        assert_eq!(tangle_ref(&refs, &name, None, None).unwrap(), source);
+    }
+
     #[test]
     fn test_tangle_naked_nested_indentation() {
         let mut refs = ReferenceMap::new();
@@ -296,6 +727,84 @@ mod tests {
         assert_eq!(result, "if True:\n    if True:\n        print('deep')");
     }
 
+    fn with_includes(refs: ReferenceMap) -> ReferenceMap {
+        let mut fs = crate::io::VirtualFS::new();
+        fs.add_file("/project/assets/data.json", "{\n  \"a\": 1\n}\n");
+        refs.with_file_includes(
+            std::sync::Arc::new(fs),
+            std::path::PathBuf::from("/project"),
+        )
+    }
+
+    #[test]
+    fn test_tangle_file_include() {
+        let mut refs = with_includes(ReferenceMap::new());
+        refs.insert(make_block(
+            "main",
+            "data = \\\n    <<file:assets/data.json>>",
+        ));
+
+        let expected = "data = \\\n    {\n      \"a\": 1\n    }";
+        let name = ReferenceName::new("main");
+        assert_eq!(tangle_ref(&refs, &name, None, None).unwrap(), expected);
+
+        let comment = Comment::line("#");
+        let markers = Markers::default();
+        let annotated = tangle_ref(&refs, &name, Some(&comment), Some(&markers)).unwrap();
+        assert!(annotated.contains("\n    {\n      \"a\": 1\n    }\n# ~/~ end"));
+
+        // Without file includes, the reference names blocks
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block("main", "<<file:assets/data.json>>"));
+        assert!(matches!(
+            tangle_ref(&refs, &name, None, None),
+            Err(EntangledError::ReferenceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_tangle_file_include_prefers_blocks() {
+        let mut refs = with_includes(ReferenceMap::new());
+        refs.insert(make_block(
+            "main",
+            "<<file:assets/data.json>>\n<<file:missing.txt>>",
+        ));
+        refs.insert(make_block("file:assets/data.json", "block"));
+
+        let result = tangle_ref(&refs, &ReferenceName::new("main"), None, None);
+        assert!(matches!(result, Err(EntangledError::Other(msg)) if msg.contains("missing.txt")));
+
+        refs.insert(make_block("file:missing.txt", "other"));
+        let result = tangle_ref(&refs, &ReferenceName::new("main"), None, None).unwrap();
+        assert_eq!(result, "block\nother");
+    }
+
+    #[test]
+    fn test_tangle_file_include_outside_project() {
+        let mut fs = crate::io::VirtualFS::new();
+        fs.add_file("/secret.txt", "secret");
+        let fs: std::sync::Arc<dyn crate::io::FileCache> = std::sync::Arc::new(fs);
+        let name = ReferenceName::new("main");
+
+        for include in ["<<file:../secret.txt>>", "<<file:/secret.txt>>"] {
+            let mut refs = ReferenceMap::new().with_file_includes(
+                std::sync::Arc::clone(&fs),
+                std::path::PathBuf::from("/project"),
+            );
+            refs.insert(make_block("main", include));
+            assert!(matches!(
+                tangle_ref(&refs, &name, None, None),
+                Err(EntangledError::InvalidProperty(msg)) if msg.contains("outside the project")
+            ));
+        }
+
+        let mut refs = ReferenceMap::new()
+            .with_file_includes(fs, std::path::PathBuf::from("/project"))
+            .with_escaping_file_includes(true);
+        refs.insert(make_block("main", "<<file:/secret.txt>>"));
+        assert_eq!(tangle_ref(&refs, &name, None, None).unwrap(), "secret");
+    }
+
     #[test]
     fn test_tangle_cycle_detection() {
         let mut refs = ReferenceMap::new();
@@ -352,6 +861,30 @@ mod tests {
         assert!(result.contains("# ~/~ end"));
     }
 
+    #[test]
+    fn test_tangle_annotated_compact() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block("main", "def main():\n    <<body>>"));
+        refs.insert(make_block("body", "x = 1\nreturn x"));
+
+        let comment = Comment::line("#");
+        let markers = Markers::default().with_compact(true);
+
+        let result = tangle_ref(
+            &refs,
+            &ReferenceName::new("main"),
+            Some(&comment),
+            Some(&markers),
+        )
+        .unwrap();
+
+        assert_eq!(
+            result,
+            "# ~/~ begin <<main[0]>>\ndef main():\n    x = 1\n    return x\n    \
+             # ~/~ end <<body[0]>> lines:2\n# ~/~ end"
+        );
+    }
+
     #[test]
     fn test_tangle_multiple_blocks_same_name() {
         let mut refs = ReferenceMap::new();
@@ -362,6 +895,42 @@ mod tests {
         assert_eq!(result, "line1\nline2");
     }
 
+    #[test]
+    fn test_tangle_reference_by_count() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block("main", "start\n<<part[1]>>\nend"));
+        refs.insert(make_block("part", "first"));
+        refs.insert(make_block("part", "second"));
+
+        let result = tangle_ref(&refs, &ReferenceName::new("main"), None, None).unwrap();
+        assert_eq!(result, "start\nsecond\nend");
+
+        let comment = Comment::line("#");
+        let markers = Markers::default();
+        let result = tangle_ref(
+            &refs,
+            &ReferenceName::new("main"),
+            Some(&comment),
+            Some(&markers),
+        )
+        .unwrap();
+        assert!(result.contains("# ~/~ begin <<part[1]>>"));
+        assert!(!result.contains("first"));
+    }
+
+    #[test]
+    fn test_tangle_reference_count_out_of_range() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block("main", "<<part[5]>>"));
+        refs.insert(make_block("part", "only"));
+
+        let result = tangle_ref(&refs, &ReferenceName::new("main"), None, None);
+        match result {
+            Err(EntangledError::ReferenceNotFound(name)) => assert_eq!(name.as_str(), "part[5]"),
+            other => panic!("expected ReferenceNotFound, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_tangle_not_found() {
         let refs = ReferenceMap::new();
@@ -420,6 +989,62 @@ mod tests {
         assert!(!result.contains("~/~"));
     }
 
+    #[test]
+    fn test_tangle_omits_skipped_blocks() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block("main", "print('kept')"));
+        refs.insert(make_block("main", "print('skipped')").with_skip(true));
+
+        let naked = tangle_ref(&refs, &ReferenceName::new("main"), None, None).unwrap();
+        assert_eq!(naked, "print('kept')");
+
+        let comment = Comment::line("#");
+        let markers = Markers::default();
+        let annotated = tangle_ref(
+            &refs,
+            &ReferenceName::new("main"),
+            Some(&comment),
+            Some(&markers),
+        )
+        .unwrap();
+        assert!(annotated.contains("<<main[0]>>"));
+        assert!(!annotated.contains("<<main[1]>>"));
+        assert!(!annotated.contains("skipped"));
+    }
+
+    #[test]
+    fn test_tangle_line_directives() {
+        use crate::text_location::TextLocation;
+        use std::path::PathBuf;
+
+        let mut refs = ReferenceMap::new();
+        let mut main = make_block("main", "int main() {\n    <<body>>\n    return 0;\n}");
+        main.location = TextLocation::file_line(PathBuf::from("doc.md"), 10);
+        refs.insert(main);
+        let mut body = make_block("body", "puts(\"hi\");");
+        body.location = TextLocation::file_line(PathBuf::from("doc.md"), 20);
+        refs.insert(body);
+
+        let result = tangle_ref_with_line_directives(
+            &refs,
+            &ReferenceName::new("main"),
+            &Comment::line("//"),
+            &Markers::default(),
+        )
+        .unwrap();
+
+        let lines: Vec<&str> = result.lines().collect();
+        assert_eq!(lines[0], "// ~/~ begin <<main[0]>>");
+        assert_eq!(lines[1], "#line 11 \"doc.md\"");
+        assert_eq!(lines[2], "int main() {");
+        assert_eq!(lines[3], "    // ~/~ begin <<body[0]>>");
+        assert_eq!(lines[4], "    #line 21 \"doc.md\"");
+        assert_eq!(lines[5], "    puts(\"hi\");");
+        assert_eq!(lines[6], "    // ~/~ end");
+        assert_eq!(lines[7], "#line 13 \"doc.md\"");
+        assert_eq!(lines[8], "    return 0;");
+    }
+
     #[test]
     fn test_collapse_blank_lines() {
         assert_eq!(collapse_blank_lines("a\n\n\n\nb"), "a\n\nb");
@@ -0,0 +1,511 @@
+//! Reference validation without tangling.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::config::{ref_count, ref_name, Config, REF_PATTERN};
+use crate::errors::{EntangledError, Result};
+use crate::text_location::TextLocation;
+
+use super::code_block::CodeBlock;
+use super::reference_id::ReferenceId;
+use super::reference_map::ReferenceMap;
+use super::reference_name::ReferenceName;
+use super::tangle::{reference_key, resolve_reference, CycleDetector};
+
+/// A problem found by [`ReferenceMap::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// A `<<ref>>` names no block.
+    UndefinedReference {
+        /// Block containing the reference.
+        block: ReferenceId,
+        /// The reference as resolved, with its `[n]` suffix if it has one.
+        reference: String,
+        /// Line of the reference.
+        location: TextLocation,
+    },
+    /// Expanding a reference leads back to a reference being expanded.
+    Cycle {
+        /// Names in the cycle, starting and ending with the repeated one.
+        cycle: Vec<ReferenceName>,
+        /// Line of the reference that closes the cycle.
+        location: TextLocation,
+    },
+    /// A block that is not referenced and does not produce a file, so it
+    /// never appears in tangled output.
+    UnusedBlock {
+        /// The unused block.
+        block: ReferenceId,
+        /// Opening line of the block.
+        location: TextLocation,
+    },
+    /// An annotated block whose language is not configured, so its
+    /// annotations fall back to `#` comments.
+    UnknownLanguage {
+        /// The block.
+        block: ReferenceId,
+        /// The language as written.
+        language: String,
+        /// Opening line of the block.
+        location: TextLocation,
+    },
+}
+
+impl ValidationIssue {
+    /// Returns where the issue should be reported.
+    pub fn location(&self) -> &TextLocation {
+        match self {
+            ValidationIssue::UndefinedReference { location, .. }
+            | ValidationIssue::Cycle { location, .. }
+            | ValidationIssue::UnusedBlock { location, .. }
+            | ValidationIssue::UnknownLanguage { location, .. } => location,
+        }
+    }
+
+    /// Returns true for issues a check fails on: those that make tangling
+    /// fail, and unknown languages, which tangle with the wrong comments.
+    /// Unused blocks are only worth a warning.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, ValidationIssue::UnusedBlock { .. })
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::UndefinedReference { reference, .. } => {
+                write!(f, "Reference not found: {}", reference)
+            }
+            ValidationIssue::Cycle { cycle, .. } => {
+                let cycle: Vec<&str> = cycle.iter().map(ReferenceName::as_str).collect();
+                write!(f, "Cycle detected in references: {}", cycle.join(" -> "))
+            }
+            ValidationIssue::UnusedBlock { block, .. } => {
+                write!(f, "Block {} is never referenced", block)
+            }
+            ValidationIssue::UnknownLanguage {
+                block, language, ..
+            } => write!(
+                f,
+                "Block {} has unknown language `{}`; add a [[languages]] entry or set language=",
+                block, language
+            ),
+        }
+    }
+}
+
+/// Returns the location of content line `offset` of a block.
+fn line_location(block: &CodeBlock, offset: usize) -> TextLocation {
+    // Content starts on the line after the fence
+    TextLocation {
+        line: block.location.line + 1 + offset,
+        ..block.location.clone()
+    }
+}
+
+/// A `<<ref>>` line of a block that names existing blocks: the resolved
+/// name, the `[n]` count if any, and the line's offset in the block.
+type Reference = (ReferenceName, Option<usize>, usize);
+
+impl ReferenceMap {
+    /// Checks the map for undefined references, reference cycles and
+    /// unused blocks, without expanding anything.
+    ///
+    /// Issues are returned in block order. References that include a file
+    /// are considered defined. Skipped blocks are not checked.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let mut used_names = HashSet::new();
+        let mut used_ids = HashSet::new();
+
+        for (id, block) in self.iter().filter(|(_, b)| !b.skip) {
+            for (offset, line) in block.source.lines().enumerate() {
+                let Some(caps) = REF_PATTERN.captures(line) else {
+                    continue;
+                };
+                let refname = ref_name(&caps);
+                let name = resolve_reference(self, &refname, block.namespace.as_deref());
+                let count = ref_count(&caps);
+                let found = match count {
+                    Some(n) => self.contains_id(&ReferenceId::new(name.clone(), n)),
+                    None => self.contains_name(&name) || self.is_file_include(&refname),
+                };
+                if !found {
+                    issues.push(ValidationIssue::UndefinedReference {
+                        block: id.clone(),
+                        reference: reference_key(&name, count).to_string(),
+                        location: line_location(block, offset),
+                    });
+                }
+                match count {
+                    Some(n) => used_ids.insert(ReferenceId::new(name, n)),
+                    None => used_names.insert(name),
+                };
+            }
+        }
+
+        let mut detector = CycleDetector::new();
+        let mut done = HashSet::new();
+        // Walk names in block order so the reported cycles are stable
+        for name in self.iter().map(|(id, _)| &id.name) {
+            if !done.contains(name) {
+                detector.enter(name).ok();
+                self.find_cycles(name, None, &mut detector, &mut done, &mut issues);
+                detector.exit();
+                done.insert(name.clone());
+            }
+        }
+
+        let targets: HashSet<&ReferenceName> = self
+            .targets()
+            .filter_map(|t| self.get_target_name(t))
+            .collect();
+        for (id, block) in self.iter().filter(|(_, b)| !b.skip) {
+            let used = block.has_target()
+                || targets.contains(&id.name)
+                || used_names.contains(&id.name)
+                || used_ids.contains(id);
+            if !used {
+                issues.push(ValidationIssue::UnusedBlock {
+                    block: id.clone(),
+                    location: block.location.clone(),
+                });
+            }
+        }
+
+        issues
+    }
+
+    /// Checks that the language of every annotated block that ends up in an
+    /// annotated target is known to `config`, so that its annotations use
+    /// the right comment syntax.
+    ///
+    /// Skipped blocks, blocks no target reaches, blocks without a language
+    /// and blocks whose language is tangled without comments (`naked`,
+    /// `bare` or listed in `no_annotation_languages`) are not checked.
+    pub fn validate_languages(&self, config: &Config) -> Vec<ValidationIssue> {
+        let reachable = self.reachable_from_targets(config);
+        self.iter()
+            .filter(|(id, b)| !b.skip && reachable.contains(*id))
+            .filter_map(|(id, block)| {
+                let language = block.language.as_deref()?;
+                let annotated = config.annotation_for(Some(language)).has_annotations();
+                (annotated && config.find_language(language).is_none()).then(|| {
+                    ValidationIssue::UnknownLanguage {
+                        block: id.clone(),
+                        language: language.to_string(),
+                        location: block.location.clone(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the IDs of the blocks that end up in a target annotated
+    /// under `config`: the blocks writing it and every block they reference,
+    /// directly or not.
+    fn reachable_from_targets(&self, config: &Config) -> HashSet<ReferenceId> {
+        let annotated = |name: &ReferenceName| {
+            let language = self
+                .get_by_name(name)
+                .first()
+                .and_then(|b| b.language.as_deref());
+            config.annotation_for(language).has_annotations()
+        };
+        let mut pending: Vec<(ReferenceName, Option<usize>)> = self
+            .targets()
+            .filter_map(|t| self.get_target_name(t))
+            .chain(
+                self.iter()
+                    .filter(|(_, b)| b.has_target())
+                    .map(|(id, _)| &id.name),
+            )
+            .filter(|name| annotated(name))
+            .map(|name| (name.clone(), None))
+            .collect();
+
+        let mut seen = HashSet::new();
+        let mut reachable = HashSet::new();
+        while let Some((name, count)) = pending.pop() {
+            if !seen.insert((name.clone(), count)) {
+                continue;
+            }
+            let ids: Vec<ReferenceId> = match count {
+                Some(n) => vec![ReferenceId::new(name, n)],
+                None => self.get_ids_by_name(&name).into_iter().cloned().collect(),
+            };
+            for id in ids {
+                let Some(block) = self.get(&id).filter(|b| !b.skip) else {
+                    continue;
+                };
+                for (child, child_count, _) in self.block_references(block) {
+                    pending.push((child, child_count));
+                }
+                reachable.insert(id);
+            }
+        }
+        reachable
+    }
+
+    /// Checks that tangling `name` would succeed, without expanding
+    /// anything: every reference it reaches names a block that is not
+    /// skipped, or a file to include, and none leads back to a reference
+    /// being expanded.
+    ///
+    /// Returns the error tangling would raise, so that it can be reported
+    /// before output is written.
+    pub fn check_expansion(&self, name: &ReferenceName) -> Result<()> {
+        let mut detector = CycleDetector::new();
+        let mut done = HashSet::new();
+        self.check_expansion_of(name, None, &mut detector, &mut done)
+    }
+
+    fn check_expansion_of(
+        &self,
+        name: &ReferenceName,
+        count: Option<usize>,
+        detector: &mut CycleDetector,
+        done: &mut HashSet<ReferenceName>,
+    ) -> Result<()> {
+        let key = reference_key(name, count).into_owned();
+        if done.contains(&key) {
+            return Ok(());
+        }
+        detector.enter(&key)?;
+
+        let blocks: Vec<&CodeBlock> = match count {
+            Some(n) => self
+                .get(&ReferenceId::new(name.clone(), n))
+                .into_iter()
+                .collect(),
+            None => self.get_by_name(name),
+        };
+        let blocks: Vec<&CodeBlock> = blocks.into_iter().filter(|b| !b.skip).collect();
+        if blocks.is_empty() {
+            return Err(EntangledError::ReferenceNotFound(key));
+        }
+        for block in blocks {
+            for line in block.source.lines() {
+                let Some(caps) = REF_PATTERN.captures(line) else {
+                    continue;
+                };
+                let refname = ref_name(&caps);
+                let child_count = ref_count(&caps);
+                if child_count.is_none() && self.is_file_include(&refname) {
+                    continue;
+                }
+                let child = resolve_reference(self, &refname, block.namespace.as_deref());
+                self.check_expansion_of(&child, child_count, detector, done)?;
+            }
+        }
+
+        detector.exit();
+        done.insert(key);
+        Ok(())
+    }
+
+    /// Returns the `<<ref>>` lines of a block that name existing blocks.
+    fn block_references(&self, block: &CodeBlock) -> Vec<Reference> {
+        block
+            .source
+            .lines()
+            .enumerate()
+            .filter_map(|(offset, line)| {
+                let caps = REF_PATTERN.captures(line)?;
+                let name = resolve_reference(self, &ref_name(&caps), block.namespace.as_deref());
+                let count = ref_count(&caps);
+                let exists = match count {
+                    Some(n) => self.contains_id(&ReferenceId::new(name.clone(), n)),
+                    None => self.contains_name(&name),
+                };
+                exists.then_some((name, count, offset))
+            })
+            .collect()
+    }
+
+    /// Walks the references of the blocks `<<name>>` (or `<<name[n]>>`)
+    /// expands to, reporting a cycle where a reference re-enters one being
+    /// expanded. Walked references are added to `done`.
+    fn find_cycles(
+        &self,
+        name: &ReferenceName,
+        count: Option<usize>,
+        detector: &mut CycleDetector,
+        done: &mut HashSet<ReferenceName>,
+        issues: &mut Vec<ValidationIssue>,
+    ) {
+        let blocks: Vec<&CodeBlock> = match count {
+            Some(n) => self
+                .get(&ReferenceId::new(name.clone(), n))
+                .into_iter()
+                .collect(),
+            None => self.get_by_name(name),
+        };
+        for block in blocks.into_iter().filter(|b| !b.skip) {
+            for (child, child_count, offset) in self.block_references(block) {
+                let key = reference_key(&child, child_count).into_owned();
+                if done.contains(&key) {
+                    continue;
+                }
+                match detector.enter(&key) {
+                    Err(EntangledError::CycleDetected(cycle)) => {
+                        issues.push(ValidationIssue::Cycle {
+                            cycle,
+                            location: line_location(block, offset),
+                        });
+                    }
+                    _ => {
+                        self.find_cycles(&child, child_count, detector, done, issues);
+                        detector.exit();
+                        done.insert(key);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AnnotationMethod;
+    use crate::test_utils::{make_block, make_block_lang};
+    use std::path::PathBuf;
+
+    fn located(name: &str, source: &str, line: usize) -> CodeBlock {
+        let mut block = make_block(name, source);
+        block.location = TextLocation::file_line(PathBuf::from("doc.md"), line);
+        block
+    }
+
+    #[test]
+    fn test_check_expansion() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(make_block("main", "<<helper>>"));
+        refs.insert(make_block("helper", "x = 1"));
+        refs.insert(make_block("helper", "<<missing>>").with_skip(true));
+        refs.insert(make_block("skipped", "<<helper[1]>>"));
+        refs.insert(make_block("broken", "<<missing>>"));
+        refs.insert(make_block("loop", "<<again>>"));
+        refs.insert(make_block("again", "<<loop>>"));
+
+        // Only references reachable from the name are checked
+        assert!(refs.check_expansion(&ReferenceName::new("main")).is_ok());
+        assert!(matches!(
+            refs.check_expansion(&ReferenceName::new("skipped")),
+            Err(EntangledError::ReferenceNotFound(name)) if name.as_str() == "helper[1]"
+        ));
+        assert!(matches!(
+            refs.check_expansion(&ReferenceName::new("broken")),
+            Err(EntangledError::ReferenceNotFound(name)) if name.as_str() == "missing"
+        ));
+        assert!(matches!(
+            refs.check_expansion(&ReferenceName::new("loop")),
+            Err(EntangledError::CycleDetected(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_clean_map() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(
+            located("main", "<<helper>>\n<<helper[1]>>", 1).with_target(PathBuf::from("out.py")),
+        );
+        refs.insert(located("helper", "x = 1", 10));
+        refs.insert(located("helper", "y = 2", 20));
+        assert_eq!(refs.validate(), Vec::new());
+    }
+
+    #[test]
+    fn test_validate_undefined_reference() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(
+            located("main", "x = 1\n    <<missing>>\n<<main[3]>>", 5)
+                .with_target(PathBuf::from("out.py")),
+        );
+
+        let issues = refs.validate();
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].to_string(), "Reference not found: missing");
+        assert_eq!(issues[0].location().line, 7);
+        assert_eq!(issues[1].to_string(), "Reference not found: main[3]");
+        assert!(issues.iter().all(ValidationIssue::is_error));
+    }
+
+    #[test]
+    fn test_validate_cycle() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(located("a", "<<b>>", 1).with_target(PathBuf::from("out.py")));
+        refs.insert(located("b", "x = 1\n<<c>>", 10));
+        refs.insert(located("c", "<<b>>", 20));
+
+        let issues = refs.validate();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].to_string(),
+            "Cycle detected in references: a -> b -> c -> b"
+        );
+        assert_eq!(issues[0].location().line, 21);
+    }
+
+    #[test]
+    fn test_validate_unused_block() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(located("main", "<<used[0]>>", 1).with_target(PathBuf::from("out.py")));
+        refs.insert(located("used", "x = 1", 10));
+        refs.insert(located("used", "y = 2", 20));
+        let mut skipped = located("skipped", "z = 3", 30);
+        skipped.skip = true;
+        refs.insert(skipped);
+
+        let issues = refs.validate();
+        assert_eq!(
+            issues,
+            vec![ValidationIssue::UnusedBlock {
+                block: ReferenceId::new(ReferenceName::new("used"), 1),
+                location: TextLocation::file_line(PathBuf::from("doc.md"), 20),
+            }]
+        );
+        assert!(!issues[0].is_error());
+    }
+
+    #[test]
+    fn test_validate_languages() {
+        let mut refs = ReferenceMap::new();
+        refs.insert(located("main", "x = 1", 1).with_target(PathBuf::from("out.py")));
+        let mut logic = make_block_lang("logic", "X is 1 + 2.", "prolog");
+        logic.location = TextLocation::file_line(PathBuf::from("doc.md"), 10);
+        refs.insert(logic.with_target(PathBuf::from("out.pl")));
+        let mut skipped = make_block_lang("skipped", "x", "prolog");
+        skipped.skip = true;
+        refs.insert(skipped);
+        // Only blocks that end up in a target are checked
+        refs.insert(make_block_lang("example", "OUTPUT = 3", "snobol"));
+
+        let config = Config::default();
+        let issues = refs.validate_languages(&config);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].to_string(),
+            "Block logic[0] has unknown language `prolog`; add a [[languages]] entry or set language="
+        );
+        assert_eq!(issues[0].location().line, 10);
+        assert!(issues[0].is_error());
+
+        refs.insert(
+            make_block_lang("uses", "<<example>>", "python").with_target(PathBuf::from("b.py")),
+        );
+        let issues = refs.validate_languages(&config);
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].to_string().split('`').nth(1), Some("prolog"));
+        assert_eq!(issues[1].to_string().split('`').nth(1), Some("snobol"));
+
+        // Naked blocks have no comments to get wrong
+        let config = Config {
+            annotation: AnnotationMethod::Naked,
+            ..Default::default()
+        };
+        assert!(refs.validate_languages(&config).is_empty());
+    }
+}
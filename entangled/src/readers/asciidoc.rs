@@ -0,0 +1,172 @@
+//! AsciiDoc parsing for code block extraction.
+//!
+//! A source block is a `----` (or `====`) delimited block directly below a
+//! `[source,python]` attribute list, which is read by
+//! [`Properties::parse_asciidoc`](crate::model::Properties::parse_asciidoc).
+//! A `[[id]]` anchor line above the block names it. Example blocks that are
+//! not source blocks are searched for source blocks of their own.
+
+use std::path::Path;
+
+use crate::config::Config;
+use crate::errors::Result;
+use crate::model::{asciidoc_anchor, Property};
+use crate::style::Style;
+use crate::text_location::TextLocation;
+
+use super::delimiters::{tokens_with, ExtractResult, ASCIIDOC_FENCES};
+use super::markdown::{block_from_properties, parse_by_style, ParsedDocument, BOM};
+
+/// Parses an AsciiDoc document and extracts source blocks.
+pub fn parse_asciidoc(
+    input: &str,
+    source_path: Option<&Path>,
+    config: &Config,
+) -> Result<ParsedDocument> {
+    let mut doc = ParsedDocument::new();
+    if let Some(path) = source_path {
+        doc.source_path = Some(path.to_path_buf());
+    }
+
+    let input = input.strip_prefix(BOM).unwrap_or(input);
+    read_blocks(input, 0, source_path, config, &mut doc)?;
+    Ok(doc)
+}
+
+/// Reads the source blocks of `input`, whose first line is line
+/// `line_offset + 1` of the document.
+fn read_blocks(
+    input: &str,
+    line_offset: usize,
+    source_path: Option<&Path>,
+    config: &Config,
+    doc: &mut ParsedDocument,
+) -> Result<()> {
+    // Attribute list and anchor of the lines right above the current one
+    let mut attributes: Option<String> = None;
+    let mut anchor: Option<String> = None;
+
+    for result in tokens_with(input, ASCIIDOC_FENCES) {
+        match result {
+            ExtractResult::Token(mut token) => {
+                let line = token.location.line + line_offset;
+                let Some(attributes) = attributes.take() else {
+                    // An example block may hold source blocks; a listing
+                    // block without a `[source]` list holds none
+                    if token.line.trim_start().starts_with('=') {
+                        read_blocks(&token.content, line, source_path, config, doc)?;
+                    }
+                    anchor = None;
+                    continue;
+                };
+
+                token.location.line = line;
+                let location = match source_path {
+                    Some(path) => TextLocation::file_line(path.to_path_buf(), line),
+                    None => token.location.clone(),
+                };
+                token.info = attributes;
+                let (mut props, content, skip) =
+                    parse_by_style(Style::AsciiDoc, &token, &location, config)?;
+                if props.items.is_empty() {
+                    anchor = None;
+                    continue;
+                }
+                if let Some(anchor) = anchor.take() {
+                    props.items.insert(
+                        props.items.iter().take_while(|p| p.is_class()).count(),
+                        Property::Id(anchor),
+                    );
+                }
+                if let Some(block) =
+                    block_from_properties(&props, content, skip, location, source_path, config)?
+                {
+                    doc.refs.insert(block);
+                }
+            }
+            ExtractResult::NotDelimited(text) => {
+                let trimmed = text.trim();
+                if let Some(id) = asciidoc_anchor(trimmed) {
+                    anchor = Some(id.to_string());
+                } else if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                    attributes = Some(trimmed.to_string());
+                } else if !(trimmed.starts_with('.') && trimmed.len() > 1) {
+                    // Only a block title may stand between the attribute
+                    // lines and the block
+                    attributes = None;
+                    anchor = None;
+                }
+            }
+            ExtractResult::Unclosed { .. } => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ReferenceName;
+    use std::path::PathBuf;
+
+    const ADOC: &str = "\
+= Guide
+
+[[greet]]
+[source,python]
+----
+def greet():
+    print('hi')
+----
+
+.Main program
+[source,python,file=out.py]
+----
+<<greet>>
+greet()
+----
+
+====
+[source#helper,python]
+----
+pass
+----
+====
+
+[source,python]
+----
+print('anonymous')
+----
+";
+
+    #[test]
+    fn test_parse_asciidoc_blocks() {
+        let doc = parse_asciidoc(ADOC, None, &Config::default()).unwrap();
+        assert_eq!(doc.refs.len(), 3);
+
+        let greet = doc.refs.get_by_name(&ReferenceName::new("greet"));
+        assert_eq!(greet[0].source, "def greet():\n    print('hi')");
+        assert_eq!(greet[0].language.as_deref(), Some("python"));
+        assert_eq!(greet[0].location.line, 5);
+
+        let out = doc
+            .refs
+            .get_by_name(&ReferenceName::from_file_path("out.py"));
+        assert_eq!(out[0].target, Some(PathBuf::from("out.py")));
+        assert_eq!(out[0].source, "<<greet>>\ngreet()");
+        assert_eq!(out[0].location.line, 12);
+
+        // Found inside the example block
+        let helper = doc.refs.get_by_name(&ReferenceName::new("helper"));
+        assert_eq!(helper[0].source, "pass");
+        assert_eq!(helper[0].location.line, 19);
+    }
+
+    #[test]
+    fn test_parse_asciidoc_attributes_must_precede_block() {
+        let input = "[source,sh,file=run.sh]\n\nSome text.\n\n----\necho\n----\n";
+        let doc = parse_asciidoc(input, None, &Config::default()).unwrap();
+        assert!(doc.refs.is_empty());
+    }
+}
@@ -4,24 +4,25 @@
 //! code blocks for updating the original markdown.
 
 use once_cell::sync::Lazy;
-use regex::Regex;
-use std::path::Path;
+use regex::{Captures, Regex};
+use std::path::{Path, PathBuf};
 
+use crate::config::{begin_source_file, AnnotationPatterns};
 use crate::errors::{EntangledError, Result};
 use crate::model::{CodeBlock, ReferenceId, ReferenceMap};
 use crate::text_location::TextLocation;
 
-/// Pattern for matching annotation begin markers.
-static BEGIN_PATTERN: Lazy<Regex> = Lazy::new(|| {
-    // Matches: # ~/~ begin <<refid>>
-    Regex::new(r"^\s*(?P<prefix>\S+)\s+~/~\s+begin\s+<<(?P<ref>[^>]+)>>").unwrap()
-});
+/// Pattern for `#line` directives emitted when tangling with line directives.
+pub(crate) static LINE_DIRECTIVE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^\s*#line\s+\d+\s+".*"\s*$"#).unwrap());
 
-/// Pattern for matching annotation end markers.
-static END_PATTERN: Lazy<Regex> = Lazy::new(|| {
-    // Matches: # ~/~ end
-    Regex::new(r"^\s*\S+\s+~/~\s+end\s*$").unwrap()
-});
+/// Attribute holding the fingerprint of a block read by
+/// [`read_annotated_content_with`].
+pub const FINGERPRINT_ATTRIBUTE: &str = "hash";
+
+/// Attribute holding the markdown file recorded in the begin marker of a
+/// block read by [`read_annotated_content_with`].
+pub const SOURCE_FILE_ATTRIBUTE: &str = "from";
 
 /// A code block extracted from annotated source.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -36,59 +37,183 @@ pub struct AnnotatedBlock {
     pub start_line: usize,
     /// Ending line number.
     pub end_line: usize,
+    /// Fingerprint of the markdown source the block was tangled from, if
+    /// the begin marker carries one.
+    pub fingerprint: Option<String>,
+    /// Markdown file the block was tangled from, if the begin marker
+    /// records one.
+    pub source_file: Option<PathBuf>,
+}
+
+/// Optional fields of a begin marker.
+struct BeginFields {
+    source_file: Option<PathBuf>,
+    fingerprint: Option<String>,
+}
+
+impl BeginFields {
+    fn from_captures(caps: &Captures<'_>) -> Self {
+        Self {
+            source_file: begin_source_file(caps).map(PathBuf::from),
+            fingerprint: caps.name("hash").map(|m| m.as_str().to_string()),
+        }
+    }
+}
+
+/// A block whose end marker has not been read yet: ID, begin marker fields,
+/// indent, start line and content lines.
+type OpenBlock = (ReferenceId, BeginFields, String, usize, Vec<String>);
+
+/// Returns the leading whitespace of a line.
+fn line_indent(line: &str) -> String {
+    line.chars().take_while(|c| c.is_whitespace()).collect()
+}
+
+/// Returns `line` without `indent`, if it starts with it.
+fn strip_indent<'a>(line: &'a str, indent: &str) -> &'a str {
+    line.strip_prefix(indent).unwrap_or(line)
+}
+
+/// Reads the block closed by a compact marker on line `line_number`: the
+/// `lines:` content lines right before it, which must be among the last
+/// `run` lines read since the previous marker.
+fn compact_block(
+    caps: &Captures<'_>,
+    lines: &[&str],
+    line_number: usize,
+    run: usize,
+) -> Result<AnnotatedBlock> {
+    let parse_error = |message: String| EntangledError::Parse {
+        location: TextLocation::line_only(line_number),
+        message,
+    };
+    let ref_str = &caps["ref"];
+    let id = ReferenceId::parse(ref_str)
+        .ok_or_else(|| parse_error(format!("Invalid reference ID: {}", ref_str)))?;
+    let count: usize = caps["lines"]
+        .parse()
+        .map_err(|_| parse_error(format!("Invalid line count in compact marker for {}", id)))?;
+    if count > run {
+        return Err(parse_error(format!(
+            "Compact block {} should have {} lines, but only {} precede its marker",
+            id, count, run
+        )));
+    }
+
+    let marker = lines[line_number - 1];
+    let indent = line_indent(marker);
+    let first = line_number - 1 - count;
+    let fields = BeginFields::from_captures(caps);
+    Ok(AnnotatedBlock {
+        id,
+        source: lines[first..line_number - 1]
+            .iter()
+            .map(|line| strip_indent(line, &indent))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        indent,
+        // The line a begin marker would be on
+        start_line: first,
+        end_line: line_number,
+        fingerprint: fields.fingerprint,
+        source_file: fields.source_file,
+    })
+}
+
+/// Reads annotated code written with the default markers and extracts blocks.
+pub fn read_annotated_code(input: &str, source_path: Option<&Path>) -> Result<Vec<AnnotatedBlock>> {
+    read_annotated_code_with(input, source_path, &AnnotationPatterns::default())
 }
 
-/// Reads annotated code and extracts blocks.
-pub fn read_annotated_code(
+/// Reads annotated code and extracts blocks, recognizing markers with `patterns`.
+///
+/// A compact marker closes a block made of the content lines right before
+/// it, which are taken out of the enclosing block's content. Outside any
+/// block, text between a marker and the lines of a compact block means its
+/// `lines:` count is wrong, and is an error.
+pub fn read_annotated_code_with(
     input: &str,
     _source_path: Option<&Path>,
+    patterns: &AnnotationPatterns,
 ) -> Result<Vec<AnnotatedBlock>> {
     let mut blocks = Vec::new();
-    let mut stack: Vec<(ReferenceId, String, usize, Vec<String>)> = Vec::new();
-
-    for (line_num, line) in input.lines().enumerate() {
+    let mut stack: Vec<OpenBlock> = Vec::new();
+    // A generated `#line` directive may only follow a begin or end marker
+    let mut directive_allowed = false;
+    // Content lines read since the last marker or directive
+    let mut run = 0;
+    let lines: Vec<&str> = input.lines().collect();
+
+    for (line_num, &line) in lines.iter().enumerate() {
         let line_number = line_num + 1;
+        let after_marker = std::mem::take(&mut directive_allowed);
+        let run_length = std::mem::take(&mut run);
 
-        if let Some(caps) = BEGIN_PATTERN.captures(line) {
+        if let Some(caps) = patterns.begin.captures(line) {
             let ref_str = &caps["ref"];
             let id = ReferenceId::parse(ref_str).ok_or_else(|| EntangledError::Parse {
                 location: TextLocation::line_only(line_number),
                 message: format!("Invalid reference ID: {}", ref_str),
             })?;
 
-            // Calculate indent from the line
-            let indent = line
-                .chars()
-                .take_while(|c| c.is_whitespace())
-                .collect::<String>();
-
-            stack.push((id, indent, line_number, Vec::new()));
-        } else if END_PATTERN.is_match(line) {
-            if let Some((id, indent, start_line, content_lines)) = stack.pop() {
+            stack.push((
+                id,
+                BeginFields::from_captures(&caps),
+                line_indent(line),
+                line_number,
+                Vec::new(),
+            ));
+            directive_allowed = true;
+        } else if patterns.end.is_match(line) {
+            directive_allowed = true;
+            if let Some((id, fields, indent, start_line, content_lines)) = stack.pop() {
                 blocks.push(AnnotatedBlock {
                     id,
                     source: content_lines.join("\n"),
                     indent,
                     start_line,
                     end_line: line_number,
+                    fingerprint: fields.fingerprint,
+                    source_file: fields.source_file,
                 });
             } else {
                 tracing::warn!("Unmatched end marker at line {}", line_number);
             }
-        } else if let Some((_, ref indent, _, ref mut content)) = stack.last_mut() {
-            // Strip the block's indent from content lines
-            let stripped = if line.starts_with(indent.as_str()) {
-                &line[indent.len()..]
-            } else {
-                line
-            };
-            content.push(stripped.to_string());
+        } else if let Some(caps) = patterns.compact.captures(line) {
+            directive_allowed = true;
+            let block = compact_block(&caps, &lines, line_number, run_length)?;
+            // The block's lines were read as content of the enclosing block
+            if let Some((_, _, _, _, content)) = stack.last_mut() {
+                content.truncate(content.len() - (block.end_line - block.start_line - 1));
+            } else if line_number > run_length + 1 {
+                // Tangling writes nothing between top-level blocks
+                let stray = &lines[line_number - 1 - run_length..block.start_line];
+                if stray.iter().any(|line| !line.trim().is_empty()) {
+                    return Err(EntangledError::Parse {
+                        location: TextLocation::line_only(line_number),
+                        message: format!(
+                            "Compact block {} is preceded by lines outside any block; \
+                             is its line count right?",
+                            block.id
+                        ),
+                    });
+                }
+            }
+            blocks.push(block);
+        } else if after_marker && LINE_DIRECTIVE_PATTERN.is_match(line) {
+            // Generated by tangling, not part of the block content
+        } else {
+            run = run_length + 1;
+            if let Some((_, _, ref indent, _, ref mut content)) = stack.last_mut() {
+                // Strip the block's indent from content lines
+                content.push(strip_indent(line, indent).to_string());
+            }
         }
     }
 
     // Check for unclosed blocks
     if !stack.is_empty() {
-        let (id, _, start_line, _) = stack.pop().unwrap();
+        let (id, _, _, start_line, _) = stack.pop().unwrap();
         return Err(EntangledError::Parse {
             location: TextLocation::line_only(start_line),
             message: format!("Unclosed block: {}", id),
@@ -101,16 +226,43 @@ pub fn read_annotated_code(
 /// Reads an annotated code file and returns a reference map.
 pub fn read_annotated_file(path: &Path) -> Result<ReferenceMap> {
     let content = std::fs::read_to_string(path)?;
-    let blocks = read_annotated_code(&content, Some(path))?;
+    read_annotated_content(&content, path)
+}
+
+/// Reads the annotated content of the tangled file at `path` into a reference map.
+pub fn read_annotated_content(content: &str, path: &Path) -> Result<ReferenceMap> {
+    read_annotated_content_with(content, path, &AnnotationPatterns::default())
+}
+
+/// Like [`read_annotated_content`], recognizing markers with `patterns`.
+///
+/// A fingerprint on a begin marker is kept as the block's
+/// [`FINGERPRINT_ATTRIBUTE`], and a source file as its
+/// [`SOURCE_FILE_ATTRIBUTE`].
+pub fn read_annotated_content_with(
+    content: &str,
+    path: &Path,
+    patterns: &AnnotationPatterns,
+) -> Result<ReferenceMap> {
+    let blocks = read_annotated_code_with(content, Some(path), patterns)?;
 
     let mut refs = ReferenceMap::new();
     for block in blocks {
-        let code_block = CodeBlock::new(
+        let mut code_block = CodeBlock::new(
             block.id.clone(),
             None, // Language not available from annotations
             block.source,
             TextLocation::file_line(path.to_path_buf(), block.start_line),
         );
+        if let Some(fingerprint) = block.fingerprint {
+            code_block = code_block.with_attribute(FINGERPRINT_ATTRIBUTE.to_string(), fingerprint);
+        }
+        if let Some(source_file) = block.source_file {
+            code_block = code_block.with_attribute(
+                SOURCE_FILE_ATTRIBUTE.to_string(),
+                source_file.display().to_string(),
+            );
+        }
         refs.insert_with_id(block.id, code_block);
     }
 
@@ -120,68 +272,75 @@ pub fn read_annotated_file(path: &Path) -> Result<ReferenceMap> {
 /// Extracts top-level blocks (not nested).
 /// For top-level blocks, the content includes any nested annotations.
 pub fn read_top_level_blocks(input: &str) -> Result<Vec<AnnotatedBlock>> {
+    read_top_level_blocks_with(input, &AnnotationPatterns::default())
+}
+
+/// Like [`read_top_level_blocks`], recognizing markers with `patterns`.
+pub fn read_top_level_blocks_with(
+    input: &str,
+    patterns: &AnnotationPatterns,
+) -> Result<Vec<AnnotatedBlock>> {
     let mut depth: i32 = 0;
-    let mut current_block: Option<(ReferenceId, String, usize, Vec<String>)> = None;
+    let mut current_block: Option<OpenBlock> = None;
     let mut top_level = Vec::new();
+    // Lines read outside any block since the last marker
+    let mut run = 0;
+    let lines: Vec<&str> = input.lines().collect();
 
-    for (line_num, line) in input.lines().enumerate() {
+    for (line_num, &line) in lines.iter().enumerate() {
         let line_number = line_num + 1;
+        let run_length = std::mem::take(&mut run);
 
-        if let Some(caps) = BEGIN_PATTERN.captures(line) {
+        if let Some(caps) = patterns.begin.captures(line) {
             if depth == 0 {
                 let ref_str = &caps["ref"];
                 if let Some(id) = ReferenceId::parse(ref_str) {
-                    let indent = line
-                        .chars()
-                        .take_while(|c| c.is_whitespace())
-                        .collect::<String>();
-                    current_block = Some((id, indent, line_number, Vec::new()));
+                    current_block = Some((
+                        id,
+                        BeginFields::from_captures(&caps),
+                        line_indent(line),
+                        line_number,
+                        Vec::new(),
+                    ));
                 }
-            } else if depth >= 1 {
+            } else if let Some((_, _, ref indent, _, ref mut content)) = current_block.as_mut() {
                 // Nested begin marker - include it in content
-                if let Some((_, ref indent, _, ref mut content)) = current_block.as_mut() {
-                    let stripped = if line.starts_with(indent.as_str()) {
-                        &line[indent.len()..]
-                    } else {
-                        line
-                    };
-                    content.push(stripped.to_string());
-                }
+                content.push(strip_indent(line, indent).to_string());
             }
             depth += 1;
-        } else if END_PATTERN.is_match(line) {
+        } else if patterns.end.is_match(line) {
             depth -= 1;
             if depth == 0 {
-                if let Some((id, indent, start_line, content_lines)) = current_block.take() {
+                if let Some((id, fields, indent, start_line, content_lines)) = current_block.take()
+                {
                     top_level.push(AnnotatedBlock {
                         id,
                         source: content_lines.join("\n"),
                         indent,
                         start_line,
                         end_line: line_number,
+                        fingerprint: fields.fingerprint,
+                        source_file: fields.source_file,
                     });
                 }
-            } else if depth >= 1 {
+            } else if let Some((_, _, ref indent, _, ref mut content)) = current_block.as_mut() {
                 // Nested end marker - include it in content
-                if let Some((_, ref indent, _, ref mut content)) = current_block.as_mut() {
-                    let stripped = if line.starts_with(indent.as_str()) {
-                        &line[indent.len()..]
-                    } else {
-                        line
-                    };
-                    content.push(stripped.to_string());
-                }
+                content.push(strip_indent(line, indent).to_string());
+            }
+        } else if let Some(caps) = patterns.compact.captures(line) {
+            if depth == 0 {
+                top_level.push(compact_block(&caps, &lines, line_number, run_length)?);
+            } else if let Some((_, _, ref indent, _, ref mut content)) = current_block.as_mut() {
+                // Nested compact marker - include it in content
+                content.push(strip_indent(line, indent).to_string());
             }
         } else if depth >= 1 {
             // Regular content inside a top-level block (at any nesting depth)
-            if let Some((_, ref indent, _, ref mut content)) = current_block.as_mut() {
-                let stripped = if line.starts_with(indent.as_str()) {
-                    &line[indent.len()..]
-                } else {
-                    line
-                };
-                content.push(stripped.to_string());
+            if let Some((_, _, ref indent, _, ref mut content)) = current_block.as_mut() {
+                content.push(strip_indent(line, indent).to_string());
             }
+        } else {
+            run = run_length + 1;
         }
     }
 
@@ -192,6 +351,33 @@ pub fn read_top_level_blocks(input: &str) -> Result<Vec<AnnotatedBlock>> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_read_skips_line_directives() {
+        let input = r#"// ~/~ begin <<main[0]>>
+#line 11 "doc.md"
+int main() {
+    // ~/~ begin <<body[0]>>
+    #line 21 "doc.md"
+    puts("hi");
+    // ~/~ end
+#line 13 "doc.md"
+    return 0;
+}
+// ~/~ end"#;
+
+        let blocks = read_annotated_code(input, None).unwrap();
+        let body = blocks
+            .iter()
+            .find(|b| b.id.name.as_str() == "body")
+            .unwrap();
+        assert_eq!(body.source, "puts(\"hi\");");
+        let main = blocks
+            .iter()
+            .find(|b| b.id.name.as_str() == "main")
+            .unwrap();
+        assert_eq!(main.source, "int main() {\n    return 0;\n}");
+    }
+
     #[test]
     fn test_read_simple_block() {
         let input = r#"# ~/~ begin <<main[0]>>
@@ -280,6 +466,104 @@ def main():
         assert!(blocks[0].source.contains("# ~/~ begin <<inner[0]>>"));
     }
 
+    #[test]
+    fn test_read_custom_markers() {
+        use crate::config::Markers;
+
+        let input = "-- %% start {{outer[0]}}\nx\n    -- %% start {{inner[0]}}\n    y\n    -- %% stop\n-- %% stop";
+        let markers = Markers::new("{{", "}}", "start", "stop").with_sigil("%%");
+        let patterns = AnnotationPatterns::new(&markers).unwrap();
+
+        let blocks = read_annotated_code_with(input, None, &patterns).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].id.to_string(), "inner[0]");
+        assert_eq!(blocks[0].source, "y");
+        assert_eq!(blocks[1].source, "x");
+
+        let top = read_top_level_blocks_with(input, &patterns).unwrap();
+        assert_eq!(top.len(), 1);
+        assert_eq!(
+            top[0].source,
+            "x\n    -- %% start {{inner[0]}}\n    y\n    -- %% stop"
+        );
+
+        // Default patterns see no annotations at all
+        assert!(read_annotated_code(input, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_source_file() {
+        let input = "# ~/~ begin <<main[0]>> from:docs/a.md hash:0badf00d\n\
+                     x = 1\n\
+                     # ~/~ begin <<helper[0]>> from:\"my docs/b.md\"\n\
+                     y = 2\n\
+                     # ~/~ end\n\
+                     # ~/~ end";
+        let blocks = read_annotated_code(input, None).unwrap();
+        assert_eq!(blocks[0].source_file, Some(PathBuf::from("my docs/b.md")));
+        assert_eq!(blocks[1].source_file, Some(PathBuf::from("docs/a.md")));
+        assert_eq!(blocks[1].fingerprint.as_deref(), Some("0badf00d"));
+
+        let refs = read_annotated_content(input, Path::new("out.py")).unwrap();
+        let block = refs.get(&blocks[1].id).unwrap();
+        assert_eq!(
+            block.get_attribute(SOURCE_FILE_ATTRIBUTE),
+            Some("docs/a.md")
+        );
+
+        let top = read_top_level_blocks(input).unwrap();
+        assert_eq!(top[0].source_file, Some(PathBuf::from("docs/a.md")));
+    }
+
+    #[test]
+    fn test_read_compact_blocks() {
+        let input = "# ~/~ begin <<main[0]>>\n\
+                     def f():\n\
+                     \x20   x = 1\n\
+                     \x20   return x\n\
+                     \x20   # ~/~ end <<body[0]>> lines:2 hash:0badf00d\n\
+                     print(f())\n\
+                     # ~/~ end\n\
+                     y = 2\n\
+                     # ~/~ end <<tail[0]>> lines:1";
+        let blocks = read_annotated_code(input, None).unwrap();
+        let ids: Vec<String> = blocks.iter().map(|b| b.id.to_string()).collect();
+        assert_eq!(ids, ["body[0]", "main[0]", "tail[0]"]);
+        assert_eq!(blocks[0].source, "x = 1\nreturn x");
+        assert_eq!(blocks[0].indent, "    ");
+        assert_eq!(blocks[0].fingerprint.as_deref(), Some("0badf00d"));
+        assert_eq!(blocks[1].source, "def f():\nprint(f())");
+        assert_eq!(blocks[2].source, "y = 2");
+
+        let top = read_top_level_blocks(input).unwrap();
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[1].source, "y = 2");
+
+        // More lines than precede the marker since the last one
+        let input = "# ~/~ begin <<main[0]>>\nx = 1\n# ~/~ end <<body[0]>> lines:2\n# ~/~ end";
+        assert!(read_annotated_code(input, None).is_err());
+
+        // Fewer lines than were written after the last top-level block
+        let input = "# ~/~ begin <<main[0]>>\nx = 1\n# ~/~ end\n\ny = 2\nz = 3\n\
+                     # ~/~ end <<tail[0]>> lines:1";
+        assert!(read_annotated_code(input, None).is_err());
+        let input = "# ~/~ begin <<main[0]>>\nx = 1\n# ~/~ end\n\nz = 3\n\
+                     # ~/~ end <<tail[0]>> lines:1";
+        assert!(read_annotated_code(input, None).is_ok());
+    }
+
+    #[test]
+    fn test_read_fingerprint() {
+        let input = "# ~/~ begin <<main[0]>> hash:0badf00d\nx = 1\n# ~/~ end";
+        let blocks = read_annotated_code(input, None).unwrap();
+        assert_eq!(blocks[0].id.to_string(), "main[0]");
+        assert_eq!(blocks[0].fingerprint.as_deref(), Some("0badf00d"));
+
+        let refs = read_annotated_content(input, Path::new("out.py")).unwrap();
+        let block = refs.get(&blocks[0].id).unwrap();
+        assert_eq!(block.get_attribute(FINGERPRINT_ATTRIBUTE), Some("0badf00d"));
+    }
+
     #[test]
     fn test_different_comment_styles() {
         let input = r#"// ~/~ begin <<rust_block[0]>>
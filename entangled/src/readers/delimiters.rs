@@ -1,13 +1,93 @@
 //! Delimited token extraction.
 
-use once_cell::sync::Lazy;
-use regex::Regex;
+use std::collections::VecDeque;
 
 use crate::text_location::TextLocation;
 
-/// Pattern for matching code fence openings.
-static FENCE_OPEN: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^(?P<indent>\s*)(?P<fence>`{3,}|~{3,})(?P<info>.*)$").unwrap());
+/// The delimiter lines that open and close a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fences {
+    /// Characters a delimiter may be made of; all characters of one
+    /// delimiter are the same.
+    pub chars: &'static [char],
+    /// Minimum delimiter length.
+    pub min_len: usize,
+    /// Whether an info string may follow the opening delimiter.
+    pub info: bool,
+}
+
+/// Markdown code fences: three or more backticks or tildes, followed by an
+/// info string.
+pub const MARKDOWN_FENCES: Fences = Fences {
+    chars: &['`', '~'],
+    min_len: 3,
+    info: true,
+};
+
+/// AsciiDoc delimited blocks: four or more `-` (listing) or `=` (example) on
+/// a line of their own.
+pub const ASCIIDOC_FENCES: Fences = Fences {
+    chars: &['-', '='],
+    min_len: 4,
+    info: false,
+};
+
+/// An opening delimiter line.
+struct FenceOpen<'a> {
+    indent: &'a str,
+    fence_char: char,
+    fence_len: usize,
+    /// Everything after the delimiter, untrimmed.
+    info: &'a str,
+    /// Byte offset of `info` within the line.
+    info_start: usize,
+}
+
+/// Returns the length of the blockquote markers (`>`, each with the
+/// whitespace around it) at the start of `line`, or 0 if there are none.
+fn quote_prefix_len(line: &str) -> usize {
+    let mut len = 0;
+    loop {
+        let rest = line[len..].trim_start();
+        let Some(after) = rest.strip_prefix('>') else {
+            return len;
+        };
+        len = line.len() - after.len();
+        if after.starts_with(' ') {
+            len += 1;
+        }
+    }
+}
+
+/// Returns the number of blockquote markers at the start of `line`.
+fn quote_depth(line: &str) -> usize {
+    line[..quote_prefix_len(line)].matches('>').count()
+}
+
+/// Matches an opening delimiter of `fences`, preceded by blockquote markers
+/// if `quotes` is set.
+fn fence_open<'a>(line: &'a str, fences: &Fences, quotes: bool) -> Option<FenceOpen<'a>> {
+    let quote_len = if quotes { quote_prefix_len(line) } else { 0 };
+    let rest = line[quote_len..].trim_start();
+    let indent = &line[..line.len() - rest.len()];
+    let fence_char = rest.chars().next().filter(|c| fences.chars.contains(c))?;
+    let fence_len = rest.len() - rest.trim_start_matches(fence_char).len();
+    if fence_len < fences.min_len {
+        return None;
+    }
+    let info_start = indent.len() + fence_len;
+    let info = &line[info_start..];
+    if !fences.info && !info.trim().is_empty() {
+        return None;
+    }
+    Some(FenceOpen {
+        indent,
+        fence_char,
+        fence_len,
+        info,
+        info_start,
+    })
+}
 
 /// Checks if a line is a closing fence (same or more fence chars, no other content).
 #[inline]
@@ -29,8 +109,15 @@ pub struct DelimitedToken {
     pub content: String,
     /// Location of the opening delimiter.
     pub location: TextLocation,
-    /// Indentation of the code fence.
+    /// Line number of the closing delimiter.
+    pub end_line: usize,
+    /// Indentation of the code fence, including any blockquote markers
+    /// when those are read (e.g. `"> "`).
     pub indent: String,
+    /// The opening delimiter line as written.
+    pub line: String,
+    /// Byte offset of `info` within `line`.
+    pub info_offset: usize,
 }
 
 /// Result of attempting to extract a delimited token.
@@ -52,17 +139,53 @@ pub enum ExtractResult {
 pub struct DelimitedTokenGetter {
     /// Current line number (1-indexed).
     line_number: usize,
+    /// Delimiters that open and close a block.
+    fences: Fences,
+    /// Whether fences inside blockquotes are read.
+    quotes: bool,
+    /// Results already read, returned before any further input.
+    queued: VecDeque<ExtractResult>,
+    /// A line already read and counted, to be scanned before further input.
+    pending: Option<String>,
 }
 
 impl DelimitedTokenGetter {
-    /// Creates a new getter.
+    /// Creates a new getter for markdown code fences.
     pub fn new() -> Self {
-        Self { line_number: 1 }
+        Self::with_fences(MARKDOWN_FENCES)
     }
 
     /// Creates a new getter starting at a specific line.
     pub fn at_line(line: usize) -> Self {
-        Self { line_number: line }
+        Self {
+            line_number: line,
+            ..Self::new()
+        }
+    }
+
+    /// Creates a new getter for blocks delimited by `fences`.
+    pub fn with_fences(fences: Fences) -> Self {
+        Self {
+            line_number: 1,
+            fences,
+            quotes: false,
+            queued: VecDeque::new(),
+            pending: None,
+        }
+    }
+
+    /// Sets whether fences preceded by blockquote markers (`> `, `> > `)
+    /// are read.
+    ///
+    /// The markers become part of the token's `indent` and are stripped from
+    /// every content line, like the fence's indentation. A blank quoted line
+    /// (`>`) is read as an empty line. A line with fewer markers than the
+    /// fence ends the blockquote: if no closing fence came before it, the
+    /// fence and its lines are read as prose, and reading resumes at that
+    /// line.
+    pub fn with_quotes(mut self, quotes: bool) -> Self {
+        self.quotes = quotes;
+        self
     }
 
     /// Extracts the next token from the line iterator.
@@ -70,22 +193,36 @@ impl DelimitedTokenGetter {
     where
         I: Iterator<Item = &'a str>,
     {
-        let line = lines.next()?;
-        let start_line = self.line_number;
-        self.line_number += 1;
+        if let Some(result) = self.queued.pop_front() {
+            return Some(result);
+        }
+        let line = match self.pending.take() {
+            Some(line) => line,
+            None => {
+                let line = lines.next()?.to_string();
+                self.line_number += 1;
+                line
+            }
+        };
+        let start_line = self.line_number - 1;
 
         // Check for fence opening
-        let Some(caps) = FENCE_OPEN.captures(line) else {
-            return Some(ExtractResult::NotDelimited(line.to_string()));
+        let Some(open) = fence_open(&line, &self.fences, self.quotes) else {
+            return Some(ExtractResult::NotDelimited(line));
         };
 
-        let indent = caps["indent"].to_string();
-        let fence = &caps["fence"];
-        let info = caps["info"].trim().to_string();
-        let fence_char = fence.chars().next().unwrap();
-        let fence_len = fence.len();
+        let indent = open.indent.to_string();
+        let info = open.info.trim().to_string();
+        let info_offset = open.info_start + open.info.len() - open.info.trim_start().len();
+        let fence_char = open.fence_char;
+        let fence_len = open.fence_len;
+        // The markers alone, as they appear on a blank line of the quote
+        let quote = indent.trim_end();
+        let quoted = quote.ends_with('>');
+        let depth = quote_depth(&indent);
 
         let mut content_lines = Vec::new();
+        let mut raw_lines = Vec::new();
 
         // Collect content until closing fence
         loop {
@@ -93,24 +230,46 @@ impl DelimitedTokenGetter {
                 Some(content_line) => {
                     self.line_number += 1;
 
-                    if is_closing_fence(content_line, fence_char, fence_len) {
+                    if quoted && quote_depth(content_line) < depth {
+                        // The quote ended before the fence was closed
+                        self.pending = Some(content_line.to_string());
+                        self.queued.extend(
+                            raw_lines
+                                .into_iter()
+                                .map(|l: &str| ExtractResult::NotDelimited(l.to_string())),
+                        );
+                        return Some(ExtractResult::NotDelimited(line));
+                    }
+
+                    let unquoted = if quoted {
+                        &content_line[quote_prefix_len(content_line)..]
+                    } else {
+                        content_line
+                    };
+                    if is_closing_fence(unquoted, fence_char, fence_len) {
                         // Found closing fence
                         let content = content_lines.join("\n");
                         return Some(ExtractResult::Token(DelimitedToken {
                             info,
                             content,
                             location: TextLocation::line_only(start_line),
+                            end_line: self.line_number - 1,
                             indent,
+                            line,
+                            info_offset,
                         }));
                     }
 
                     // Strip indent from content if present
                     let stripped = if content_line.starts_with(&indent) {
                         &content_line[indent.len()..]
+                    } else if quoted && content_line.trim_end() == quote {
+                        ""
                     } else {
                         content_line
                     };
                     content_lines.push(stripped.to_string());
+                    raw_lines.push(content_line);
                 }
                 None => {
                     // Reached end without closing fence
@@ -132,19 +291,32 @@ impl Default for DelimitedTokenGetter {
     }
 }
 
-/// Convenience function to extract all tokens from a string.
-pub fn extract_all_tokens(input: &str) -> Vec<ExtractResult> {
-    let mut getter = DelimitedTokenGetter::new();
-    let mut lines = input.lines().peekable();
-    let mut results = Vec::new();
+/// Lazily extracts tokens from a string, one per prose line or code block.
+///
+/// Each token is parsed only when requested, so callers can process blocks
+/// as they appear and stop early. An unclosed fence is the last item.
+pub fn tokens(input: &str) -> impl Iterator<Item = ExtractResult> + '_ {
+    tokens_with(input, MARKDOWN_FENCES)
+}
 
-    while lines.peek().is_some() {
-        if let Some(result) = getter.extract(&mut lines) {
-            results.push(result);
-        }
-    }
+/// Like [`tokens`], for blocks delimited by `fences`.
+pub fn tokens_with(input: &str, fences: Fences) -> impl Iterator<Item = ExtractResult> + '_ {
+    let mut getter = DelimitedTokenGetter::with_fences(fences);
+    let mut lines = input.lines();
+    std::iter::from_fn(move || getter.extract(&mut lines))
+}
+
+/// Like [`tokens`], also reading code blocks inside blockquotes; see
+/// [`DelimitedTokenGetter::with_quotes`].
+pub fn quoted_tokens(input: &str) -> impl Iterator<Item = ExtractResult> + '_ {
+    let mut getter = DelimitedTokenGetter::new().with_quotes(true);
+    let mut lines = input.lines();
+    std::iter::from_fn(move || getter.extract(&mut lines))
+}
 
-    results
+/// Convenience function to extract all tokens from a string.
+pub fn extract_all_tokens(input: &str) -> Vec<ExtractResult> {
+    tokens(input).collect()
 }
 
 #[cfg(test)]
@@ -161,6 +333,7 @@ mod tests {
             assert_eq!(token.info, "python");
             assert_eq!(token.content, "print('hello')");
             assert_eq!(token.location.line, 1);
+            assert_eq!(token.end_line, 3);
         } else {
             panic!("Expected Token");
         }
@@ -173,6 +346,20 @@ mod tests {
 
         if let ExtractResult::Token(token) = &results[0] {
             assert_eq!(token.info, "python #main file=out.py");
+            assert_eq!(&token.line[token.info_offset..], token.info);
+        } else {
+            panic!("Expected Token");
+        }
+    }
+
+    #[test]
+    fn test_info_offset_skips_indent_and_space() {
+        let input = "  ```  python #main\n  code\n  ```";
+        let results = extract_all_tokens(input);
+
+        if let ExtractResult::Token(token) = &results[0] {
+            assert_eq!(token.line, "  ```  python #main");
+            assert_eq!(token.info_offset, 7);
         } else {
             panic!("Expected Token");
         }
@@ -242,6 +429,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_quoted_fence() {
+        let input = "> Example:\n>\n> ```python\n> if x:\n>\n>     pass\n> ```\n";
+
+        // Quote markers are prose unless enabled
+        assert!(extract_all_tokens(input)
+            .iter()
+            .all(|r| matches!(r, ExtractResult::NotDelimited(_))));
+
+        let results: Vec<_> = quoted_tokens(input).collect();
+        assert_eq!(results.len(), 3);
+        if let ExtractResult::Token(token) = &results[2] {
+            assert_eq!(token.indent, "> ");
+            assert_eq!(token.info, "python");
+            assert_eq!(token.content, "if x:\n\n    pass");
+            assert_eq!(token.end_line, 7);
+        } else {
+            panic!("Expected Token");
+        }
+    }
+
+    #[test]
+    fn test_quote_ends_unclosed_fence() {
+        let input = "> ```python\n> x = 1\n\n```python\ny = 2\n```\nafter";
+        let results: Vec<_> = quoted_tokens(input).collect();
+
+        // The quoted fence is prose, and the fence after the quote is read
+        assert_eq!(results.len(), 5);
+        assert!(matches!(&results[0], ExtractResult::NotDelimited(l) if l == "> ```python"));
+        assert!(matches!(&results[1], ExtractResult::NotDelimited(l) if l == "> x = 1"));
+        assert!(matches!(&results[2], ExtractResult::NotDelimited(l) if l.is_empty()));
+        if let ExtractResult::Token(token) = &results[3] {
+            assert_eq!(token.content, "y = 2");
+            assert_eq!(token.location.line, 4);
+            assert_eq!(token.end_line, 6);
+        } else {
+            panic!("Expected Token");
+        }
+        assert!(matches!(&results[4], ExtractResult::NotDelimited(l) if l == "after"));
+    }
+
+    #[test]
+    fn test_nested_quote_in_list_item() {
+        let input = "- item\n\n  > > ```rust\n  > > fn main() {}\n  > > ```";
+        let results: Vec<_> = quoted_tokens(input).collect();
+
+        if let ExtractResult::Token(token) = &results[2] {
+            assert_eq!(token.indent, "  > > ");
+            assert_eq!(token.content, "fn main() {}");
+            assert_eq!(&token.line[token.info_offset..], "rust");
+        } else {
+            panic!("Expected Token");
+        }
+    }
+
     #[test]
     fn test_multiple_blocks() {
         let input = "text\n```python\ncode1\n```\nmore text\n```rust\ncode2\n```";
@@ -266,6 +508,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tokens_is_lazy() {
+        let input = "text\n```python\ncode1\n```\n```rust\nunclosed";
+
+        let first_block = tokens(input).find_map(|result| match result {
+            ExtractResult::Token(token) => Some(token),
+            _ => None,
+        });
+        assert_eq!(first_block.unwrap().content, "code1");
+
+        let mut stream = tokens(input);
+        assert!(matches!(
+            stream.next(),
+            Some(ExtractResult::NotDelimited(_))
+        ));
+        assert!(matches!(stream.next(), Some(ExtractResult::Token(_))));
+        assert!(matches!(
+            stream.next(),
+            Some(ExtractResult::Unclosed { location, .. }) if location.line == 5
+        ));
+        assert!(stream.next().is_none());
+    }
+
     #[test]
     fn test_multiline_content() {
         let input = "```python\nline1\nline2\nline3\n```";
@@ -277,4 +542,26 @@ mod tests {
             panic!("Expected Token");
         }
     }
+
+    #[test]
+    fn test_asciidoc_fences() {
+        let input = "[source,python]\n----\nprint('hi')\n----\n---- not a fence";
+        let results: Vec<_> = tokens_with(input, ASCIIDOC_FENCES).collect();
+
+        assert_eq!(results.len(), 3);
+        if let ExtractResult::Token(token) = &results[1] {
+            assert_eq!(token.info, "");
+            assert_eq!(token.content, "print('hi')");
+            assert_eq!(token.location.line, 2);
+        } else {
+            panic!("Expected Token");
+        }
+        assert!(matches!(&results[2], ExtractResult::NotDelimited(_)));
+    }
+
+    #[test]
+    fn test_short_fence_is_not_delimited() {
+        let results = extract_all_tokens("``python\n``");
+        assert!(matches!(&results[0], ExtractResult::NotDelimited(_)));
+    }
 }
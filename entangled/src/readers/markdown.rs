@@ -2,15 +2,20 @@
 
 use std::path::{Path, PathBuf};
 
-use crate::config::Config;
-use crate::errors::Result;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::config::{escape_reference, Config};
+use crate::errors::{EntangledError, Result};
+use crate::io::normalize_separators;
 use crate::model::{
-    extract_quarto_options, CodeBlock, Properties, ReferenceId, ReferenceMap, ReferenceName,
+    extract_quarto_options_keeping, org_noweb_expands, CodeBlock, Properties, Property,
+    PropertySyntaxError, ReferenceId, ReferenceMap, ReferenceName,
 };
 use crate::style::Style;
 use crate::text_location::TextLocation;
 
-use super::delimiters::{extract_all_tokens, DelimitedToken, ExtractResult};
+use super::delimiters::{extract_all_tokens, quoted_tokens, DelimitedToken, ExtractResult};
 use super::yaml_header::split_yaml_header;
 
 /// A parsed markdown document.
@@ -40,6 +45,21 @@ impl ParsedDocument {
         self.source_path = Some(path);
         self
     }
+
+    /// Serializes the document as pretty-printed JSON.
+    ///
+    /// The object has the fields `source_path`, `frontmatter` (the raw YAML,
+    /// or `null`) and `blocks`, every code block in document order as
+    /// serialized by [`CodeBlock`]. These field names are stable.
+    pub fn to_json(&self) -> Result<String> {
+        let blocks: Vec<&CodeBlock> = self.refs.iter().map(|(_, block)| block).collect();
+        let document = serde_json::json!({
+            "source_path": self.source_path,
+            "frontmatter": self.frontmatter,
+            "blocks": blocks,
+        });
+        Ok(serde_json::to_string_pretty(&document)?)
+    }
 }
 
 impl Default for ParsedDocument {
@@ -48,7 +68,13 @@ impl Default for ParsedDocument {
     }
 }
 
+/// The UTF-8 byte order mark, stripped from the start of a document.
+pub(super) const BOM: char = '\u{feff}';
+
 /// Parses a markdown document and extracts code blocks.
+///
+/// Documents in the Org and AsciiDoc styles are read by
+/// [`parse_org`](super::parse_org) and [`parse_asciidoc`](super::parse_asciidoc).
 pub fn parse_markdown(
     input: &str,
     source_path: Option<&Path>,
@@ -62,27 +88,128 @@ pub fn parse_markdown(
 
     // Determine document style based on file extension, with config default as fallback
     let doc_style = Style::for_document(source_path, config.style);
+    match doc_style {
+        Style::Org => return super::parse_org(input, source_path, config),
+        Style::AsciiDoc => return super::parse_asciidoc(input, source_path, config),
+        _ => {}
+    }
+
+    // A leading byte order mark would hide the frontmatter or a fence on
+    // line 1; it is not a line, so line numbers are unaffected
+    let input = input.strip_prefix(BOM).unwrap_or(input);
 
     // Extract YAML frontmatter
     let (yaml_header, content) = split_yaml_header(input);
+    let header_lines = yaml_header.as_ref().map_or(0, |h| h.lines_consumed);
     if let Some(header) = yaml_header {
         doc.frontmatter = Some(header.content);
     }
 
     // Parse code blocks
-    let tokens = extract_all_tokens(content);
-
-    for result in tokens {
-        if let ExtractResult::Token(token) = result {
-            if let Some(block) = process_code_block(&token, source_path, config, doc_style)? {
-                doc.refs.insert(block);
+    let tokens = if config.quoted_blocks {
+        quoted_tokens(content).collect()
+    } else {
+        extract_all_tokens(content)
+    };
+    let mut tokens = tokens.into_iter().peekable();
+    // Line of the next result, relative to the content after the YAML header
+    let mut line = 1;
+
+    while let Some(result) = tokens.next() {
+        match result {
+            ExtractResult::Token(mut token) => {
+                line = token.end_line + 1;
+                // Report lines relative to the full input, including the YAML header
+                token.location.line += header_lines;
+                if let Some(block) = process_code_block(&token, source_path, config, doc_style)? {
+                    doc.refs.insert(block);
+                }
             }
+            ExtractResult::NotDelimited(text) => {
+                let directive_line = line;
+                line += 1;
+                if !config.indented_blocks {
+                    continue;
+                }
+                let Some(target) = file_directive(&text) else {
+                    continue;
+                };
+
+                // Blank lines are taken too, but trailing ones are dropped below
+                let mut content_lines = Vec::new();
+                while let Some(ExtractResult::NotDelimited(next)) = tokens.peek() {
+                    let stripped = if next.trim().is_empty() {
+                        ""
+                    } else if let Some(code) = strip_code_indent(next) {
+                        code
+                    } else {
+                        break;
+                    };
+                    content_lines.push(stripped.to_string());
+                    tokens.next();
+                    line += 1;
+                }
+                while content_lines.last().is_some_and(|l| l.is_empty()) {
+                    content_lines.pop();
+                }
+                // The code must start right after the directive
+                if content_lines.first().is_none_or(|l| l.is_empty()) {
+                    continue;
+                }
+
+                let location = TextLocation::new(
+                    source_path.map(Path::to_path_buf),
+                    directive_line + header_lines,
+                    1,
+                );
+                let props = directive_properties(target);
+                if let Some(block) = block_from_properties(
+                    &props,
+                    content_lines.join("\n"),
+                    false,
+                    location,
+                    source_path,
+                    config,
+                )? {
+                    doc.refs.insert(block);
+                }
+            }
+            ExtractResult::Unclosed { .. } => {}
         }
     }
 
     Ok(doc)
 }
 
+/// Matches a `<!-- file: path -->` directive on a line of its own.
+static FILE_DIRECTIVE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^ {0,3}<!--\s*file:\s*(?P<path>\S.*?)\s*-->\s*$").unwrap());
+
+/// Returns the target of a `<!-- file: path -->` directive line.
+fn file_directive(line: &str) -> Option<&str> {
+    FILE_DIRECTIVE
+        .captures(line)
+        .and_then(|caps| caps.name("path"))
+        .map(|m| m.as_str())
+}
+
+/// Strips the four spaces or tab that make `line` part of an indented code block.
+fn strip_code_indent(line: &str) -> Option<&str> {
+    line.strip_prefix("    ")
+        .or_else(|| line.strip_prefix('\t'))
+}
+
+/// Properties of an indented block targeting `target`; the language is
+/// taken from the file extension.
+fn directive_properties(target: &str) -> Properties {
+    let mut items = Vec::new();
+    if let Some(ext) = Path::new(target).extension().and_then(|e| e.to_str()) {
+        items.push(Property::Class(ext.to_string()));
+    }
+    items.push(Property::Attribute("file".to_string(), target.to_string()));
+    Properties::new(items)
+}
+
 /// Processes a delimited token into a CodeBlock.
 fn process_code_block(
     token: &DelimitedToken,
@@ -90,36 +217,70 @@ fn process_code_block(
     config: &Config,
     doc_style: Style,
 ) -> Result<Option<CodeBlock>> {
+    // Build location
+    let location = if let Some(path) = source_path {
+        TextLocation::file_line(path.to_path_buf(), token.location.line)
+    } else {
+        token.location.clone()
+    };
+
     // Parse properties and content based on document style
-    let (props, content) = parse_by_style(doc_style, &token.info, &token.content, config)?;
+    let (props, content, skip) = parse_by_style(doc_style, token, &location, config)?;
+
+    block_from_properties(&props, content, skip, location, source_path, config)
+}
 
-    // Get language from first class
-    let language = props.first_class().map(|s| s.to_string());
+/// Classes that exclude a block from tangling, like Quarto's `eval: false`.
+const SKIP_CLASSES: [&str; 2] = ["skip", "ignore"];
+
+/// Builds a code block from parsed properties.
+///
+/// Returns `None` for anonymous blocks, which have neither an ID nor a file
+/// target. A `.skip` or `.ignore` class marks the block as skipped. A
+/// `language` attribute overrides the language given by the first class.
+pub(super) fn block_from_properties(
+    props: &Properties,
+    content: String,
+    skip: bool,
+    location: TextLocation,
+    source_path: Option<&Path>,
+    config: &Config,
+) -> Result<Option<CodeBlock>> {
+    // Get language from the language attribute, or else the first class
+    let language = props
+        .get_attribute("language")
+        .or_else(|| props.first_class())
+        .map(|s| s.to_string());
 
     // Skip blocks without an ID or file target (anonymous blocks)
     let id_str = props.first_id();
-    let file_target = props.file();
+    // A `dir` attribute is prepended to every target. Separators are
+    // normalized so targets match across platforms
+    let dir = props.get_attribute("dir").map(Path::new);
+    let targets: Vec<PathBuf> = props
+        .files()
+        .into_iter()
+        .map(|file| normalize_separators(&dir.map_or_else(|| file.into(), |d| d.join(file))))
+        .collect();
+    let file_target = targets.first();
 
     if id_str.is_none() && file_target.is_none() {
         // Anonymous block, skip it
         return Ok(None);
     }
 
+    let namespace = source_path.and_then(|p| config.namespace_default.prefix_for(p));
+
     // Determine the reference name - prioritize explicit ID over file target
     let name = if let Some(id) = id_str {
         // Apply namespace if configured
-        let name = if let Some(ns_prefix) = source_path
-            .and_then(|p| p.file_name())
-            .and_then(|n| n.to_str())
-            .and_then(|n| config.namespace_default.prefix_for(n))
-        {
-            format!("{}#{}", ns_prefix, id)
-        } else {
-            id.to_string()
+        let name = match &namespace {
+            Some(ns_prefix) => format!("{}#{}", ns_prefix, id),
+            None => id.to_string(),
         };
         ReferenceName::new(name)
     } else if let Some(file) = file_target {
-        ReferenceName::from_file_path(file)
+        ReferenceName::from_file_path(&file.to_string_lossy())
     } else {
         return Err(crate::errors::EntangledError::Other(
             "Internal error: code block has neither ID nor file target after guard check"
@@ -127,20 +288,22 @@ fn process_code_block(
         ));
     };
 
-    // Build location
-    let location = if let Some(path) = source_path {
-        TextLocation::file_line(path.to_path_buf(), token.location.line)
-    } else {
-        token.location.clone()
-    };
+    let skip = skip
+        || props
+            .classes()
+            .into_iter()
+            .skip(1)
+            .any(|c| SKIP_CLASSES.contains(&c));
 
     // Create the code block
-    let mut block = CodeBlock::new(ReferenceId::first(name), language, content, location);
+    let mut block = CodeBlock::new(ReferenceId::first(name), language, content, location)
+        .with_skip(skip)
+        .with_namespace(namespace);
 
-    // Set target if specified
-    if let Some(file) = file_target {
-        block.target = Some(PathBuf::from(file));
-    }
+    // Set targets if specified; every file receives the same content
+    let mut targets = targets.into_iter();
+    block.target = targets.next();
+    block.extra_targets = targets.collect();
 
     // Add additional classes
     for class in props.classes().into_iter().skip(1) {
@@ -149,7 +312,7 @@ fn process_code_block(
 
     // Add attributes
     for (key, value) in props.attributes() {
-        if key != "file" {
+        if !["file", "files", "dir", "language"].contains(&key) {
             block = block.with_attribute(key.to_string(), value.to_string());
         }
     }
@@ -158,32 +321,56 @@ fn process_code_block(
 }
 
 /// Parse properties and content based on the detected style.
-fn parse_by_style(
+///
+/// The returned flag is true when the block should be skipped during tangling
+/// (Quarto `#| eval: false`). Org blocks expand references only with
+/// `:noweb yes` (or another value that expands when tangling); otherwise
+/// reference lines are escaped so they are tangled as written. A malformed info string is reported as
+/// [`EntangledError::InvalidHeader`] pointing into the fence line.
+pub(super) fn parse_by_style(
     style: Style,
-    info: &str,
-    content: &str,
+    token: &DelimitedToken,
+    location: &TextLocation,
     config: &Config,
-) -> Result<(Properties, String)> {
+) -> Result<(Properties, String, bool)> {
+    let info = token.info.as_str();
+    let content = token.content.as_str();
+    let header_error = |e: PropertySyntaxError| {
+        // Org header arguments may continue on `#+header:` lines; a failure
+        // there is reported at the end of the fence line
+        let mut offset = (token.info_offset + e.offset).min(token.line.len());
+        while !token.line.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let column = token.line[..offset].chars().count() + 1;
+        EntangledError::InvalidHeader {
+            location: TextLocation::new(location.filename.clone(), location.line, column),
+            line: token.line.clone(),
+            message: e.message,
+        }
+    };
     match style {
         Style::EntangledRs => {
-            let props = Properties::parse(info)?;
-            Ok((props, content.to_string()))
+            let props = Properties::try_parse(info).map_err(header_error)?;
+            Ok((props, content.to_string(), false))
         }
         Style::Pandoc => {
-            let props = Properties::parse_pandoc(info)?;
-            Ok((props, content.to_string()))
+            let props = Properties::try_parse_pandoc(info).map_err(header_error)?;
+            Ok((props, content.to_string(), false))
         }
         Style::Knitr => {
-            let props = Properties::parse_knitr(info)?;
-            Ok((props, content.to_string()))
+            let props = Properties::try_parse_knitr(info).map_err(header_error)?;
+            Ok((props, content.to_string(), false))
         }
         Style::Quarto => {
             // Extract language from info string
-            let info_props = Properties::parse_quarto_info(info)?;
+            let info_props = Properties::try_parse_quarto_info(info).map_err(header_error)?;
             let language = info_props.first_class();
 
-            // Extract #| options from content
-            let (quarto_opts, remaining_content) = extract_quarto_options(content);
+            // Extract #| options from content, keeping the lines of
+            // options listed in strip_quarto_options_except
+            let (quarto_opts, remaining_content) =
+                extract_quarto_options_keeping(content, &config.strip_quarto_options_except);
 
             // Merge language with quarto options
             let props = quarto_opts.to_properties(language);
@@ -195,7 +382,27 @@ fn parse_by_style(
                 content.to_string()
             };
 
-            Ok((props, final_content))
+            Ok((props, final_content, quarto_opts.skip()))
+        }
+        Style::Org => {
+            let props = Properties::try_parse_org(info).map_err(header_error)?;
+            let content = if org_noweb_expands(props.get_attribute("noweb")) {
+                content.to_string()
+            } else {
+                content
+                    .split('\n')
+                    .map(|line| {
+                        escape_reference(line, &config.reference_escape)
+                            .unwrap_or_else(|| line.to_string())
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            Ok((props, content, false))
+        }
+        Style::AsciiDoc => {
+            let props = Properties::parse_asciidoc(info)?;
+            Ok((props, content.to_string(), false))
         }
     }
 }
@@ -248,6 +455,161 @@ print('hello')
         assert_eq!(blocks[0].target, Some(PathBuf::from("output.py")));
     }
 
+    #[test]
+    fn test_parse_with_files_list() {
+        let input = "```python files=\"a.py b.py\" file=c.py\nprint('hello')\n```\n";
+        let doc = parse_markdown(input, None, &default_config()).unwrap();
+
+        assert_eq!(doc.refs.len(), 1);
+        let blocks = doc.refs.get_by_name(&ReferenceName::from_file_path("a.py"));
+        assert_eq!(blocks.len(), 1);
+        let targets: Vec<&PathBuf> = blocks[0].targets().collect();
+        assert_eq!(
+            targets,
+            vec![Path::new("a.py"), Path::new("b.py"), Path::new("c.py")]
+        );
+        assert_eq!(blocks[0].get_attribute("files"), None);
+
+        for target in ["a.py", "b.py", "c.py"] {
+            assert_eq!(
+                doc.refs.get_target_name(Path::new(target)),
+                Some(blocks[0].name())
+            );
+        }
+    }
+
+    #[test]
+    fn test_dir_attribute() {
+        let input = "```python file=out.py dir=generated tag=x\nprint('hi')\n```\n\n```python files=\"a.py b.py\" dir=gen/sub\nx\n```\n";
+        let doc = parse_markdown(input, None, &default_config()).unwrap();
+
+        let blocks = doc
+            .refs
+            .get_by_name(&ReferenceName::from_file_path("generated/out.py"));
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].target, Some(PathBuf::from("generated/out.py")));
+        assert_eq!(blocks[0].get_attribute("dir"), None);
+        assert_eq!(blocks[0].get_attribute("tag"), Some("x"));
+
+        let blocks = doc
+            .refs
+            .get_by_name(&ReferenceName::from_file_path("gen/sub/a.py"));
+        let targets: Vec<&PathBuf> = blocks[0].targets().collect();
+        assert_eq!(
+            targets,
+            vec![Path::new("gen/sub/a.py"), Path::new("gen/sub/b.py")]
+        );
+    }
+
+    #[test]
+    fn test_parse_with_bom() {
+        let input =
+            "\u{feff}---\ntitle: Demo\n---\n```python #main file=out.py\nprint('hi')\n```\n";
+        let doc = parse_markdown(input, None, &default_config()).unwrap();
+
+        assert_eq!(doc.frontmatter.as_deref(), Some("title: Demo"));
+        let blocks = doc.refs.get_by_name(&ReferenceName::new("main"));
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].location.line, 4);
+
+        // A fence on the first line
+        let doc =
+            parse_markdown("\u{feff}```python #main\nx\n```\n", None, &default_config()).unwrap();
+        assert_eq!(doc.refs.len(), 1);
+    }
+
+    #[test]
+    fn test_target_separators_normalized() {
+        // The quoted value unescapes to `src\lib\out.py`, which is
+        // `src/lib/out.py` on Windows
+        let input = "```python file=\"src\\\\lib\\\\out.py\"\nprint('hello')\n```\n";
+        let doc = parse_markdown(input, None, &default_config()).unwrap();
+
+        assert!(doc
+            .refs
+            .get_target_name(Path::new("src\\lib\\out.py"))
+            .is_some());
+        // Elsewhere `\` is part of the file name
+        let expected = if cfg!(windows) {
+            "src/lib/out.py"
+        } else {
+            "src\\lib\\out.py"
+        };
+        let blocks = doc
+            .refs
+            .get_by_name(&ReferenceName::from_file_path(expected));
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].target, Some(PathBuf::from(expected)));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let input =
+            "---\ntitle: Demo\n---\n\n```python #main file=out.py tag=a tag=b\nprint('hi')\n```\n";
+        let doc = parse_markdown(input, Some(Path::new("doc.md")), &default_config()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&doc.to_json().unwrap()).unwrap();
+        assert_eq!(json["source_path"], "doc.md");
+        assert_eq!(json["frontmatter"], "title: Demo");
+        let block = &json["blocks"][0];
+        assert_eq!(block["id"], "doc.md#main[0]");
+        assert_eq!(block["name"], "doc.md#main");
+        assert_eq!(block["language"], "python");
+        assert_eq!(block["source"], "print('hi')");
+        assert_eq!(block["target"], "out.py");
+        assert_eq!(block["targets"], serde_json::json!(["out.py"]));
+        assert_eq!(
+            block["attributes"],
+            serde_json::json!([["tag", "a"], ["tag", "b"]])
+        );
+        assert_eq!(block["location"]["filename"], "doc.md");
+        assert_eq!(block["location"]["line"], 5);
+        assert_eq!(block["skip"], false);
+    }
+
+    #[test]
+    fn test_language_attribute_overrides_class() {
+        let input = "```text #main file=run.sh language=bash\necho hi\n```\n";
+        let doc = parse_markdown(input, None, &default_config()).unwrap();
+
+        let blocks = doc.refs.get_by_name(&ReferenceName::new("main"));
+        assert_eq!(blocks[0].language.as_deref(), Some("bash"));
+        assert_eq!(blocks[0].get_attribute("language"), None);
+        assert!(blocks[0].classes.is_empty());
+    }
+
+    #[test]
+    fn test_skip_class_excludes_block() {
+        use crate::model::tangle_ref;
+
+        let input = r#"
+```python #main file=out.py
+<<helper>>
+```
+
+```python .skip #helper
+print('not ready')
+```
+
+```python .ignore #main
+print('illustration')
+```
+"#;
+        let doc = parse_markdown(input, None, &default_config()).unwrap();
+
+        let helper = doc.refs.get_by_name(&ReferenceName::new("helper"));
+        assert!(helper[0].skip);
+        assert_eq!(helper[0].language.as_deref(), Some("python"));
+        assert!(!doc.refs.is_defined(&ReferenceName::new("helper")));
+        assert!(doc.refs.is_defined(&ReferenceName::new("main")));
+
+        // Referencing a skipped block is an undefined reference
+        let err = tangle_ref(&doc.refs, &ReferenceName::new("main"), None, None).unwrap_err();
+        assert!(
+            matches!(err, EntangledError::ReferenceNotFound(name) if name.as_str() == "helper")
+        );
+    }
+
     #[test]
     fn test_skip_anonymous_block() {
         let input = r#"
@@ -331,6 +693,25 @@ code
         assert_eq!(blocks.len(), 1);
     }
 
+    #[test]
+    fn test_namespace_default_path() {
+        let input = "```python #main\ncode\n```\n";
+        let config = Config {
+            namespace_default: crate::config::NamespaceDefault::Path,
+            ..Default::default()
+        };
+
+        let a = parse_markdown(input, Some(Path::new("docs/a/util.md")), &config).unwrap();
+        let b = parse_markdown(input, Some(Path::new("docs/b/util.md")), &config).unwrap();
+
+        assert!(a
+            .refs
+            .contains_name(&ReferenceName::new("docs/a/util.md#main")));
+        assert!(b
+            .refs
+            .contains_name(&ReferenceName::new("docs/b/util.md#main")));
+    }
+
     #[test]
     fn test_location_tracking() {
         let input = r#"# Header
@@ -372,8 +753,10 @@ def main():
     pass
 ```
 "#;
-        let mut config = Config::default();
-        config.namespace_default = NamespaceDefault::None;
+        let config = Config {
+            namespace_default: NamespaceDefault::None,
+            ..Default::default()
+        };
 
         let doc = parse_markdown(input, None, &config).unwrap();
 
@@ -408,10 +791,11 @@ mod style_tests {
     use crate::style::Style;
 
     fn config_with_style(style: Style) -> Config {
-        let mut config = Config::default();
-        config.namespace_default = NamespaceDefault::None;
-        config.style = style;
-        config
+        Config {
+            namespace_default: NamespaceDefault::None,
+            style,
+            ..Default::default()
+        }
     }
 
     // EntangledRs style tests
@@ -583,6 +967,46 @@ print('hello')
         assert!(blocks[0].source.contains("#| label: main"));
     }
 
+    #[test]
+    fn test_quarto_strip_except() {
+        let input = "```{python}\n#| label: main\n#| echo: false\nprint('hello')\n```\n";
+        let mut config = config_with_style(Style::Quarto);
+        config.strip_quarto_options_except = vec!["label".to_string()];
+        let doc = parse_markdown(input, None, &config).unwrap();
+
+        let blocks = doc.refs.get_by_name(&ReferenceName::new("main"));
+        assert_eq!(blocks[0].source, "#| label: main\nprint('hello')");
+    }
+
+    #[test]
+    fn test_quarto_eval_false_skips_block() {
+        let input = r#"
+```{python}
+#| label: main
+print('hello')
+```
+
+```{python}
+#| label: main
+#| eval: false
+print('example only')
+```
+"#;
+        let config = config_with_style(Style::Quarto);
+        let doc = parse_markdown(input, None, &config).unwrap();
+
+        let blocks = doc.refs.get_by_name(&ReferenceName::new("main"));
+        assert_eq!(blocks.len(), 2);
+        assert!(!blocks[0].skip);
+        assert!(blocks[1].skip);
+        assert_eq!(
+            doc.refs
+                .concatenate_source(&ReferenceName::new("main"))
+                .unwrap(),
+            "print('hello')"
+        );
+    }
+
     #[test]
     fn test_quarto_via_extension() {
         // .qmd files automatically use Quarto style
@@ -612,9 +1036,11 @@ print('hello')
 native style
 ```
 "#;
-        let mut config = Config::default();
-        config.namespace_default = NamespaceDefault::None;
-        config.style = Style::EntangledRs;
+        let config = Config {
+            namespace_default: NamespaceDefault::None,
+            style: Style::EntangledRs,
+            ..Default::default()
+        };
         let path = Path::new("doc.md");
         let doc = parse_markdown(input, Some(path), &config).unwrap();
 
@@ -631,10 +1057,12 @@ native style
 quarto style
 ```
 "#;
-        let mut config = Config::default();
-        config.namespace_default = NamespaceDefault::None;
-        config.style = Style::EntangledRs; // This is ignored for .qmd
-        config.strip_quarto_options = true;
+        let config = Config {
+            namespace_default: NamespaceDefault::None,
+            style: Style::EntangledRs, // This is ignored for .qmd
+            strip_quarto_options: true,
+            ..Default::default()
+        };
         let path = Path::new("doc.qmd");
         let doc = parse_markdown(input, Some(path), &config).unwrap();
 
@@ -650,9 +1078,11 @@ quarto style
 knitr style
 ```
 "#;
-        let mut config = Config::default();
-        config.namespace_default = NamespaceDefault::None;
-        config.style = Style::EntangledRs; // This is ignored for .Rmd
+        let config = Config {
+            namespace_default: NamespaceDefault::None,
+            style: Style::EntangledRs, // This is ignored for .Rmd
+            ..Default::default()
+        };
         let path = Path::new("doc.Rmd");
         let doc = parse_markdown(input, Some(path), &config).unwrap();
 
@@ -712,4 +1142,71 @@ print('hello')
             .get_by_name(&ReferenceName::from_file_path("out.py"));
         assert_eq!(blocks.len(), 1);
     }
+
+    #[test]
+    fn test_location_includes_yaml_header() {
+        let input = "---\ntitle: Test\n---\n\n```python #main\ncode\n```\n";
+        let doc = parse_markdown(input, None, &config_with_style(Style::EntangledRs)).unwrap();
+
+        let blocks = doc.refs.get_by_name(&ReferenceName::new("main"));
+        assert_eq!(blocks[0].location.line, 5);
+    }
+
+    #[test]
+    fn test_header_error_points_at_failure() {
+        let input = "# Title\n\n```python #main ==bad\ncode\n```\n";
+        let err =
+            parse_markdown(input, Some(Path::new("docs/a.md")), &Config::default()).unwrap_err();
+
+        let EntangledError::InvalidHeader { location, line, .. } = &err else {
+            panic!("Expected InvalidHeader, got {:?}", err);
+        };
+        assert_eq!(line, "```python #main ==bad");
+        assert_eq!(location.filename.as_deref(), Some(Path::new("docs/a.md")));
+        assert_eq!((location.line, location.column), (3, 17));
+        assert_eq!(
+            err.snippet().unwrap(),
+            " --> docs/a.md:3:17\n  |\n3 | ```python #main ==bad\n  |                 ^"
+        );
+    }
+
+    #[test]
+    fn test_header_error_in_braced_styles() {
+        let column = |input: &str, style: Style| {
+            let config = Config {
+                style,
+                ..Config::default()
+            };
+            match parse_markdown(input, None, &config).unwrap_err() {
+                EntangledError::InvalidHeader { location, .. } => location.column,
+                other => panic!("Expected InvalidHeader, got {:?}", other),
+            }
+        };
+        // The caret points at the quote that is never closed
+        assert_eq!(
+            column("```{python, label=\"main}\ncode\n```\n", Style::Knitr),
+            19
+        );
+        // or at the end of a brace that is never closed
+        assert_eq!(column("```{python\ncode\n```\n", Style::Quarto), 11);
+    }
+
+    #[test]
+    fn test_indented_block_after_directive() {
+        let input = "<!-- file: hello.py -->\n    print('hi')\n\n\tprint('bye')\n\nText\n\n<!-- file: other.py -->\n\n    not paired\n";
+        let config = Config::builder().indented_blocks(true).build();
+        let doc = parse_markdown(input, None, &config).unwrap();
+
+        assert_eq!(doc.refs.len(), 1);
+        let blocks = doc
+            .refs
+            .get_by_name(&ReferenceName::from_file_path("hello.py"));
+        assert_eq!(blocks[0].source, "print('hi')\n\nprint('bye')");
+        assert_eq!(blocks[0].language.as_deref(), Some("py"));
+        assert_eq!(blocks[0].location.line, 1);
+
+        // Off by default: indented code stays prose
+        let doc = parse_markdown(input, None, &Config::default()).unwrap();
+        assert!(doc.refs.is_empty());
+    }
 }
@@ -1,13 +1,46 @@
 //! Readers for parsing markdown and annotated code.
 
+mod asciidoc;
 mod code;
 mod delimiters;
 mod markdown;
+mod notebook;
+mod org;
 mod types;
 mod yaml_header;
 
-pub use code::{read_annotated_code, read_annotated_file, read_top_level_blocks, AnnotatedBlock};
-pub use delimiters::{extract_all_tokens, DelimitedToken, DelimitedTokenGetter, ExtractResult};
+pub use asciidoc::parse_asciidoc;
+pub(crate) use code::LINE_DIRECTIVE_PATTERN;
+pub use code::{
+    read_annotated_code, read_annotated_code_with, read_annotated_content,
+    read_annotated_content_with, read_annotated_file, read_top_level_blocks,
+    read_top_level_blocks_with, AnnotatedBlock, FINGERPRINT_ATTRIBUTE, SOURCE_FILE_ATTRIBUTE,
+};
+pub use delimiters::{
+    extract_all_tokens, quoted_tokens, tokens, tokens_with, DelimitedToken, DelimitedTokenGetter,
+    ExtractResult, Fences, ASCIIDOC_FENCES, MARKDOWN_FENCES,
+};
 pub use markdown::{parse_markdown, read_markdown_file, ParsedDocument};
+pub use notebook::{is_notebook, parse_notebook};
+pub use org::parse_org;
 pub use types::InputToken;
 pub use yaml_header::{extract_yaml_header, parse_simple_yaml, split_yaml_header, YamlHeader};
+
+use std::path::Path;
+
+use crate::config::Config;
+use crate::errors::Result;
+
+/// Parses a source document, choosing the reader from the file extension.
+///
+/// `.ipynb` files are read as Jupyter notebooks; everything else as markdown.
+pub fn parse_document(
+    input: &str,
+    source_path: Option<&Path>,
+    config: &Config,
+) -> Result<ParsedDocument> {
+    match source_path {
+        Some(path) if is_notebook(path) => parse_notebook(input, source_path, config),
+        _ => parse_markdown(input, source_path, config),
+    }
+}
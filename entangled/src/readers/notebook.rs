@@ -0,0 +1,193 @@
+//! Jupyter notebook (`.ipynb`) parsing for code block extraction.
+//!
+//! Code cells are read like Quarto chunks: `#|` option lines (or the cell's
+//! `label`/`file` metadata) name the block and its target. Code cells without
+//! a label or file are ignored. Markdown cells are read as Quarto markdown, so
+//! named fenced blocks in them can be referenced from code cells and other
+//! documents; their prose is ignored. Since a notebook has no meaningful line
+//! numbers, each block's location uses the 1-based cell number as its line.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::errors::{EntangledError, Result};
+use crate::model::extract_quarto_options_keeping;
+use crate::text_location::TextLocation;
+
+use super::markdown::{block_from_properties, parse_markdown, ParsedDocument};
+
+/// Returns true if `path` has the `.ipynb` extension.
+pub fn is_notebook(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ipynb"))
+}
+
+/// Parses a Jupyter notebook and extracts code blocks.
+pub fn parse_notebook(
+    input: &str,
+    source_path: Option<&Path>,
+    config: &Config,
+) -> Result<ParsedDocument> {
+    let mut doc = ParsedDocument::new();
+    if let Some(path) = source_path {
+        doc.source_path = Some(path.to_path_buf());
+    }
+
+    let notebook: Value = serde_json::from_str(input)?;
+    let cells = notebook
+        .get("cells")
+        .and_then(Value::as_array)
+        .ok_or_else(|| EntangledError::Parse {
+            location: location(source_path, 1),
+            message: "notebook has no cells".to_string(),
+        })?;
+    let language = notebook_language(&notebook);
+
+    for (index, cell) in cells.iter().enumerate() {
+        match cell.get("cell_type").and_then(Value::as_str) {
+            Some("code") => {}
+            Some("markdown") => {
+                let cell_doc = parse_markdown(&cell_source(cell), source_path, config)?;
+                for (_, block) in cell_doc.refs.iter() {
+                    let mut block = block.clone();
+                    block.location = location(source_path, index + 1);
+                    doc.refs.insert(block);
+                }
+                continue;
+            }
+            _ => continue,
+        }
+
+        let source = cell_source(cell);
+        let (mut options, remaining) =
+            extract_quarto_options_keeping(&source, &config.strip_quarto_options_except);
+        // Fall back to cell metadata for options not given as #| lines
+        let metadata = |key: &str| {
+            cell.get("metadata")
+                .and_then(|m| m.get(key))
+                .and_then(Value::as_str)
+                .map(String::from)
+        };
+        if options.label.is_none() {
+            options.label = metadata("label");
+        }
+        if options.file.is_none() {
+            options.file = metadata("file");
+        }
+
+        let content = if config.strip_quarto_options {
+            remaining
+        } else {
+            source
+        };
+        let props = options.to_properties(language.as_deref());
+        let block = block_from_properties(
+            &props,
+            content,
+            options.skip(),
+            location(source_path, index + 1),
+            source_path,
+            config,
+        )?;
+        if let Some(block) = block {
+            doc.refs.insert(block);
+        }
+    }
+
+    Ok(doc)
+}
+
+/// Returns the notebook's kernel language, if recorded.
+fn notebook_language(notebook: &Value) -> Option<String> {
+    let metadata = notebook.get("metadata")?;
+    metadata
+        .get("kernelspec")
+        .and_then(|k| k.get("language"))
+        .or_else(|| metadata.get("language_info").and_then(|l| l.get("name")))
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// Returns a cell's source, which may be stored as a string or a list of lines.
+fn cell_source(cell: &Value) -> String {
+    let source = match cell.get("source") {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    };
+    // Match markdown blocks, whose content has no trailing newline
+    match source.strip_suffix('\n') {
+        Some(stripped) => stripped.to_string(),
+        None => source,
+    }
+}
+
+fn location(source_path: Option<&Path>, line: usize) -> TextLocation {
+    match source_path {
+        Some(path) => TextLocation::file_line(path.to_path_buf(), line),
+        None => TextLocation::line_only(line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ReferenceName;
+    use std::path::PathBuf;
+
+    const NOTEBOOK: &str = r##"{
+ "cells": [
+  {"cell_type": "markdown", "metadata": {}, "source": ["# Title\n", "See <<main>>.\n", "\n",
+   "```{python}\n", "#| label: setup\n", "import os\n", "```\n"]},
+  {"cell_type": "code", "metadata": {}, "outputs": [], "execution_count": 1,
+   "source": ["#| label: main\n", "#| file: out.py\n", "print('hi')\n"]},
+  {"cell_type": "code", "metadata": {"label": "helper"}, "outputs": [],
+   "source": "def helper():\n    pass"},
+  {"cell_type": "code", "metadata": {}, "outputs": [], "source": ["1 + 1"]}
+ ],
+ "metadata": {"kernelspec": {"language": "python", "name": "python3"}},
+ "nbformat": 4,
+ "nbformat_minor": 5
+}"##;
+
+    #[test]
+    fn test_parse_notebook() {
+        let path = PathBuf::from("analysis.ipynb");
+        let config = Config {
+            namespace_default: crate::config::NamespaceDefault::None,
+            ..Default::default()
+        };
+        let doc = parse_notebook(NOTEBOOK, Some(&path), &config).unwrap();
+
+        assert_eq!(doc.refs.len(), 3);
+        let main = doc.refs.get_by_name(&ReferenceName::new("main"));
+        assert_eq!(main[0].source, "print('hi')");
+        assert_eq!(main[0].target, Some(PathBuf::from("out.py")));
+        assert_eq!(main[0].language.as_deref(), Some("python"));
+        assert_eq!(main[0].location.line, 2);
+
+        let helper = doc.refs.get_by_name(&ReferenceName::new("helper"));
+        assert_eq!(helper[0].source, "def helper():\n    pass");
+
+        // Fenced blocks in markdown cells are read too
+        let setup = doc.refs.get_by_name(&ReferenceName::new("setup"));
+        assert_eq!(setup[0].source, "import os");
+        assert_eq!(setup[0].location.line, 1);
+    }
+
+    #[test]
+    fn test_parse_notebook_invalid() {
+        assert!(parse_notebook("not json", None, &Config::default()).is_err());
+        assert!(parse_notebook("{}", None, &Config::default()).is_err());
+    }
+
+    #[test]
+    fn test_is_notebook() {
+        assert!(is_notebook(Path::new("docs/intro.ipynb")));
+        assert!(is_notebook(Path::new("INTRO.IPYNB")));
+        assert!(!is_notebook(Path::new("intro.md")));
+    }
+}
@@ -0,0 +1,226 @@
+//! Org-mode parsing for code block extraction.
+//!
+//! Source blocks are delimited by `#+begin_src` and `#+end_src` lines. The
+//! words after `#+begin_src` give the language and header arguments, which
+//! are read by [`Properties::parse_org`]; `#+header:` lines in front of the
+//! block add more. A `#+name:` line in front of the block names it. As when
+//! Org tangles, the content is dedented by its common indentation. A block
+//! without `#+end_src` is an error, rather than running to the end of the
+//! document.
+
+use std::path::Path;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::config::Config;
+use crate::errors::{EntangledError, Result};
+use crate::model::Property;
+use crate::style::Style;
+use crate::text_location::TextLocation;
+
+use super::delimiters::DelimitedToken;
+use super::markdown::{block_from_properties, parse_by_style, ParsedDocument, BOM};
+
+/// Matches the opening line of a source block.
+static BEGIN_SRC: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^(?P<indent>\s*)#\+begin_src\b(?P<info>.*)$").unwrap());
+
+/// Matches the closing line of a source block.
+static END_SRC: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^\s*#\+end_src\s*$").unwrap());
+
+/// Matches a keyword line such as `#+name: main`.
+static KEYWORD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*#\+(?P<key>[\w-]+):\s*(?P<value>.*?)\s*$").unwrap());
+
+/// Parses an Org document and extracts source blocks.
+pub fn parse_org(
+    input: &str,
+    source_path: Option<&Path>,
+    config: &Config,
+) -> Result<ParsedDocument> {
+    let mut doc = ParsedDocument::new();
+    if let Some(path) = source_path {
+        doc.source_path = Some(path.to_path_buf());
+    }
+
+    let input = input.strip_prefix(BOM).unwrap_or(input);
+    let mut lines = input.lines().enumerate();
+    // Keywords of the lines right above the current one
+    let mut name: Option<String> = None;
+    let mut headers: Vec<String> = Vec::new();
+
+    while let Some((index, line)) = lines.next() {
+        let Some(caps) = BEGIN_SRC.captures(line) else {
+            match KEYWORD.captures(line) {
+                Some(keyword) => match keyword["key"].to_lowercase().as_str() {
+                    "name" => name = Some(keyword["value"].to_string()),
+                    "header" => headers.push(keyword["value"].to_string()),
+                    _ => {}
+                },
+                None => {
+                    name = None;
+                    headers.clear();
+                }
+            }
+            continue;
+        };
+
+        let mut content_lines = Vec::new();
+        let mut closed = false;
+        for (_, content_line) in lines.by_ref() {
+            if END_SRC.is_match(content_line) {
+                closed = true;
+                break;
+            }
+            content_lines.push(content_line);
+        }
+        if !closed {
+            let line = index + 1;
+            return Err(EntangledError::Parse {
+                location: match source_path {
+                    Some(path) => TextLocation::file_line(path.to_path_buf(), line),
+                    None => TextLocation::line_only(line),
+                },
+                message: "#+begin_src without a matching #+end_src".to_string(),
+            });
+        }
+
+        let raw_info = caps.name("info").unwrap();
+        let info = std::iter::once(raw_info.as_str().trim().to_string())
+            .chain(headers.drain(..))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let token = DelimitedToken {
+            info_offset: raw_info.start() + raw_info.as_str().len()
+                - raw_info.as_str().trim_start().len(),
+            info,
+            content: dedent(&content_lines),
+            location: TextLocation::line_only(index + 1),
+            end_line: index + content_lines.len() + 2,
+            indent: caps["indent"].to_string(),
+            line: line.to_string(),
+        };
+
+        let location = match source_path {
+            Some(path) => TextLocation::file_line(path.to_path_buf(), index + 1),
+            None => token.location.clone(),
+        };
+        let (mut props, content, skip) = parse_by_style(Style::Org, &token, &location, config)?;
+        if let Some(name) = name.take() {
+            props.items.insert(
+                props.items.iter().take_while(|p| p.is_class()).count(),
+                Property::Id(name),
+            );
+        }
+        if let Some(block) =
+            block_from_properties(&props, content, skip, location, source_path, config)?
+        {
+            doc.refs.insert(block);
+        }
+    }
+
+    Ok(doc)
+}
+
+/// Removes the indentation shared by all non-blank lines.
+fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|l| l.get(indent..).unwrap_or("").trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ReferenceName;
+    use std::path::PathBuf;
+
+    const ORG: &str = "\
+* Example
+
+#+name: greet
+#+begin_src python
+  def greet():
+      print('hi')
+#+end_src
+
+#+header: :noweb yes
+#+BEGIN_SRC python :tangle out.py
+<<greet>>
+greet()
+#+END_SRC
+
+#+begin_src python :tangle raw.py
+<<greet>>
+#+end_src
+
+#+begin_src python
+print('anonymous')
+#+end_src
+";
+
+    #[test]
+    fn test_parse_org_blocks() {
+        let doc = parse_org(ORG, None, &Config::default()).unwrap();
+        assert_eq!(doc.refs.len(), 3);
+
+        let greet = doc.refs.get_by_name(&ReferenceName::new("greet"));
+        assert_eq!(greet[0].source, "def greet():\n    print('hi')");
+        assert_eq!(greet[0].language.as_deref(), Some("python"));
+        assert_eq!(greet[0].location.line, 4);
+
+        let out = doc
+            .refs
+            .get_by_name(&ReferenceName::from_file_path("out.py"));
+        assert_eq!(out[0].target, Some(PathBuf::from("out.py")));
+        assert_eq!(out[0].source, "<<greet>>\ngreet()");
+        assert_eq!(out[0].location.line, 10);
+    }
+
+    #[test]
+    fn test_parse_org_without_noweb_keeps_references() {
+        let doc = parse_org(ORG, None, &Config::default()).unwrap();
+        let raw = doc
+            .refs
+            .get_by_name(&ReferenceName::from_file_path("raw.py"));
+        assert_eq!(raw[0].source, "\\<<greet>>");
+    }
+
+    #[test]
+    fn test_parse_org_keywords_must_precede_block() {
+        let input = "#+name: lost\n\n#+begin_src sh :tangle run.sh\necho\n#+end_src\n";
+        let doc = parse_org(input, None, &Config::default()).unwrap();
+        assert!(!doc.refs.contains_name(&ReferenceName::new("lost")));
+        assert_eq!(doc.refs.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_org_unclosed_block() {
+        let input = "#+begin_src python :tangle out.py\nprint('hi')\n\n* Next heading\n";
+        let err = parse_org(input, Some(Path::new("notes.org")), &Config::default()).unwrap_err();
+        match err {
+            EntangledError::Parse { location, .. } => assert_eq!(location.line, 1),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_org_header_error_points_at_quote() {
+        let input = "* Code
+#+begin_src python :tangle \"out.py\n#+end_src\n";
+        let err = parse_org(input, Some(Path::new("notes.org")), &Config::default()).unwrap_err();
+        let EntangledError::InvalidHeader { location, .. } = &err else {
+            panic!("Expected InvalidHeader, got {:?}", err);
+        };
+        assert_eq!((location.line, location.column), (2, 28));
+    }
+}
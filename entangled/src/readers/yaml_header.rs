@@ -27,11 +27,9 @@ pub fn extract_yaml_header(input: &str) -> Option<YamlHeader> {
     }
 
     let mut content_lines = Vec::new();
-    let mut line_count = 1;
 
-    // Collect until closing ---
-    for line in lines {
-        line_count += 1;
+    // Collect until closing --- (line 1 was the opening delimiter)
+    for (line_count, line) in (2..).zip(lines) {
         if line.trim() == "---" {
             return Some(YamlHeader {
                 content: content_lines.join("\n"),
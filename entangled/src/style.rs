@@ -5,6 +5,8 @@
 //! - `pandoc`: Original entangled style with `{.python #name file=path}`
 //! - `quarto`: Quarto/Jupyter style with `{python}` and `#|` comments
 //! - `knitr`: RMarkdown style with `{python, label=name, file=path}`
+//! - `org`: Org-mode `#+begin_src python :tangle path` blocks
+//! - `asciidoc`: AsciiDoc `[source,python]` blocks delimited by `----`
 
 use std::path::Path;
 
@@ -24,6 +26,12 @@ pub enum Style {
     Quarto,
     /// RMarkdown/knitr style: ```{python, label=main, file=out.py}
     Knitr,
+    /// Org-mode style: #+begin_src python :tangle out.py ... #+end_src
+    Org,
+    /// AsciiDoc style: [source,python,file=out.py] above a ---- listing block
+    #[serde(rename = "asciidoc")]
+    #[cfg_attr(feature = "clap", value(name = "asciidoc"))]
+    AsciiDoc,
 }
 
 impl Style {
@@ -36,6 +44,10 @@ impl Style {
         match ext.to_lowercase().as_str() {
             "qmd" => Some(Style::Quarto),
             "rmd" => Some(Style::Knitr),
+            "org" => Some(Style::Org),
+            "adoc" | "asciidoc" => Some(Style::AsciiDoc),
+            // Notebook cells carry Quarto-style #| options
+            "ipynb" => Some(Style::Quarto),
             // .md files don't indicate a specific style
             _ => None,
         }
@@ -44,7 +56,8 @@ impl Style {
     /// Determine the style for a document.
     ///
     /// Priority:
-    /// 1. File extension (`.qmd` -> Quarto, `.Rmd` -> Knitr)
+    /// 1. File extension (`.qmd`/`.ipynb` -> Quarto, `.Rmd` -> Knitr, `.org` -> Org,
+    ///    `.adoc`/`.asciidoc` -> AsciiDoc)
     /// 2. Configured default style
     pub fn for_document(path: Option<&Path>, config_default: Style) -> Style {
         if let Some(path) = path {
@@ -62,6 +75,8 @@ impl Style {
             Style::Pandoc => "pandoc",
             Style::Quarto => "quarto",
             Style::Knitr => "knitr",
+            Style::Org => "org",
+            Style::AsciiDoc => "asciidoc",
         }
     }
 }
@@ -81,8 +96,10 @@ impl std::str::FromStr for Style {
             "pandoc" => Ok(Style::Pandoc),
             "quarto" => Ok(Style::Quarto),
             "knitr" | "rmarkdown" | "rmd" => Ok(Style::Knitr),
+            "org" | "org-mode" => Ok(Style::Org),
+            "asciidoc" | "adoc" => Ok(Style::AsciiDoc),
             _ => Err(format!(
-                "Unknown style '{}'. Valid styles: entangled-rs, pandoc, quarto, knitr",
+                "Unknown style '{}'. Valid styles: entangled-rs, pandoc, quarto, knitr, org, asciidoc",
                 s
             )),
         }
@@ -125,6 +142,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_extension_org() {
+        assert_eq!(
+            Style::from_extension(Path::new("notes.org")),
+            Some(Style::Org)
+        );
+        assert_eq!(
+            Style::from_extension(Path::new("NOTES.ORG")),
+            Some(Style::Org)
+        );
+    }
+
+    #[test]
+    fn test_from_extension_asciidoc() {
+        assert_eq!(
+            Style::from_extension(Path::new("guide.adoc")),
+            Some(Style::AsciiDoc)
+        );
+        assert_eq!(
+            Style::from_extension(Path::new("guide.asciidoc")),
+            Some(Style::AsciiDoc)
+        );
+    }
+
     #[test]
     fn test_from_extension_md() {
         assert_eq!(Style::from_extension(Path::new("doc.md")), None);
@@ -193,6 +234,8 @@ mod tests {
         assert_eq!("quarto".parse::<Style>().unwrap(), Style::Quarto);
         assert_eq!("knitr".parse::<Style>().unwrap(), Style::Knitr);
         assert_eq!("rmarkdown".parse::<Style>().unwrap(), Style::Knitr);
+        assert_eq!("org".parse::<Style>().unwrap(), Style::Org);
+        assert_eq!("asciidoc".parse::<Style>().unwrap(), Style::AsciiDoc);
         assert!("invalid".parse::<Style>().is_err());
     }
 
@@ -1,5 +1,6 @@
 //! Python bindings for Entangled literate programming engine.
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
@@ -8,15 +9,110 @@ use pyo3::types::PyDict;
 
 use entangled::Style;
 use entangled::config::{self, AnnotationMethod, NamespaceDefault};
+use entangled::errors::EntangledError;
+use entangled::hooks::{Hook, PostTangleResult, PreTangleResult};
 use entangled::interface::{self, Context, Document};
 use entangled::io::Transaction;
-use entangled::model::{CodeBlock, ReferenceMap, ReferenceName};
+use entangled::model::{CodeBlock, ReferenceId, ReferenceMap, ReferenceName, ValidationIssue};
 
 /// Convert entangled errors to Python exceptions.
 fn to_py_err(e: entangled::errors::EntangledError) -> PyErr {
     PyRuntimeError::new_err(e.to_string())
 }
 
+/// Hook that calls the methods of a Python object.
+///
+/// Any of `pre_tangle(block)`, `post_tangle(content, block)` and
+/// `pre_stitch(content, block)` the object defines is called; a returned
+/// string replaces the block source or content, `None` leaves it unchanged.
+struct PyHook {
+    name: String,
+    priority: i32,
+    object: Py<PyAny>,
+}
+
+impl PyHook {
+    /// Calls `method` on the hook object if it defines it, converting a
+    /// raised exception to an error.
+    fn call<'py>(
+        &self,
+        py: Python<'py>,
+        method: &str,
+        args: impl pyo3::call::PyCallArgs<'py>,
+    ) -> entangled::errors::Result<Option<String>> {
+        let object = self.object.bind(py);
+        let to_err = |e: PyErr| {
+            EntangledError::Other(format!(
+                "Python hook '{}' failed in {}: {}",
+                self.name, method, e
+            ))
+        };
+        if !object.hasattr(method).map_err(to_err)? {
+            return Ok(None);
+        }
+        object
+            .call_method1(method, args)
+            .and_then(|result| result.extract::<Option<String>>())
+            .map_err(to_err)
+    }
+}
+
+impl Hook for PyHook {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn pre_tangle(&self, block: &CodeBlock) -> entangled::errors::Result<Option<PreTangleResult>> {
+        Python::attach(|py| {
+            let block = PyCodeBlock {
+                inner: block.clone(),
+            };
+            Ok(self
+                .call(py, "pre_tangle", (block,))?
+                .map(|source| PreTangleResult {
+                    source,
+                    metadata: Vec::new(),
+                }))
+        })
+    }
+
+    fn post_tangle(
+        &self,
+        content: &str,
+        block: &CodeBlock,
+    ) -> entangled::errors::Result<Option<PostTangleResult>> {
+        Python::attach(|py| {
+            let block = PyCodeBlock {
+                inner: block.clone(),
+            };
+            Ok(self
+                .call(py, "post_tangle", (content, block))?
+                .map(|content| PostTangleResult {
+                    prefix: None,
+                    content,
+                    suffix: None,
+                }))
+        })
+    }
+
+    fn pre_stitch(
+        &self,
+        content: &str,
+        block: &CodeBlock,
+    ) -> entangled::errors::Result<Option<String>> {
+        Python::attach(|py| {
+            let block = PyCodeBlock {
+                inner: block.clone(),
+            };
+            self.call(py, "pre_stitch", (content, block))
+        })
+    }
+}
+
 /// Python wrapper for Config.
 #[pyclass(name = "Config")]
 #[derive(Clone)]
@@ -90,6 +186,7 @@ impl PyConfig {
     fn namespace_default(&self) -> String {
         match self.inner.namespace_default {
             NamespaceDefault::File => "file".to_string(),
+            NamespaceDefault::Path => "path".to_string(),
             NamespaceDefault::None => "none".to_string(),
         }
     }
@@ -99,6 +196,7 @@ impl PyConfig {
     fn set_namespace_default(&mut self, value: &str) -> PyResult<()> {
         self.inner.namespace_default = match value {
             "file" => NamespaceDefault::File,
+            "path" => NamespaceDefault::Path,
             "none" => NamespaceDefault::None,
             _ => return Err(PyValueError::new_err("Invalid namespace default")),
         };
@@ -232,6 +330,22 @@ impl PyTransaction {
         self.inner.diffs()
     }
 
+    /// Get each action as a dict with keys "target", "kind" and "diff".
+    ///
+    /// "diff" is None when the action would not change the file.
+    fn actions<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.inner
+            .actions()
+            .map(|action| {
+                let dict = PyDict::new(py);
+                dict.set_item("target", action.target().display().to_string())?;
+                dict.set_item("kind", action.kind())?;
+                dict.set_item("diff", action.diff())?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
     fn __repr__(&self) -> String {
         format!("Transaction({} actions)", self.inner.len())
     }
@@ -246,6 +360,8 @@ pub struct PyContext {
 #[pymethods]
 impl PyContext {
     /// Create a new context with configuration and base directory.
+    ///
+    /// The context locks the file database until it is garbage collected.
     #[new]
     #[pyo3(signature = (config=None, base_dir=None))]
     fn new(config: Option<PyConfig>, base_dir: Option<&str>) -> PyResult<Self> {
@@ -313,9 +429,38 @@ impl PyContext {
             .collect()
     }
 
-    /// Clear the file database.
+    /// Clear the file database and the cached documents.
     fn clear_filedb(&mut self) {
         self.inner.filedb.clear();
+        self.inner.document_cache.clear();
+    }
+
+    /// Register a hook object.
+    ///
+    /// The object may define `pre_tangle(block)`, `post_tangle(content, block)`
+    /// and `pre_stitch(content, block)`, each returning a replacement string
+    /// or None. `name` defaults to the object's `name` attribute, or its
+    /// class name. Hooks run in ascending `priority`.
+    #[pyo3(signature = (hook, name=None, priority=0))]
+    fn add_hook(
+        &mut self,
+        hook: Bound<'_, PyAny>,
+        name: Option<String>,
+        priority: i32,
+    ) -> PyResult<()> {
+        let name = match name {
+            Some(name) => name,
+            None => match hook.getattr("name") {
+                Ok(name) => name.extract()?,
+                Err(_) => hook.get_type().name()?.extract()?,
+            },
+        };
+        self.inner.add_hook(PyHook {
+            name,
+            priority,
+            object: hook.unbind(),
+        });
+        Ok(())
     }
 
     fn __repr__(&self) -> String {
@@ -366,6 +511,15 @@ impl PyCodeBlock {
         self.inner.target.as_ref().map(|p| p.display().to_string())
     }
 
+    /// Get every target file path, the primary target first.
+    #[getter]
+    fn targets(&self) -> Vec<String> {
+        self.inner
+            .targets()
+            .map(|p| p.display().to_string())
+            .collect()
+    }
+
     /// Check if block is empty.
     fn is_empty(&self) -> bool {
         self.inner.is_empty()
@@ -396,6 +550,9 @@ impl PyCodeBlock {
 pub struct PyDocument {
     #[pyo3(get)]
     path: Option<String>,
+    /// Raw YAML frontmatter, if present.
+    #[pyo3(get)]
+    frontmatter: Option<String>,
     refs: ReferenceMap,
 }
 
@@ -407,6 +564,7 @@ impl PyDocument {
         let doc = Document::load(&PathBuf::from(path), &ctx.inner).map_err(to_py_err)?;
         Ok(PyDocument {
             path: Some(path.to_string()),
+            frontmatter: doc.frontmatter().map(String::from),
             refs: doc.refs().clone(),
         })
     }
@@ -420,10 +578,20 @@ impl PyDocument {
             .map_err(to_py_err)?;
         Ok(PyDocument {
             path: path.map(String::from),
+            frontmatter: doc.frontmatter,
             refs: doc.refs,
         })
     }
 
+    /// Frontmatter parsed as simple `key: value` pairs.
+    #[getter]
+    fn metadata(&self) -> HashMap<String, String> {
+        self.frontmatter
+            .as_deref()
+            .map(entangled::readers::parse_simple_yaml)
+            .unwrap_or_default()
+    }
+
     /// Get all code blocks.
     fn blocks(&self) -> Vec<PyCodeBlock> {
         self.refs
@@ -441,6 +609,49 @@ impl PyDocument {
             .collect()
     }
 
+    /// Remove a block by its ID (e.g. "main[0]"), returning it if present.
+    fn remove_block(&mut self, id: &str) -> PyResult<Option<PyCodeBlock>> {
+        let id = ReferenceId::parse(id)
+            .ok_or_else(|| PyValueError::new_err(format!("Invalid block ID: {}", id)))?;
+        Ok(self.refs.remove(&id).map(|inner| PyCodeBlock { inner }))
+    }
+
+    /// Rename all blocks called `old` to `new`.
+    fn rename(&mut self, old: &str, new: &str) -> PyResult<()> {
+        self.refs
+            .rename(&ReferenceName::new(old), ReferenceName::new(new))
+            .map_err(to_py_err)
+    }
+
+    /// Check for undefined references, reference cycles and unused blocks.
+    ///
+    /// Returns one dict per issue with keys "kind" ("undefined_reference",
+    /// "cycle" or "unused_block"), "message", "file" and "line".
+    fn validate<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.refs
+            .validate()
+            .into_iter()
+            .map(|issue| {
+                let kind = match issue {
+                    ValidationIssue::UndefinedReference { .. } => "undefined_reference",
+                    ValidationIssue::Cycle { .. } => "cycle",
+                    ValidationIssue::UnusedBlock { .. } => "unused_block",
+                    ValidationIssue::UnknownLanguage { .. } => "unknown_language",
+                };
+                let location = issue.location();
+                let dict = PyDict::new(py);
+                dict.set_item("kind", kind)?;
+                dict.set_item("message", issue.to_string())?;
+                dict.set_item(
+                    "file",
+                    location.filename.as_ref().map(|f| f.display().to_string()),
+                )?;
+                dict.set_item("line", location.line)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
     /// Get all target file paths.
     fn targets(&self) -> Vec<String> {
         self.refs
@@ -508,14 +719,29 @@ fn stitch_files(ctx: &mut PyContext, source_files: Vec<String>) -> PyResult<PyTr
 }
 
 /// Execute a transaction.
+///
+/// If `paths` is given, only actions targeting one of those paths (relative
+/// to the context's base directory, or absolute) are applied.
 #[pyfunction]
-#[pyo3(signature = (transaction, ctx, force=false))]
+#[pyo3(signature = (transaction, ctx, force=false, paths=None))]
 fn execute_transaction(
     transaction: &PyTransaction,
     ctx: &mut PyContext,
     force: bool,
+    paths: Option<Vec<String>>,
 ) -> PyResult<()> {
-    if force {
+    if let Some(paths) = paths {
+        let selected: Vec<PathBuf> = paths
+            .iter()
+            .map(|p| ctx.inner.resolve_path(&PathBuf::from(p)))
+            .collect();
+        transaction
+            .inner
+            .execute_filtered(&mut ctx.inner.filedb, force, |action| {
+                selected.iter().any(|p| p == action.target())
+            })
+            .map_err(to_py_err)?;
+    } else if force {
         transaction
             .inner
             .execute_force(&mut ctx.inner.filedb)
@@ -1,6 +1,6 @@
 """Type stubs for pyentangled._core Rust bindings."""
 
-from typing import Optional, Sequence
+from typing import Any, Optional, Sequence
 
 class Config:
     """Configuration for Entangled."""
@@ -52,6 +52,15 @@ class Transaction:
     def describe(self) -> list[str]:
         """Get descriptions of all actions."""
         ...
+    def diffs(self) -> list[str]:
+        """Get unified diffs for all actions."""
+        ...
+    def actions(self) -> list[dict[str, Optional[str]]]:
+        """Get each action as a dict with keys "target", "kind" and "diff".
+
+        "diff" is None when the action would not change the file.
+        """
+        ...
     def __repr__(self) -> str: ...
 
 class Context:
@@ -92,6 +101,20 @@ class Context:
     def clear_filedb(self) -> None:
         """Clear the file database."""
         ...
+    def add_hook(
+        self,
+        hook: object,
+        name: Optional[str] = None,
+        priority: int = 0,
+    ) -> None:
+        """Register a hook object.
+
+        The object may define `pre_tangle(block)`, `post_tangle(content, block)`
+        and `pre_stitch(content, block)`, each returning a replacement string
+        or None. `name` defaults to the object's `name` attribute, or its
+        class name. Hooks run in ascending `priority`.
+        """
+        ...
     def __repr__(self) -> str: ...
 
 class CodeBlock:
@@ -117,6 +140,10 @@ class CodeBlock:
     def target(self) -> Optional[str]:
         """Get the target file path if this is a file target."""
         ...
+    @property
+    def targets(self) -> list[str]:
+        """Get every target file path, the primary target first."""
+        ...
     def is_empty(self) -> bool:
         """Check if block is empty."""
         ...
@@ -129,6 +156,13 @@ class Document:
     """A parsed markdown document."""
 
     path: Optional[str]
+    frontmatter: Optional[str]
+    """Raw YAML frontmatter, if present."""
+
+    @property
+    def metadata(self) -> dict[str, str]:
+        """Frontmatter parsed as simple `key: value` pairs."""
+        ...
 
     @staticmethod
     def load(path: str, ctx: Context) -> Document:
@@ -148,6 +182,19 @@ class Document:
     def get_by_name(self, name: str) -> list[CodeBlock]:
         """Get blocks by name."""
         ...
+    def remove_block(self, id: str) -> Optional[CodeBlock]:
+        """Remove a block by its ID (e.g. "main[0]"), returning it if present."""
+        ...
+    def rename(self, old: str, new: str) -> None:
+        """Rename all blocks called `old` to `new`."""
+        ...
+    def validate(self) -> list[dict[str, Any]]:
+        """Check for undefined references, reference cycles and unused blocks.
+
+        Returns one dict per issue with keys "kind" ("undefined_reference",
+        "cycle" or "unused_block"), "message", "file" and "line".
+        """
+        ...
     def targets(self) -> list[str]:
         """Get all target file paths."""
         ...
@@ -174,8 +221,13 @@ def execute_transaction(
     transaction: Transaction,
     ctx: Context,
     force: bool = False,
+    paths: Optional[list[str]] = None,
 ) -> None:
-    """Execute a transaction."""
+    """Execute a transaction.
+
+    If `paths` is given, only actions targeting one of those paths (relative
+    to the context's base directory, or absolute) are applied.
+    """
     ...
 
 def sync_documents(ctx: Context, force: bool = False) -> None:
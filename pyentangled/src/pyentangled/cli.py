@@ -38,7 +38,7 @@ version = "2.0"
 source_patterns = ["**/*.md"]
 
 # Code block syntax style for .md files
-# Options: "entangled-rs" (default), "pandoc", "quarto", "knitr"
+# Options: "entangled-rs" (default), "pandoc", "quarto", "knitr", "org", "asciidoc"
 style = "entangled-rs"
 
 # How to annotate output files
@@ -540,7 +540,7 @@ def create_parser() -> argparse.ArgumentParser:
     parser.add_argument(
         "-s", "--style",
         metavar="STYLE",
-        choices=["entangled-rs", "pandoc", "quarto", "knitr"],
+        choices=["entangled-rs", "pandoc", "quarto", "knitr", "org", "asciidoc"],
         help="Code block syntax style",
     )
     parser.add_argument(
@@ -249,6 +249,16 @@ class TestDocument:
             doc = Document.load(str(md_path), ctx)
             assert len(doc) >= 1
 
+    def test_frontmatter_and_metadata(self):
+        doc = Document.parse('---\ntitle: "Guide"\nauthor: Ada\n---\n\n' + SIMPLE_MD)
+        assert doc.frontmatter == 'title: "Guide"\nauthor: Ada'
+        assert doc.metadata == {"title": "Guide", "author": "Ada"}
+
+    def test_no_frontmatter(self):
+        doc = Document.parse(SIMPLE_MD)
+        assert doc.frontmatter is None
+        assert doc.metadata == {}
+
     def test_blocks(self):
         doc = Document.parse(SIMPLE_MD)
         blocks = doc.blocks()
@@ -265,6 +275,28 @@ class TestDocument:
         blocks = doc.get_by_name("nonexistent")
         assert blocks == []
 
+    def test_remove_block_and_rename(self):
+        doc = Document.parse(MULTI_BLOCK_MD)
+        doc.rename("greet", "hello")
+        assert doc.get_by_name("greet") == []
+        assert doc.get_by_name("hello")[0].name == "hello"
+
+        removed = doc.remove_block("main[0]")
+        assert removed.name == "main"
+        assert doc.remove_block("main[0]") is None
+        assert doc.targets() == []
+
+    def test_validate(self):
+        assert Document.parse(MULTI_BLOCK_MD).validate() == []
+
+        doc = Document.parse(
+            "```python #main file=out.py\n<<missing>>\n```\n\n```python #extra\nx = 1\n```\n"
+        )
+        issues = doc.validate()
+        assert [i["kind"] for i in issues] == ["undefined_reference", "unused_block"]
+        assert issues[0]["message"] == "Reference not found: missing"
+        assert issues[0]["line"] == 2
+
     def test_targets(self):
         doc = Document.parse(SIMPLE_MD)
         targets = doc.targets()
@@ -387,6 +419,27 @@ class TestTangleDocuments:
             assert len(diffs) >= 1
             assert any("hello.py" in diff for diff in diffs)
 
+    def test_transaction_actions(self):
+        with tempfile.TemporaryDirectory() as d:
+            (Path(d) / "test.md").write_text(SIMPLE_MD)
+            ctx = Context.default_for_dir(d)
+            tx = tangle_documents(ctx)
+            actions = tx.actions()
+            assert len(actions) == 1
+            assert actions[0]["kind"] == "write"
+            assert actions[0]["target"].endswith("hello.py")
+            assert "+print('hello')" in actions[0]["diff"]
+
+    def test_execute_transaction_paths(self):
+        md = SIMPLE_MD + "\n```python #other file=other.py\nprint('other')\n```\n"
+        with tempfile.TemporaryDirectory() as d:
+            (Path(d) / "test.md").write_text(md)
+            ctx = Context.default_for_dir(d)
+            tx = tangle_documents(ctx)
+            execute_transaction(tx, ctx, paths=["other.py"])
+            assert (Path(d) / "other.py").exists()
+            assert not (Path(d) / "hello.py").exists()
+
 
 # --- tangle_files ---
 
@@ -496,3 +549,68 @@ class TestLocateSource:
             # Line 1 is the annotation begin marker -- should return None
             result = locate_source(ctx, output_path, 1)
             assert result is None
+
+
+# --- hooks ---
+
+
+class TestHooks:
+    def test_post_tangle_hook(self):
+        class Upper:
+            def post_tangle(self, content, block):
+                assert block.target == "hello.py"
+                return content.upper()
+
+        with tempfile.TemporaryDirectory() as d:
+            (Path(d) / "test.md").write_text(SIMPLE_MD)
+            ctx = Context.default_for_dir(d)
+            ctx.add_hook(Upper())
+            tx = tangle_documents(ctx)
+            execute_transaction(tx, ctx)
+            assert "PRINT('HELLO')" in (Path(d) / "hello.py").read_text()
+
+    def test_pre_tangle_hook(self):
+        class Rename:
+            def pre_tangle(self, block):
+                return block.source.replace("hello", "bye")
+
+            def pre_stitch(self, content, block):
+                return content.replace("bye", "hello")
+
+        with tempfile.TemporaryDirectory() as d:
+            (Path(d) / "test.md").write_text(SIMPLE_MD)
+            ctx = Context.default_for_dir(d)
+            ctx.add_hook(Rename())
+            tx = tangle_documents(ctx)
+            execute_transaction(tx, ctx)
+            tangled = (Path(d) / "hello.py").read_text()
+            assert "print('bye')" in tangled
+            assert "print('hello')" not in tangled
+            tx = stitch_documents(ctx)
+            execute_transaction(tx, ctx)
+            assert (Path(d) / "test.md").read_text() == SIMPLE_MD
+
+    def test_hook_returning_none(self):
+        class Noop:
+            def post_tangle(self, content, block):
+                return None
+
+        with tempfile.TemporaryDirectory() as d:
+            (Path(d) / "test.md").write_text(SIMPLE_MD)
+            ctx = Context.default_for_dir(d)
+            ctx.add_hook(Noop(), name="noop", priority=5)
+            tx = tangle_documents(ctx)
+            execute_transaction(tx, ctx)
+            assert "print('hello')" in (Path(d) / "hello.py").read_text()
+
+    def test_hook_exception(self):
+        class Failing:
+            def post_tangle(self, content, block):
+                raise ValueError("boom")
+
+        with tempfile.TemporaryDirectory() as d:
+            (Path(d) / "test.md").write_text(SIMPLE_MD)
+            ctx = Context.default_for_dir(d)
+            ctx.add_hook(Failing())
+            with pytest.raises(RuntimeError, match="Failing.*boom"):
+                tangle_documents(ctx)